@@ -0,0 +1,102 @@
+//! Encoding and decoding throughput of [Packet]s, signed with RSA as before
+//! sessions, and sealed with the session keys as they are now.
+
+use std::hint::black_box;
+
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use openssl::{
+    pkey::{PKey, Private, Public},
+    rsa::Rsa,
+};
+
+use grapevine_lib::{AesKey, FromPacket, IntoPacket, Message, Opener, Packet, Sealer, new_aes_key};
+
+struct Keys {
+    private: PKey<Private>,
+    public: PKey<Public>,
+    ours: AesKey,
+    theirs: AesKey,
+}
+
+impl Keys {
+    fn new() -> Self {
+        let private = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let public = PKey::public_key_from_pem(&private.public_key_to_pem().unwrap()).unwrap();
+        Self {
+            private,
+            public,
+            ours: new_aes_key().unwrap(),
+            theirs: new_aes_key().unwrap(),
+        }
+    }
+}
+
+fn message() -> Message {
+    Message::new("The quick brown fox jumps over the lazy dog".repeat(4))
+}
+
+fn signed(message: &Message, keys: &Keys) -> Vec<u8> {
+    let mut packet = message.into_packet(&keys.private).unwrap();
+    packet.encrypt(&keys.theirs).unwrap();
+    let mut buf = Vec::new();
+    packet.to_writer(&mut buf).unwrap();
+    buf
+}
+
+fn sealed(message: &Message, sealer: &mut Sealer) -> Vec<u8> {
+    let packet = sealer.seal(bitcode::serialize(message).unwrap()).unwrap();
+    let mut buf = Vec::new();
+    packet.to_writer(&mut buf).unwrap();
+    buf
+}
+
+fn encode(c: &mut Criterion) {
+    let keys = Keys::new();
+    let message = message();
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(1));
+
+    group.bench_function("signed", |b| b.iter(|| signed(black_box(&message), &keys)));
+
+    let mut sealer = Sealer::new(keys.theirs, &keys.ours).unwrap();
+    group.bench_function("sealed", |b| {
+        b.iter(|| sealed(black_box(&message), &mut sealer))
+    });
+    group.finish();
+}
+
+fn decode(c: &mut Criterion) {
+    let keys = Keys::new();
+    let message = message();
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(1));
+
+    let encoded = signed(&message, &keys);
+    group.bench_function("signed", |b| {
+        b.iter(|| {
+            let mut packet = Packet::from_reader(&mut black_box(encoded.as_slice())).unwrap();
+            // the other party decrypts with what is their key to us
+            packet.decrypt(&keys.theirs).unwrap();
+            assert!(packet.verify(&keys.public));
+            Message::from_packet(&packet).unwrap()
+        })
+    });
+
+    // each sealed frame opens only once, as the next one of its session
+    let encoded = sealed(&message, &mut Sealer::new(keys.theirs, &keys.ours).unwrap());
+    group.bench_function("sealed", |b| {
+        b.iter_batched(
+            || Opener::new(keys.theirs, &keys.ours).unwrap(),
+            |mut opener| {
+                let mut packet = Packet::from_reader(&mut black_box(encoded.as_slice())).unwrap();
+                assert!(opener.open(&mut packet).unwrap());
+                Message::from_packet(&packet).unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encode, decode);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+//! A bot repeating whatever it's told.
+//!
+//! ```sh
+//! cargo run -p grapevine_lib --example echo_bot -- 127.0.0.1:7777 echo.json
+//! ```
+//!
+//! Then create a channel to the address in the GUI, and say something.
+//! Everybody gets accepted, and remembered in the identity file, so that
+//! they can reconnect using the keys they already exchanged.
+
+use std::{env, error::Error, time::Duration};
+
+use grapevine_lib::{AcceptPolicy, Bot, HostAddr};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let addr: HostAddr = args.next().as_deref().unwrap_or("127.0.0.1:7777").parse()?;
+    let identity = args.next().unwrap_or_else(|| "echo.json".to_string());
+
+    let started = std::time::Instant::now();
+    let mut bot = Bot::new()
+        .with_accept(AcceptPolicy::Everyone)
+        .with_identity(identity)?
+        .with_command("status", "tells how long the bot has been up", move |_| {
+            Some(format!("up for {}s", started.elapsed().as_secs()))
+        })
+        .with_command("echo", "repeats the rest of the message", |context| {
+            Some(context.args().to_string())
+        })
+        .on_message(|context| Some(context.message().content().clone()));
+
+    let bound = bot.listen(addr).await?;
+    println!("listening on {}", bound);
+
+    tokio::select! {
+        result = bot.run() => result?,
+        _ = tokio::signal::ctrl_c() => {
+            bot.broadcast("going away, bye").await;
+            bot.app_mut().shutdown(Duration::from_secs(2)).await?;
+        }
+    }
+    Ok(())
+}
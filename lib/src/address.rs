@@ -0,0 +1,285 @@
+use std::{
+    fmt, io,
+    net::{SocketAddr, ToSocketAddrs},
+    str::FromStr,
+    vec,
+};
+#[cfg(feature = "async")]
+use std::{net::TcpStream, sync::mpsc, thread, time::Duration};
+
+use derive_more::{Display, Error};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// How long to wait for a connection attempt, before starting the next one
+#[cfg(feature = "async")]
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+/// How long a single connection attempt may take
+#[cfg(feature = "async")]
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An address of a peer, either an IP address or a hostname, alongside a port.
+/// Hostnames are kept as typed, and are only resolved when connecting.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct HostAddr {
+    host: String,
+    port: u16,
+}
+
+impl HostAddr {
+    /// Get the host part, without the port
+    pub fn host(&self) -> &str {
+        self.host.as_str()
+    }
+
+    /// Get the port
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Resolves the address, returning every address it resolved to
+    pub fn resolve(&self) -> io::Result<Vec<SocketAddr>> {
+        Ok(self.to_socket_addrs()?.collect())
+    }
+}
+
+/// `localhost:0`, a placeholder for peers reached outside of the network
+impl Default for HostAddr {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 0,
+        }
+    }
+}
+
+impl From<SocketAddr> for HostAddr {
+    fn from(addr: SocketAddr) -> Self {
+        Self {
+            host: addr.ip().to_string(),
+            port: addr.port(),
+        }
+    }
+}
+
+impl fmt::Display for HostAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl fmt::Debug for HostAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// The provided string isn't a `host:port` pair
+#[derive(Debug, Display, Error)]
+#[display("invalid address, expected host:port")]
+pub struct HostAddrParseError;
+
+impl FromStr for HostAddr {
+    type Err = HostAddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(addr) = SocketAddr::from_str(s) {
+            return Ok(addr.into());
+        }
+
+        let (host, port) = s.rsplit_once(':').ok_or(HostAddrParseError)?;
+        let port = port.parse().map_err(|_| HostAddrParseError)?;
+        let valid_host = !host.is_empty()
+            && host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'));
+        if !valid_host {
+            return Err(HostAddrParseError);
+        }
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl ToSocketAddrs for HostAddr {
+    type Iter = vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        (self.host.as_str(), self.port).to_socket_addrs()
+    }
+}
+
+impl Serialize for HostAddr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for HostAddr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let s = String::deserialize(deserializer)?;
+        HostAddr::from_str(&s).map_err(D::Error::custom)
+    }
+}
+
+/// Orders resolved addresses so that the address families alternate,
+/// starting with the family of the first address, as per RFC 8305.
+#[cfg(feature = "async")]
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_v6 = first.is_ipv6();
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv6() == first_v6);
+    preferred.reverse();
+    other.reverse();
+
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    while !preferred.is_empty() || !other.is_empty() {
+        ordered.extend(preferred.pop());
+        ordered.extend(other.pop());
+    }
+    ordered
+}
+
+/// Connects to the address, trying every address it resolves to.
+///
+/// Attempts are staggered in the happy eyeballs fashion: if an attempt
+/// hasn't finished within [ATTEMPT_DELAY], the next address is tried
+/// alongside it, and the first successful connection wins.
+#[cfg(feature = "async")]
+pub fn connect(addr: &HostAddr) -> io::Result<TcpStream> {
+    let candidates = interleave(addr.resolve()?);
+    if candidates.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} did not resolve to any address", addr),
+        ));
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut in_flight = 0;
+    let mut last_error = None;
+    for candidate in candidates {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let _ = tx.send(TcpStream::connect_timeout(&candidate, CONNECT_TIMEOUT));
+        });
+        in_flight += 1;
+
+        match rx.recv_timeout(ATTEMPT_DELAY) {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => {
+                in_flight -= 1;
+                last_error = Some(e);
+            }
+            Err(_) => {}
+        }
+    }
+    drop(tx);
+
+    while in_flight > 0 {
+        match rx.recv() {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => {
+                in_flight -= 1;
+                last_error = Some(e);
+            }
+            Err(_) => break,
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::TimedOut)))
+}
+
+/// Asynchronous version of [connect]. The attempts are made on the blocking
+/// thread pool, so that they don't hold up the runtime.
+#[cfg(feature = "async")]
+pub async fn connect_async(addr: &HostAddr) -> io::Result<tokio::net::TcpStream> {
+    let addr = addr.clone();
+    let stream = tokio::task::spawn_blocking(move || connect(&addr))
+        .await
+        .map_err(io::Error::other)??;
+    stream.set_nonblocking(true)?;
+    tokio::net::TcpStream::from_std(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "async")]
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_ip() {
+        let addr = HostAddr::from_str("127.0.0.1:4000").unwrap();
+        assert_eq!(addr.host(), "127.0.0.1");
+        assert_eq!(addr.port(), 4000);
+
+        let addr = HostAddr::from_str("[::1]:4000").unwrap();
+        assert_eq!(addr.host(), "::1");
+        assert_eq!(addr.to_string(), "[::1]:4000");
+    }
+
+    #[test]
+    fn test_parse_hostname() {
+        let addr = HostAddr::from_str("alice.example.lan:4000").unwrap();
+        assert_eq!(addr.host(), "alice.example.lan");
+        assert_eq!(addr.port(), 4000);
+        assert_eq!(addr.to_string(), "alice.example.lan:4000");
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(HostAddr::from_str("alice.example.lan").is_err());
+        assert!(HostAddr::from_str(":4000").is_err());
+        assert!(HostAddr::from_str("alice:port").is_err());
+        assert!(HostAddr::from_str("al ice:4000").is_err());
+        assert!(HostAddr::from_str("::1:4000").is_err());
+    }
+
+    #[test]
+    fn test_serde_compatible_with_socket_addr() {
+        let addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let json = serde_json::to_string(&addr).unwrap();
+        let host: HostAddr = serde_json::from_str(&json).unwrap();
+        assert_eq!(host, HostAddr::from(addr));
+        assert_eq!(serde_json::to_string(&host).unwrap(), json);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn test_interleave() {
+        let v4a: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let v4b: SocketAddr = "10.0.0.2:1".parse().unwrap();
+        let v6a: SocketAddr = "[::1]:1".parse().unwrap();
+        let v6b: SocketAddr = "[::2]:1".parse().unwrap();
+
+        assert_eq!(
+            interleave(vec![v6a, v6b, v4a, v4b]),
+            vec![v6a, v4a, v6b, v4b]
+        );
+        assert_eq!(interleave(vec![v4a, v4b, v6a]), vec![v4a, v6a, v4b]);
+        assert!(interleave(Vec::new()).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn test_connect_hostname() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let addr = HostAddr::from_str(&format!("localhost:{}", port)).unwrap();
+        let stream = connect(&addr).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+    }
+}
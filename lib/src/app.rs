@@ -1,313 +1,967 @@
 use std::{
-    net::{SocketAddr, TcpStream},
-    sync::{
-        Arc, Mutex,
-        atomic::{AtomicBool, Ordering},
-    },
-    thread::{self, JoinHandle},
+    io,
+    net::SocketAddr,
+    panic::resume_unwind,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
 };
 
+use derive_more::{Display, Error};
+
 use openssl::pkey::{PKey, Private, Public};
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::{
+    net::TcpStream,
+    runtime::{Builder, Handle, Runtime, RuntimeFlavor},
+    task::block_in_place,
+};
 
 use super::{
-    Shared,
+    address::HostAddr,
+    async_app::AsyncGrapevineApp,
     channel::{Channel, ChannelDesc, ProtocolError},
-    events::{HandleChannelCreationError, HandleNewChannel, HandleThreadError},
-    handler::{EventHandler, EventRecipient},
-    listener::{PendingAesHandshake, PendingConnection, PendingRsaHandshake, listener_thread},
-    protocol::{Handshake, ProtocolPath},
+    contacts::ContactBook,
+    discovery::{Announcement, DiscoveredPeer},
+    events::{Event, EventReceiver},
+    firewall::{AccessRules, Firewall},
+    gossip::{GossipConfig, MessageId},
+    group::{Group, GroupError},
+    listener::{
+        ListenerConfig, ListenerPolicy, PendingAesHandshake, PendingConnection, PendingRsaHandshake,
+    },
+    outbox::QueueConfig,
+    protocol::{Fingerprint, Message},
+    rpc::CallError,
+    state::{ConnectionAttempt, Timeouts},
+    transport::{Acceptor, Endpoint, Transport},
 };
 
-type ChannelThreadResult = Result<(), (ProtocolError, Arc<Channel>)>;
-type ChannelCreationThreadResult = Result<Arc<Channel>, ProtocolError>;
+/// A blocking call was made from within the current thread runtime the
+/// call has to be finished by, which can't both wait, and do the work
+#[derive(Debug, Display, Error)]
+#[display("blocking calls can't be made from within their own current thread runtime")]
+pub struct BlockingError;
 
-/// Convenience function that properly initializes the channel and starts listening
-/// Meant to be used in a thread
-fn add_channel(channels: Shared<Vec<Arc<Channel>>>, channel: Arc<Channel>) -> ChannelThreadResult {
-    channels.lock().unwrap().push(channel.clone());
-
-    if let Err(err) = channel.listen() {
-        return Err((err, channel));
-    } else {
-        Ok(())
+impl From<BlockingError> for io::Error {
+    fn from(error: BlockingError) -> Self {
+        io::Error::new(io::ErrorKind::WouldBlock, error)
     }
 }
 
-/// Thread that monitors other threads for failures, and forwards that
-/// information to the [EventHandler]
-fn watchdog(
-    threads: Shared<Vec<JoinHandle<ChannelThreadResult>>>,
-    creation_threads: Shared<Vec<JoinHandle<ChannelCreationThreadResult>>>,
-    handler: Shared<EventHandler>,
-) {
-    loop {
-        thread::sleep(std::time::Duration::from_millis(500));
-        for thread in threads
-            .lock()
-            .unwrap()
-            .extract_if(.., |thread| thread.is_finished())
+/// Waits for the future to finish on the given runtime, see [wait_on].
+/// Fails instead of deadlocking, when called from within the very same
+/// runtime, if it's a current thread one.
+pub(crate) fn block_on<F>(runtime: &Handle, future: F) -> Result<F::Output, BlockingError>
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    match Handle::try_current() {
+        Ok(current)
+            if current.runtime_flavor() == RuntimeFlavor::CurrentThread
+                && current.id() == runtime.id() =>
         {
-            if let Err((err, channel)) = thread.join().unwrap() {
-                handler.lock().unwrap().on_thread_error(&err, &channel);
-            }
+            Err(BlockingError)
         }
+        _ => Ok(wait_on(runtime, future)),
+    }
+}
 
-        for thread in creation_threads
-            .lock()
-            .unwrap()
-            .extract_if(.., |thread| thread.is_finished())
-        {
-            match thread.join().unwrap() {
-                Ok(channel) => handler.lock().unwrap().on_new_channel(&channel),
-                Err(err) => handler.lock().unwrap().on_channel_creation_error(&err),
-            }
+/// Waits for the future to finish on the given runtime. Works from within
+/// a multi-threaded runtime, by letting it know the current thread is about
+/// to block, and from within other current thread runtimes, by waiting on a
+/// thread of its own. Must not be called from within a current thread
+/// runtime, that's the given one.
+fn wait_on<F>(runtime: &Handle, future: F) -> F::Output
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    match Handle::try_current() {
+        Ok(current) if current.runtime_flavor() == RuntimeFlavor::CurrentThread => {
+            thread::scope(|scope| {
+                scope
+                    .spawn(|| runtime.block_on(future))
+                    .join()
+                    .unwrap_or_else(|panic| resume_unwind(panic))
+            })
         }
+        Ok(_) => block_in_place(|| runtime.block_on(future)),
+        Err(_) => runtime.block_on(future),
     }
 }
 
 /// App backend. A facade over the toolkit. With this struct, you can create, manage, and interact with channels.
 /// The app should self monitor its state and handle errors gracefully.
-pub struct GrapevineApp {
-    /// Active channels
-    channels: Shared<Vec<Arc<Channel>>>,
-    /// Incoming connections we aren't sure we want to accept
-    pending_connections: Shared<Vec<PendingConnection>>,
-
-    /// Control mechanism that allows us to stop the [Self::server_thread]
-    listening: Arc<AtomicBool>,
-    /// Thread responsible for listening to new incoming connections
-    server_thread: Option<JoinHandle<()>>,
-    /// Threads listening for new messages
-    channel_threads: Shared<Vec<JoinHandle<ChannelThreadResult>>>,
-    /// Threads creating new channels
-    channel_creation_threads: Shared<Vec<JoinHandle<ChannelCreationThreadResult>>>,
-    /// Thread monitoring other threads
-    watchdog_thread: JoinHandle<()>,
-
-    /// Our internal event handler
-    handler: Shared<EventHandler>,
+///
+/// A thin blocking wrapper around [AsyncGrapevineApp], running it on its
+/// own runtime, for callers that aren't async themselves, like GUIs.
+/// Must not be dropped from within an async context.
+///
+/// Generic over the [Transport] the channels run over, TCP by default.
+/// Convenience methods dealing with addresses are TCP only, other transports
+/// can be used through [Self::open_rsa_channel] and [Self::add_acceptor].
+pub struct GrapevineApp<T: Transport = TcpStream> {
+    /// The app itself, declared first so that it is dropped before the
+    /// runtime
+    core: AsyncGrapevineApp<T>,
+    /// Runtime all the tasks of the [Self::core] run on
+    runtime: Runtime,
+}
+
+/// Starts the runtime, and the [AsyncGrapevineApp] within it
+impl<T: Transport> Default for GrapevineApp<T> {
+    fn default() -> Self {
+        let runtime = Builder::new_multi_thread()
+            .thread_name("grapevine")
+            .enable_all()
+            .build()
+            .expect("failed to start the async runtime");
+        let core = {
+            let _guard = runtime.enter();
+            AsyncGrapevineApp::default()
+        };
+        Self { core, runtime }
+    }
 }
 
 impl GrapevineApp {
-    /// Create a new app instance
-    ///
-    /// Initializes the [EventHandler] and [Self::watchdog_thread].
+    /// Create a new app instance, communicating over TCP.
+    /// Apps using other transports can be created with [Self::default].
     pub fn new() -> Self {
-        let channels = Arc::new(Mutex::new(Vec::new()));
-        let channel_threads = Arc::new(Mutex::new(Vec::new()));
-        let channel_creation_threads = Arc::new(Mutex::new(Vec::new()));
-
-        let handler = Arc::new(Mutex::new(EventHandler::new(channels.clone())));
-
-        Self {
-            channels: channels,
-            pending_connections: Arc::new(Mutex::new(Vec::new())),
-            listening: Arc::new(AtomicBool::new(false)),
-            server_thread: None,
-            channel_threads: channel_threads.clone(),
-            channel_creation_threads: channel_creation_threads.clone(),
-            handler: handler.clone(),
-            watchdog_thread: thread::spawn(move || {
-                watchdog(channel_threads, channel_creation_threads, handler)
-            }),
-        }
+        Self::default()
     }
 
     /// Creates a new connection, assuming the RSA handshake will happen next.
-    ///
-    /// ## Args
-    ///
-    /// - addr: the address to which the new [Channel] should connect to
-    /// - name: the name to give the [Channel]
-    ///
-    /// ## Returns
-    ///
-    /// In case of immediate connection errors, it will return an error.
-    /// The [Channel] creation will happen in a separate thread though,
-    /// meaning immediately after calling, the [Channel] won't be added to
-    /// [Self::channels].
+    /// See [AsyncGrapevineApp::new_rsa_channel].
     pub fn new_rsa_channel(
         &mut self,
-        addr: SocketAddr,
+        addr: HostAddr,
         name: Option<String>,
-    ) -> Result<(), ProtocolError> {
-        self.new_channel(
-            addr,
-            ProtocolPath::RsaExchange,
-            |stream, message_handler| Channel::new(stream, name, message_handler),
-        )
+    ) -> Arc<ConnectionAttempt> {
+        self.core.new_rsa_channel(addr, name)
     }
 
     /// Creates a new connection, assuming the AES handshake will happen next.
-    /// For more details look at [Self::new_rsa_channel].
-    ///
-    /// ## Args
-    ///
-    /// - addr: The address the new channel should connect to
-    /// - our_key: Our private key, the recipient should have the corresponding public key
-    /// - their_key: Their public key, the recipient should have the corresponding private key
-    /// - name: The name to give the channel
+    /// See [AsyncGrapevineApp::new_aes_channel].
     pub fn new_aes_channel(
         &mut self,
-        addr: SocketAddr,
+        addr: HostAddr,
         our_key: PKey<Private>,
         their_key: PKey<Public>,
         name: Option<String>,
-    ) -> Result<(), ProtocolError> {
-        self.new_channel(
-            addr,
-            ProtocolPath::AesExchange,
-            |stream, message_handler| {
-                Channel::with_keys(stream, our_key, their_key, name, message_handler)
-            },
-        )
+    ) -> Arc<ConnectionAttempt> {
+        self.core.new_aes_channel(addr, our_key, their_key, name)
     }
 
     /// Recreates a new connection, based on the [ChannelDesc]ription.
-    /// Roughly equivalent to [Self::new_aes_channel], except uses a compact
-    /// struct for argument passing.
-    ///
-    /// ## Args
-    ///
-    /// - addr: the address to connect to
-    /// - desc: The channel description struct
+    /// See [AsyncGrapevineApp::new_channel_from_desc].
     pub fn new_channel_from_desc(
         &mut self,
-        addr: SocketAddr,
+        addr: HostAddr,
         desc: ChannelDesc,
-    ) -> Result<(), ProtocolError> {
-        self.new_channel(
-            addr,
-            ProtocolPath::AesExchange,
-            |stream, message_handler| Channel::from_desc(stream, desc, message_handler),
-        )
+    ) -> Arc<ConnectionAttempt> {
+        self.core.new_channel_from_desc(addr, desc)
     }
 
-    /// Helper method that estabilishes the connection and handles the threading.
-    ///
-    /// The connection will be created immediately, alongside the handshake.
-    /// The channel will be created in a helper thread, so that it can wait
-    /// for getting accepted on the other side ([Self::channel_creation_threads]).
-    /// After finishing the handshakes, the channel listening will happen
-    /// in a new thread ([Self::channel_threads])
-    fn new_channel(
+    /// Reconnects a closed channel.
+    /// See [AsyncGrapevineApp::reconnect].
+    pub fn reconnect(
         &mut self,
-        addr: SocketAddr,
-        path: ProtocolPath,
-        creator: impl 'static
-        + Send
-        + FnOnce(TcpStream, Shared<EventHandler>) -> Result<Option<Channel>, ProtocolError>,
-    ) -> Result<(), ProtocolError> {
-        let mut stream = TcpStream::connect(addr)?;
-
-        let channels = self.channels.clone();
-        let message_handler = self.handler.clone();
-        let channel_threads = self.channel_threads.clone();
-
-        let handshake = Handshake::new(path);
-        handshake.to_writer(&mut stream)?;
-
-        self.channel_creation_threads
-            .lock()
-            .unwrap()
-            .push(thread::spawn(
-                move || -> Result<Arc<Channel>, ProtocolError> {
-                    match creator(stream, message_handler)? {
-                        Some(channel) => {
-                            let channel = Arc::new(channel);
-                            let channel_copy = channel.clone();
-                            channel_threads
-                                .lock()
-                                .unwrap()
-                                .push(thread::spawn(move || add_channel(channels, channel_copy)));
-                            Ok(channel)
-                        }
-                        None => Err(ProtocolError::VerificationError),
-                    }
-                },
-            ));
-        Ok(())
-    }
-
-    /// Accepts a [PendingRsaHandshake], and adds it as a [Channel] to the app
+        channel: &Channel,
+    ) -> Result<Arc<ConnectionAttempt>, ProtocolError> {
+        self.core.reconnect(channel)
+    }
+
+    /// Creates a new connection through a relay.
+    /// See [AsyncGrapevineApp::new_rsa_channel_through_relay].
+    pub fn new_rsa_channel_through_relay(
+        &mut self,
+        relay: HostAddr,
+        id: Fingerprint,
+        name: Option<String>,
+    ) -> Arc<ConnectionAttempt> {
+        self.core.new_rsa_channel_through_relay(relay, id, name)
+    }
+
+    /// Recreates a channel through a relay.
+    /// See [AsyncGrapevineApp::new_channel_through_relay].
+    pub fn new_channel_through_relay(
+        &mut self,
+        relay: HostAddr,
+        desc: ChannelDesc,
+    ) -> Arc<ConnectionAttempt> {
+        self.core.new_channel_through_relay(relay, desc)
+    }
+
+    /// Registers at a relay under the keys, to be connected with through
+    /// it. See [AsyncGrapevineApp::listen_through_relay].
+    pub fn listen_through_relay(
+        &mut self,
+        relay: HostAddr,
+        key: PKey<Private>,
+        contact_keys: Vec<PKey<Private>>,
+        policy: ListenerPolicy,
+    ) -> io::Result<Endpoint> {
+        self.core
+            .listen_through_relay(relay, key, contact_keys, policy)
+    }
+
+    /// Starts finding peers on the local network.
+    /// See [AsyncGrapevineApp::start_discovery].
+    pub fn start_discovery(
+        &mut self,
+        announcement: Option<Announcement>,
+        keys: &[PKey<Private>],
+    ) -> io::Result<()> {
+        self.core.start_discovery(announcement, keys)
+    }
+
+    /// Stops finding peers. See [AsyncGrapevineApp::stop_discovery].
+    pub fn stop_discovery(&mut self) {
+        self.core.stop_discovery()
+    }
+
+    /// Gets the peers found on the local network.
+    /// See [AsyncGrapevineApp::discovered].
+    pub fn discovered(&self) -> Vec<DiscoveredPeer> {
+        self.core.discovered()
+    }
+
+    /// Binds a new listener. See [AsyncGrapevineApp::add_listener].
+    pub fn add_listener(&mut self, config: ListenerConfig) -> io::Result<SocketAddr> {
+        wait_on(self.runtime.handle(), self.core.add_listener(config))
+    }
+
+    /// Replaces all listeners with a single one with the default
+    /// [ListenerPolicy]. See [AsyncGrapevineApp::start_listening].
+    pub fn start_listening(&mut self, addr: HostAddr) -> io::Result<SocketAddr> {
+        wait_on(self.runtime.handle(), self.core.start_listening(addr))
+    }
+}
+
+impl<T: Transport> GrapevineApp<T> {
+    /// Gets the asynchronous app this one wraps
+    pub fn core(&self) -> &AsyncGrapevineApp<T> {
+        &self.core
+    }
+
+    /// Gets the asynchronous app this one wraps, mutably
+    pub fn core_mut(&mut self) -> &mut AsyncGrapevineApp<T> {
+        &mut self.core
+    }
+
+    /// Gets the runtime the app runs on
+    pub fn runtime(&self) -> &Handle {
+        self.runtime.handle()
+    }
+
+    /// Starts a new channel over an already connected transport.
+    /// See [AsyncGrapevineApp::open_rsa_channel].
+    pub fn open_rsa_channel(
+        &mut self,
+        transport: T,
+        name: Option<String>,
+    ) -> Arc<ConnectionAttempt> {
+        self.core.open_rsa_channel(transport, name)
+    }
+
+    /// Starts a new channel over an already connected transport.
+    /// See [AsyncGrapevineApp::open_aes_channel].
+    pub fn open_aes_channel(
+        &mut self,
+        transport: T,
+        our_key: PKey<Private>,
+        their_key: PKey<Public>,
+        name: Option<String>,
+    ) -> Arc<ConnectionAttempt> {
+        self.core
+            .open_aes_channel(transport, our_key, their_key, name)
+    }
+
+    /// Restarts a channel over an already connected transport.
+    /// See [AsyncGrapevineApp::open_channel_from_desc].
+    pub fn open_channel_from_desc(
+        &mut self,
+        transport: T,
+        desc: ChannelDesc,
+    ) -> Arc<ConnectionAttempt> {
+        self.core.open_channel_from_desc(transport, desc)
+    }
+
+    /// Accepts a [PendingRsaHandshake], and adds it as a [Channel] to the app.
+    /// See [AsyncGrapevineApp::add_rsa_channel].
     pub fn add_rsa_channel(
         &mut self,
-        pending: PendingRsaHandshake,
+        pending: PendingRsaHandshake<T>,
         name: Option<String>,
-    ) -> Result<(), ProtocolError> {
-        if let Some(channel) = pending.accept(name, self.handler.clone())? {
-            self.add_channel(channel);
-        }
-        Ok(())
+    ) -> Arc<ConnectionAttempt> {
+        self.core.add_rsa_channel(pending, name)
     }
 
     /// Accepts a [PendingAesHandshake], and adds it as a [Channel] to the app
     pub fn add_aes_channel(
         &mut self,
-        pending: PendingAesHandshake,
+        pending: PendingAesHandshake<T>,
         name: Option<String>,
         our_key: PKey<Private>,
         their_key: PKey<Public>,
-    ) -> Result<(), ProtocolError> {
-        if let Some(channel) = pending.accept(name, our_key, their_key, self.handler.clone())? {
-            self.add_channel(channel);
+    ) -> Arc<ConnectionAttempt> {
+        self.core.add_aes_channel(pending, name, our_key, their_key)
+    }
+
+    /// Accepts a [PendingAesHandshake] from a saved contact, using the keys
+    /// saved alongside them. See [PendingAesHandshake::contact].
+    pub fn add_known_aes_channel(
+        &mut self,
+        pending: PendingAesHandshake<T>,
+    ) -> Arc<ConnectionAttempt> {
+        self.core.add_known_aes_channel(pending)
+    }
+
+    /// Gets the list of channels, including the closed ones.
+    /// See [AsyncGrapevineApp::channels].
+    pub fn channels(&self) -> &Arc<Mutex<Vec<Arc<Channel<T>>>>> {
+        self.core.channels()
+    }
+
+    /// Closes the channel if need be, and forgets about it
+    pub fn remove_channel(&mut self, channel: &Arc<Channel<T>>) -> io::Result<()> {
+        self.core.remove_channel(channel)
+    }
+
+    /// Gets the list of channels we are establishing, that aren't open yet.
+    /// See [AsyncGrapevineApp::connecting].
+    pub fn connecting(&self) -> &Arc<Mutex<Vec<Arc<ConnectionAttempt>>>> {
+        self.core.connecting()
+    }
+
+    /// Gets how long establishing new channels may take
+    pub fn timeouts(&self) -> &Timeouts {
+        self.core.timeouts()
+    }
+
+    /// Changes how long establishing new channels may take.
+    /// See [AsyncGrapevineApp::set_timeouts].
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.core.set_timeouts(timeouts);
+    }
+
+    /// Gets how messages are queued on new channels
+    pub fn send_queue(&self) -> QueueConfig {
+        self.core.send_queue()
+    }
+
+    /// Changes how messages are queued. Channels already open keep the old
+    /// queue.
+    pub fn set_send_queue(&mut self, config: QueueConfig) {
+        self.core.set_send_queue(config);
+    }
+
+    /// Gets how many frames new channels send between checkpoints
+    pub fn checkpoints(&self) -> Option<u64> {
+        self.core.checkpoints()
+    }
+
+    /// Makes new channels sign a checkpoint every so many frames they send.
+    /// See [AsyncGrapevineApp::set_checkpoints].
+    pub fn set_checkpoints(&mut self, every: Option<u64>) {
+        self.core.set_checkpoints(every);
+    }
+
+    /// Gets the contact book, used for recognizing incoming connections
+    pub fn contacts(&self) -> &Arc<Mutex<ContactBook>> {
+        self.core.contacts()
+    }
+
+    /// Replaces the contact book
+    pub fn set_contacts(&mut self, contacts: ContactBook) {
+        self.core.set_contacts(contacts);
+    }
+
+    /// Gets the firewall, which holds the [AccessRules] and the log of
+    /// rejected connections
+    pub fn firewall(&self) -> &Arc<Mutex<Firewall>> {
+        self.core.firewall()
+    }
+
+    /// Replaces the [AccessRules] applied to incoming connections
+    pub fn set_access_rules(&mut self, rules: AccessRules) {
+        self.core.set_access_rules(rules);
+    }
+
+    /// Clears and returns the list of currently pending connections.
+    /// See [AsyncGrapevineApp::inspect_pending].
+    pub fn inspect_pending(&mut self) -> Vec<PendingConnection<T>> {
+        self.core.inspect_pending()
+    }
+
+    /// Adds a new pending connection
+    pub fn add_pending(&mut self, pending: PendingConnection<T>) {
+        self.core.add_pending(pending);
+    }
+
+    /// Subscribes to all future [Event]s.
+    /// See [AsyncGrapevineApp::subscribe].
+    pub fn subscribe(&mut self) -> EventReceiver<T> {
+        self.core.subscribe()
+    }
+
+    /// Invokes the callback on all future [Event]s.
+    /// See [AsyncGrapevineApp::on_event].
+    pub fn on_event(&mut self, callback: impl FnMut(&Event<T>) + Send + 'static) {
+        self.core.on_event(callback);
+    }
+
+    /// Invokes the callback on all future [Payload](super::Payload)s with the given tag.
+    /// See [AsyncGrapevineApp::on_payload].
+    pub fn on_payload<P: DeserializeOwned>(
+        &mut self,
+        tag: impl Into<String>,
+        callback: impl FnMut(&Arc<Channel<T>>, P) + Send + 'static,
+    ) {
+        self.core.on_payload(tag, callback);
+    }
+
+    /// Answers requests for the method, made by peers.
+    /// See [AsyncGrapevineApp::add_method].
+    pub fn add_method<P, R, F, Fut>(&mut self, name: impl Into<String>, handler: F)
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: Fn(Arc<Channel<T>>, P) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<R, String>> + Send + 'static,
+    {
+        self.core.add_method(name, handler);
+    }
+
+    /// Asks the peer to run the method, blocking until it responds.
+    /// See [AsyncGrapevineApp::call].
+    pub fn call<P: Serialize + Sync + ?Sized, R: DeserializeOwned + Send>(
+        &self,
+        channel: &Arc<Channel<T>>,
+        method: &str,
+        params: &P,
+        wait: Duration,
+    ) -> Result<R, CallError> {
+        wait_on(
+            self.runtime.handle(),
+            self.core.call(channel, method, params, wait),
+        )
+    }
+
+    /// Creates a new group, with us as its only member.
+    /// See [AsyncGrapevineApp::create_group].
+    pub fn create_group(
+        &mut self,
+        name: String,
+        our_name: String,
+    ) -> Result<Arc<Group>, GroupError> {
+        wait_on(
+            self.runtime.handle(),
+            self.core.create_group(name, our_name),
+        )
+    }
+
+    /// Gets the groups, including the ones we are out of
+    pub fn groups(&self) -> &Arc<Mutex<Vec<Arc<Group>>>> {
+        self.core.groups()
+    }
+
+    /// Adds the other party of the open channel to a group we created.
+    /// See [AsyncGrapevineApp::invite_to_group].
+    pub fn invite_to_group(
+        &mut self,
+        group: &Group,
+        channel: &Arc<Channel<T>>,
+    ) -> Result<(), GroupError> {
+        self.core.invite_to_group(group, channel)
+    }
+
+    /// Removes a member from a group we created
+    pub fn remove_from_group(
+        &mut self,
+        group: &Group,
+        member: &Fingerprint,
+    ) -> Result<(), GroupError> {
+        self.core.remove_from_group(group, member)
+    }
+
+    /// Leaves the group. See [AsyncGrapevineApp::leave_group].
+    pub fn leave_group(&mut self, group: &Arc<Group>) -> Result<(), GroupError> {
+        self.core.leave_group(group)
+    }
+
+    /// Leaves the group if need be, and forgets about it
+    pub fn remove_group(&mut self, group: &Arc<Group>) -> Result<(), GroupError> {
+        self.core.remove_group(group)
+    }
+
+    /// Sends the message to the group.
+    /// See [AsyncGrapevineApp::send_to_group].
+    pub fn send_to_group(&mut self, group: &Group, message: Message) -> Result<(), GroupError> {
+        self.core.send_to_group(group, message)
+    }
+
+    /// Sends the message to the other party of the channel, through the
+    /// peers we both are connected with.
+    /// See [AsyncGrapevineApp::send_through_peers].
+    pub fn send_through_peers(
+        &mut self,
+        desc: &ChannelDesc,
+        message: Message,
+    ) -> Result<MessageId, ProtocolError> {
+        self.core.send_through_peers(desc, message)
+    }
+
+    /// Gets how messages travel through peers
+    pub fn gossip(&self) -> GossipConfig {
+        self.core.gossip()
+    }
+
+    /// Changes how messages travel through peers, including whether we pass
+    /// on the messages of others
+    pub fn set_gossip(&mut self, config: GossipConfig) {
+        self.core.set_gossip(config);
+    }
+
+    /// Starts accepting incoming connections from the [Acceptor].
+    /// See [AsyncGrapevineApp::add_acceptor].
+    pub fn add_acceptor<A: Acceptor<Transport = T>>(
+        &mut self,
+        acceptor: A,
+        policy: ListenerPolicy,
+    ) -> io::Result<Endpoint> {
+        self.core.add_acceptor(acceptor, policy)
+    }
+
+    /// Stops the listener reachable at the given endpoint
+    pub fn remove_listener(&mut self, local: &Endpoint) {
+        wait_on(self.runtime.handle(), self.core.remove_listener(local))
+    }
+
+    /// Gets where the running listeners can be reached, alongside their
+    /// policies
+    pub fn listeners(&self) -> impl Iterator<Item = (&Endpoint, &ListenerPolicy)> {
+        self.core.listeners()
+    }
+
+    /// Stops all listeners
+    pub fn stop_listening(&mut self) {
+        wait_on(self.runtime.handle(), self.core.stop_listening())
+    }
+
+    /// Says goodbye on every open channel, and stops everything running
+    /// in the background. See [AsyncGrapevineApp::shutdown].
+    pub fn shutdown(&mut self, timeout: Duration) -> io::Result<()> {
+        wait_on(self.runtime.handle(), self.core.shutdown(timeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Handshake, ProtocolPath};
+    use crate::{
+        firewall::{IpNet, Rejection},
+        listener::AutoAccept,
+    };
+    use std::{io::Read, net::TcpStream, str::FromStr, thread, time::Duration};
+
+    /// Polls the condition for up to two seconds
+    fn eventually(mut condition: impl FnMut() -> bool) -> bool {
+        for _ in 0..40 {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(50));
         }
-        Ok(())
+        false
+    }
+
+    fn local_listener(policy: ListenerPolicy) -> ListenerConfig {
+        ListenerConfig::new(HostAddr::from_str("127.0.0.1:0").unwrap(), policy)
+    }
+
+    #[test]
+    fn test_blocking_from_current_thread_runtime() {
+        let mut app = GrapevineApp::new();
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        let bound =
+            runtime.block_on(async { app.add_listener(local_listener(ListenerPolicy::default())) });
+        assert!(bound.is_ok());
+    }
+
+    #[test]
+    fn test_multiple_listeners_report_bound_ports() {
+        let mut app = GrapevineApp::new();
+        let a = app
+            .add_listener(local_listener(ListenerPolicy::default()))
+            .unwrap();
+        let b = app
+            .add_listener(local_listener(ListenerPolicy::default()))
+            .unwrap();
+
+        assert_ne!(a.port(), 0);
+        assert_ne!(b.port(), 0);
+        assert_ne!(a, b);
+        assert_eq!(app.listeners().count(), 2);
+
+        app.remove_listener(&a.into());
+        assert_eq!(
+            app.listeners()
+                .map(|(local, _)| local.clone())
+                .collect::<Vec<_>>(),
+            vec![b.into()]
+        );
+        app.stop_listening();
+        assert_eq!(app.listeners().count(), 0);
+    }
+
+    #[test]
+    fn test_listener_drops_disallowed_path() {
+        let mut app = GrapevineApp::new();
+        let policy = ListenerPolicy::new(vec![ProtocolPath::AesExchange], true, Default::default());
+        let addr = app.add_listener(local_listener(policy)).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        Handshake::new(ProtocolPath::RsaExchange)
+            .to_writer(&mut stream)
+            .unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+        assert!(app.inspect_pending().is_empty());
     }
 
-    /// Internal method that handles all the necessary details behind adding a [Channel]
-    fn add_channel(&mut self, channel: Channel) {
-        let channels = self.channels.clone();
-        let channel = Arc::new(channel);
-        self.channel_threads
+    #[test]
+    fn test_listener_queues_allowed_path() {
+        let mut app = GrapevineApp::new();
+        let addr = app
+            .add_listener(local_listener(ListenerPolicy::default()))
+            .unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        Handshake::new(ProtocolPath::AesExchange)
+            .to_writer(&mut stream)
+            .unwrap();
+
+        let mut pending = Vec::new();
+        assert!(eventually(|| {
+            pending.extend(app.inspect_pending());
+            !pending.is_empty()
+        }));
+        assert!(matches!(pending[0], PendingConnection::Aes(_)));
+    }
+
+    #[test]
+    fn test_idle_peer_does_not_block_listener() {
+        let mut app = GrapevineApp::new();
+        let addr = app
+            .add_listener(local_listener(ListenerPolicy::default()))
+            .unwrap();
+
+        // never sends a handshake
+        let _idle = TcpStream::connect(addr).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        Handshake::new(ProtocolPath::AesExchange)
+            .to_writer(&mut stream)
+            .unwrap();
+
+        assert!(eventually(|| !app
+            .core
+            .pending_connections()
             .lock()
             .unwrap()
-            .push(thread::spawn(move || add_channel(channels, channel)));
+            .is_empty()));
     }
 
-    /// Gets the list of currently ongoing channels
-    pub fn channels(&self) -> &Arc<Mutex<Vec<Arc<Channel>>>> {
-        &self.channels
-    }
+    #[test]
+    fn test_disconnected_pending_is_cleaned_up() {
+        let mut app = GrapevineApp::new();
+        let addr = app
+            .add_listener(local_listener(ListenerPolicy::default()))
+            .unwrap();
 
-    /// Clears and returns the list of currently pending connections
-    pub fn inspect_pending(&mut self) -> Vec<PendingConnection> {
-        self.pending_connections
+        let mut stream = TcpStream::connect(addr).unwrap();
+        Handshake::new(ProtocolPath::AesExchange)
+            .to_writer(&mut stream)
+            .unwrap();
+        assert!(eventually(|| !app
+            .core
+            .pending_connections()
             .lock()
             .unwrap()
-            .drain(..)
-            .collect::<Vec<_>>()
+            .is_empty()));
+
+        // the peer going away is noticed once the connections are looked at
+        drop(stream);
+        assert!(eventually(|| {
+            let pending = app.inspect_pending();
+            let cleaned_up = pending.is_empty();
+            for connection in pending {
+                app.add_pending(connection);
+            }
+            cleaned_up
+        }));
     }
 
-    /// Adds a new pending connection
-    pub fn add_pending(&mut self, pending: PendingConnection) {
-        self.pending_connections.lock().unwrap().push(pending);
+    #[test]
+    fn test_bind_error_is_returned() {
+        let mut app = GrapevineApp::new();
+        let addr = app
+            .add_listener(local_listener(ListenerPolicy::default()))
+            .unwrap();
+
+        let taken = ListenerConfig::new(addr.into(), ListenerPolicy::default());
+        assert!(app.add_listener(taken).is_err());
+        assert_eq!(app.listeners().count(), 1);
     }
 
-    /// Adds a listener that will receive all app wide events
-    pub fn add_event_recipient(&mut self, recipient: Shared<dyn EventRecipient>) {
-        self.handler.lock().unwrap().add_recipient(recipient);
+    #[test]
+    fn test_blocked_address_is_logged() {
+        let mut app = GrapevineApp::new();
+        let mut rules = AccessRules::default();
+        rules.block(IpNet::from_str("127.0.0.0/8").unwrap());
+        app.set_access_rules(rules);
+        let addr = app
+            .add_listener(local_listener(ListenerPolicy::default()))
+            .unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+
+        let firewall = app.firewall().lock().unwrap();
+        let events = firewall.events().collect::<Vec<_>>();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].reason(), &Rejection::Blocked);
     }
 
-    /// Stops the server thread
-    pub fn stop_listening(&mut self) {
-        if let Some(server) = self.server_thread.take() {
-            self.listening.store(false, Ordering::Relaxed);
+    #[test]
+    fn test_pending_queue_is_capped() {
+        let mut app = GrapevineApp::new();
+        app.set_access_rules(AccessRules::new(
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            None,
+            1,
+        ));
+        let addr = app
+            .add_listener(local_listener(ListenerPolicy::default()))
+            .unwrap();
 
-            server.join().unwrap();
+        let mut streams = Vec::new();
+        for _ in 0..2 {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            Handshake::new(ProtocolPath::AesExchange)
+                .to_writer(&mut stream)
+                .unwrap();
+            streams.push(stream);
         }
+
+        assert!(eventually(|| {
+            app.firewall()
+                .lock()
+                .unwrap()
+                .events()
+                .any(|event| event.reason() == &Rejection::QueueFull)
+        }));
+        assert_eq!(app.core.pending_connections().lock().unwrap().len(), 1);
     }
 
-    /// Starts the server thread
-    pub fn start_listening(&mut self, addr: SocketAddr) {
-        if self.listening.swap(true, Ordering::Relaxed) {
-            self.stop_listening();
-        }
+    /// Two matching descriptions, as both sides would have saved them
+    fn desc_pair() -> (ChannelDesc, ChannelDesc) {
+        use openssl::rsa::Rsa;
+
+        let public = |key: &PKey<Private>| {
+            PKey::public_key_from_pem(&key.public_key_to_pem().unwrap()).unwrap()
+        };
+        let a = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let b = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        (
+            ChannelDesc::new(
+                "b".to_string(),
+                HostAddr::from_str("10.9.9.9:4000").unwrap(),
+                a.clone(),
+                public(&b),
+            ),
+            ChannelDesc::new(
+                "a".to_string(),
+                HostAddr::from_str("10.9.9.9:4000").unwrap(),
+                b.clone(),
+                public(&a),
+            ),
+        )
+    }
+
+    #[test]
+    fn test_known_peer_is_matched_by_key_hint() {
+        let (ours, theirs) = desc_pair();
+        let mut receiver = GrapevineApp::new();
+        receiver.set_contacts(ContactBook::from(vec![theirs]));
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::AesExchange], false, AutoAccept::Contacts);
+        let addr = receiver.add_listener(local_listener(policy)).unwrap();
+
+        let mut sender = GrapevineApp::new();
+        sender.new_channel_from_desc(addr.into(), ours);
+
+        assert!(eventually(|| receiver.channels().lock().unwrap().len() == 1));
+        assert!(eventually(|| sender.channels().lock().unwrap().len() == 1));
+
+        // the peer connected from somewhere other than the saved address
+        let contacts = receiver.contacts().lock().unwrap();
+        let contact = contacts.iter().next().unwrap();
+        assert_eq!(
+            contact.desc().last_addr(),
+            &HostAddr::from_str("127.0.0.1:4000").unwrap()
+        );
+        assert_eq!(contact.addresses().len(), 2);
+    }
+
+    #[test]
+    fn test_rsa_from_contact_address_is_a_stranger() {
+        // the contact is saved under the address the sender connects from
+        let (_, mut theirs) = desc_pair();
+        theirs.change_addr(HostAddr::from_str("127.0.0.1:4000").unwrap());
+        let mut receiver = GrapevineApp::new();
+        receiver.set_contacts(ContactBook::from(vec![theirs]));
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::RsaExchange], true, AutoAccept::Contacts);
+        let addr = receiver.add_listener(local_listener(policy)).unwrap();
+
+        let mut sender = GrapevineApp::new();
+        sender.new_rsa_channel(addr.into(), None);
+        assert!(eventually(|| {
+            receiver.core.pending_connections().lock().unwrap().len() == 1
+        }));
+        let pending = receiver.core.pending_connections().lock().unwrap();
+        // not shown under the name of the contact either
+        assert_eq!(pending[0].name(), pending[0].peer().to_string());
+        assert!(receiver.channels().lock().unwrap().is_empty());
+        drop(pending);
+
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::RsaExchange], false, AutoAccept::Contacts);
+        let addr = receiver.add_listener(local_listener(policy)).unwrap();
+        sender.new_rsa_channel(addr.into(), None);
+        assert!(eventually(|| {
+            receiver
+                .firewall()
+                .lock()
+                .unwrap()
+                .events()
+                .any(|event| event.reason() == &Rejection::StrangerRsa)
+        }));
+    }
+
+    #[test]
+    fn test_reconnect_keeps_history() {
+        use crate::{protocol::Message, state::ChannelState};
+
+        let (ours, theirs) = desc_pair();
+        let mut receiver = GrapevineApp::new();
+        receiver.set_contacts(ContactBook::from(vec![theirs]));
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::AesExchange], false, AutoAccept::Contacts);
+        let addr = receiver.add_listener(local_listener(policy)).unwrap();
+
+        let mut sender = GrapevineApp::new();
+        sender.new_channel_from_desc(addr.into(), ours);
+        assert!(eventually(|| sender.channels().lock().unwrap().len() == 1));
+        let channel = sender.channels().lock().unwrap()[0].clone();
+        channel
+            .send_message(Message::new("hello".to_string()))
+            .unwrap();
+        assert!(sender.reconnect(&channel).is_err());
+
+        // the closed channel sticks around, until it's replaced
+        channel.close().unwrap();
+        assert!(eventually(|| channel.state().is_finished()));
+        assert_eq!(sender.channels().lock().unwrap().len(), 1);
+        sender.reconnect(&channel).unwrap();
+
+        assert!(eventually(|| {
+            let reconnected = sender.channels().lock().unwrap()[0].clone();
+            !Arc::ptr_eq(&reconnected, &channel)
+        }));
+        let reconnected = sender.channels().lock().unwrap()[0].clone();
+        assert!(matches!(reconnected.state(), ChannelState::Open));
+        assert_eq!(reconnected.messages().lock().unwrap()[0].content(), "hello");
+        assert!(eventually(|| {
+            let channels = receiver.channels().lock().unwrap();
+            channels.len() == 1 && matches!(channels[0].state(), ChannelState::Open)
+        }));
+    }
+
+    #[test]
+    fn test_known_peer_pending_has_contact() {
+        let (ours, theirs) = desc_pair();
+        let mut receiver = GrapevineApp::new();
+        receiver.set_contacts(ContactBook::from(vec![theirs]));
+        let addr = receiver
+            .add_listener(local_listener(ListenerPolicy::default()))
+            .unwrap();
+
+        let mut sender = GrapevineApp::new();
+        sender.new_channel_from_desc(addr.into(), ours);
+
+        let mut pending = Vec::new();
+        assert!(eventually(|| {
+            pending.extend(receiver.inspect_pending());
+            !pending.is_empty()
+        }));
+        let Some(PendingConnection::Aes(aes)) = pending.pop() else {
+            panic!("expected an AES handshake");
+        };
+        assert_eq!(aes.contact().unwrap().name(), "a");
+        assert_eq!(aes.name(), "a");
+
+        receiver.add_known_aes_channel(aes);
+        assert!(eventually(|| sender.channels().lock().unwrap().len() == 1));
+    }
+
+    #[test]
+    fn test_channel_over_memory_transport() {
+        use crate::{
+            protocol::Message,
+            transport::{MemoryListener, MemoryTransport},
+        };
+
+        let mut receiver = GrapevineApp::<MemoryTransport>::default();
+        let (listener, connector) = MemoryListener::new();
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::RsaExchange], true, AutoAccept::Everyone);
+        let local = receiver.add_acceptor(listener, policy).unwrap();
+        assert_eq!(local.ip(), None);
+
+        let mut sender = GrapevineApp::<MemoryTransport>::default();
+        sender.open_rsa_channel(connector.connect().unwrap(), Some("receiver".to_string()));
+
+        assert!(eventually(|| receiver.channels().lock().unwrap().len() == 1));
+        assert!(eventually(|| sender.channels().lock().unwrap().len() == 1));
+
+        let channel = sender.channels().lock().unwrap()[0].clone();
+        assert_eq!(channel.peer().to_string(), "memory");
+        channel
+            .send_message(Message::new("hello".to_string()))
+            .unwrap();
 
-        let pending = self.pending_connections.clone();
-        let listening = self.listening.clone();
-        self.server_thread = Some(thread::spawn(move || {
-            listener_thread(addr, pending, listening)
-        }))
+        let channel = receiver.channels().lock().unwrap()[0].clone();
+        assert!(eventually(|| {
+            channel
+                .messages()
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|message| message.content() == "hello")
+        }));
     }
 }
@@ -0,0 +1,2188 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::Utc;
+use openssl::pkey::{PKey, Private, Public};
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    runtime::Handle,
+    sync::watch,
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+
+use super::{
+    Shared,
+    address::{self, HostAddr},
+    channel::{Channel, ChannelDesc, ProtocolError},
+    contacts::ContactBook,
+    discovery::{self, Announcement, DiscoveredPeer, discovery_task},
+    events::{CloseReason, Event, EventReceiver},
+    firewall::{AccessRules, Firewall},
+    gossip::{Gossip, GossipConfig, MessageId},
+    group::{Group, GroupError, Groups},
+    handler::EventHandler,
+    listener::{
+        ListenerConfig, ListenerContext, ListenerPolicy, PendingAesHandshake, PendingConnection,
+        PendingRsaHandshake, check_key, listener_task, note_roaming,
+    },
+    outbox::QueueConfig,
+    protocol::{Fingerprint, Handshake, Message, ProtocolPath},
+    relay::{self, RelayListener},
+    rpc::{CallError, Rpc},
+    state::{ChannelState, ConnectionAttempt, Timeouts},
+    transport::{Acceptor, Endpoint, Transport},
+};
+
+/// What the other parties are told by [AsyncGrapevineApp::shutdown]
+const SHUTDOWN_REASON: &str = "shutting down";
+
+/// Sends the [Handshake] over the transport, and lets the creator do the
+/// rest
+async fn open<T: Transport, F, Fut>(
+    mut transport: T,
+    handshake: Handshake,
+    message_handler: Shared<EventHandler<T>>,
+    state: watch::Sender<ChannelState>,
+    creator: F,
+) -> Result<Option<Channel<T>>, ProtocolError>
+where
+    F: FnOnce(T, Shared<EventHandler<T>>, watch::Sender<ChannelState>) -> Fut,
+    Fut: Future<Output = Result<Option<Channel<T>>, ProtocolError>>,
+{
+    handshake.to_async_writer(&mut transport).await?;
+    state.send_replace(ChannelState::AwaitingAccept);
+    creator(transport, message_handler, state).await
+}
+
+/// Resolves once the key exchange has been going on for longer than the
+/// limit. Waiting for the transport, or to be accepted doesn't count.
+async fn handshake_deadline(mut state: watch::Receiver<ChannelState>, limit: Duration) {
+    let _ = state
+        .wait_for(|state| {
+            !matches!(
+                state,
+                ChannelState::Connecting | ChannelState::AwaitingAccept
+            )
+        })
+        .await;
+    sleep(limit).await;
+}
+
+fn timed_out(what: &str) -> ProtocolError {
+    io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out", what)).into()
+}
+
+/// Listens on the channel until it closes, at which point the [EventHandler]
+/// is told why. The channel stays around with its history, until removed.
+async fn run_channel<T: Transport>(channel: Arc<Channel<T>>, handler: Shared<EventHandler<T>>) {
+    let reason = channel.listen().await.unwrap_or_else(CloseReason::from);
+    channel.finish(reason.clone());
+    handler
+        .lock()
+        .unwrap()
+        .emit(Event::ChannelClosed { channel, reason });
+}
+
+/// The part of the app, that is needed to bring new [Channel]s to life.
+/// Cheap to clone, so that it can be handed out to the listener tasks.
+pub(crate) struct ChannelSpawner<T: Transport> {
+    /// Active channels
+    channels: Shared<Vec<Arc<Channel<T>>>>,
+    /// Our internal event handler
+    handler: Shared<EventHandler<T>>,
+    /// The runtime channel tasks are spawned on
+    runtime: Handle,
+    /// How new channels queue their messages
+    queue: Shared<QueueConfig>,
+    /// How many frames new channels send between signed checkpoints
+    checkpoints: Shared<Option<u64>>,
+}
+
+// derived Clone would needlessly require T: Clone
+impl<T: Transport> Clone for ChannelSpawner<T> {
+    fn clone(&self) -> Self {
+        Self {
+            channels: self.channels.clone(),
+            handler: self.handler.clone(),
+            runtime: self.runtime.clone(),
+            queue: self.queue.clone(),
+            checkpoints: self.checkpoints.clone(),
+        }
+    }
+}
+
+impl<T: Transport> ChannelSpawner<T> {
+    /// Creates the channel in a separate task, so that it can wait for
+    /// getting accepted on the other side. Once the handshakes are done,
+    /// the channel is added with [Self::add], and an [Event] is emitted
+    /// either way.
+    pub fn spawn<F, Fut>(&self, creator: F)
+    where
+        F: FnOnce(Shared<EventHandler<T>>) -> Fut,
+        Fut: Future<Output = Result<Option<Channel<T>>, ProtocolError>> + Send + 'static,
+    {
+        let spawner = self.clone();
+        let creation = creator(self.handler.clone());
+        self.runtime.spawn(async move {
+            if let Err(error) = spawner.finish(creation.await) {
+                spawner
+                    .handler
+                    .lock()
+                    .unwrap()
+                    .emit(Event::HandshakeFailed { error });
+            }
+        });
+    }
+
+    /// Adds the freshly created channel, or tells why it couldn't be created
+    fn finish(
+        &self,
+        created: Result<Option<Channel<T>>, ProtocolError>,
+    ) -> Result<Arc<Channel<T>>, Arc<ProtocolError>> {
+        match created {
+            Ok(Some(channel)) => Ok(self.add(channel)),
+            Ok(None) => Err(Arc::new(ProtocolError::VerificationError)),
+            Err(err) => Err(Arc::new(err)),
+        }
+    }
+
+    /// Gets the event handler, channels are created with
+    pub fn handler(&self) -> &Shared<EventHandler<T>> {
+        &self.handler
+    }
+
+    /// Adds an already established [Channel], and starts listening on it
+    /// in a new task. A closed channel with the same party is replaced,
+    /// with the new channel picking up its history.
+    pub fn add(&self, channel: Channel<T>) -> Arc<Channel<T>> {
+        let channel = Arc::new(
+            channel
+                .with_queue(*self.queue.lock().unwrap())
+                .with_checkpoints(*self.checkpoints.lock().unwrap()),
+        );
+        {
+            let mut channels = self.channels.lock().unwrap();
+            let closed = channels.iter_mut().find(|other| {
+                other.fingerprint() == channel.fingerprint() && other.state().is_finished()
+            });
+            match closed {
+                Some(closed) => {
+                    let history = closed.messages().lock().unwrap().clone();
+                    *channel.messages().lock().unwrap() = history;
+                    *closed = channel.clone();
+                }
+                None => channels.push(channel.clone()),
+            }
+        }
+        self.handler.lock().unwrap().emit(Event::ChannelOpened {
+            channel: channel.clone(),
+        });
+        self.runtime
+            .spawn(run_channel(channel.clone(), self.handler.clone()));
+        channel
+    }
+}
+
+/// A listener started by [AsyncGrapevineApp::add_acceptor]
+struct RunningListener {
+    /// Where the listener can be reached
+    local: Endpoint,
+    policy: ListenerPolicy,
+    /// Tells the [Self::task] to stop
+    stop: watch::Sender<bool>,
+    /// Task responsible for accepting new incoming connections
+    task: JoinHandle<()>,
+}
+
+impl RunningListener {
+    /// Stops the listener, and waits for it to finish
+    async fn stop(self) {
+        self.stop.send_replace(true);
+        // the task never panics, and is never aborted
+        let _ = self.task.await;
+    }
+}
+
+/// The asynchronous core of the app. Everything happens in tasks on the
+/// tokio runtime it was created in, which makes the app react to events as
+/// they come, rather than checking up on them.
+/// [GrapevineApp](super::GrapevineApp) wraps it for blocking callers.
+///
+/// Generic over the [Transport] the channels run over, TCP by default.
+/// Convenience methods dealing with addresses are TCP only, other transports
+/// can be used through [Self::open_rsa_channel] and [Self::add_acceptor].
+pub struct AsyncGrapevineApp<T: Transport = TcpStream> {
+    /// Everything needed for creating new channels
+    spawner: ChannelSpawner<T>,
+    /// Incoming connections we aren't sure we want to accept
+    pending_connections: Shared<Vec<PendingConnection<T>>>,
+    /// Channels we are establishing, that aren't open yet
+    attempts: Shared<Vec<Arc<ConnectionAttempt>>>,
+    timeouts: Timeouts,
+    /// Known peers, consulted when deciding on incoming connections
+    contacts: Shared<ContactBook>,
+    /// Access rules, shared by all listeners
+    firewall: Shared<Firewall>,
+
+    /// Listeners accepting new incoming connections
+    listeners: Vec<RunningListener>,
+    /// Requests made over the channels, in both directions
+    rpc: Arc<Rpc<T>>,
+    /// Groups we are in, run over the channels
+    groups: Arc<Groups<T>>,
+    /// Task sending the frames of the [Self::groups]
+    group_task: JoinHandle<()>,
+    /// Messages travelling through peers
+    gossip: Arc<Gossip<T>>,
+    /// Task sending the envelopes of the [Self::gossip]
+    gossip_task: JoinHandle<()>,
+    /// Peers announcing themselves on the local network
+    discovered: Shared<Vec<DiscoveredPeer>>,
+    /// Task announcing us, and finding the [Self::discovered] peers
+    discovery: Option<JoinHandle<()>>,
+}
+
+/// Initializes the [EventHandler], [AsyncGrapevineApp::rpc], [AsyncGrapevineApp::groups] and
+/// [AsyncGrapevineApp::gossip].
+/// Has to be called from within a tokio runtime.
+impl<T: Transport> Default for AsyncGrapevineApp<T> {
+    fn default() -> Self {
+        let channels = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(Mutex::new(EventHandler::new(channels.clone())));
+        let pending_connections = Arc::new(Mutex::new(Vec::new()));
+        let runtime = Handle::current();
+        let rpc = Rpc::new(runtime.clone());
+        handler.lock().unwrap().add_callback({
+            let rpc = rpc.clone();
+            move |event| rpc.on_event(event)
+        });
+        let (groups, group_task) = Groups::new(channels.clone(), handler.clone(), &runtime);
+        handler.lock().unwrap().add_callback({
+            let groups = groups.clone();
+            move |event| groups.on_event(event)
+        });
+        let contacts = Arc::new(Mutex::new(ContactBook::default()));
+        let (gossip, gossip_task) = Gossip::new(
+            channels.clone(),
+            contacts.clone(),
+            handler.clone(),
+            &runtime,
+        );
+        handler.lock().unwrap().add_callback({
+            let gossip = gossip.clone();
+            move |event| gossip.on_event(event)
+        });
+
+        Self {
+            spawner: ChannelSpawner {
+                channels,
+                handler,
+                runtime,
+                queue: Arc::new(Mutex::new(QueueConfig::default())),
+                checkpoints: Arc::new(Mutex::new(None)),
+            },
+            pending_connections,
+            attempts: Arc::new(Mutex::new(Vec::new())),
+            timeouts: Timeouts::default(),
+            contacts,
+            firewall: Arc::new(Mutex::new(Firewall::default())),
+            listeners: Vec::new(),
+            rpc,
+            groups,
+            group_task,
+            gossip,
+            gossip_task,
+            discovered: Arc::new(Mutex::new(Vec::new())),
+            discovery: None,
+        }
+    }
+}
+
+impl AsyncGrapevineApp {
+    /// Create a new app instance, communicating over TCP.
+    /// Apps using other transports can be created with [Self::default].
+    /// Has to be called from within a tokio runtime.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new connection, assuming the RSA handshake will happen next.
+    /// Connecting, and the handshakes happen in a separate task, within the
+    /// configured [Timeouts].
+    ///
+    /// ## Args
+    ///
+    /// - addr: the address to which the new [Channel] should connect to.
+    ///   Hostnames are resolved, and every resolved address is tried.
+    /// - name: the name to give the [Channel], the address by default
+    ///
+    /// ## Returns
+    ///
+    /// The [ConnectionAttempt], tracking the progress. Once the channel
+    /// opens, it's added to [Self::channels], and [Event::ChannelOpened] is
+    /// emitted. Failures are emitted as [Event::ConnectionFailed].
+    pub fn new_rsa_channel(
+        &mut self,
+        addr: HostAddr,
+        name: Option<String>,
+    ) -> Arc<ConnectionAttempt> {
+        let name = name.unwrap_or_else(|| addr.to_string());
+        self.connect(
+            name.clone(),
+            addr,
+            None,
+            Handshake::new(ProtocolPath::RsaExchange),
+            |stream, message_handler, state| {
+                Channel::new_tracked(stream, None, Some(name), message_handler, state)
+            },
+        )
+    }
+
+    /// Creates a new connection, assuming the AES handshake will happen next.
+    /// For more details look at [Self::new_rsa_channel].
+    ///
+    /// ## Args
+    ///
+    /// - addr: The address the new channel should connect to
+    /// - our_key: Our private key, the recipient should have the corresponding public key
+    /// - their_key: Their public key, the recipient should have the corresponding private key
+    /// - name: The name to give the channel
+    pub fn new_aes_channel(
+        &mut self,
+        addr: HostAddr,
+        our_key: PKey<Private>,
+        their_key: PKey<Public>,
+        name: Option<String>,
+    ) -> Arc<ConnectionAttempt> {
+        let name = name.unwrap_or_else(|| addr.to_string());
+        let desc = ChannelDesc::new(name, addr.clone(), our_key, their_key);
+        self.new_channel_from_desc(addr, desc)
+    }
+
+    /// Recreates a new connection, based on the [ChannelDesc]ription.
+    /// Roughly equivalent to [Self::new_aes_channel], except uses a compact
+    /// struct for argument passing. If a peer on the local network announced
+    /// itself at the address as the contact behind the description, the
+    /// contact is known to be there once the channel opens, and the address
+    /// is added to them, see [Self::start_discovery].
+    ///
+    /// ## Args
+    ///
+    /// - addr: the address to connect to
+    /// - desc: The channel description struct
+    pub fn new_channel_from_desc(
+        &mut self,
+        addr: HostAddr,
+        desc: ChannelDesc,
+    ) -> Arc<ConnectionAttempt> {
+        let handshake = Handshake::with_hint(ProtocolPath::AesExchange, desc.our_fingerprint());
+        let (discovered, contacts) = (self.discovered.clone(), self.contacts.clone());
+        let at = addr.clone();
+        self.connect(
+            desc.name().to_string(),
+            addr,
+            None,
+            handshake,
+            move |stream, message_handler, state| async move {
+                let channel =
+                    Channel::from_desc_tracked(stream, desc, message_handler, state).await?;
+                if let Some(channel) = &channel {
+                    discovery::refresh_contact(&discovered, &contacts, &at, channel.fingerprint());
+                }
+                Ok(channel)
+            },
+        )
+    }
+
+    /// Reconnects a closed channel, running the handshake anew from its
+    /// [ChannelDesc]. Once open, the new channel takes the place of the old
+    /// one, along with its history.
+    /// For more details look at [Self::new_rsa_channel].
+    pub fn reconnect(
+        &mut self,
+        channel: &Channel,
+    ) -> Result<Arc<ConnectionAttempt>, ProtocolError> {
+        if !channel.state().is_finished() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "still connected").into());
+        }
+        let desc = channel.desc().clone();
+        Ok(self.new_channel_from_desc(desc.last_addr().clone(), desc))
+    }
+
+    /// Creates a new connection through the relay, to whoever is registered
+    /// there under the fingerprint, assuming the RSA handshake will happen
+    /// next. The attempt fails, unless they exchange the key behind the
+    /// fingerprint. See [Relay](super::Relay), and for more details
+    /// [Self::new_rsa_channel].
+    pub fn new_rsa_channel_through_relay(
+        &mut self,
+        relay: HostAddr,
+        id: Fingerprint,
+        name: Option<String>,
+    ) -> Arc<ConnectionAttempt> {
+        let name = name.unwrap_or_else(|| id.short());
+        self.connect(
+            name.clone(),
+            relay,
+            Some(id),
+            Handshake::new(ProtocolPath::RsaExchange),
+            move |stream, message_handler, state| async move {
+                let channel =
+                    Channel::new_tracked(stream, None, Some(name), message_handler, state).await?;
+                match channel {
+                    Some(channel) if *channel.fingerprint() != id => {
+                        Err(ProtocolError::VerificationError)
+                    }
+                    channel => Ok(channel),
+                }
+            },
+        )
+    }
+
+    /// Recreates a channel through the relay, based on the
+    /// [ChannelDesc]ription. The other party has to be registered there under
+    /// the key they have for us, see [Self::listen_through_relay].
+    /// For more details look at [Self::new_rsa_channel].
+    pub fn new_channel_through_relay(
+        &mut self,
+        relay: HostAddr,
+        desc: ChannelDesc,
+    ) -> Arc<ConnectionAttempt> {
+        let handshake = Handshake::with_hint(ProtocolPath::AesExchange, desc.our_fingerprint());
+        self.connect(
+            desc.name().to_string(),
+            relay,
+            Some(desc.fingerprint()),
+            handshake,
+            |stream, message_handler, state| {
+                Channel::from_desc_tracked(stream, desc, message_handler, state)
+            },
+        )
+    }
+
+    /// Registers at the relay under the fingerprints of the keys, handling
+    /// the connections made to us through it according to the
+    /// [ListenerPolicy], like any other listener.
+    ///
+    /// ## Args
+    ///
+    /// - relay: the address of the relay
+    /// - key: what strangers exchange RSA keys with us under
+    /// - contact_keys: the [ChannelDesc::our_key]s of the channels with the
+    ///   contacts to be reachable by. Each only lets the contact it's meant
+    ///   for through, by the AES handshake.
+    /// - policy: how the connections are handled
+    ///
+    /// ## Returns
+    ///
+    /// How the listener is known, see [Self::remove_listener]
+    pub fn listen_through_relay(
+        &mut self,
+        relay: HostAddr,
+        key: PKey<Private>,
+        contact_keys: Vec<PKey<Private>>,
+        policy: ListenerPolicy,
+    ) -> io::Result<Endpoint> {
+        self.add_acceptor(RelayListener::new(relay, key, contact_keys), policy)
+    }
+
+    /// Helper method that connects to the address, and establishes the
+    /// channel over the connection, in a new [ConnectionAttempt]. If given
+    /// a fingerprint, the address is of a relay, and the fingerprint of who
+    /// to connect with through it.
+    fn connect<F, Fut>(
+        &mut self,
+        name: String,
+        addr: HostAddr,
+        relayed: Option<Fingerprint>,
+        handshake: Handshake,
+        creator: F,
+    ) -> Arc<ConnectionAttempt>
+    where
+        F: FnOnce(TcpStream, Shared<EventHandler>, watch::Sender<ChannelState>) -> Fut
+            + Send
+            + 'static,
+        Fut: Future<Output = Result<Option<Channel>, ProtocolError>> + Send + 'static,
+    {
+        let limit = self.timeouts.connect();
+        let target = match relayed {
+            Some(id) => format!("{} through {}", id.short(), addr),
+            None => addr.to_string(),
+        };
+        self.attempt(
+            name,
+            target,
+            ChannelState::Connecting,
+            move |message_handler, state| async move {
+                let stream = match relayed {
+                    Some(id) => timeout(limit, relay::connect_through(&addr, id)).await,
+                    None => timeout(limit, address::connect_async(&addr)).await,
+                }
+                .map_err(|_| timed_out("connecting"))??;
+                let channel = open(stream, handshake, message_handler, state, creator).await?;
+                Ok(channel.map(|channel| channel.with_addr(addr)))
+            },
+        )
+    }
+
+    /// Binds a new listener, which will handle incoming connections
+    /// according to its [ListenerPolicy]. Any number of listeners may run
+    /// at once.
+    ///
+    /// ## Returns
+    ///
+    /// The address that was actually bound, which is useful when binding
+    /// to port 0, or an error if binding failed. Errors are also emitted as
+    /// [Event::ListenerError].
+    pub async fn add_listener(&mut self, config: ListenerConfig) -> io::Result<SocketAddr> {
+        let addr = config.addr();
+        let bind = async || -> io::Result<_> {
+            let listener = TcpListener::bind((addr.host(), addr.port())).await?;
+            Ok((listener.local_addr()?, listener))
+        };
+        let (local_addr, listener) = bind().await.inspect_err(|e| {
+            self.spawner
+                .handler
+                .lock()
+                .unwrap()
+                .emit(Event::ListenerError {
+                    listener: config.addr().to_string(),
+                    // io errors can't be cloned, so the caller gets the original
+                    error: Arc::new(io::Error::new(e.kind(), e.to_string())),
+                })
+        })?;
+
+        self.add_acceptor(listener, config.policy().clone())?;
+        Ok(local_addr)
+    }
+
+    /// Replaces all listeners with a single one with the default
+    /// [ListenerPolicy]. Hostnames are resolved, and the first resolved
+    /// address that can be bound to is used.
+    pub async fn start_listening(&mut self, addr: HostAddr) -> io::Result<SocketAddr> {
+        self.stop_listening().await;
+        self.add_listener(ListenerConfig::new(addr, ListenerPolicy::default()))
+            .await
+    }
+}
+
+impl<T: Transport> AsyncGrapevineApp<T> {
+    /// Starts a new channel over an already connected transport, assuming the
+    /// RSA handshake will happen next. The transport equivalent of
+    /// [AsyncGrapevineApp::new_rsa_channel].
+    pub fn open_rsa_channel(
+        &mut self,
+        transport: T,
+        name: Option<String>,
+    ) -> Arc<ConnectionAttempt> {
+        self.open(
+            transport,
+            name.clone(),
+            Handshake::new(ProtocolPath::RsaExchange),
+            |transport, message_handler, state| {
+                Channel::new_tracked(transport, None, name, message_handler, state)
+            },
+        )
+    }
+
+    /// Starts a new channel over an already connected transport, assuming the
+    /// AES handshake will happen next. The transport equivalent of
+    /// [AsyncGrapevineApp::new_aes_channel].
+    pub fn open_aes_channel(
+        &mut self,
+        transport: T,
+        our_key: PKey<Private>,
+        their_key: PKey<Public>,
+        name: Option<String>,
+    ) -> Arc<ConnectionAttempt> {
+        let handshake = Handshake::with_hint(ProtocolPath::AesExchange, Fingerprint::of(&our_key));
+        self.open(
+            transport,
+            name.clone(),
+            handshake,
+            |transport, message_handler, state| async move {
+                let desc = ChannelDesc::for_transport(&transport, our_key, their_key, name)?;
+                Channel::from_desc_tracked(transport, desc, message_handler, state).await
+            },
+        )
+    }
+
+    /// Restarts a channel over an already connected transport.
+    /// The transport equivalent of [AsyncGrapevineApp::new_channel_from_desc].
+    pub fn open_channel_from_desc(
+        &mut self,
+        transport: T,
+        desc: ChannelDesc,
+    ) -> Arc<ConnectionAttempt> {
+        let handshake = Handshake::with_hint(ProtocolPath::AesExchange, desc.our_fingerprint());
+        self.open(
+            transport,
+            Some(desc.name().to_string()),
+            handshake,
+            |transport, message_handler, state| {
+                Channel::from_desc_tracked(transport, desc, message_handler, state)
+            },
+        )
+    }
+
+    /// Helper method that establishes the channel over an already connected
+    /// transport, in a new [ConnectionAttempt]
+    fn open<F, Fut>(
+        &mut self,
+        transport: T,
+        name: Option<String>,
+        handshake: Handshake,
+        creator: F,
+    ) -> Arc<ConnectionAttempt>
+    where
+        F: FnOnce(T, Shared<EventHandler<T>>, watch::Sender<ChannelState>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Option<Channel<T>>, ProtocolError>> + Send + 'static,
+    {
+        let target = transport
+            .peer()
+            .map(|peer| peer.to_string())
+            .unwrap_or_default();
+        self.attempt(
+            name.unwrap_or_else(|| target.clone()),
+            target,
+            ChannelState::Connecting,
+            |message_handler, state| open(transport, handshake, message_handler, state, creator),
+        )
+    }
+
+    /// Runs the future establishing a channel in a separate task, tracking
+    /// it as a [ConnectionAttempt]. The future is given up on once the
+    /// attempt is cancelled, or the key exchange takes too long.
+    fn attempt<F, Fut>(
+        &mut self,
+        name: String,
+        target: String,
+        state: ChannelState,
+        establish: F,
+    ) -> Arc<ConnectionAttempt>
+    where
+        F: FnOnce(Shared<EventHandler<T>>, watch::Sender<ChannelState>) -> Fut,
+        Fut: Future<Output = Result<Option<Channel<T>>, ProtocolError>> + Send + 'static,
+    {
+        let attempt = Arc::new(ConnectionAttempt::new(name, target, state));
+        self.attempts.lock().unwrap().push(attempt.clone());
+
+        let state = attempt.state_sender().clone();
+        let deadline = handshake_deadline(state.subscribe(), self.timeouts.handshake());
+        let establishing = establish(self.spawner.handler.clone(), state);
+        let spawner = self.spawner.clone();
+        let attempts = self.attempts.clone();
+        let tracked = attempt.clone();
+        self.spawner.runtime.spawn(async move {
+            // cancelling drops the transport, along with the rest of the attempt
+            let created = tokio::select! {
+                created = establishing => Some(created),
+                _ = deadline => Some(Err(timed_out("the key exchange"))),
+                _ = tracked.cancelled() => None,
+            };
+            // an open channel keeps the state, and updates it on its own
+            match created.map(|created| spawner.finish(created)) {
+                Some(Ok(_)) => {}
+                Some(Err(error)) => {
+                    tracked
+                        .state_sender()
+                        .send_replace(ChannelState::Failed(error.clone()));
+                    spawner
+                        .handler
+                        .lock()
+                        .unwrap()
+                        .emit(Event::ConnectionFailed {
+                            attempt: tracked.clone(),
+                            error,
+                        });
+                }
+                None => {
+                    tracked
+                        .state_sender()
+                        .send_replace(ChannelState::Closed(CloseReason::Closed));
+                }
+            }
+            attempts
+                .lock()
+                .unwrap()
+                .retain(|other| !Arc::ptr_eq(other, &tracked));
+        });
+        attempt
+    }
+
+    /// Accepts a [PendingRsaHandshake], and adds it as a [Channel] to the app,
+    /// once the handshakes are done in a separate task.
+    /// See [Self::new_rsa_channel] for how the results are reported.
+    pub fn add_rsa_channel(
+        &mut self,
+        pending: PendingRsaHandshake<T>,
+        name: Option<String>,
+    ) -> Arc<ConnectionAttempt> {
+        let peer = pending.peer().clone();
+        let firewall = self.firewall.clone();
+        self.attempt(
+            name.clone().unwrap_or_else(|| pending.name().to_string()),
+            peer.to_string(),
+            ChannelState::Handshaking,
+            |message_handler, _| async move {
+                let channel = pending.accept(name, message_handler).await?;
+                check_key(&firewall, &peer, channel)
+            },
+        )
+    }
+
+    /// Accepts a [PendingAesHandshake], and adds it as a [Channel] to the app.
+    /// See [Self::add_rsa_channel].
+    pub fn add_aes_channel(
+        &mut self,
+        pending: PendingAesHandshake<T>,
+        name: Option<String>,
+        our_key: PKey<Private>,
+        their_key: PKey<Public>,
+    ) -> Arc<ConnectionAttempt> {
+        let peer = pending.peer().clone();
+        let firewall = self.firewall.clone();
+        let contacts = self.contacts.clone();
+        self.attempt(
+            name.clone().unwrap_or_else(|| pending.name().to_string()),
+            peer.to_string(),
+            ChannelState::Handshaking,
+            |message_handler, _| async move {
+                let channel = pending
+                    .accept(name, our_key, their_key, message_handler)
+                    .await?;
+                check_key(
+                    &firewall,
+                    &peer,
+                    note_roaming(&contacts, &peer, channel).await,
+                )
+            },
+        )
+    }
+
+    /// Accepts a [PendingAesHandshake] from a saved contact, using the keys
+    /// saved alongside them. See [PendingAesHandshake::contact] and
+    /// [Self::add_rsa_channel].
+    pub fn add_known_aes_channel(
+        &mut self,
+        pending: PendingAesHandshake<T>,
+    ) -> Arc<ConnectionAttempt> {
+        let peer = pending.peer().clone();
+        let firewall = self.firewall.clone();
+        let contacts = self.contacts.clone();
+        self.attempt(
+            pending.name().to_string(),
+            peer.to_string(),
+            ChannelState::Handshaking,
+            |message_handler, _| async move {
+                let channel = pending.accept_known(message_handler).await?;
+                check_key(
+                    &firewall,
+                    &peer,
+                    note_roaming(&contacts, &peer, channel).await,
+                )
+            },
+        )
+    }
+
+    /// Gets the list of channels, including the closed ones, which are kept
+    /// with their history until removed
+    pub fn channels(&self) -> &Arc<Mutex<Vec<Arc<Channel<T>>>>> {
+        &self.spawner.channels
+    }
+
+    /// Closes the channel if need be, and forgets about it
+    pub fn remove_channel(&mut self, channel: &Arc<Channel<T>>) -> io::Result<()> {
+        self.spawner
+            .channels
+            .lock()
+            .unwrap()
+            .retain(|other| !Arc::ptr_eq(other, channel));
+        channel.close()
+    }
+
+    /// Gets the list of channels we are establishing, either by connecting
+    /// or accepting, that aren't open yet. Once opened, they move over to
+    /// [Self::channels].
+    pub fn connecting(&self) -> &Arc<Mutex<Vec<Arc<ConnectionAttempt>>>> {
+        &self.attempts
+    }
+
+    /// Gets how long establishing new channels may take
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    /// Changes how long establishing new channels may take. Channels being
+    /// established already keep the old timeouts.
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// Gets how messages are queued on new channels
+    pub fn send_queue(&self) -> QueueConfig {
+        *self.spawner.queue.lock().unwrap()
+    }
+
+    /// Changes how messages are queued. Channels already open keep the old
+    /// queue.
+    pub fn set_send_queue(&mut self, config: QueueConfig) {
+        *self.spawner.queue.lock().unwrap() = config;
+    }
+
+    /// Gets how many frames new channels send between checkpoints
+    pub fn checkpoints(&self) -> Option<u64> {
+        *self.spawner.checkpoints.lock().unwrap()
+    }
+
+    /// Makes new channels sign a checkpoint every so many frames they send,
+    /// vouching with our key for everything sent before. Frames are
+    /// authenticated with session keys either way, which both parties know,
+    /// so checkpoints are what ties the conversation to us alone.
+    pub fn set_checkpoints(&mut self, every: Option<u64>) {
+        *self.spawner.checkpoints.lock().unwrap() = every;
+    }
+
+    /// Gets the contact book, used for recognizing incoming connections
+    pub fn contacts(&self) -> &Arc<Mutex<ContactBook>> {
+        &self.contacts
+    }
+
+    /// Replaces the contact book
+    pub fn set_contacts(&mut self, contacts: ContactBook) {
+        *self.contacts.lock().unwrap() = contacts;
+    }
+
+    /// Gets the firewall, which holds the [AccessRules] and the log of
+    /// rejected connections
+    pub fn firewall(&self) -> &Arc<Mutex<Firewall>> {
+        &self.firewall
+    }
+
+    /// Replaces the [AccessRules] applied to incoming connections
+    pub fn set_access_rules(&mut self, rules: AccessRules) {
+        self.firewall.lock().unwrap().set_rules(rules);
+    }
+
+    /// Clears and returns the list of currently pending connections.
+    /// Connections that are left pending for too long expire on their own,
+    /// those whose peers disconnected expire here, see
+    /// [Event::PendingConnectionExpired].
+    pub fn inspect_pending(&mut self) -> Vec<PendingConnection<T>> {
+        let (stale, pending): (Vec<_>, _) = self
+            .pending_connections
+            .lock()
+            .unwrap()
+            .drain(..)
+            .partition(PendingConnection::is_stale);
+        for stale in stale {
+            stale.expire(&self.spawner.handler);
+        }
+        pending
+    }
+
+    /// Adds a new pending connection, unless it went stale in the meantime
+    pub fn add_pending(&mut self, pending: PendingConnection<T>) {
+        if pending.is_stale() {
+            pending.expire(&self.spawner.handler);
+        } else {
+            self.pending_connections.lock().unwrap().push(pending);
+        }
+    }
+
+    /// Peeks at the pending connections, without taking them
+    #[cfg(test)]
+    pub(crate) fn pending_connections(&self) -> &Shared<Vec<PendingConnection<T>>> {
+        &self.pending_connections
+    }
+
+    /// Subscribes to all future [Event]s. Dropping the receiver
+    /// unsubscribes.
+    pub fn subscribe(&mut self) -> EventReceiver<T> {
+        self.spawner.handler.lock().unwrap().subscribe()
+    }
+
+    /// Invokes the callback on all future [Event]s, right as they happen.
+    /// The callback must not call back into the app.
+    pub fn on_event(&mut self, callback: impl FnMut(&Event<T>) + Send + 'static) {
+        self.spawner.handler.lock().unwrap().add_callback(callback);
+    }
+
+    /// Invokes the callback on all future [Payload](super::Payload)s with the given tag,
+    /// decoded as `P`. Payloads that don't decode are left to
+    /// [Event::PayloadReceived]. Like [Self::on_event], the callback must not
+    /// call back into the app.
+    pub fn on_payload<P: DeserializeOwned>(
+        &mut self,
+        tag: impl Into<String>,
+        mut callback: impl FnMut(&Arc<Channel<T>>, P) + Send + 'static,
+    ) {
+        let tag = tag.into();
+        self.on_event(move |event| {
+            if let Event::PayloadReceived { channel, payload } = event
+                && payload.tag() == tag
+                && let Ok(value) = payload.decode()
+            {
+                callback(channel, value);
+            }
+        });
+    }
+
+    /// Answers requests for the method, made by peers through [Self::call].
+    /// The handler runs in a task of its own, which gets aborted if the caller
+    /// cancels. What it returns is sent back, errors as
+    /// [RemoteError::Failed](super::RemoteError::Failed). Replaces an earlier
+    /// handler of the method.
+    pub fn add_method<P, R, F, Fut>(&mut self, name: impl Into<String>, handler: F)
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: Fn(Arc<Channel<T>>, P) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<R, String>> + Send + 'static,
+    {
+        self.rpc.add_method(name.into(), handler);
+    }
+
+    /// Asks the peer on the other end of the channel to run the method,
+    /// waiting at most `wait` for the response. Dropping the future cancels
+    /// the request, and so does timing out, letting the peer know.
+    pub async fn call<P: Serialize + ?Sized, R: DeserializeOwned>(
+        &self,
+        channel: &Arc<Channel<T>>,
+        method: &str,
+        params: &P,
+        wait: Duration,
+    ) -> Result<R, CallError> {
+        let params = bitcode::serialize(params)?;
+        let response = self
+            .rpc
+            .call(channel, method.to_string(), params, wait)
+            .await?;
+        Ok(bitcode::deserialize(&response)?)
+    }
+
+    /// Creates a new group, with us as its only member, going by the given
+    /// name. We run the group: members are invited over the channels with
+    /// them, and their messages go through us.
+    pub async fn create_group(
+        &mut self,
+        name: String,
+        our_name: String,
+    ) -> Result<Arc<Group>, GroupError> {
+        self.groups.create(name, our_name).await
+    }
+
+    /// Gets the groups, including the ones we are out of, which are kept
+    /// with their history until removed
+    pub fn groups(&self) -> &Arc<Mutex<Vec<Arc<Group>>>> {
+        self.groups.groups()
+    }
+
+    /// Adds the other party of the open channel to a group we created. The
+    /// group moves on to a new key, which every member gets along with the
+    /// signed membership. Inviting a member again catches them up.
+    pub fn invite_to_group(
+        &mut self,
+        group: &Group,
+        channel: &Arc<Channel<T>>,
+    ) -> Result<(), GroupError> {
+        self.groups.invite(group, channel)
+    }
+
+    /// Removes a member from a group we created, moving on to a new key
+    pub fn remove_from_group(
+        &mut self,
+        group: &Group,
+        member: &Fingerprint,
+    ) -> Result<(), GroupError> {
+        self.groups.remove(group, member)
+    }
+
+    /// Leaves the group, letting its creator know. If we are the creator,
+    /// the group ends for everyone.
+    pub fn leave_group(&mut self, group: &Arc<Group>) -> Result<(), GroupError> {
+        self.groups.leave(group)
+    }
+
+    /// Leaves the group if need be, and forgets about it
+    pub fn remove_group(&mut self, group: &Arc<Group>) -> Result<(), GroupError> {
+        if group.is_active() {
+            self.leave_group(group)?;
+        }
+        self.groups
+            .groups()
+            .lock()
+            .unwrap()
+            .retain(|other| !Arc::ptr_eq(other, group));
+        Ok(())
+    }
+
+    /// Sends the message to the group. Members write to the creator, who
+    /// passes the message on to everyone else connected.
+    pub fn send_to_group(&mut self, group: &Group, message: Message) -> Result<(), GroupError> {
+        self.groups.send(group, message)
+    }
+
+    /// Sends the message to the other party of the channel, who we aren't
+    /// connected with, through the peers we both are connected with. Only
+    /// they can open it, and tell it came from us. Peers who opted in pass
+    /// it on, see [GossipConfig], and everyone we connect with before it
+    /// expires gets it too. The message is added to the history of the
+    /// channel, if it's still around.
+    pub fn send_through_peers(
+        &mut self,
+        desc: &ChannelDesc,
+        message: Message,
+    ) -> Result<MessageId, ProtocolError> {
+        self.gossip.send(desc, message)
+    }
+
+    /// Gets how messages travel through peers
+    pub fn gossip(&self) -> GossipConfig {
+        self.gossip.config()
+    }
+
+    /// Changes how messages travel through peers, including whether we pass
+    /// on the messages of others
+    pub fn set_gossip(&mut self, config: GossipConfig) {
+        self.gossip.set_config(config);
+    }
+
+    /// Starts finding peers on the local network, through the multicast
+    /// group at [DISCOVERY_ADDR](discovery::DISCOVERY_ADDR). Unless only
+    /// browsing, we announce ourselves there as well, signed with the keys
+    /// behind the fingerprints of the announcement. Peers are only taken in
+    /// when signed by the key they announce themselves under, keeping the
+    /// keys of their contacts they signed for, and new ones are reported
+    /// with [Event::PeerDiscovered]. The peers only suggest where contacts
+    /// are, their addresses are refreshed once a channel opens with them at
+    /// the address suggested, see [Self::new_channel_from_desc].
+    /// Replaces the discovery started before, if any.
+    pub fn start_discovery(
+        &mut self,
+        announcement: Option<Announcement>,
+        keys: &[PKey<Private>],
+    ) -> io::Result<()> {
+        self.stop_discovery();
+        let instance = discovery::new_instance();
+        let beacon = announcement
+            .map(|announcement| discovery::beacon(instance, announcement, keys))
+            .transpose()
+            .map_err(io::Error::other)?;
+        let socket = {
+            let _runtime = self.spawner.runtime.enter();
+            discovery::bind()?
+        };
+        self.discovery = Some(self.spawner.runtime.spawn(discovery_task(
+            socket,
+            instance,
+            beacon,
+            self.discovered.clone(),
+            self.spawner.handler.clone(),
+        )));
+        Ok(())
+    }
+
+    /// Stops announcing ourselves, and forgets the peers found so far
+    pub fn stop_discovery(&mut self) {
+        if let Some(task) = self.discovery.take() {
+            task.abort();
+        }
+        self.discovered.lock().unwrap().clear();
+    }
+
+    /// Gets the peers announcing themselves on the local network, see
+    /// [Self::start_discovery]
+    pub fn discovered(&self) -> Vec<DiscoveredPeer> {
+        discovery::prune(&self.discovered, Utc::now());
+        self.discovered.lock().unwrap().clone()
+    }
+
+    /// Starts accepting incoming connections from the [Acceptor], handling
+    /// them according to the [ListenerPolicy].
+    ///
+    /// ## Returns
+    ///
+    /// Where the acceptor can be reached
+    pub fn add_acceptor<A: Acceptor<Transport = T>>(
+        &mut self,
+        acceptor: A,
+        policy: ListenerPolicy,
+    ) -> io::Result<Endpoint> {
+        let local = acceptor.local()?;
+
+        let (stop, stopped) = watch::channel(false);
+        let ctx = ListenerContext {
+            policy: policy.clone(),
+            pending: self.pending_connections.clone(),
+            contacts: self.contacts.clone(),
+            firewall: self.firewall.clone(),
+            spawner: self.spawner.clone(),
+        };
+        let task = self
+            .spawner
+            .runtime
+            .spawn(listener_task(acceptor, ctx, stopped));
+
+        self.listeners.push(RunningListener {
+            local: local.clone(),
+            policy,
+            stop,
+            task,
+        });
+        Ok(local)
+    }
+
+    /// Stops the listener reachable at the given endpoint
+    pub async fn remove_listener(&mut self, local: &Endpoint) {
+        let removed = self
+            .listeners
+            .extract_if(.., |listener| &listener.local == local)
+            .collect::<Vec<_>>();
+        for listener in removed {
+            listener.stop().await;
+        }
+    }
+
+    /// Gets where the running listeners can be reached, alongside their
+    /// policies
+    pub fn listeners(&self) -> impl Iterator<Item = (&Endpoint, &ListenerPolicy)> {
+        self.listeners
+            .iter()
+            .map(|listener| (&listener.local, &listener.policy))
+    }
+
+    /// Stops all listeners
+    pub async fn stop_listening(&mut self) {
+        for listener in self.listeners.drain(..) {
+            listener.stop().await;
+        }
+    }
+
+    /// Says goodbye on every open channel, and stops everything else
+    /// running in the background: listeners, pending connections, and
+    /// channels still being established. Waits for the channels to close,
+    /// for up to the given timeout.
+    ///
+    /// ## Returns
+    ///
+    /// An error if some channels didn't close in time
+    pub async fn shutdown(&mut self, timeout: Duration) -> io::Result<()> {
+        self.stop_listening().await;
+        self.stop_discovery();
+        for pending in self.inspect_pending() {
+            pending.reject();
+        }
+        for attempt in self.attempts.lock().unwrap().iter() {
+            attempt.cancel();
+        }
+
+        let channels = self
+            .spawner
+            .channels
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|channel| !channel.state().is_finished())
+            .cloned()
+            .collect::<Vec<_>>();
+        for channel in &channels {
+            channel.close_with_reason(SHUTDOWN_REASON.to_string())?;
+        }
+        tokio::time::timeout(timeout, async {
+            for channel in &channels {
+                let _ = channel
+                    .watch_state()
+                    .wait_for(ChannelState::is_finished)
+                    .await;
+            }
+        })
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "channels didn't close in time"))
+    }
+}
+
+/// Background tasks would otherwise outlive the app
+impl<T: Transport> Drop for AsyncGrapevineApp<T> {
+    fn drop(&mut self) {
+        self.group_task.abort();
+        self.gossip_task.abort();
+        if let Some(task) = &self.discovery {
+            task.abort();
+        }
+        for listener in &self.listeners {
+            listener.stop.send_replace(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        firewall::Rejection,
+        listener::AutoAccept,
+        outbox::{SendError, SendStatus},
+        protocol::{Message, Payload},
+        rpc::RemoteError,
+        transport::{MemoryConnector, MemoryListener, MemoryTransport},
+    };
+    use tokio::time::timeout;
+
+    /// Waits for the next event matching the predicate
+    async fn next_event<T: Transport>(
+        events: &mut EventReceiver<T>,
+        mut predicate: impl FnMut(&Event<T>) -> bool,
+    ) -> Event<T> {
+        timeout(Duration::from_secs(5), async {
+            loop {
+                let event = events.recv().await.unwrap();
+                if predicate(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Two apps, with the sender connected to the receiver. The connector
+    /// keeps the receiver's listener alive.
+    async fn connected_pair() -> (
+        AsyncGrapevineApp<MemoryTransport>,
+        AsyncGrapevineApp<MemoryTransport>,
+        MemoryConnector,
+    ) {
+        let mut receiver = AsyncGrapevineApp::<MemoryTransport>::default();
+        let (listener, connector) = MemoryListener::new();
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::RsaExchange], true, AutoAccept::Everyone);
+        receiver.add_acceptor(listener, policy).unwrap();
+
+        let mut sender = AsyncGrapevineApp::<MemoryTransport>::default();
+        sender.open_rsa_channel(connector.connect().unwrap(), Some("receiver".to_string()));
+        (sender, receiver, connector)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_async_channel_lifecycle() {
+        let mut receiver = AsyncGrapevineApp::<MemoryTransport>::default();
+        let mut events = receiver.subscribe();
+        let (listener, connector) = MemoryListener::new();
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::RsaExchange], true, AutoAccept::Everyone);
+        receiver.add_acceptor(listener, policy).unwrap();
+
+        let mut sender = AsyncGrapevineApp::<MemoryTransport>::default();
+        sender.open_rsa_channel(connector.connect().unwrap(), Some("receiver".to_string()));
+
+        // the event arrives as soon as the channel is up, no polling needed
+        let opened = next_event(&mut events, |e| matches!(e, Event::ChannelOpened { .. })).await;
+        let Event::ChannelOpened { channel: theirs } = opened else {
+            unreachable!()
+        };
+        assert!(Arc::ptr_eq(
+            &theirs,
+            &receiver.channels().lock().unwrap()[0]
+        ));
+
+        let ours = loop {
+            if let Some(channel) = sender.channels().lock().unwrap().first() {
+                break channel.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        ours.send(Message::new("hello".to_string())).await.unwrap();
+
+        // closing on one end closes both ends, which keep the history
+        ours.close().unwrap();
+        timeout(Duration::from_secs(5), async {
+            while !(ours.state().is_finished() && theirs.state().is_finished()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(Arc::ptr_eq(
+            &theirs,
+            &receiver.channels().lock().unwrap()[0]
+        ));
+        assert_eq!(theirs.messages().lock().unwrap()[0].content(), "hello");
+
+        receiver.remove_channel(&theirs).unwrap();
+        assert!(receiver.channels().lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_message_and_close_events() {
+        let (mut sender, mut receiver, _connector) = connected_pair().await;
+        let mut sent = sender.subscribe();
+        let mut received = receiver.subscribe();
+        let (callback_tx, mut from_callback) = tokio::sync::mpsc::unbounded_channel();
+        receiver.on_event(move |event| {
+            if let Event::MessageReceived { .. } = event {
+                let _ = callback_tx.send(event.to_string());
+            }
+        });
+
+        let ours = loop {
+            if let Some(channel) = sender.channels().lock().unwrap().first() {
+                break channel.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let handle = ours.send(Message::new("hello".to_string())).await.unwrap();
+        handle.sent().await.unwrap();
+        assert!(matches!(handle.status(), SendStatus::Sent));
+
+        let event = next_event(&mut received, |e| {
+            matches!(e, Event::MessageReceived { .. })
+        })
+        .await;
+        let Event::MessageReceived { message, .. } = &event else {
+            unreachable!()
+        };
+        assert_eq!(message.content(), "hello");
+        let described = timeout(Duration::from_secs(5), from_callback.recv()).await;
+        assert_eq!(described.unwrap().unwrap(), event.to_string());
+
+        assert!(matches!(ours.state(), ChannelState::Open));
+        ours.close().unwrap();
+        assert!(matches!(ours.state(), ChannelState::Closing));
+        let closed = next_event(&mut sent, |e| matches!(e, Event::ChannelClosed { .. })).await;
+        assert!(matches!(
+            ours.state(),
+            ChannelState::Closed(CloseReason::Closed)
+        ));
+        assert!(matches!(
+            closed,
+            Event::ChannelClosed {
+                reason: CloseReason::Closed,
+                ..
+            }
+        ));
+        // the other party got a goodbye, rather than the connection dropping
+        let closed = next_event(&mut received, |e| matches!(e, Event::ChannelClosed { .. })).await;
+        assert!(matches!(
+            closed,
+            Event::ChannelClosed {
+                reason: CloseReason::PeerClosed(None),
+                ..
+            }
+        ));
+        assert_eq!(closed.to_string(), "memory closed the conversation");
+    }
+
+    #[tokio::test]
+    async fn test_blocking_within_own_current_thread_runtime() {
+        let (sender, _receiver, _connector) = connected_pair().await;
+        let ours = loop {
+            if let Some(channel) = sender.channels().lock().unwrap().first() {
+                break channel.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        // waiting would leave nobody to do the work
+        let blocked = ours.send_message(Message::new("hi".to_string()));
+        assert!(matches!(blocked, Err(SendError::Blocking(_))));
+        let handle = ours.send(Message::new("hi".to_string())).await.unwrap();
+        assert!(matches!(handle.wait(), Err(SendError::Blocking(_))));
+        handle.sent().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_typed_payloads() {
+        let (sender, mut receiver, _connector) = connected_pair().await;
+        let (typed_tx, mut typed) = tokio::sync::mpsc::unbounded_channel();
+        receiver.on_payload("build", move |_, status: (u32, bool)| {
+            let _ = typed_tx.send(status);
+        });
+        let mut received = receiver.subscribe();
+
+        let ours = loop {
+            if let Some(channel) = sender.channels().lock().unwrap().first() {
+                break channel.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        // other tags are left to the event
+        ours.send_payload(Payload::new("other", "hi").unwrap())
+            .await
+            .unwrap();
+        ours.send_payload(Payload::new("build", &(42u32, true)).unwrap())
+            .await
+            .unwrap();
+
+        let event = next_event(&mut received, |e| {
+            matches!(e, Event::PayloadReceived { .. })
+        })
+        .await;
+        let Event::PayloadReceived { payload, .. } = event else {
+            unreachable!()
+        };
+        assert_eq!(payload.tag(), "other");
+        let status = timeout(Duration::from_secs(5), typed.recv()).await;
+        assert_eq!(status.unwrap(), Some((42, true)));
+        // payloads aren't part of the conversation
+        assert!(ours.messages().lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_groups() {
+        let mut creator = AsyncGrapevineApp::<MemoryTransport>::default();
+        let (listener, connector) = MemoryListener::new();
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::RsaExchange], true, AutoAccept::Everyone);
+        creator.add_acceptor(listener, policy).unwrap();
+
+        let mut alice = AsyncGrapevineApp::<MemoryTransport>::default();
+        let mut bob = AsyncGrapevineApp::<MemoryTransport>::default();
+        let mut alice_events = alice.subscribe();
+        let mut bob_events = bob.subscribe();
+        let mut creator_events = creator.subscribe();
+        alice.open_rsa_channel(connector.connect().unwrap(), Some("creator".to_string()));
+        bob.open_rsa_channel(connector.connect().unwrap(), Some("creator".to_string()));
+        let channels = loop {
+            let channels = creator.channels().lock().unwrap().clone();
+            if channels.len() == 2 {
+                break channels;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        let group = creator
+            .create_group("team".to_string(), "carol".to_string())
+            .await
+            .unwrap();
+        for channel in &channels {
+            creator.invite_to_group(&group, channel).unwrap();
+        }
+        assert_eq!(group.members().len(), 3);
+        assert_eq!(group.epoch(), 2);
+
+        // the members end up with the latest membership
+        let joined = |e: &Event<MemoryTransport>| matches!(e, Event::GroupUpdated { group } if group.members().len() == 3);
+        let Event::GroupUpdated { group: theirs } = next_event(&mut alice_events, joined).await
+        else {
+            unreachable!()
+        };
+        next_event(&mut bob_events, joined).await;
+        assert_eq!(theirs.id(), group.id());
+        assert_eq!(theirs.name(), "team");
+        assert!(!theirs.is_creator());
+
+        alice
+            .send_to_group(&theirs, Message::new("hi all".to_string()))
+            .unwrap();
+        for events in [&mut bob_events, &mut creator_events] {
+            let event =
+                next_event(events, |e| matches!(e, Event::GroupMessageReceived { .. })).await;
+            let Event::GroupMessageReceived { message, .. } = event else {
+                unreachable!()
+            };
+            assert_eq!(message.message().content(), "hi all");
+            assert_eq!(message.author().id(), theirs.me());
+        }
+        assert_eq!(group.messages().lock().unwrap().len(), 1);
+        // group messages aren't part of the conversations
+        assert!(channels[0].messages().lock().unwrap().is_empty());
+
+        // removing a member moves everyone else on to a new key
+        let bob_id = bob.groups().lock().unwrap()[0].me().to_owned();
+        creator.remove_from_group(&group, &bob_id).unwrap();
+        let removed = next_event(
+            &mut bob_events,
+            |e| matches!(e, Event::GroupUpdated { group } if !group.is_active()),
+        )
+        .await;
+        assert!(matches!(removed, Event::GroupUpdated { .. }));
+        next_event(
+            &mut alice_events,
+            |e| matches!(e, Event::GroupUpdated { group } if group.members().len() == 2),
+        )
+        .await;
+        assert_eq!(theirs.epoch(), group.epoch());
+        let bobs = bob.groups().lock().unwrap()[0].clone();
+        assert!(matches!(
+            bob.send_to_group(&bobs, Message::new("still here?".to_string())),
+            Err(GroupError::Left)
+        ));
+
+        // the group ends with its creator
+        creator.leave_group(&group).unwrap();
+        next_event(
+            &mut alice_events,
+            |e| matches!(e, Event::GroupUpdated { group } if !group.is_active()),
+        )
+        .await;
+        assert_eq!(theirs.messages().lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_messages_through_peers() {
+        // alice and carol know each other, but are no longer connected
+        let (mut alice, mut carol, _carols) = connected_pair().await;
+        let mut alice_events = alice.subscribe();
+        let mut carol_events = carol.subscribe();
+        let Event::ChannelOpened { channel } = next_event(&mut alice_events, |e| {
+            matches!(e, Event::ChannelOpened { .. })
+        })
+        .await
+        else {
+            unreachable!()
+        };
+        channel.close().unwrap();
+        next_event(&mut carol_events, |e| {
+            matches!(e, Event::ChannelClosed { .. })
+        })
+        .await;
+
+        // bob passes messages on, once he opts in
+        let mut bob = AsyncGrapevineApp::<MemoryTransport>::default();
+        let (listener, bobs) = MemoryListener::new();
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::RsaExchange], true, AutoAccept::Everyone);
+        bob.add_acceptor(listener, policy).unwrap();
+        let config = GossipConfig::new(true, 1, Duration::from_secs(60), 16);
+        bob.set_gossip(config);
+        assert!(!alice.gossip().relay());
+        let mut bob_events = bob.subscribe();
+        alice.open_rsa_channel(bobs.connect().unwrap(), Some("bob".to_string()));
+        next_event(&mut bob_events, |e| {
+            matches!(e, Event::ChannelOpened { .. })
+        })
+        .await;
+
+        let message = Message::new("are you there?".to_string());
+        alice.send_through_peers(channel.desc(), message).unwrap();
+        assert_eq!(channel.messages().lock().unwrap().len(), 1);
+
+        // bob holds on to it, until carol shows up
+        carol.open_rsa_channel(bobs.connect().unwrap(), Some("bob".to_string()));
+        let Event::RelayedMessageReceived {
+            sender, message, ..
+        } = next_event(&mut carol_events, |e| {
+            matches!(e, Event::RelayedMessageReceived { .. })
+        })
+        .await
+        else {
+            unreachable!()
+        };
+        assert_eq!(sender, channel.desc().our_fingerprint());
+        assert_eq!(message.content(), "are you there?");
+        assert!(!message.is_ours());
+        let theirs = carol.channels().lock().unwrap()[0].clone();
+        assert_eq!(theirs.fingerprint(), &sender);
+        assert_eq!(theirs.messages().lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_channels_through_relay() {
+        let relay = crate::Relay::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr: HostAddr = listener.local_addr().unwrap().into();
+        tokio::spawn({
+            let relay = relay.clone();
+            async move { relay.serve(listener, |_| {}).await }
+        });
+        let registered = |waiting: usize| {
+            let relay = relay.clone();
+            timeout(Duration::from_secs(5), async move {
+                while relay.waiting() != waiting {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+        };
+
+        // both parties are behind NAT, the receiver waits at the relay under
+        // a key of theirs
+        let mut receiver = AsyncGrapevineApp::new();
+        let mut sender = AsyncGrapevineApp::new();
+        let mut received = receiver.subscribe();
+        let mut sent = sender.subscribe();
+        let key = PKey::from_rsa(openssl::rsa::Rsa::generate(1024).unwrap()).unwrap();
+        let id = Fingerprint::of(&key);
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::RsaExchange], true, AutoAccept::Everyone);
+        receiver
+            .listen_through_relay(relay_addr.clone(), key, Vec::new(), policy)
+            .unwrap();
+        registered(1).await.unwrap();
+
+        let stranger =
+            Fingerprint::of(&PKey::from_rsa(openssl::rsa::Rsa::generate(1024).unwrap()).unwrap());
+        sender.new_rsa_channel_through_relay(relay_addr.clone(), stranger, None);
+        let failed = next_event(&mut sent, |e| matches!(e, Event::ConnectionFailed { .. })).await;
+        assert!(failed.to_string().contains("nobody is registered"));
+
+        sender.new_rsa_channel_through_relay(relay_addr.clone(), id, Some("receiver".to_string()));
+        let Event::ChannelOpened { channel: ours } =
+            next_event(&mut sent, |e| matches!(e, Event::ChannelOpened { .. })).await
+        else {
+            unreachable!()
+        };
+        let Event::ChannelOpened { channel: theirs } =
+            next_event(&mut received, |e| matches!(e, Event::ChannelOpened { .. })).await
+        else {
+            unreachable!()
+        };
+        assert_eq!(theirs.fingerprint(), &ours.desc().our_fingerprint());
+        // the sender knows them by the key they were asked for
+        assert_eq!(ours.fingerprint(), &id);
+        ours.send_message(Message::new("hello".to_string()))
+            .unwrap();
+        next_event(&mut received, |e| {
+            matches!(e, Event::MessageReceived { .. })
+        })
+        .await;
+
+        // the receiver waits under the key the sender knows them by as well,
+        // for the channel to be recreated
+        ours.close().unwrap();
+        next_event(&mut received, |e| matches!(e, Event::ChannelClosed { .. })).await;
+        receiver
+            .contacts()
+            .lock()
+            .unwrap()
+            .insert(theirs.desc().clone().into());
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::AesExchange], false, AutoAccept::Contacts);
+        let key = PKey::from_rsa(openssl::rsa::Rsa::generate(1024).unwrap()).unwrap();
+        receiver
+            .listen_through_relay(
+                relay_addr.clone(),
+                key,
+                vec![theirs.desc().our_key().clone()],
+                policy,
+            )
+            .unwrap();
+        registered(2).await.unwrap();
+        sender.new_channel_through_relay(relay_addr, ours.desc().clone());
+        next_event(&mut sent, |e| matches!(e, Event::ChannelOpened { .. })).await;
+        next_event(&mut received, |e| matches!(e, Event::ChannelOpened { .. })).await;
+        assert_eq!(theirs.messages().lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_contact_keys_at_relay_are_for_contacts() {
+        let relay = crate::Relay::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr: HostAddr = listener.local_addr().unwrap().into();
+        tokio::spawn({
+            let relay = relay.clone();
+            async move { relay.serve(listener, |_| {}).await }
+        });
+        let new_key = || PKey::from_rsa(openssl::rsa::Rsa::generate(1024).unwrap()).unwrap();
+        let public = |key: &PKey<Private>| {
+            PKey::public_key_from_pem(&key.public_key_to_pem().unwrap()).unwrap()
+        };
+        let desc = |our_key: &PKey<Private>, their_key: &PKey<Private>| {
+            ChannelDesc::new(
+                "contact".to_string(),
+                relay_addr.clone(),
+                our_key.clone(),
+                public(their_key),
+            )
+        };
+
+        // the receiver knows the sender, and another contact, under keys of
+        // their own
+        let (ours, theirs, other) = (new_key(), new_key(), new_key());
+        let mut receiver = AsyncGrapevineApp::new();
+        let mut received = receiver.subscribe();
+        receiver
+            .contacts()
+            .lock()
+            .unwrap()
+            .insert(desc(&theirs, &ours).into());
+        let other_contact = new_key();
+        receiver
+            .contacts()
+            .lock()
+            .unwrap()
+            .insert(desc(&other, &other_contact).into());
+        let policy = ListenerPolicy::new(
+            vec![ProtocolPath::RsaExchange, ProtocolPath::AesExchange],
+            true,
+            AutoAccept::Everyone,
+        );
+        receiver
+            .listen_through_relay(relay_addr.clone(), new_key(), vec![theirs.clone()], policy)
+            .unwrap();
+        let registered = || async {
+            timeout(Duration::from_secs(5), async {
+                while relay.waiting() != 1 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap()
+        };
+        registered().await;
+        let mut sender = AsyncGrapevineApp::new();
+        let mut sent = sender.subscribe();
+        let mut rejected = async || {
+            next_event(&mut sent, |e| matches!(e, Event::ConnectionFailed { .. })).await;
+            let rejected = next_event(&mut received, |e| {
+                matches!(e, Event::ConnectionRejected { .. })
+            })
+            .await;
+            assert!(matches!(
+                rejected,
+                Event::ConnectionRejected {
+                    reason: Rejection::WrongKey,
+                    ..
+                }
+            ));
+        };
+
+        // strangers can't exchange keys under the key meant for a contact
+        let id = Fingerprint::of(&theirs);
+        sender.new_rsa_channel_through_relay(relay_addr.clone(), id, None);
+        rejected().await;
+        registered().await;
+
+        // nor can another contact reach us under it
+        sender.new_channel_through_relay(relay_addr.clone(), desc(&other_contact, &theirs));
+        rejected().await;
+        registered().await;
+
+        // only the contact it's meant for
+        sender.new_channel_through_relay(relay_addr.clone(), desc(&ours, &theirs));
+        next_event(&mut sent, |e| matches!(e, Event::ChannelOpened { .. })).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_discovery_suggests_addresses() {
+        let new_key = || PKey::from_rsa(openssl::rsa::Rsa::generate(1024).unwrap()).unwrap();
+        let (our_key, their_key) = (new_key(), new_key());
+        let their_public =
+            PKey::public_key_from_pem(&their_key.public_key_to_pem().unwrap()).unwrap();
+
+        // we know them under an address they have long left
+        let mut ours = AsyncGrapevineApp::<TcpStream>::new();
+        let mut events = ours.subscribe();
+        ours.contacts().lock().unwrap().insert(
+            ChannelDesc::new(
+                "them".to_string(),
+                "10.255.0.1:7777".parse().unwrap(),
+                our_key,
+                their_public,
+            )
+            .into(),
+        );
+        ours.start_discovery(None, &[]).unwrap();
+
+        // they claim a key they don't hold as well
+        let mut theirs = AsyncGrapevineApp::<TcpStream>::new();
+        let key = new_key();
+        let id = Fingerprint::of(&key);
+        let unproven = Fingerprint::of(&new_key());
+        let announcement = Announcement::new(
+            "discovery test".to_string(),
+            7778,
+            id,
+            vec![Fingerprint::of(&their_key), unproven],
+        );
+        theirs
+            .start_discovery(Some(announcement), &[key, their_key.clone()])
+            .unwrap();
+
+        let Event::PeerDiscovered { peer } = next_event(
+            &mut events,
+            |e| matches!(e, Event::PeerDiscovered { peer } if peer.id() == &id),
+        )
+        .await
+        else {
+            unreachable!()
+        };
+        assert_eq!(peer.name(), "discovery test");
+        assert_eq!(peer.addr().port(), 7778);
+        assert!(ours.discovered().contains(&peer));
+        assert_eq!(peer.known_as(), &[Fingerprint::of(&their_key)]);
+        {
+            // the address is only suggested, the contact is left alone
+            let contacts = ours.contacts().lock().unwrap();
+            let contact = contacts.get(&Fingerprint::of(&their_key)).unwrap();
+            assert_eq!(contact.desc().last_addr().port(), 7777);
+            assert_eq!(contact.addresses().len(), 1);
+        }
+
+        // their own announcements aren't discoveries
+        assert!(!theirs.discovered().iter().any(|peer| peer.id() == &id));
+        theirs.stop_discovery();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_discovery_refreshes_contacts() {
+        let new_key = || PKey::from_rsa(openssl::rsa::Rsa::generate(1024).unwrap()).unwrap();
+        let public = |key: &PKey<Private>| {
+            PKey::public_key_from_pem(&key.public_key_to_pem().unwrap()).unwrap()
+        };
+        let (our_key, their_key) = (new_key(), new_key());
+        let stale: HostAddr = "10.255.0.1:7777".parse().unwrap();
+
+        // they know us, and announce the key we have for them
+        let mut theirs = AsyncGrapevineApp::<TcpStream>::new();
+        theirs.contacts().lock().unwrap().insert(
+            ChannelDesc::new(
+                "us".to_string(),
+                stale.clone(),
+                their_key.clone(),
+                public(&our_key),
+            )
+            .into(),
+        );
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::AesExchange], false, AutoAccept::Contacts);
+        let port = theirs
+            .add_listener(ListenerConfig::new("0.0.0.0:0".parse().unwrap(), policy))
+            .await
+            .unwrap()
+            .port();
+        let key = new_key();
+        let id = Fingerprint::of(&key);
+        let announcement = Announcement::new(
+            "refresh test".to_string(),
+            port,
+            id,
+            vec![Fingerprint::of(&their_key)],
+        );
+        theirs
+            .start_discovery(Some(announcement), &[key, their_key.clone()])
+            .unwrap();
+
+        // we know them under an address they have long left
+        let mut ours = AsyncGrapevineApp::<TcpStream>::new();
+        let mut events = ours.subscribe();
+        let desc = ChannelDesc::new("them".to_string(), stale, our_key, public(&their_key));
+        ours.contacts().lock().unwrap().insert(desc.clone().into());
+        ours.start_discovery(None, &[]).unwrap();
+        let Event::PeerDiscovered { peer } = next_event(
+            &mut events,
+            |e| matches!(e, Event::PeerDiscovered { peer } if peer.id() == &id),
+        )
+        .await
+        else {
+            unreachable!()
+        };
+
+        // the handshake proves they are where they announced themselves
+        ours.new_channel_from_desc(peer.addr().clone(), desc);
+        next_event(&mut events, |e| matches!(e, Event::ChannelOpened { .. })).await;
+        {
+            let contacts = ours.contacts().lock().unwrap();
+            let contact = contacts.get(&Fingerprint::of(&their_key)).unwrap();
+            assert_eq!(contact.desc().last_addr(), peer.addr());
+            assert_eq!(contact.addresses().len(), 2);
+        }
+        theirs.stop_discovery();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_calls() {
+        let (sender, mut receiver, _connector) = connected_pair().await;
+        receiver.add_method("build_status", |_, build: u32| async move {
+            Ok::<_, String>((build, build.is_multiple_of(2)))
+        });
+        receiver.add_method("broken", |_, ()| async {
+            Err::<(), _>("on fire".to_string())
+        });
+        // tells when the handler is gone, by dropping the sender
+        let (handled_tx, handled) = tokio::sync::oneshot::channel::<()>();
+        let handled_tx = Mutex::new(Some(handled_tx));
+        receiver.add_method("slow", move |_, ()| {
+            let handled_tx = handled_tx.lock().unwrap().take();
+            async move {
+                let _handled_tx = handled_tx;
+                std::future::pending::<Result<(), String>>().await
+            }
+        });
+
+        let ours = loop {
+            if let Some(channel) = sender.channels().lock().unwrap().first() {
+                break channel.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let wait = Duration::from_secs(5);
+        let status: (u32, bool) = sender
+            .call(&ours, "build_status", &42u32, wait)
+            .await
+            .unwrap();
+        assert_eq!(status, (42, true));
+
+        let result = sender.call::<_, ()>(&ours, "broken", &(), wait).await;
+        assert!(matches!(
+            result,
+            Err(CallError::Remote(RemoteError::Failed(e))) if e == "on fire"
+        ));
+        let result = sender.call::<_, ()>(&ours, "missing", &(), wait).await;
+        assert!(matches!(
+            result,
+            Err(CallError::Remote(RemoteError::UnknownMethod(_)))
+        ));
+        let result = sender.call::<_, ()>(&ours, "build_status", &(), wait).await;
+        assert!(matches!(
+            result,
+            Err(CallError::Remote(RemoteError::InvalidParams(_)))
+        ));
+
+        // timing out cancels the handler on the other end
+        let result = sender
+            .call::<_, ()>(&ours, "slow", &(), Duration::from_millis(100))
+            .await;
+        assert!(matches!(result, Err(CallError::TimedOut)));
+        assert!(timeout(wait, handled).await.unwrap().is_err());
+        // payloads aren't part of the conversation
+        assert!(ours.messages().lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_checkpoints_are_accepted() {
+        let mut receiver = AsyncGrapevineApp::<MemoryTransport>::default();
+        let mut received = receiver.subscribe();
+        let (listener, connector) = MemoryListener::new();
+        let policy =
+            ListenerPolicy::new(vec![ProtocolPath::RsaExchange], true, AutoAccept::Everyone);
+        receiver.add_acceptor(listener, policy).unwrap();
+
+        let mut sender = AsyncGrapevineApp::<MemoryTransport>::default();
+        sender.set_checkpoints(Some(1));
+        sender.open_rsa_channel(connector.connect().unwrap(), None);
+        let ours = loop {
+            if let Some(channel) = sender.channels().lock().unwrap().first() {
+                break channel.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        // every message is followed by a checkpoint
+        for content in ["first", "second"] {
+            let handle = ours.send(Message::new(content.to_string())).await.unwrap();
+            handle.sent().await.unwrap();
+            let event = next_event(&mut received, |e| {
+                matches!(e, Event::MessageReceived { .. })
+            })
+            .await;
+            let Event::MessageReceived { message, channel } = event else {
+                unreachable!()
+            };
+            assert_eq!(message.content(), content);
+            assert!(matches!(channel.state(), ChannelState::Open));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_outgoing_connection_can_be_cancelled() {
+        let mut receiver = AsyncGrapevineApp::<MemoryTransport>::default();
+        let (listener, connector) = MemoryListener::new();
+        let policy = ListenerPolicy::new(vec![ProtocolPath::RsaExchange], true, AutoAccept::Never);
+        receiver.add_acceptor(listener, policy).unwrap();
+
+        let mut sender = AsyncGrapevineApp::<MemoryTransport>::default();
+        let attempt =
+            sender.open_rsa_channel(connector.connect().unwrap(), Some("receiver".to_string()));
+        assert!(Arc::ptr_eq(
+            &attempt,
+            &sender.connecting().lock().unwrap()[0]
+        ));
+        assert_eq!(attempt.name(), "receiver");
+
+        // nobody accepts, so the connection stays in progress
+        timeout(Duration::from_secs(5), async {
+            while !matches!(attempt.state(), ChannelState::AwaitingAccept) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        attempt.cancel();
+        timeout(Duration::from_secs(5), async {
+            while !sender.connecting().lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(matches!(
+            attempt.state(),
+            ChannelState::Closed(CloseReason::Closed)
+        ));
+        assert!(sender.channels().lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_attempt_knows_its_channel() {
+        let (_, _receiver, connector) = connected_pair().await;
+        let mut sender = AsyncGrapevineApp::<MemoryTransport>::default();
+        let name = Some("receiver".to_string());
+        let first = sender.open_rsa_channel(connector.connect().unwrap(), name.clone());
+        let second = sender.open_rsa_channel(connector.connect().unwrap(), name);
+
+        timeout(Duration::from_secs(5), async {
+            while sender.channels().lock().unwrap().len() < 2 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        // both go by the same name, yet each attempt opened just one
+        let channels = sender.channels().lock().unwrap().clone();
+        let opened = |attempt: &ConnectionAttempt| {
+            channels
+                .iter()
+                .filter(|channel| attempt.opened(channel))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let (first, second) = (opened(&first), opened(&second));
+        assert_eq!((first.len(), second.len()), (1, 1));
+        assert!(!Arc::ptr_eq(&first[0], &second[0]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pending_connection_expires() {
+        let mut receiver = AsyncGrapevineApp::<MemoryTransport>::default();
+        let mut events = receiver.subscribe();
+        let (listener, connector) = MemoryListener::new();
+        let policy = ListenerPolicy::new(vec![ProtocolPath::RsaExchange], true, AutoAccept::Never);
+        receiver.add_acceptor(listener, policy).unwrap();
+
+        let mut sender = AsyncGrapevineApp::<MemoryTransport>::default();
+        sender.open_rsa_channel(connector.connect().unwrap(), None);
+        next_event(&mut events, |e| {
+            matches!(e, Event::PendingConnectionArrived { .. })
+        })
+        .await;
+        assert_eq!(receiver.pending_connections().lock().unwrap().len(), 1);
+
+        // nobody polls, the connection expires on its own
+        sleep(crate::listener::PENDING_TIMEOUT).await;
+        next_event(&mut events, |e| {
+            matches!(e, Event::PendingConnectionExpired { .. })
+        })
+        .await;
+        assert!(receiver.pending_connections().lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_malformed_aes_handshake_fails() {
+        use crate::protocol::{IntoPacket, Packet};
+
+        /// Laid out like the AES handshake, with a key nobody encrypted
+        #[derive(Serialize)]
+        struct Malformed {
+            encrypted_aes_key: Vec<u8>,
+        }
+
+        let new_key = || PKey::from_rsa(openssl::rsa::Rsa::generate(1024).unwrap()).unwrap();
+        let (our_key, their_key) = (new_key(), new_key());
+        let public = |key: &PKey<Private>| {
+            PKey::public_key_from_pem(&key.public_key_to_pem().unwrap()).unwrap()
+        };
+        let desc = ChannelDesc::new(
+            "them".to_string(),
+            "127.0.0.1:7777".parse().unwrap(),
+            our_key,
+            public(&their_key),
+        );
+
+        let mut app = AsyncGrapevineApp::<MemoryTransport>::default();
+        let (ours, mut theirs) = MemoryTransport::pair();
+        let attempt = app.open_channel_from_desc(ours, desc);
+
+        Handshake::from_async_reader(&mut theirs).await.unwrap();
+        Packet::from_async_reader(&mut theirs).await.unwrap();
+        Malformed {
+            encrypted_aes_key: vec![7; 3],
+        }
+        .into_packet(&their_key)
+        .unwrap()
+        .to_async_writer(&mut theirs)
+        .await
+        .unwrap();
+
+        timeout(Duration::from_secs(5), async {
+            while !matches!(attempt.state(), ChannelState::Failed(_)) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_failed_connection_is_reported() {
+        // nobody listens on the port, once the listener is gone
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut app = AsyncGrapevineApp::default();
+        let mut events = app.subscribe();
+        app.set_timeouts(Timeouts::new(
+            Duration::from_millis(500),
+            Duration::from_millis(500),
+        ));
+
+        // the call returns right away, the connection is made in the background
+        let attempt = app.new_rsa_channel(addr.into(), None);
+        let failed = next_event(&mut events, |e| matches!(e, Event::ConnectionFailed { .. })).await;
+        let Event::ConnectionFailed {
+            attempt: theirs, ..
+        } = failed
+        else {
+            unreachable!()
+        };
+        assert!(Arc::ptr_eq(&attempt, &theirs));
+        assert!(matches!(attempt.state(), ChannelState::Failed(_)));
+        timeout(Duration::from_secs(5), async {
+            while !app.connecting().lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(app.channels().lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shutdown_says_goodbye() {
+        let (mut sender, mut receiver, _connector) = connected_pair().await;
+        let mut received = receiver.subscribe();
+        timeout(Duration::from_secs(5), async {
+            while sender.channels().lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        sender.shutdown(Duration::from_secs(5)).await.unwrap();
+        let ours = sender.channels().lock().unwrap()[0].clone();
+        assert!(matches!(
+            ours.state(),
+            ChannelState::Closed(CloseReason::Closed)
+        ));
+
+        let closed = next_event(&mut received, |e| matches!(e, Event::ChannelClosed { .. })).await;
+        let Event::ChannelClosed {
+            reason: CloseReason::PeerClosed(Some(reason)),
+            ..
+        } = closed
+        else {
+            panic!("expected a goodbye");
+        };
+        assert_eq!(reason, SHUTDOWN_REASON);
+    }
+}
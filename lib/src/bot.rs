@@ -0,0 +1,450 @@
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    mem,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use tokio::net::TcpStream;
+
+use super::{
+    address::HostAddr,
+    async_app::AsyncGrapevineApp,
+    channel::Channel,
+    contacts::{Contact, ContactBook},
+    events::{Event, EventReceiver},
+    listener::{AutoAccept, ListenerConfig, ListenerPolicy, PendingConnection},
+    protocol::{Message, ProtocolPath},
+    transport::{Acceptor, Endpoint, Transport},
+};
+
+/// What starts a command, within a message
+const COMMAND_PREFIX: char = '/';
+
+/// Handles a message, possibly returning a reply
+type Handler<T> = Box<dyn FnMut(&Context<T>) -> Option<String> + Send>;
+
+/// Decides whether to accept a pending connection
+type Predicate<T> = Box<dyn FnMut(&PendingConnection<T>) -> bool + Send>;
+
+struct Command<T: Transport> {
+    description: String,
+    handler: Handler<T>,
+}
+
+/// Which incoming connections a [Bot] accepts. The others get rejected.
+pub enum AcceptPolicy<T: Transport = TcpStream> {
+    Never,
+    /// Saved contacts, reconnecting with the keys they share with the bot
+    Contacts,
+    /// Anybody, exchanging keys with the unknown ones
+    Everyone,
+    /// Whoever the predicate approves of. Only key exchanges and saved
+    /// contacts can be accepted.
+    Custom(Predicate<T>),
+}
+
+/// A received message, along with where it came from
+pub struct Context<T: Transport = TcpStream> {
+    channel: Arc<Channel<T>>,
+    message: Message,
+    /// The command name and its arguments, if the message is a command
+    command: Option<(String, String)>,
+    /// Replies queued by the handler, sent once it returns
+    replies: Mutex<Vec<String>>,
+}
+
+impl<T: Transport> Context<T> {
+    fn new(channel: Arc<Channel<T>>, message: Message) -> Self {
+        let command = message
+            .content()
+            .trim()
+            .strip_prefix(COMMAND_PREFIX)
+            .filter(|command| !command.is_empty())
+            .map(|command| {
+                let (name, args) = command
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((command, ""));
+                (name.to_string(), args.trim().to_string())
+            });
+        Self {
+            channel,
+            message,
+            command,
+            replies: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Gets the channel the message came from
+    pub fn channel(&self) -> &Arc<Channel<T>> {
+        &self.channel
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// Gets the name of the command, without the prefix
+    pub fn command(&self) -> Option<&str> {
+        self.command.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    /// Gets whatever follows the command name, or the whole message if it
+    /// isn't a command
+    pub fn args(&self) -> &str {
+        match &self.command {
+            Some((_, args)) => args,
+            None => self.message.content(),
+        }
+    }
+
+    /// Sends a message back where this one came from, besides what the
+    /// handler returns. The replies go out in order once the handler
+    /// returns, ahead of whatever it returns.
+    pub fn reply(&self, content: impl Into<String>) {
+        self.replies.lock().unwrap().push(content.into());
+    }
+}
+
+/// High level wrapper around an [AsyncGrapevineApp], for automated peers.
+///
+/// Messages starting with `/` are dispatched to the registered commands,
+/// the rest to the [Self::on_message] handler. Whatever a handler returns
+/// is sent back as a reply. `/help` lists the commands, unless overridden.
+///
+/// The bot's identity is the keys it shares with each peer. With
+/// [Self::with_identity], every peer the bot talks to is saved as a contact,
+/// so that they are recognized after a restart.
+pub struct Bot<T: Transport = TcpStream> {
+    app: AsyncGrapevineApp<T>,
+    events: EventReceiver<T>,
+    commands: BTreeMap<String, Command<T>>,
+    fallback: Option<Handler<T>>,
+    accept: AcceptPolicy<T>,
+    /// Where the contacts are kept
+    identity: Option<PathBuf>,
+}
+
+/// Creates the bot, along with its app. Needs to be called from within a
+/// tokio runtime.
+impl<T: Transport> Default for Bot<T> {
+    fn default() -> Self {
+        let mut app = AsyncGrapevineApp::default();
+        let events = app.subscribe();
+        Self {
+            app,
+            events,
+            commands: BTreeMap::new(),
+            fallback: None,
+            accept: AcceptPolicy::Never,
+            identity: None,
+        }
+    }
+}
+
+impl Bot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Listens for connections on the given address, accepting them
+    /// according to the [AcceptPolicy]
+    pub async fn listen(&mut self, addr: HostAddr) -> io::Result<SocketAddr> {
+        let policy = self.listener_policy();
+        self.app
+            .add_listener(ListenerConfig::new(addr, policy))
+            .await
+    }
+}
+
+impl<T: Transport> Bot<T> {
+    /// Registers a command, invoked by messages starting with `/name`
+    pub fn with_command(
+        mut self,
+        name: impl Into<String>,
+        description: impl Into<String>,
+        handler: impl FnMut(&Context<T>) -> Option<String> + Send + 'static,
+    ) -> Self {
+        self.commands.insert(
+            name.into(),
+            Command {
+                description: description.into(),
+                handler: Box::new(handler),
+            },
+        );
+        self
+    }
+
+    /// Sets the handler of messages that aren't commands. They are ignored
+    /// otherwise.
+    pub fn on_message(
+        mut self,
+        handler: impl FnMut(&Context<T>) -> Option<String> + Send + 'static,
+    ) -> Self {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Sets who may connect. Only affects listeners added afterwards.
+    pub fn with_accept(mut self, accept: AcceptPolicy<T>) -> Self {
+        self.accept = accept;
+        self
+    }
+
+    /// Keeps the contacts at the given path, loading them if the file exists
+    pub fn with_identity(mut self, path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        match fs::read(&path) {
+            Ok(data) => self.app.set_contacts(
+                serde_json::from_slice(&data)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            ),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        self.identity = Some(path);
+        Ok(self)
+    }
+
+    /// Gets the app the bot runs, for anything not covered by the bot
+    pub fn app(&self) -> &AsyncGrapevineApp<T> {
+        &self.app
+    }
+
+    pub fn app_mut(&mut self) -> &mut AsyncGrapevineApp<T> {
+        &mut self.app
+    }
+
+    /// Accepts connections from the acceptor, according to the
+    /// [AcceptPolicy]
+    pub fn add_acceptor<A: Acceptor<Transport = T>>(
+        &mut self,
+        acceptor: A,
+    ) -> io::Result<Endpoint> {
+        let policy = self.listener_policy();
+        self.app.add_acceptor(acceptor, policy)
+    }
+
+    /// Sends the message on every open channel
+    pub async fn broadcast(&self, content: &str) {
+        let channels: Vec<_> = self
+            .app
+            .channels()
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|channel| !channel.state().is_finished())
+            .cloned()
+            .collect();
+        for channel in channels {
+            // the channel may be closing in the meantime
+            let _ = channel.send(Message::new(content.to_string())).await;
+        }
+    }
+
+    fn listener_policy(&self) -> ListenerPolicy {
+        let auto_accept = match self.accept {
+            AcceptPolicy::Contacts => AutoAccept::Contacts,
+            AcceptPolicy::Everyone => AutoAccept::Everyone,
+            AcceptPolicy::Never | AcceptPolicy::Custom(_) => AutoAccept::Never,
+        };
+        ListenerPolicy::new(
+            vec![ProtocolPath::RsaExchange, ProtocolPath::AesExchange],
+            !matches!(self.accept, AcceptPolicy::Contacts),
+            auto_accept,
+        )
+    }
+
+    /// Handles events until the app stops, or the identity can't be saved
+    pub async fn run(&mut self) -> io::Result<()> {
+        while let Some(event) = self.events.recv().await {
+            self.handle(event).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle(&mut self, event: Event<T>) -> io::Result<()> {
+        match event {
+            Event::MessageReceived { channel, message } => {
+                let context = Context::new(channel, message);
+                let returned = self.dispatch(&context);
+                let mut replies = mem::take(&mut *context.replies.lock().unwrap());
+                replies.extend(returned);
+                for reply in replies {
+                    // the peer may have left in the meantime
+                    let _ = context.channel.send(Message::new(reply)).await;
+                }
+            }
+            Event::ChannelOpened { channel } => {
+                if let Some(path) = &self.identity {
+                    let mut contacts = self.app.contacts().lock().unwrap();
+                    if contacts.get(channel.fingerprint()).is_none() {
+                        contacts.insert(Contact::from(channel.desc().clone()));
+                        save_contacts(&contacts, path)?;
+                    }
+                }
+            }
+            Event::PendingConnectionArrived { .. } => self.settle_pending(),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, context: &Context<T>) -> Option<String> {
+        let Some(name) = context.command() else {
+            return self.fallback.as_mut().and_then(|handler| handler(context));
+        };
+        match self.commands.get_mut(name) {
+            Some(command) => (command.handler)(context),
+            None if name == "help" => Some(self.help()),
+            None => Some(format!(
+                "unknown command {}{}, see {}help",
+                COMMAND_PREFIX, name, COMMAND_PREFIX
+            )),
+        }
+    }
+
+    fn help(&self) -> String {
+        self.commands
+            .iter()
+            .map(|(name, command)| format!("{}{} - {}", COMMAND_PREFIX, name, command.description))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Accepts or rejects whatever the listeners left waiting
+    fn settle_pending(&mut self) {
+        for pending in self.app.inspect_pending() {
+            let accepted = match &mut self.accept {
+                AcceptPolicy::Custom(predicate) => predicate(&pending),
+                _ => false,
+            };
+            match pending {
+                PendingConnection::Rsa(rsa) if accepted => {
+                    self.app.add_rsa_channel(rsa, None);
+                }
+                PendingConnection::Aes(aes) if accepted && aes.contact().is_some() => {
+                    self.app.add_known_aes_channel(aes);
+                }
+                pending => pending.reject(),
+            }
+        }
+    }
+}
+
+/// Replaces the file at once, so that a crash never leaves half of it.
+/// The file holds our private keys, so only the user can read it.
+fn save_contacts(contacts: &ContactBook, path: &Path) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    // a temporary file left behind by a crash may be readable by others
+    let _ = fs::remove_file(&temporary);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options
+        .open(&temporary)?
+        .write_all(&serde_json::to_vec(contacts)?)?;
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, time::Duration};
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::transport::{MemoryListener, MemoryTransport};
+
+    async fn next_message(events: &mut EventReceiver<MemoryTransport>) -> String {
+        timeout(Duration::from_secs(5), async {
+            loop {
+                if let Event::MessageReceived { message, .. } = events.recv().await.unwrap() {
+                    return message.content().clone();
+                }
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_bot_answers_commands() {
+        let identity = env::temp_dir().join(format!("grapevine-bot-{}.json", std::process::id()));
+        let (listener, connector) = MemoryListener::new();
+        let mut bot = Bot::<MemoryTransport>::default()
+            .with_command("status", "how the bot is doing", |_| {
+                Some("all good".to_string())
+            })
+            .with_command("echo", "repeats after you", |context| {
+                Some(context.args().to_string())
+            })
+            .with_command("count", "counts to three", |context| {
+                context.reply("one");
+                context.reply("two");
+                Some("three".to_string())
+            })
+            .with_accept(AcceptPolicy::Everyone)
+            .with_identity(&identity)
+            .unwrap();
+        bot.add_acceptor(listener).unwrap();
+        let running = tokio::spawn(async move { bot.run().await });
+
+        let mut user = AsyncGrapevineApp::<MemoryTransport>::default();
+        let mut events = user.subscribe();
+        user.open_rsa_channel(connector.connect().unwrap(), Some("bot".to_string()));
+        let channel = timeout(Duration::from_secs(5), async {
+            loop {
+                if let Event::ChannelOpened { channel } = events.recv().await.unwrap() {
+                    return channel;
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        channel
+            .send(Message::new("/status".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(next_message(&mut events).await, "all good");
+        channel
+            .send(Message::new("/echo hello there".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(next_message(&mut events).await, "hello there");
+        channel
+            .send(Message::new("/count".to_string()))
+            .await
+            .unwrap();
+        for expected in ["one", "two", "three"] {
+            assert_eq!(next_message(&mut events).await, expected);
+        }
+        channel
+            .send(Message::new("/nope".to_string()))
+            .await
+            .unwrap();
+        assert!(
+            next_message(&mut events)
+                .await
+                .starts_with("unknown command")
+        );
+        channel
+            .send(Message::new("/help".to_string()))
+            .await
+            .unwrap();
+        assert!(next_message(&mut events).await.contains("/status - how"));
+
+        // the bot remembers whoever it talked to
+        let saved: ContactBook = serde_json::from_slice(&fs::read(&identity).unwrap()).unwrap();
+        fs::remove_file(&identity).unwrap();
+        assert_eq!(saved.len(), 1);
+        running.abort();
+    }
+}
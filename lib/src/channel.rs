@@ -1,36 +1,55 @@
-use std::{
-    io,
-    net::{Shutdown, SocketAddr, TcpStream},
-    ops::Deref,
-    sync::Mutex,
-};
+use std::io;
+#[cfg(feature = "async")]
+use std::sync::Mutex;
 
+#[cfg(feature = "async")]
 use chrono::Utc;
 use derive_more::{Display, Error, From};
+#[cfg(feature = "async")]
+use openssl::rsa::Rsa;
 use openssl::{
     error::ErrorStack,
     pkey::{PKey, Private, Public},
-    rsa::Rsa,
 };
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "async")]
+use tokio::{
+    net::TcpStream,
+    runtime::Handle,
+    sync::{mpsc, watch},
+};
 
+#[cfg(feature = "async")]
 use super::{
     Shared,
-    events::HandleMessage,
+    app::block_on,
+    events::{CloseReason, HandleMessage},
+    outbox::{
+        Backpressure, Outbox, Outgoing, QueueConfig, SendError, SendHandle, SendStatus,
+        WriterSession, write_queue,
+    },
     protocol::{
-        AesHandshake, AesKey, FromPacket, IntoPacket, Message, Packet, RsaHandshake, new_aes_key,
+        AesHandshake, AesKey, Frame, FromPacket, IntoPacket, Message, Opener, Packet, Payload,
+        RsaHandshake, Sealer, Transcript, new_aes_key,
     },
+    state::ChannelState,
+    transport::{Endpoint, Transport},
 };
+use super::{address::HostAddr, protocol::Fingerprint};
 
-const RSA_KEY_SIZE: u32 = 2048;
+#[cfg(feature = "async")]
+pub(crate) const RSA_KEY_SIZE: u32 = 2048;
 
 /// An error that has occured during [Packet] exchange
 #[derive(Debug, Display, From, Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum ProtocolError {
     IoError(io::Error),
     VerificationError,
     SerializationError(bitcode::Error),
     OpenSSLError(ErrorStack),
+    /// The other party's key is blocked
+    Blocked,
 }
 
 fn serialize_private_key<S: Serializer>(
@@ -83,7 +102,8 @@ fn deserialize_public_key<'de, D: Deserializer<'de>>(de: D) -> Result<PKey<Publi
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ChannelDesc {
     name: String,
-    last_addr: SocketAddr,
+    /// The address as originally entered, which may be a hostname
+    last_addr: HostAddr,
     /// Our key for signing and AES key decryption
     #[serde(
         serialize_with = "serialize_private_key",
@@ -99,13 +119,28 @@ pub struct ChannelDesc {
 }
 
 impl ChannelDesc {
+    /// Creates a new description out of previously exchanged keys
+    pub fn new(
+        name: String,
+        last_addr: HostAddr,
+        our_rsa_private_key: PKey<Private>,
+        their_rsa_public_key: PKey<Public>,
+    ) -> Self {
+        Self {
+            name,
+            last_addr,
+            our_rsa_private_key,
+            their_rsa_public_key,
+        }
+    }
+
     /// Get the name of the channel
     pub fn name(&self) -> &str {
         self.name.as_str()
     }
 
     /// Get the address, which was last assigned to the channel
-    pub fn last_addr(&self) -> &SocketAddr {
+    pub fn last_addr(&self) -> &HostAddr {
         &self.last_addr
     }
 
@@ -115,31 +150,95 @@ impl ChannelDesc {
     }
 
     /// Change the address of the channel
-    pub fn change_addr(&mut self, addr: SocketAddr) {
+    pub fn change_addr(&mut self, addr: HostAddr) {
         self.last_addr = addr;
     }
+
+    /// Get the [Fingerprint] of the other party's public key, which
+    /// identifies them regardless of the name or address
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&self.their_rsa_public_key)
+    }
+
+    /// Get the [Fingerprint] of our public key, which the other party
+    /// knows us by
+    pub fn our_fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&self.our_rsa_private_key)
+    }
+
+    /// Get our key, for signing and decrypting what's meant for us alone
+    pub fn our_key(&self) -> &PKey<Private> {
+        &self.our_rsa_private_key
+    }
+
+    /// Get the other party's key, for checking their signatures and
+    /// encrypting what's meant for them alone
+    #[cfg(feature = "async")]
+    pub(crate) fn their_key(&self) -> &PKey<Public> {
+        &self.their_rsa_public_key
+    }
+
+    /// Creates a description of a channel running over the transport.
+    /// Transports outside of the network leave us nothing to reconnect to.
+    #[cfg(feature = "async")]
+    pub(crate) fn for_transport<T: Transport>(
+        transport: &T,
+        our_rsa_private_key: PKey<Private>,
+        their_rsa_public_key: PKey<Public>,
+        name: Option<String>,
+    ) -> io::Result<Self> {
+        let last_addr = transport.peer()?.host_addr().unwrap_or_default();
+        Ok(Self {
+            name: name.unwrap_or(last_addr.to_string()),
+            last_addr,
+            our_rsa_private_key,
+            their_rsa_public_key,
+        })
+    }
 }
 
-/// A channel for exchanging messages, through a specified stream
-pub struct Channel {
-    stream: Mutex<TcpStream>,
+/// A channel for exchanging messages, through a specified [Transport]
+#[cfg(feature = "async")]
+pub struct Channel<T: Transport = TcpStream> {
+    /// Taken by [Self::listen]
+    reader: Mutex<Option<T::Reader>>,
+    /// Taken by [Self::listen], which hands it to the writer task along
+    /// with the receiving end of the [Self::outbox]
+    writer: Mutex<Option<(T::Writer, mpsc::Receiver<Outgoing>)>>,
+    /// Frames waiting for the writer task
+    outbox: Outbox,
+    /// How many frames we send between signed checkpoints, if any
+    checkpoints: Option<u64>,
+    /// Tells [Self::listen] to stop
+    closing: watch::Sender<bool>,
+    /// What to tell the other party when closing
+    goodbye: Mutex<Option<String>>,
+    /// Where the channel is in its lifecycle
+    state: watch::Sender<ChannelState>,
+    /// The runtime the transport belongs to, used by the blocking methods
+    runtime: Handle,
+    /// Description of the other end of the transport
+    peer: Endpoint,
     messages: Mutex<Vec<Message>>,
     desc: ChannelDesc,
+    /// Cached [ChannelDesc::fingerprint]
+    fingerprint: Fingerprint,
     /// The key for decrypting messages
     their_aes_key: AesKey,
     /// Our key for encrypting messages
     our_aes_key: AesKey,
     /// An abstract listener for new messages
-    message_handler: Shared<dyn HandleMessage>,
+    message_handler: Shared<dyn HandleMessage<T>>,
 }
 
-impl Channel {
+#[cfg(feature = "async")]
+impl<T: Transport> Channel<T> {
     /// Create a new channel on the given stream with a certain name.
     /// First the RSA exchange (handshake) is performed, followed by the AES key exchange.
     ///
     /// ## Args
     ///
-    /// - `stream`: The stream to use for communication
+    /// - `transport`: The transport to use for communication
     /// - `name`: The name of the channel
     /// - `message_handler`: The handler for new messages, which will be notified when a new message is received
     ///
@@ -148,20 +247,53 @@ impl Channel {
     /// A new channel instance, or an error if the channel could not be created.
     /// This may mainly happen if the handshake fails. In case, the verification
     /// of the other party's public key fails, None is returned.
-    pub fn new(
-        mut stream: TcpStream,
+    pub async fn new(
+        transport: T,
         name: Option<String>,
-        message_handler: Shared<dyn HandleMessage>,
+        message_handler: Shared<dyn HandleMessage<T>>,
     ) -> Result<Option<Self>, ProtocolError> {
-        // ok so first we generate a new private key for us
-        let private_rsa_key = PKey::from_rsa(Rsa::generate(RSA_KEY_SIZE)?)?;
+        Self::new_as(transport, None, name, message_handler).await
+    }
+
+    /// [Self::new], sending the other party the given key, rather than a
+    /// new one, if given one
+    pub(crate) async fn new_as(
+        transport: T,
+        our_rsa_private_key: Option<PKey<Private>>,
+        name: Option<String>,
+        message_handler: Shared<dyn HandleMessage<T>>,
+    ) -> Result<Option<Self>, ProtocolError> {
+        let state = watch::Sender::new(ChannelState::AwaitingAccept);
+        Self::new_tracked(transport, our_rsa_private_key, name, message_handler, state).await
+    }
+
+    /// [Self::new_as], reporting the progress through the given state, which
+    /// the channel keeps afterwards
+    pub(crate) async fn new_tracked(
+        mut transport: T,
+        our_rsa_private_key: Option<PKey<Private>>,
+        name: Option<String>,
+        message_handler: Shared<dyn HandleMessage<T>>,
+        state: watch::Sender<ChannelState>,
+    ) -> Result<Option<Self>, ProtocolError> {
+        // ok so first we generate a new private key for us, which takes a while
+        let private_rsa_key = match our_rsa_private_key {
+            Some(key) => key,
+            None => tokio::task::spawn_blocking(|| -> Result<_, ErrorStack> {
+                PKey::from_rsa(Rsa::generate(RSA_KEY_SIZE)?)
+            })
+            .await
+            .map_err(io::Error::other)??,
+        };
 
         // then we send it to the other party
         let our_handshake = RsaHandshake::new(&private_rsa_key).into_packet(&private_rsa_key)?;
-        our_handshake.to_writer(&mut stream)?;
+        our_handshake.to_async_writer(&mut transport).await?;
 
         // then we receive the other party's handshake
-        let their_handshake_packet = Packet::from_reader(&mut stream)?;
+        let their_handshake_packet = Packet::from_async_reader(&mut transport).await?;
+        // they wouldn't have answered, if they didn't accept us
+        state.send_replace(ChannelState::Handshaking);
         let their_handshake = RsaHandshake::from_packet(&their_handshake_packet)?;
         // and get the public key from that handshake
         let their_public_key = their_handshake.public_key();
@@ -170,13 +302,9 @@ impl Channel {
         if !their_handshake_packet.verify(&their_public_key) {
             Ok(None)
         } else {
-            Self::with_keys(
-                stream,
-                private_rsa_key,
-                their_public_key,
-                name,
-                message_handler,
-            )
+            let desc =
+                ChannelDesc::for_transport(&transport, private_rsa_key, their_public_key, name)?;
+            Self::from_desc_tracked(transport, desc, message_handler, state).await
         }
     }
 
@@ -184,7 +312,7 @@ impl Channel {
     ///
     /// ## Args
     ///
-    /// - `stream`: The stream to use for communication
+    /// - `transport`: The transport to use for communication
     /// - `our_rsa_private_key`: Our private RSA key
     /// - `their_rsa_public_key`: The public RSA key of the other party
     /// - `name`: The name of the channel
@@ -193,88 +321,195 @@ impl Channel {
     /// ## Returns
     ///
     /// A new channel, Err if the handshake failed, or None if the verification of the other party's messages failed.
-    pub fn with_keys(
-        stream: TcpStream,
+    pub async fn with_keys(
+        transport: T,
         our_rsa_private_key: PKey<Private>,
         their_rsa_public_key: PKey<Public>,
         name: Option<String>,
-        message_handler: Shared<dyn HandleMessage>,
+        message_handler: Shared<dyn HandleMessage<T>>,
     ) -> Result<Option<Self>, ProtocolError> {
-        let last_addr = stream.peer_addr()?;
-        let desc = ChannelDesc {
-            name: name.unwrap_or(last_addr.to_string()),
-            last_addr,
+        let desc = ChannelDesc::for_transport(
+            &transport,
             our_rsa_private_key,
             their_rsa_public_key,
-        };
-        Self::from_desc(stream, desc, message_handler)
+            name,
+        )?;
+        Self::from_desc(transport, desc, message_handler).await
     }
 
     /// Create a new channel, utilizing a previously saved [ChannelDesc].
     ///
     /// ## Args
     ///
-    /// - `stream`: The TCP stream to use for the channel.
+    /// - `transport`: The transport to use for the channel.
     /// - `desc`: The channel description.
     /// - `message_handler`: The message handler to use for the channel.
     ///
     /// ## Returns
     ///
     /// A new channel, Err if the handshake fails, None if the verification fails.
-    pub fn from_desc(
-        mut stream: TcpStream,
+    pub async fn from_desc(
+        transport: T,
         desc: ChannelDesc,
-        message_handler: Shared<dyn HandleMessage>,
+        message_handler: Shared<dyn HandleMessage<T>>,
+    ) -> Result<Option<Self>, ProtocolError> {
+        let state = watch::Sender::new(ChannelState::AwaitingAccept);
+        Self::from_desc_tracked(transport, desc, message_handler, state).await
+    }
+
+    /// [Self::from_desc], reporting the progress through the given state,
+    /// which the channel keeps afterwards
+    pub(crate) async fn from_desc_tracked(
+        mut transport: T,
+        desc: ChannelDesc,
+        message_handler: Shared<dyn HandleMessage<T>>,
+        state: watch::Sender<ChannelState>,
     ) -> Result<Option<Self>, ProtocolError> {
         let our_aes_key = new_aes_key()?;
 
         let our_aes_handshake = AesHandshake::new(&our_aes_key, &desc.their_rsa_public_key)?;
         our_aes_handshake
             .into_packet(&desc.our_rsa_private_key)?
-            .to_writer(&mut stream)?;
+            .to_async_writer(&mut transport)
+            .await?;
 
-        let their_aes_handshake_packet = Packet::from_reader(&mut stream)?;
+        let their_aes_handshake_packet = Packet::from_async_reader(&mut transport).await?;
+        state.send_replace(ChannelState::Handshaking);
         if !their_aes_handshake_packet.verify(&desc.their_rsa_public_key) {
             return Ok(None);
         }
         let their_aes_key = AesHandshake::from_packet(&their_aes_handshake_packet)?
-            .decrypt_key(&desc.our_rsa_private_key)
-            .unwrap();
+            .decrypt_key(&desc.our_rsa_private_key)?;
 
+        let peer = transport.peer()?;
+        let (reader, writer) = transport.split();
+        let (outbox, queue) = Outbox::new(QueueConfig::default());
+        state.send_replace(ChannelState::Open);
         Ok(Some(Self {
-            stream: Mutex::new(stream),
+            reader: Mutex::new(Some(reader)),
+            writer: Mutex::new(Some((writer, queue))),
+            outbox,
+            checkpoints: None,
+            closing: watch::Sender::new(false),
+            goodbye: Mutex::new(None),
+            state,
+            runtime: Handle::current(),
+            peer,
             messages: Mutex::new(Vec::new()),
+            fingerprint: desc.fingerprint(),
             desc,
             our_aes_key,
             their_aes_key,
-            message_handler: message_handler,
+            message_handler,
         }))
     }
 
     /// Listen for incoming messages on the channel.
-    /// This function will continuously listen for incoming messages until an
-    /// error occurs, so ideally it should be run in a separate thread.
-    pub fn listen(&self) -> Result<(), ProtocolError> {
-        let mut stream = self.stream.lock().unwrap().try_clone()?; // important to avoid deadlocks
+    /// This future will continuously listen for incoming messages until an
+    /// error occurs, or the channel gets closed by either party, so ideally
+    /// it should be spawned as a separate task. Messages sent before are
+    /// written only once listening starts.
+    /// Only one task may listen at a time.
+    ///
+    /// ## Returns
+    ///
+    /// Who closed the channel, and why
+    pub async fn listen(&self) -> Result<CloseReason, ProtocolError> {
+        // reading happens here, while writing gets a task of its own
+        let reader = self
+            .reader
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::AlreadyExists, "already listening"))?;
+        let (writer, queue) = self.writer.lock().unwrap().take().unwrap();
+        let session = WriterSession::new(
+            Sealer::new(self.their_aes_key, &self.our_aes_key)?,
+            self.desc.our_rsa_private_key.clone(),
+            self.checkpoints,
+        )?;
+        let (stop, stopped) = watch::channel(false);
+        let mut writing = self
+            .runtime
+            .spawn(write_queue(writer, queue, stopped, session));
+
+        let receiving = self.receive(reader);
+        tokio::pin!(receiving);
+        tokio::select! {
+            reason = &mut receiving => {
+                stop.send_replace(true);
+                // the writer says its goodbye, or shuts down whatever it's doing
+                let _ = writing.await;
+                reason
+            }
+            written = &mut writing => match written {
+                // a failed writer takes the channel down with it
+                Ok(Err(error)) => Err(io::Error::other(error).into()),
+                // the goodbye is out, what's left is hearing theirs
+                _ => receiving.await,
+            },
+        }
+    }
+
+    /// Reads incoming frames, until the channel closes
+    async fn receive(&self, mut reader: T::Reader) -> Result<CloseReason, ProtocolError> {
+        let mut closing = self.closing.subscribe();
+        let mut opener = Opener::new(self.our_aes_key, &self.their_aes_key)?;
+        // what their checkpoints vouch for
+        let mut transcript = Transcript::new()?;
         loop {
-            let mut packet = Packet::from_reader(&mut stream)?;
-            packet.decrypt(&self.our_aes_key)?;
-            if !packet.verify(&self.desc.their_rsa_public_key) {
+            let packet = tokio::select! {
+                packet = Packet::from_async_reader(&mut reader) => Some(packet?),
+                _ = closing.wait_for(|closing| *closing) => None,
+            };
+            let Some(mut packet) = packet else {
+                let reason = self.goodbye.lock().unwrap().take();
+                // the goodbye goes out after whatever is queued already
+                if let Ok(goodbye) = self
+                    .outbox
+                    .push_with(Frame::Close { reason }, Backpressure::Block)
+                    .await
+                {
+                    // we are closing either way
+                    let _ = goodbye.sent().await;
+                }
+                return Ok(CloseReason::Closed);
+            };
+            if !opener.open(&mut packet)? {
                 return Err(ProtocolError::VerificationError);
             }
 
-            let message = Message::from_packet(&packet)?;
+            let message = match Frame::from_packet(&packet)? {
+                Frame::Message(message) => message,
+                Frame::Close { reason } => return Ok(CloseReason::PeerClosed(reason)),
+                Frame::Checkpoint(checkpoint) => {
+                    if !checkpoint.verify(&transcript, &self.desc.their_rsa_public_key) {
+                        return Err(ProtocolError::VerificationError);
+                    }
+                    continue;
+                }
+                Frame::Payload(payload) => {
+                    transcript.record(packet.data())?;
+                    self.message_handler
+                        .lock()
+                        .unwrap()
+                        .on_payload(&payload, self);
+                    continue;
+                }
+            };
+            // checkpoints vouch for everything but themselves
+            transcript.record(packet.data())?;
 
             if message.timestamp() > &Utc::now() {
                 // if we received a message from the future
                 continue;
             }
 
-            if let Some(last_msg) = self.messages().lock().unwrap().last() {
-                if last_msg.timestamp() > message.timestamp() {
-                    // if we received a message from the past
-                    continue;
-                }
+            if let Some(last_msg) = self.messages().lock().unwrap().last()
+                && last_msg.timestamp() > message.timestamp()
+            {
+                // if we received a message from the past
+                continue;
             }
 
             self.message_handler
@@ -286,13 +521,52 @@ impl Channel {
         }
     }
 
-    /// Send a message to the channel
-    pub fn send_message(&self, message: Message) -> Result<(), ProtocolError> {
-        let mut packet = message.into_packet(&self.desc.our_rsa_private_key)?;
-        packet.encrypt(&self.their_aes_key)?;
-        self.messages.lock().unwrap().push(message);
-        packet.to_writer(&mut self.stream.lock().unwrap().deref())?;
-        Ok(())
+    /// Queues a message for sending. Waits only if the queue is full, and
+    /// the channel is set to [Backpressure::Block].
+    ///
+    /// ## Returns
+    ///
+    /// A handle for following the message, or why it couldn't be queued
+    pub async fn send(&self, message: Message) -> Result<SendHandle, SendError> {
+        let handle = self.outbox.push(Frame::Message(message.clone())).await?;
+        if !matches!(handle.status(), SendStatus::Dropped) {
+            self.messages.lock().unwrap().push(message);
+        }
+        Ok(handle)
+    }
+
+    /// Queues a message for sending, blocking only while the queue is full.
+    /// The blocking counterpart of [Self::send].
+    pub fn send_message(&self, message: Message) -> Result<SendHandle, SendError> {
+        block_on(&self.runtime, self.send(message)).map_err(SendError::Blocking)?
+    }
+
+    /// Queues application defined data for sending, like [Self::send].
+    /// Unlike messages, payloads aren't kept in [Self::messages].
+    pub async fn send_payload(&self, payload: Payload) -> Result<SendHandle, SendError> {
+        self.outbox.push(Frame::Payload(payload)).await
+    }
+
+    /// The blocking counterpart of [Self::send_payload]
+    pub fn send_payload_blocking(&self, payload: Payload) -> Result<SendHandle, SendError> {
+        block_on(&self.runtime, self.send_payload(payload)).map_err(SendError::Blocking)?
+    }
+
+    /// Makes us sign a checkpoint every so many frames we send
+    pub(crate) fn with_checkpoints(mut self, every: Option<u64>) -> Self {
+        self.checkpoints = every;
+        self
+    }
+
+    /// Changes how messages are queued. Meant for freshly created channels,
+    /// as the queue starts out empty.
+    pub(crate) fn with_queue(mut self, config: QueueConfig) -> Self {
+        let (outbox, queue) = Outbox::new(config);
+        if let Some((_, old)) = self.writer.get_mut().unwrap().as_mut() {
+            *old = queue;
+        }
+        self.outbox = outbox;
+        self
     }
 
     /// Get the name of the channel
@@ -305,19 +579,60 @@ impl Channel {
         &self.messages
     }
 
-    /// Closes the channel
+    /// Closes the channel, letting the other party know why.
+    /// See [Self::close].
+    pub fn close_with_reason(&self, reason: String) -> Result<(), io::Error> {
+        *self.goodbye.lock().unwrap() = Some(reason);
+        self.close()
+    }
+
+    /// Closes the channel. [Self::listen] says goodbye to the other party,
+    /// and finishes shortly after.
     pub fn close(&self) -> Result<(), io::Error> {
-        self.stream.lock().unwrap().shutdown(Shutdown::Both)
+        self.state.send_if_modified(|state| {
+            let open = matches!(state, ChannelState::Open);
+            if open {
+                *state = ChannelState::Closing;
+            }
+            open
+        });
+        self.closing.send_replace(true);
+        Ok(())
+    }
+
+    /// Get the current [ChannelState]
+    pub fn state(&self) -> ChannelState {
+        self.state.borrow().clone()
+    }
+
+    /// Subscribes to changes of the [ChannelState]
+    pub fn watch_state(&self) -> watch::Receiver<ChannelState> {
+        self.state.subscribe()
+    }
+
+    /// Records why the channel stopped
+    pub(crate) fn finish(&self, reason: CloseReason) {
+        self.state.send_replace(reason.into());
+    }
+
+    /// Get the description of the other end of the transport
+    pub fn peer(&self) -> &Endpoint {
+        &self.peer
     }
 
     /// Get the description of the channel
     pub fn desc(&self) -> &ChannelDesc {
         &self.desc
     }
-}
 
-impl PartialEq for Channel {
-    fn eq(&self, other: &Self) -> bool {
-        self.desc.name == other.desc.name
+    /// Records the address, under which the other party was reached
+    pub(crate) fn with_addr(mut self, addr: HostAddr) -> Self {
+        self.desc.change_addr(addr);
+        self
+    }
+
+    /// Get the [Fingerprint] of the other party
+    pub fn fingerprint(&self) -> &Fingerprint {
+        &self.fingerprint
     }
 }
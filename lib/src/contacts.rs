@@ -0,0 +1,291 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{address::HostAddr, channel::ChannelDesc, protocol::Fingerprint};
+
+/// A known peer. Wraps the [ChannelDesc] needed to reconnect, alongside
+/// user maintained metadata.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Contact {
+    desc: ChannelDesc,
+    /// Every address this peer was known under, most recent first
+    addresses: Vec<HostAddr>,
+    notes: String,
+    /// Whether the user has confirmed the fingerprint out of band
+    verified: bool,
+    last_seen: Option<DateTime<Utc>>,
+}
+
+impl From<ChannelDesc> for Contact {
+    fn from(desc: ChannelDesc) -> Self {
+        Self {
+            addresses: vec![desc.last_addr().clone()],
+            desc,
+            notes: String::new(),
+            verified: false,
+            last_seen: None,
+        }
+    }
+}
+
+impl Contact {
+    /// Stable identifier of the contact, derived from their public key
+    pub fn id(&self) -> Fingerprint {
+        self.desc.fingerprint()
+    }
+
+    /// Get the name of the contact
+    pub fn name(&self) -> &str {
+        self.desc.name()
+    }
+
+    /// Rename the contact
+    pub fn rename(&mut self, new: String) {
+        self.desc.rename(new);
+    }
+
+    /// Description of the channel, pointed at the most recent address
+    pub fn desc(&self) -> &ChannelDesc {
+        &self.desc
+    }
+
+    /// All known addresses, most recent first
+    pub fn addresses(&self) -> &[HostAddr] {
+        &self.addresses
+    }
+
+    /// Marks the address as the most recent one, adding it if it's new
+    pub fn add_address(&mut self, addr: HostAddr) {
+        self.addresses.retain(|known| known != &addr);
+        self.addresses.insert(0, addr.clone());
+        self.desc.change_addr(addr);
+    }
+
+    /// Forgets an address. The last remaining address can't be removed.
+    pub fn remove_address(&mut self, addr: &HostAddr) {
+        if self.addresses.len() > 1 {
+            self.addresses.retain(|known| known != addr);
+            self.desc.change_addr(self.addresses[0].clone());
+        }
+    }
+
+    /// Get the user notes
+    pub fn notes(&self) -> &str {
+        self.notes.as_str()
+    }
+
+    /// Replace the user notes
+    pub fn set_notes(&mut self, notes: String) {
+        self.notes = notes;
+    }
+
+    /// Check if the fingerprint was verified by the user
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+
+    /// Set the verification status
+    pub fn set_verified(&mut self, verified: bool) {
+        self.verified = verified;
+    }
+
+    /// When was this contact last seen in an open channel
+    pub fn last_seen(&self) -> Option<&DateTime<Utc>> {
+        self.last_seen.as_ref()
+    }
+
+    /// Record that we have just seen the contact
+    pub fn touch(&mut self) {
+        self.last_seen = Some(Utc::now());
+    }
+
+    /// Check if the contact matches a case insensitive search query.
+    /// Names, notes, addresses and fingerprints are all searched.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return true;
+        }
+
+        self.name().to_lowercase().contains(&query)
+            || self.notes.to_lowercase().contains(&query)
+            || self.id().to_string().starts_with(&query)
+            || self
+                .addresses
+                .iter()
+                .any(|addr| addr.to_string().contains(&query))
+    }
+
+    /// Folds another record of the same peer into this one
+    fn merge(&mut self, other: Contact) {
+        for addr in other.addresses.into_iter().rev() {
+            if !self.addresses.contains(&addr) {
+                self.addresses.push(addr);
+            }
+        }
+        if self.notes.is_empty() {
+            self.notes = other.notes;
+        }
+        self.verified |= other.verified;
+        self.last_seen = self.last_seen.max(other.last_seen);
+    }
+}
+
+/// Collection of [Contact]s, deduplicated by their [Fingerprint]
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ContactBook {
+    contacts: Vec<Contact>,
+}
+
+impl From<Vec<ChannelDesc>> for ContactBook {
+    fn from(descs: Vec<ChannelDesc>) -> Self {
+        let mut book = Self::default();
+        for desc in descs {
+            book.insert(desc.into());
+        }
+        book
+    }
+}
+
+impl ContactBook {
+    /// Adds the contact. If a contact with the same [Fingerprint] already
+    /// exists, the two are merged instead.
+    ///
+    /// ## Returns
+    ///
+    /// Whether the contact was new
+    pub fn insert(&mut self, contact: Contact) -> bool {
+        match self.get_mut(&contact.id()) {
+            Some(existing) => {
+                existing.merge(contact);
+                false
+            }
+            None => {
+                self.contacts.push(contact);
+                true
+            }
+        }
+    }
+
+    /// Merges all contacts from another book, returning how many were new
+    pub fn merge(&mut self, other: ContactBook) -> usize {
+        other
+            .contacts
+            .into_iter()
+            .filter(|contact| self.insert(contact.clone()))
+            .count()
+    }
+
+    /// Find a contact by their [Fingerprint]
+    pub fn get(&self, id: &Fingerprint) -> Option<&Contact> {
+        self.contacts.iter().find(|contact| &contact.id() == id)
+    }
+
+    /// Find a contact by their [Fingerprint]
+    pub fn get_mut(&mut self, id: &Fingerprint) -> Option<&mut Contact> {
+        self.contacts.iter_mut().find(|contact| &contact.id() == id)
+    }
+
+    /// Remove a contact by their [Fingerprint]
+    pub fn remove(&mut self, id: &Fingerprint) -> Option<Contact> {
+        let index = self
+            .contacts
+            .iter()
+            .position(|contact| &contact.id() == id)?;
+        Some(self.contacts.remove(index))
+    }
+
+    /// All contacts matching the query, see [Contact::matches]
+    pub fn search<'a>(&'a self, query: &'a str) -> impl Iterator<Item = &'a Contact> {
+        self.contacts
+            .iter()
+            .filter(move |contact| contact.matches(query))
+    }
+
+    /// Iterate over all contacts
+    pub fn iter(&self) -> impl Iterator<Item = &Contact> {
+        self.contacts.iter()
+    }
+
+    /// Number of contacts
+    pub fn len(&self) -> usize {
+        self.contacts.len()
+    }
+
+    /// Check if there are no contacts
+    pub fn is_empty(&self) -> bool {
+        self.contacts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::{pkey::PKey, rsa::Rsa};
+
+    fn desc(name: &str, addr: &str, their: &PKey<openssl::pkey::Private>) -> ChannelDesc {
+        let ours = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let theirs = PKey::public_key_from_pem(&their.public_key_to_pem().unwrap()).unwrap();
+        ChannelDesc::new(name.to_string(), addr.parse().unwrap(), ours, theirs)
+    }
+
+    #[test]
+    fn test_insert_deduplicates_by_key() {
+        let peer = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let mut book = ContactBook::default();
+
+        assert!(book.insert(desc("alice", "127.0.0.1:4000", &peer).into()));
+        assert!(!book.insert(desc("alice (laptop)", "127.0.0.1:4001", &peer).into()));
+
+        assert_eq!(book.len(), 1);
+        let contact = book.iter().next().unwrap();
+        assert_eq!(contact.name(), "alice");
+        assert_eq!(contact.addresses().len(), 2);
+    }
+
+    #[test]
+    fn test_same_name_different_key_is_kept() {
+        let mut book = ContactBook::default();
+        let a = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let b = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+
+        book.insert(desc("bob", "127.0.0.1:4000", &a).into());
+        book.insert(desc("bob", "127.0.0.1:4000", &b).into());
+
+        assert_eq!(book.len(), 2);
+    }
+
+    #[test]
+    fn test_search() {
+        let peer = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let mut book = ContactBook::default();
+        let mut contact: Contact = desc("Carol", "10.0.0.7:4000", &peer).into();
+        contact.set_notes("met at the conference".to_string());
+        let id = contact.id();
+        book.insert(contact);
+
+        assert_eq!(book.search("carol").count(), 1);
+        assert_eq!(book.search("conference").count(), 1);
+        assert_eq!(book.search("10.0.0.7").count(), 1);
+        assert_eq!(book.search(&id.short()).count(), 1);
+        assert_eq!(book.search("dave").count(), 0);
+        assert_eq!(book.search("").count(), 1);
+    }
+
+    #[test]
+    fn test_add_address_moves_to_front() {
+        let peer = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let mut contact: Contact = desc("dave", "127.0.0.1:4000", &peer).into();
+        let roamed: HostAddr = "dave.example.lan:4000".parse().unwrap();
+
+        contact.add_address(roamed.clone());
+        contact.add_address(roamed.clone());
+
+        assert_eq!(
+            contact.addresses(),
+            &[roamed.clone(), "127.0.0.1:4000".parse().unwrap()]
+        );
+        assert_eq!(contact.desc().last_addr(), &roamed);
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+
+use bitcode::{deserialize, serialize};
+use chrono::{DateTime, TimeDelta, Utc};
+use openssl::{
+    error::ErrorStack,
+    pkey::{PKey, Private},
+    rand::rand_bytes,
+};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{net::UdpSocket, time::interval};
+
+use super::{
+    Shared,
+    address::HostAddr,
+    contacts::ContactBook,
+    events::Event,
+    handler::EventHandler,
+    protocol::{Fingerprint, KeyProof},
+    transport::Transport,
+};
+
+/// The multicast group announcements are sent to, and listened for on
+pub const DISCOVERY_ADDR: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 71, 86), 7770);
+
+/// How often we announce ourselves
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a peer is remembered, after its last announcement
+const PEER_TIMEOUT: Duration = Duration::from_secs(20);
+/// How many peers are remembered at once, so that a noisy network can't
+/// make us remember everything
+const MAX_PEERS: usize = 256;
+/// The largest datagram UDP can carry
+const MAX_DATAGRAM: usize = 65_507;
+/// What the announcements are signed as, see [KeyProof]
+const ANNOUNCEMENT_LABEL: &[u8] = b"grapevine announcement";
+
+/// What we tell the local network about ourselves, see
+/// [AsyncGrapevineApp::start_discovery](super::AsyncGrapevineApp::start_discovery)
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Announcement {
+    name: String,
+    /// The port we listen on. The address is the one we announce from.
+    port: u16,
+    /// Who we are to new peers
+    id: Fingerprint,
+    /// The keys our contacts have for us, so that they can tell it's us.
+    /// Whoever listens learns who we know, so it's up to the app to share
+    /// them.
+    known_as: Vec<Fingerprint>,
+}
+
+impl Announcement {
+    pub fn new(name: String, port: u16, id: Fingerprint, known_as: Vec<Fingerprint>) -> Self {
+        Self {
+            name,
+            port,
+            id,
+            known_as,
+        }
+    }
+
+    /// Get the display name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the port we listen on
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Get who we are to new peers
+    pub fn id(&self) -> &Fingerprint {
+        &self.id
+    }
+
+    /// Get the keys our contacts have for us
+    pub fn known_as(&self) -> &[Fingerprint] {
+        &self.known_as
+    }
+}
+
+/// An [Announcement] on the wire
+#[derive(Serialize, Deserialize)]
+struct Beacon {
+    /// Tells the announcements of the app apart, as multicast loops them
+    /// back to us
+    instance: u64,
+    announcement: Announcement,
+    /// Signatures of the instance and announcement, by the keys behind its
+    /// fingerprints
+    proofs: Vec<KeyProof>,
+}
+
+impl Beacon {
+    /// Signs the announcement with the keys
+    fn new(
+        instance: u64,
+        announcement: Announcement,
+        keys: &[PKey<Private>],
+    ) -> Result<Self, ErrorStack> {
+        let signed = serialize(&(instance, &announcement)).unwrap();
+        let proofs = keys
+            .iter()
+            .map(|key| KeyProof::new(key, ANNOUNCEMENT_LABEL, &signed))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            instance,
+            announcement,
+            proofs,
+        })
+    }
+
+    /// Get the announcement, if signed by the key it's made under, without
+    /// the fingerprints of the contacts it wasn't signed for
+    fn verify(self) -> Option<Announcement> {
+        let signed = serialize(&(self.instance, &self.announcement)).unwrap();
+        let proven: Vec<_> = self
+            .proofs
+            .iter()
+            .filter_map(|proof| proof.verify(ANNOUNCEMENT_LABEL, &signed))
+            .collect();
+        let mut announcement = self.announcement;
+        if !proven.contains(&announcement.id) {
+            return None;
+        }
+        announcement.known_as.retain(|id| proven.contains(id));
+        Some(announcement)
+    }
+}
+
+/// Makes up what tells our announcements apart from those of others
+pub(crate) fn new_instance() -> u64 {
+    let mut instance = [0; size_of::<u64>()];
+    // without randomness the instance only needs to differ from others
+    let _ = rand_bytes(&mut instance);
+    u64::from_le_bytes(instance)
+}
+
+/// Serializes the announcement, signed with the keys, for [discovery_task]
+pub(crate) fn beacon(
+    instance: u64,
+    announcement: Announcement,
+    keys: &[PKey<Private>],
+) -> Result<Vec<u8>, ErrorStack> {
+    Ok(serialize(&Beacon::new(instance, announcement, keys)?).unwrap())
+}
+
+/// A peer that announced itself on the local network
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DiscoveredPeer {
+    instance: u64,
+    addr: HostAddr,
+    announcement: Announcement,
+    seen: DateTime<Utc>,
+}
+
+impl DiscoveredPeer {
+    /// Get the address the peer listens on
+    pub fn addr(&self) -> &HostAddr {
+        &self.addr
+    }
+
+    /// Get the display name of the peer
+    pub fn name(&self) -> &str {
+        self.announcement.name()
+    }
+
+    /// Get who the peer is to new peers. The announcement is signed with
+    /// the key, but could have been replayed from another address, so
+    /// it's nothing but a claim until a channel is established.
+    pub fn id(&self) -> &Fingerprint {
+        self.announcement.id()
+    }
+
+    /// Get the keys the peer's contacts have for it, as far as it signed
+    /// for them. Just as [Self::id], they only suggest where the contacts
+    /// can be reached.
+    pub fn known_as(&self) -> &[Fingerprint] {
+        self.announcement.known_as()
+    }
+
+    /// When the peer last announced itself
+    pub fn seen(&self) -> &DateTime<Utc> {
+        &self.seen
+    }
+}
+
+/// Binds a socket to the [DISCOVERY_ADDR], shared with other apps on the
+/// same host
+pub(crate) fn bind() -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, DISCOVERY_ADDR.port())).into())?;
+    socket.join_multicast_v4(DISCOVERY_ADDR.ip(), &Ipv4Addr::UNSPECIFIED)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Announces us every so often, if given a [beacon] made under the
+/// instance, and keeps track of the peers announcing themselves
+pub(crate) async fn discovery_task<T: Transport>(
+    socket: UdpSocket,
+    instance: u64,
+    beacon: Option<Vec<u8>>,
+    peers: Shared<Vec<DiscoveredPeer>>,
+    handler: Shared<EventHandler<T>>,
+) {
+    let mut ticker = interval(ANNOUNCE_INTERVAL);
+    let mut buf = vec![0; MAX_DATAGRAM];
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if let Some(beacon) = &beacon {
+                    // the network coming and going isn't worth reporting
+                    let _ = socket.send_to(beacon, DISCOVERY_ADDR).await;
+                }
+                prune(&peers, Utc::now());
+            }
+            received = socket.recv_from(&mut buf) => {
+                let Ok((len, from)) = received else {
+                    continue;
+                };
+                if let Ok(beacon) = deserialize::<Beacon>(&buf[..len])
+                    && beacon.instance != instance
+                {
+                    let instance = beacon.instance;
+                    let Some(announcement) = beacon.verify() else {
+                        continue;
+                    };
+                    let peer = DiscoveredPeer {
+                        instance,
+                        addr: SocketAddr::new(from.ip(), announcement.port).into(),
+                        announcement,
+                        seen: Utc::now(),
+                    };
+                    on_peer(peer, &peers, &handler);
+                }
+            }
+        }
+    }
+}
+
+/// Records the peer. The addresses of contacts are left alone, as anyone
+/// on the network could replay their announcements.
+fn on_peer<T: Transport>(
+    peer: DiscoveredPeer,
+    peers: &Shared<Vec<DiscoveredPeer>>,
+    handler: &Shared<EventHandler<T>>,
+) {
+    let mut known = peers.lock().unwrap();
+    if let Some(known) = known
+        .iter_mut()
+        .find(|known| known.instance == peer.instance)
+    {
+        *known = peer;
+    } else if known.len() < MAX_PEERS {
+        known.push(peer.clone());
+        drop(known);
+        handler.lock().unwrap().emit(Event::PeerDiscovered { peer });
+    }
+}
+
+/// Records the address of the contact under the [Fingerprint], once a
+/// channel opened with them there, if a peer announced itself at the address
+/// under their key. The handshake proves the announcement wasn't replayed.
+pub(crate) fn refresh_contact(
+    peers: &Shared<Vec<DiscoveredPeer>>,
+    contacts: &Shared<ContactBook>,
+    addr: &HostAddr,
+    id: &Fingerprint,
+) {
+    let announced = peers
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|peer| &peer.addr == addr && peer.known_as().contains(id));
+    if announced && let Some(contact) = contacts.lock().unwrap().get_mut(id) {
+        contact.add_address(addr.clone());
+    }
+}
+
+/// Forgets the peers, that stopped announcing themselves
+pub(crate) fn prune(peers: &Shared<Vec<DiscoveredPeer>>, now: DateTime<Utc>) {
+    let timeout = TimeDelta::from_std(PEER_TIMEOUT).unwrap();
+    peers
+        .lock()
+        .unwrap()
+        .retain(|peer| peer.seen + timeout > now);
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::rsa::Rsa;
+
+    use super::*;
+
+    #[test]
+    fn test_beacons_are_signed() {
+        let new_key = || PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let (key, contact_key) = (new_key(), new_key());
+        let (id, known_as) = (Fingerprint::of(&key), Fingerprint::of(&contact_key));
+        let announcement = Announcement::new("test".to_string(), 7777, id, vec![known_as]);
+
+        let beacon = Beacon::new(1, announcement.clone(), &[key.clone(), contact_key]).unwrap();
+        assert_eq!(beacon.verify(), Some(announcement.clone()));
+
+        // fingerprints nobody signed for are dropped
+        let beacon = Beacon::new(1, announcement.clone(), std::slice::from_ref(&key)).unwrap();
+        assert_eq!(beacon.verify().unwrap().known_as(), &[]);
+
+        // as are announcements not signed under their id
+        let beacon = Beacon::new(1, announcement.clone(), &[new_key()]).unwrap();
+        assert_eq!(beacon.verify(), None);
+
+        // and those passed off under another instance
+        let mut beacon = Beacon::new(1, announcement, &[key]).unwrap();
+        beacon.instance = 2;
+        assert_eq!(beacon.verify(), None);
+    }
+}
@@ -1,26 +1,243 @@
-use std::sync::Arc;
+use std::{fmt, io, sync::Arc};
+
+use tokio::{net::TcpStream, sync::mpsc::UnboundedReceiver};
 
 use super::{
     channel::{Channel, ProtocolError},
-    protocol::Message,
+    discovery::DiscoveredPeer,
+    firewall::Rejection,
+    group::{Group, GroupMessage},
+    protocol::{Fingerprint, Message, Payload},
+    state::ConnectionAttempt,
+    transport::{Endpoint, Transport},
 };
 
 /// Can handle new messages
-pub trait HandleMessage: Send {
-    fn on_message(&mut self, message: &Message, channel: &Channel);
+pub trait HandleMessage<T: Transport = TcpStream>: Send {
+    fn on_message(&mut self, message: &Message, channel: &Channel<T>);
+
+    /// Handles application defined data. Ignored by default.
+    fn on_payload(&mut self, _payload: &Payload, _channel: &Channel<T>) {}
 }
 
-/// Can handle thread errors
-pub trait HandleThreadError: Send {
-    fn on_thread_error(&mut self, error: &ProtocolError, channel: &Arc<Channel>);
+/// Why a [Channel] stopped
+#[derive(Clone, Debug)]
+pub enum CloseReason {
+    /// We closed it
+    Closed,
+    /// The other party said goodbye, possibly telling us why
+    PeerClosed(Option<String>),
+    /// The other party went away without saying goodbye
+    PeerLeft,
+    /// Something went wrong
+    Failed(Arc<ProtocolError>),
 }
 
-/// Can handle new channels
-pub trait HandleNewChannel: Send {
-    fn on_new_channel(&mut self, channel: &Arc<Channel>);
+impl From<ProtocolError> for CloseReason {
+    fn from(error: ProtocolError) -> Self {
+        match error {
+            ProtocolError::IoError(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                CloseReason::PeerLeft
+            }
+            error => CloseReason::Failed(Arc::new(error)),
+        }
+    }
 }
 
-/// Can handle errors in threads that await to be accepted
-pub trait HandleChannelCreationError: Send {
-    fn on_channel_creation_error(&mut self, error: &ProtocolError);
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseReason::Closed => write!(f, "closed"),
+            CloseReason::PeerClosed(None) => write!(f, "the other party closed the conversation"),
+            CloseReason::PeerClosed(Some(reason)) => {
+                write!(f, "the other party closed the conversation, {}", reason)
+            }
+            CloseReason::PeerLeft => write!(f, "the other party left"),
+            CloseReason::Failed(error) => write!(f, "{}", error),
+        }
+    }
 }
+
+/// Something that happened within the app
+pub enum Event<T: Transport = TcpStream> {
+    /// A new message arrived
+    MessageReceived {
+        channel: Arc<Channel<T>>,
+        message: Message,
+    },
+    /// Application defined data arrived, see [Payload]
+    PayloadReceived {
+        channel: Arc<Channel<T>>,
+        payload: Payload,
+    },
+    /// A channel finished its handshakes, and is ready for messages
+    ChannelOpened { channel: Arc<Channel<T>> },
+    /// A channel stopped. It stays in the app, until removed
+    ChannelClosed {
+        channel: Arc<Channel<T>>,
+        reason: CloseReason,
+    },
+    /// A new incoming connection is waiting to be accepted
+    PendingConnectionArrived { name: String, peer: Endpoint },
+    /// A pending connection waited for too long, or its peer went away
+    PendingConnectionExpired { name: String, peer: Endpoint },
+    /// A channel accepted automatically couldn't be created
+    HandshakeFailed { error: Arc<ProtocolError> },
+    /// A [ConnectionAttempt] failed
+    ConnectionFailed {
+        attempt: Arc<ConnectionAttempt>,
+        error: Arc<ProtocolError>,
+    },
+    /// A listener failed to bind, or to accept a connection
+    ListenerError {
+        listener: String,
+        error: Arc<io::Error>,
+    },
+    /// An incoming connection was turned away by the firewall
+    ConnectionRejected { peer: Endpoint, reason: Rejection },
+    /// We joined a group, or its membership changed. Also emitted once we
+    /// are out of the group, see [Group::is_active]
+    GroupUpdated { group: Arc<Group> },
+    /// A new message arrived in a group
+    GroupMessageReceived {
+        group: Arc<Group>,
+        message: GroupMessage,
+    },
+    /// A message reached us through peers, see
+    /// [AsyncGrapevineApp::send_through_peers](super::AsyncGrapevineApp::send_through_peers).
+    /// It's added to the history of the channel with the sender, if any.
+    RelayedMessageReceived {
+        /// What we call the sender
+        name: String,
+        sender: Fingerprint,
+        message: Message,
+    },
+    /// A peer announced itself on the local network for the first time, see
+    /// [AsyncGrapevineApp::start_discovery](super::AsyncGrapevineApp::start_discovery)
+    PeerDiscovered { peer: DiscoveredPeer },
+}
+
+// derived Clone would needlessly require T: Clone
+impl<T: Transport> Clone for Event<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Event::MessageReceived { channel, message } => Event::MessageReceived {
+                channel: channel.clone(),
+                message: message.clone(),
+            },
+            Event::PayloadReceived { channel, payload } => Event::PayloadReceived {
+                channel: channel.clone(),
+                payload: payload.clone(),
+            },
+            Event::ChannelOpened { channel } => Event::ChannelOpened {
+                channel: channel.clone(),
+            },
+            Event::ChannelClosed { channel, reason } => Event::ChannelClosed {
+                channel: channel.clone(),
+                reason: reason.clone(),
+            },
+            Event::PendingConnectionArrived { name, peer } => Event::PendingConnectionArrived {
+                name: name.clone(),
+                peer: peer.clone(),
+            },
+            Event::PendingConnectionExpired { name, peer } => Event::PendingConnectionExpired {
+                name: name.clone(),
+                peer: peer.clone(),
+            },
+            Event::HandshakeFailed { error } => Event::HandshakeFailed {
+                error: error.clone(),
+            },
+            Event::ConnectionFailed { attempt, error } => Event::ConnectionFailed {
+                attempt: attempt.clone(),
+                error: error.clone(),
+            },
+            Event::ListenerError { listener, error } => Event::ListenerError {
+                listener: listener.clone(),
+                error: error.clone(),
+            },
+            Event::ConnectionRejected { peer, reason } => Event::ConnectionRejected {
+                peer: peer.clone(),
+                reason: *reason,
+            },
+            Event::GroupUpdated { group } => Event::GroupUpdated {
+                group: group.clone(),
+            },
+            Event::GroupMessageReceived { group, message } => Event::GroupMessageReceived {
+                group: group.clone(),
+                message: message.clone(),
+            },
+            Event::RelayedMessageReceived {
+                name,
+                sender,
+                message,
+            } => Event::RelayedMessageReceived {
+                name: name.clone(),
+                sender: *sender,
+                message: message.clone(),
+            },
+            Event::PeerDiscovered { peer } => Event::PeerDiscovered { peer: peer.clone() },
+        }
+    }
+}
+
+/// Human readable description of the event
+impl<T: Transport> fmt::Display for Event<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::MessageReceived { channel, .. } => {
+                write!(f, "Received message on {}", channel.name())
+            }
+            Event::PayloadReceived { channel, payload } => {
+                write!(f, "Received {} data on {}", payload.tag(), channel.name())
+            }
+            Event::ChannelOpened { channel } => write!(f, "New channel: {}", channel.name()),
+            Event::ChannelClosed {
+                channel,
+                reason: CloseReason::PeerClosed(_),
+            } => write!(f, "{} closed the conversation", channel.name()),
+            Event::ChannelClosed { channel, reason } => {
+                write!(f, "Channel {} stopped: {}", channel.name(), reason)
+            }
+            Event::PendingConnectionArrived { name, .. } => {
+                write!(f, "Incoming connection from {}", name)
+            }
+            Event::PendingConnectionExpired { name, .. } => {
+                write!(f, "Connection from {} went away", name)
+            }
+            Event::HandshakeFailed { error } => write!(f, "Failed to create channel: {}", error),
+            Event::ConnectionFailed { attempt, error } => {
+                write!(f, "Failed to connect with {}: {}", attempt.name(), error)
+            }
+            Event::ListenerError { listener, error } => {
+                write!(f, "Listener error on {}: {}", listener, error)
+            }
+            Event::ConnectionRejected { peer, reason } => {
+                write!(f, "Rejected {}: {}", peer, reason)
+            }
+            Event::GroupUpdated { group } if !group.is_active() => {
+                write!(f, "No longer in group {}", group.name())
+            }
+            Event::GroupUpdated { group } => write!(
+                f,
+                "Group {} has {} members",
+                group.name(),
+                group.members().len()
+            ),
+            Event::GroupMessageReceived { group, message } => write!(
+                f,
+                "Received message from {} in {}",
+                message.author().name(),
+                group.name()
+            ),
+            Event::RelayedMessageReceived { name, .. } => {
+                write!(f, "Received message from {} through peers", name)
+            }
+            Event::PeerDiscovered { peer } => {
+                write!(f, "Found {} at {}", peer.name(), peer.addr())
+            }
+        }
+    }
+}
+
+/// Receiving end of [Event]s, see [AsyncGrapevineApp::subscribe](super::AsyncGrapevineApp::subscribe)
+pub type EventReceiver<T = TcpStream> = UnboundedReceiver<Event<T>>;
@@ -0,0 +1,477 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use derive_more::{Display, Error};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{protocol::Fingerprint, transport::Endpoint};
+
+/// How many [SecurityEvent]s are kept, before the oldest get dropped
+const MAX_SECURITY_EVENTS: usize = 256;
+
+/// A network in the CIDR notation, like `10.0.0.0/8` or `fe80::/10`.
+/// A plain address is treated as a network containing just that address.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+/// Error encountered while parsing an [IpNet]
+#[derive(Debug, Display, Error)]
+#[display("invalid network, expected an address or address/prefix")]
+pub struct IpNetParseError;
+
+impl IpNet {
+    /// Creates a new network, returns [None] if the prefix is too long for
+    /// the address family
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Self> {
+        let addr = addr.to_canonical();
+        (prefix <= Self::max_prefix(&addr)).then_some(Self { addr, prefix })
+    }
+
+    fn max_prefix(addr: &IpAddr) -> u8 {
+        match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    /// Checks if the address belongs to the network.
+    /// IPv4 mapped IPv6 addresses are treated as IPv4 addresses.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for IpNet {
+    fn from(addr: IpAddr) -> Self {
+        let addr = addr.to_canonical();
+        Self {
+            prefix: Self::max_prefix(&addr),
+            addr,
+        }
+    }
+}
+
+impl Display for IpNet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.prefix == Self::max_prefix(&self.addr) {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = IpNetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.split_once('/') {
+            Some((addr, prefix)) => {
+                let addr = IpAddr::from_str(addr).map_err(|_| IpNetParseError)?;
+                let prefix = u8::from_str(prefix).map_err(|_| IpNetParseError)?;
+                Self::new(addr, prefix).ok_or(IpNetParseError)
+            }
+            None => Ok(IpAddr::from_str(s).map_err(|_| IpNetParseError)?.into()),
+        }
+    }
+}
+
+impl Serialize for IpNet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpNet {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let s = String::deserialize(de)?;
+        Self::from_str(&s).map_err(D::Error::custom)
+    }
+}
+
+/// At most [Self::max] connections per source address, within [Self::window]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct RateLimit {
+    max: u32,
+    #[serde(with = "secs")]
+    window: Duration,
+}
+
+/// Serializes durations as whole seconds
+pub(crate) mod secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_secs(u64::deserialize(de)?))
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new(10, Duration::from_secs(60))
+    }
+}
+
+impl RateLimit {
+    pub fn new(max: u32, window: Duration) -> Self {
+        Self { max, window }
+    }
+
+    /// Get the amount of connections allowed within [Self::window]
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    /// Get the duration of the window
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+}
+
+/// App wide rules deciding who may connect to us
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessRules {
+    /// If not empty, only these networks may connect
+    allowed: Vec<IpNet>,
+    /// These networks may never connect, takes precedence over [Self::allowed]
+    blocked: Vec<IpNet>,
+    /// Peers with these keys are disconnected as soon as the key is known
+    blocked_keys: Vec<Fingerprint>,
+    rate_limit: Option<RateLimit>,
+    /// How many connections may wait for the user at once
+    max_pending: usize,
+}
+
+impl Default for AccessRules {
+    fn default() -> Self {
+        Self::new(
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            Some(RateLimit::default()),
+            32,
+        )
+    }
+}
+
+impl AccessRules {
+    pub fn new(
+        allowed: Vec<IpNet>,
+        blocked: Vec<IpNet>,
+        blocked_keys: Vec<Fingerprint>,
+        rate_limit: Option<RateLimit>,
+        max_pending: usize,
+    ) -> Self {
+        Self {
+            allowed,
+            blocked,
+            blocked_keys,
+            rate_limit,
+            max_pending,
+        }
+    }
+
+    /// Get the allowlist, empty if everyone is allowed
+    pub fn allowed(&self) -> &[IpNet] {
+        &self.allowed
+    }
+
+    /// Get the blocklist
+    pub fn blocked(&self) -> &[IpNet] {
+        &self.blocked
+    }
+
+    /// Get the blocked key fingerprints
+    pub fn blocked_keys(&self) -> &[Fingerprint] {
+        &self.blocked_keys
+    }
+
+    /// Get the per source rate limit
+    pub fn rate_limit(&self) -> Option<&RateLimit> {
+        self.rate_limit.as_ref()
+    }
+
+    /// Get the cap on the amount of pending connections
+    pub fn max_pending(&self) -> usize {
+        self.max_pending
+    }
+
+    /// Adds the network to the blocklist
+    pub fn block(&mut self, net: IpNet) {
+        if !self.blocked.contains(&net) {
+            self.blocked.push(net);
+        }
+    }
+
+    /// Adds the key to the blocked keys
+    pub fn block_key(&mut self, fingerprint: Fingerprint) {
+        if !self.blocked_keys.contains(&fingerprint) {
+            self.blocked_keys.push(fingerprint);
+        }
+    }
+
+    /// Checks the address against the allow- and blocklist
+    pub fn check_ip(&self, addr: &IpAddr) -> Result<(), Rejection> {
+        if self.blocked.iter().any(|net| net.contains(addr)) {
+            Err(Rejection::Blocked)
+        } else if !self.allowed.is_empty() && !self.allowed.iter().any(|net| net.contains(addr)) {
+            Err(Rejection::NotAllowed)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks if the key is blocked
+    pub fn is_key_blocked(&self, fingerprint: &Fingerprint) -> bool {
+        self.blocked_keys.contains(fingerprint)
+    }
+}
+
+/// Reason for turning away an incoming connection
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Display)]
+pub enum Rejection {
+    #[display("address not on the allowlist")]
+    NotAllowed,
+    #[display("address on the blocklist")]
+    Blocked,
+    #[display("too many connection attempts")]
+    RateLimited,
+    #[display("too many pending connections")]
+    QueueFull,
+    #[display("blocked key {}", _0.short())]
+    BlockedKey(Fingerprint),
+    #[display("protocol path not allowed by the listener")]
+    PathNotAllowed,
+    #[display("key exchange with a stranger")]
+    StrangerRsa,
+    #[display("asked for a key not meant for them")]
+    WrongKey,
+    #[display("unsupported protocol version")]
+    BadVersion,
+    #[display("no valid handshake received")]
+    BadHandshake,
+}
+
+/// A logged [Rejection]
+#[derive(Clone, Debug)]
+pub struct SecurityEvent {
+    time: DateTime<Utc>,
+    peer: Endpoint,
+    reason: Rejection,
+}
+
+impl SecurityEvent {
+    /// Get when the connection was rejected
+    pub fn time(&self) -> &DateTime<Utc> {
+        &self.time
+    }
+
+    /// Get the rejected peer
+    pub fn peer(&self) -> &Endpoint {
+        &self.peer
+    }
+
+    /// Get why the connection was rejected
+    pub fn reason(&self) -> &Rejection {
+        &self.reason
+    }
+}
+
+/// Enforces the [AccessRules], keeping track of the connection attempts and
+/// the [SecurityEvent]s
+#[derive(Default)]
+pub struct Firewall {
+    rules: AccessRules,
+    /// Recent connection attempts per source
+    attempts: HashMap<IpAddr, VecDeque<Instant>>,
+    events: VecDeque<SecurityEvent>,
+}
+
+impl Firewall {
+    /// Get the enforced rules
+    pub fn rules(&self) -> &AccessRules {
+        &self.rules
+    }
+
+    /// Replace the enforced rules
+    pub fn set_rules(&mut self, rules: AccessRules) {
+        self.rules = rules;
+    }
+
+    /// Get the logged rejections, oldest first
+    pub fn events(&self) -> impl DoubleEndedIterator<Item = &SecurityEvent> {
+        self.events.iter()
+    }
+
+    /// Clears the logged rejections
+    pub fn clear_events(&mut self) {
+        self.events.clear();
+    }
+
+    /// Decides whether a newly accepted connection may proceed to the
+    /// handshake. Every call counts towards the [RateLimit] of the source.
+    /// Rejections are logged. Peers outside of the network, like those
+    /// connecting through Unix sockets, aren't subject to any of the rules.
+    pub fn admit(&mut self, peer: &Endpoint) -> Result<(), Rejection> {
+        let Some(ip) = peer.ip() else {
+            return Ok(());
+        };
+        let ip = ip.to_canonical();
+        let result = self
+            .rules
+            .check_ip(&ip)
+            .and_then(|_| self.count_attempt(ip));
+        if let Err(reason) = result {
+            self.log(peer, reason);
+        }
+        result
+    }
+
+    fn count_attempt(&mut self, ip: IpAddr) -> Result<(), Rejection> {
+        let Some(limit) = self.rules.rate_limit else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        // forget sources that haven't been around for a while
+        self.attempts.retain(|_, attempts| {
+            while attempts
+                .front()
+                .is_some_and(|attempt| now.duration_since(*attempt) > limit.window)
+            {
+                attempts.pop_front();
+            }
+            !attempts.is_empty()
+        });
+
+        let attempts = self.attempts.entry(ip).or_default();
+        if attempts.len() >= limit.max as usize {
+            return Err(Rejection::RateLimited);
+        }
+        attempts.push_back(now);
+        Ok(())
+    }
+
+    /// Logs a rejection
+    pub fn log(&mut self, peer: &Endpoint, reason: Rejection) {
+        if self.events.len() >= MAX_SECURITY_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(SecurityEvent {
+            time: Utc::now(),
+            peer: peer.clone(),
+            reason,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipnet_contains() {
+        let net = IpNet::from_str("10.1.0.0/16").unwrap();
+        assert!(net.contains(&"10.1.200.3".parse().unwrap()));
+        assert!(!net.contains(&"10.2.0.1".parse().unwrap()));
+        assert!(net.contains(&"::ffff:10.1.0.1".parse().unwrap()));
+
+        let net = IpNet::from_str("fe80::/10").unwrap();
+        assert!(net.contains(&"fe80::1".parse().unwrap()));
+        assert!(!net.contains(&"2001:db8::1".parse().unwrap()));
+
+        let everything = IpNet::from_str("0.0.0.0/0").unwrap();
+        assert!(everything.contains(&"192.0.2.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ipnet_parse() {
+        assert_eq!(
+            IpNet::from_str("192.0.2.1").unwrap().to_string(),
+            "192.0.2.1"
+        );
+        assert_eq!(
+            IpNet::from_str(" 10.0.0.0/8 ").unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        assert!(IpNet::from_str("10.0.0.0/33").is_err());
+        assert!(IpNet::from_str("example.com/8").is_err());
+        assert!(IpNet::from_str("10.0.0.0/").is_err());
+    }
+
+    #[test]
+    fn test_blocklist_takes_precedence() {
+        let rules = AccessRules::new(
+            vec![IpNet::from_str("10.0.0.0/8").unwrap()],
+            vec![IpNet::from_str("10.0.0.1").unwrap()],
+            Vec::new(),
+            None,
+            1,
+        );
+        assert_eq!(rules.check_ip(&"10.0.0.2".parse().unwrap()), Ok(()));
+        assert_eq!(
+            rules.check_ip(&"10.0.0.1".parse().unwrap()),
+            Err(Rejection::Blocked)
+        );
+        assert_eq!(
+            rules.check_ip(&"192.0.2.1".parse().unwrap()),
+            Err(Rejection::NotAllowed)
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_is_per_source() {
+        let mut firewall = Firewall::default();
+        firewall.set_rules(AccessRules::new(
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            Some(RateLimit::new(2, Duration::from_secs(60))),
+            1,
+        ));
+
+        let a = Endpoint::Net("192.0.2.1:1000".parse().unwrap());
+        let b = Endpoint::Net("192.0.2.2:1000".parse().unwrap());
+        let local = Endpoint::Local("unix:/tmp/grapevine".to_string());
+        assert!(firewall.admit(&a).is_ok());
+        assert!(firewall.admit(&a).is_ok());
+        assert_eq!(firewall.admit(&a), Err(Rejection::RateLimited));
+        assert!(firewall.admit(&b).is_ok());
+        for _ in 0..3 {
+            assert!(firewall.admit(&local).is_ok());
+        }
+
+        let events = firewall.events().collect::<Vec<_>>();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].peer(), &a);
+    }
+}
@@ -0,0 +1,478 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use openssl::{error::ErrorStack, rand::rand_bytes};
+use serde::{Deserialize, Serialize};
+use tokio::{runtime::Handle, sync::mpsc, task::JoinHandle};
+
+use super::{
+    Shared,
+    channel::{Channel, ChannelDesc, ProtocolError},
+    contacts::ContactBook,
+    events::Event,
+    firewall::secs,
+    handler::{Deferred, EventHandler, run_deferred},
+    protocol::{
+        AesHandshake, Fingerprint, FromPacket, IntoPacket, Message, Packet, Payload, new_aes_key,
+    },
+    state::ChannelState,
+    transport::Transport,
+};
+
+/// Tag of the [Payload]s messages travel through peers with
+pub const GOSSIP_TAG: &str = "grapevine/gossip";
+
+/// The longest messages are held for
+const MAX_EXPIRY: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// How many envelopes we remember coming across at once, so that a flood
+/// of them can't make us remember everything
+const MAX_SEEN: usize = 4096;
+/// How many of the envelopes we remember a single peer may have brought,
+/// so that a flood from one of them leaves room for the others
+const MAX_SEEN_PER_PEER: usize = MAX_SEEN / 8;
+
+/// Identifies a message sent through peers, so that it's passed on, and
+/// delivered only once
+pub type MessageId = u128;
+
+fn new_message_id() -> Result<MessageId, ErrorStack> {
+    let mut id = [0; size_of::<MessageId>()];
+    rand_bytes(&mut id)?;
+    Ok(MessageId::from_le_bytes(id))
+}
+
+/// How messages travel through peers, see
+/// [AsyncGrapevineApp::send_through_peers](super::AsyncGrapevineApp::send_through_peers)
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GossipConfig {
+    /// Whether we pass on the messages of others
+    relay: bool,
+    /// How many peers a message may be passed through
+    hops: u8,
+    /// How long messages are held for, before they are given up on
+    #[serde(with = "secs")]
+    expiry: Duration,
+    /// How many messages are held at once
+    capacity: usize,
+}
+
+/// Messages of others aren't passed on, unless opted in
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self::new(false, 3, Duration::from_secs(24 * 60 * 60), 256)
+    }
+}
+
+impl GossipConfig {
+    /// The capacity is at least one, and the expiry at most 30 days
+    pub fn new(relay: bool, hops: u8, expiry: Duration, capacity: usize) -> Self {
+        Self {
+            relay,
+            hops,
+            expiry: expiry.min(MAX_EXPIRY),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Check if we pass on the messages of others
+    pub fn relay(&self) -> bool {
+        self.relay
+    }
+
+    /// Get how many peers our messages may be passed through. Messages of
+    /// others are passed on no further than that either.
+    pub fn hops(&self) -> u8 {
+        self.hops
+    }
+
+    /// Get how long messages are held for. Messages of others are held no
+    /// longer than that, even if their sender allows it.
+    pub fn expiry(&self) -> Duration {
+        self.expiry
+    }
+
+    /// Get how many messages are held at once. Once full, the messages
+    /// closest to expiring make room.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get when the messages held from now on are given up on
+    fn expires(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        TimeDelta::from_std(self.expiry)
+            .ok()
+            .and_then(|expiry| now.checked_add_signed(expiry))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+/// What the recipient gets, signed by the sender
+#[derive(Serialize, Deserialize)]
+struct Letter {
+    id: MessageId,
+    recipient: Fingerprint,
+    expires: DateTime<Utc>,
+    message: Message,
+}
+
+/// A [Letter] on its way, which only its recipient can open
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    id: MessageId,
+    /// The [Fingerprint] of the key the sender knows the recipient by
+    recipient: Fingerprint,
+    /// When the envelope is given up on
+    expires: DateTime<Utc>,
+    /// How many more times it may be passed on. The only part not covered
+    /// by the signature, as every peer counts it down.
+    hops: u8,
+    /// The AES key of the letter, encrypted for the recipient
+    key: AesHandshake,
+    /// The [Letter], as an encrypted and signed [Packet]
+    letter: Vec<u8>,
+}
+
+impl Envelope {
+    /// Signs the message for the other party of the channel, and encrypts it
+    /// for their eyes only
+    fn seal(
+        desc: &ChannelDesc,
+        message: Message,
+        hops: u8,
+        expires: DateTime<Utc>,
+    ) -> Result<Self, ProtocolError> {
+        let id = new_message_id()?;
+        let recipient = desc.fingerprint();
+        let aes_key = new_aes_key()?;
+        let mut packet = Letter {
+            id,
+            recipient,
+            expires,
+            message,
+        }
+        .into_packet(desc.our_key())?;
+        packet.encrypt(&aes_key)?;
+        let mut letter = Vec::new();
+        packet.to_writer(&mut letter)?;
+
+        Ok(Self {
+            id,
+            recipient,
+            expires,
+            hops,
+            key: AesHandshake::new(&aes_key, desc.their_key())?,
+            letter,
+        })
+    }
+
+    /// Decrypts the letter, making sure it was signed by the other party of
+    /// the channel, for this very envelope
+    fn open(&self, desc: &ChannelDesc) -> Result<Message, ProtocolError> {
+        let aes_key = self.key.decrypt_key(desc.our_key())?;
+        let mut packet = Packet::from_reader(&mut &self.letter[..])?;
+        packet.decrypt(&aes_key)?;
+        if !packet.verify(desc.their_key()) {
+            return Err(ProtocolError::VerificationError);
+        }
+        let letter = Letter::from_packet(&packet)?;
+        if letter.id != self.id
+            || letter.recipient != self.recipient
+            || letter.expires != self.expires
+        {
+            return Err(ProtocolError::VerificationError);
+        }
+        Ok(letter.message)
+    }
+}
+
+/// An envelope handed to every peer we meet
+struct Held {
+    envelope: Envelope,
+    /// When we give up on it, no later than its expiry
+    until: DateTime<Utc>,
+}
+
+/// An envelope we came across
+struct Seen {
+    /// When we forget about it, no later than its expiry
+    until: DateTime<Utc>,
+    /// The peer who brought it, unless it's ours
+    from: Option<Fingerprint>,
+}
+
+/// Messages to contacts we aren't connected with, travelling through the
+/// peers we both are connected with. Peers only ever see envelopes, they
+/// can neither open, nor forge.
+pub(crate) struct Gossip<T: Transport> {
+    config: Mutex<GossipConfig>,
+    /// Our envelopes, and the ones we pass on
+    held: Mutex<Vec<Held>>,
+    /// The envelopes we came across, until they expire
+    seen: Mutex<HashMap<MessageId, Seen>>,
+    channels: Shared<Vec<Arc<Channel<T>>>>,
+    /// Recipients may know us by the keys of our contacts
+    contacts: Shared<ContactBook>,
+    /// What the event callback leaves to a task
+    actions: mpsc::UnboundedSender<Deferred<T>>,
+}
+
+impl<T: Transport> Gossip<T> {
+    /// Creates the gossip, along with the task sending the envelopes
+    pub fn new(
+        channels: Shared<Vec<Arc<Channel<T>>>>,
+        contacts: Shared<ContactBook>,
+        handler: Shared<EventHandler<T>>,
+        runtime: &Handle,
+    ) -> (Arc<Self>, JoinHandle<()>) {
+        let (actions, queue) = mpsc::unbounded_channel();
+        let gossip = Arc::new(Self {
+            config: Mutex::new(GossipConfig::default()),
+            held: Mutex::new(Vec::new()),
+            seen: Mutex::new(HashMap::new()),
+            channels,
+            contacts,
+            actions,
+        });
+        (gossip, runtime.spawn(run_deferred(queue, handler)))
+    }
+
+    pub fn config(&self) -> GossipConfig {
+        *self.config.lock().unwrap()
+    }
+
+    pub fn set_config(&self, config: GossipConfig) {
+        *self.config.lock().unwrap() = config;
+    }
+
+    /// Sends the message to the other party of the channel, through every
+    /// peer we are connected with. The envelope is held on to, and handed to
+    /// the peers we connect with later on, until it expires.
+    pub fn send(&self, desc: &ChannelDesc, message: Message) -> Result<MessageId, ProtocolError> {
+        let config = self.config();
+        let now = Utc::now();
+        let expires = config.expires(now);
+        let envelope = Envelope::seal(desc, message.clone(), config.hops, expires)?;
+        let id = envelope.id;
+
+        self.prune(now);
+        self.see(id, expires, None);
+        self.spread(&envelope, None)?;
+        self.hold(envelope, expires, config.capacity);
+        if let Some(channel) = self.channel_with(&desc.fingerprint()) {
+            channel.messages().lock().unwrap().push(message);
+        }
+        Ok(id)
+    }
+
+    /// Passes on, or opens the envelopes coming in, and hands the held
+    /// ones to the peers we connect with. Runs as an event callback.
+    pub fn on_event(&self, event: &Event<T>) {
+        match event {
+            Event::ChannelOpened { channel } => {
+                self.prune(Utc::now());
+                for held in self.held.lock().unwrap().iter() {
+                    if let Ok(payload) = Payload::new(GOSSIP_TAG, &held.envelope) {
+                        let _ = self.actions.send(Deferred::Send(channel.clone(), payload));
+                    }
+                }
+            }
+            Event::PayloadReceived { channel, payload } if payload.tag() == GOSSIP_TAG => {
+                if let Ok(envelope) = payload.decode() {
+                    // envelopes that don't check out are dropped
+                    let _ = self.on_envelope(channel, envelope);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_envelope(
+        &self,
+        channel: &Arc<Channel<T>>,
+        mut envelope: Envelope,
+    ) -> Result<(), ProtocolError> {
+        let now = Utc::now();
+        if envelope.expires <= now {
+            return Ok(());
+        }
+        self.prune(now);
+        let config = self.config();
+        // the sender doesn't get to decide how long we remember, or hold
+        let until = envelope.expires.min(config.expires(now));
+        if !self.see(envelope.id, until, Some(*channel.fingerprint())) {
+            return Ok(());
+        }
+
+        if let Some(desc) = self.desc_for(&envelope.recipient) {
+            let message = envelope.open(&desc)?;
+            if let Some(channel) = self.channel_with(&desc.fingerprint()) {
+                channel.messages().lock().unwrap().push(message.clone());
+            }
+            let _ = self
+                .actions
+                .send(Deferred::Emit(Event::RelayedMessageReceived {
+                    name: desc.name().to_string(),
+                    sender: desc.fingerprint(),
+                    message,
+                }));
+            return Ok(());
+        }
+
+        let hops = envelope.hops.min(config.hops);
+        if !config.relay || hops == 0 {
+            return Ok(());
+        }
+        envelope.hops = hops - 1;
+        self.spread(&envelope, Some(channel))?;
+        self.hold(envelope, until, config.capacity);
+        Ok(())
+    }
+
+    /// Sends the envelope to every open channel, but the one it came from
+    fn spread(
+        &self,
+        envelope: &Envelope,
+        from: Option<&Arc<Channel<T>>>,
+    ) -> Result<(), ProtocolError> {
+        let payload = Payload::new(GOSSIP_TAG, envelope)?;
+        for channel in self.channels.lock().unwrap().iter() {
+            if matches!(channel.state(), ChannelState::Open)
+                && !from.is_some_and(|from| Arc::ptr_eq(from, channel))
+            {
+                let _ = self
+                    .actions
+                    .send(Deferred::Send(channel.clone(), payload.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Holds on to the envelope, making room if need be
+    fn hold(&self, envelope: Envelope, until: DateTime<Utc>, capacity: usize) {
+        let mut held = self.held.lock().unwrap();
+        while held.len() >= capacity {
+            let soonest = (0..held.len()).min_by_key(|&i| held[i].until).unwrap();
+            held.swap_remove(soonest);
+        }
+        held.push(Held { envelope, until });
+    }
+
+    /// Remembers coming across the envelope until the given time. Nothing
+    /// is forgotten to make room, as a flood could make us forget the
+    /// envelopes it's meant to bring back.
+    ///
+    /// ## Returns
+    ///
+    /// False if we came across it already, or there is no room for it, either
+    /// at all, or for the peer who brought it
+    fn see(&self, id: MessageId, until: DateTime<Utc>, from: Option<Fingerprint>) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        if seen.contains_key(&id) || seen.len() >= MAX_SEEN {
+            return false;
+        }
+        if from.is_some()
+            && seen.values().filter(|seen| seen.from == from).count() >= MAX_SEEN_PER_PEER
+        {
+            return false;
+        }
+        seen.insert(id, Seen { until, from });
+        true
+    }
+
+    /// Forgets the envelopes, that have expired
+    fn prune(&self, now: DateTime<Utc>) {
+        self.held.lock().unwrap().retain(|held| held.until > now);
+        self.seen.lock().unwrap().retain(|_, seen| seen.until > now);
+    }
+
+    /// Finds who the recipient is to us, if it's us the envelope is for
+    fn desc_for(&self, recipient: &Fingerprint) -> Option<ChannelDesc> {
+        let channel = self
+            .channels
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|channel| channel.desc().our_fingerprint() == *recipient)
+            .map(|channel| channel.desc().clone());
+        channel.or_else(|| {
+            self.contacts
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|contact| contact.desc().our_fingerprint() == *recipient)
+                .map(|contact| contact.desc().clone())
+        })
+    }
+
+    /// Finds the latest channel with the party, open or not
+    fn channel_with(&self, id: &Fingerprint) -> Option<Arc<Channel<T>>> {
+        self.channels
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|channel| channel.fingerprint() == id)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryTransport;
+
+    fn gossip() -> (Arc<Gossip<MemoryTransport>>, JoinHandle<()>) {
+        let channels = Shared::default();
+        let handler = Arc::new(Mutex::new(EventHandler::new(channels.clone())));
+        Gossip::new(channels, Shared::default(), handler, &Handle::current())
+    }
+
+    #[test]
+    fn test_expiry_doesnt_overflow() {
+        let config = GossipConfig::new(true, 3, Duration::MAX, 16);
+        assert_eq!(config.expiry(), MAX_EXPIRY);
+
+        let now = Utc::now();
+        assert_eq!(
+            config.expires(now),
+            now + TimeDelta::from_std(MAX_EXPIRY).unwrap()
+        );
+        // stored settings don't go through the constructor
+        let stored: GossipConfig =
+            serde_json::from_str(&format!("{{\"expiry\": {}}}", u64::MAX)).unwrap();
+        assert_eq!(stored.expires(now), DateTime::<Utc>::MAX_UTC);
+    }
+
+    #[tokio::test]
+    async fn test_seen_is_bounded() {
+        let (gossip, _task) = gossip();
+        let until = Utc::now() + TimeDelta::hours(1);
+        let peer = || {
+            let key =
+                openssl::pkey::PKey::from_rsa(openssl::rsa::Rsa::generate(1024).unwrap()).unwrap();
+            Some(Fingerprint::of(&key))
+        };
+        let (flooding, other) = (peer(), peer());
+
+        // a peer flooding us only takes up its share
+        let mut ids = 0..;
+        for id in ids.by_ref().take(MAX_SEEN_PER_PEER) {
+            assert!(gossip.see(id, until, flooding));
+        }
+        assert!(!gossip.see(ids.next().unwrap(), until, flooding));
+        assert!(gossip.see(ids.next().unwrap(), until, other));
+        assert!(!gossip.see(0, until, other));
+
+        // once full, new envelopes are refused, rather than making room
+        while gossip.seen.lock().unwrap().len() < MAX_SEEN {
+            assert!(gossip.see(ids.next().unwrap(), until, None));
+        }
+        assert!(!gossip.see(ids.next().unwrap(), until, None));
+        assert!(gossip.seen.lock().unwrap().contains_key(&0));
+    }
+}
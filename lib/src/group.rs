@@ -0,0 +1,696 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use derive_more::{Display, Error, From};
+use openssl::{
+    error::ErrorStack,
+    pkey::{PKey, Private},
+    rsa::Rsa,
+    symm::{Cipher, decrypt_aead, encrypt_aead},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{runtime::Handle, sync::mpsc, task::JoinHandle};
+
+use super::{
+    Shared,
+    channel::{Channel, RSA_KEY_SIZE},
+    events::Event,
+    handler::{Deferred, EventHandler, run_deferred},
+    protocol::{
+        AesIv, AesKey, Fingerprint, FromPacket, IntoPacket, Message, Packet, Payload, new_aes_iv,
+        new_aes_key,
+    },
+    state::ChannelState,
+    transport::Transport,
+};
+
+/// Tag of the [Payload]s groups are run with
+pub const GROUP_TAG: &str = "grapevine/group";
+
+/// Length of the GCM tag of [Sealed] messages
+const TAG_SIZE: usize = 16;
+
+/// Identifies a [Group]. It's the [Fingerprint] of the key the membership
+/// of the group is signed with.
+pub type GroupId = Fingerprint;
+
+/// Why a group operation failed
+#[derive(Debug, Display, From, Error)]
+pub enum GroupError {
+    #[display("only the creator of the group can do that")]
+    NotCreator,
+    #[display("we are no longer in the group")]
+    Left,
+    #[display("not connected with the other party")]
+    NotConnected,
+    #[display("no such member")]
+    NotMember,
+    #[display("no such group")]
+    UnknownGroup,
+    /// The membership wasn't signed by the creator, or came from someone else
+    VerificationError,
+    /// The message was sealed with the key of another epoch
+    #[display("the message was sealed with an outdated key")]
+    OutdatedKey,
+    IoError(io::Error),
+    OpenSSLError(ErrorStack),
+    SerializationError(bitcode::Error),
+}
+
+/// A participant of a [Group]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Member {
+    /// The [Fingerprint] the creator knows the member by. The creator goes by
+    /// the [GroupId].
+    id: Fingerprint,
+    name: String,
+}
+
+impl Member {
+    /// Get the id of the member within the group
+    pub fn id(&self) -> &Fingerprint {
+        &self.id
+    }
+
+    /// Get the name of the member, as the creator knows them
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Who is in a group, signed by its creator
+#[derive(Clone, Serialize, Deserialize)]
+struct Roster {
+    name: String,
+    /// Bumped along with the key, whenever the membership changes
+    epoch: u64,
+    members: Vec<Member>,
+}
+
+/// A message within a [Group], along with who wrote it
+#[derive(Clone, Debug)]
+pub struct GroupMessage {
+    author: Member,
+    message: Message,
+}
+
+impl GroupMessage {
+    /// Get who wrote the message, as they were known at the time
+    pub fn author(&self) -> &Member {
+        &self.author
+    }
+
+    /// Get the message itself
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// Check if the message was written by us
+    pub fn is_ours(&self) -> bool {
+        self.message.is_ours()
+    }
+}
+
+/// A [Message] encrypted with the key of an epoch. The group, the epoch and
+/// the author are authenticated alongside it.
+#[derive(Clone, Serialize, Deserialize)]
+struct Sealed {
+    iv: AesIv,
+    tag: [u8; TAG_SIZE],
+    data: Vec<u8>,
+}
+
+/// What the seal of a message is bound to
+fn associated_data(group: &GroupId, epoch: u64, author: &Fingerprint) -> Vec<u8> {
+    let mut aad = group.as_bytes().to_vec();
+    aad.extend_from_slice(&epoch.to_be_bytes());
+    aad.extend_from_slice(author.as_bytes());
+    aad
+}
+
+impl Sealed {
+    fn seal(message: &Message, key: &AesKey, aad: &[u8]) -> Result<Self, GroupError> {
+        let iv = new_aes_iv()?;
+        let mut tag = [0; TAG_SIZE];
+        let data = bitcode::serialize(message)?;
+        let data = encrypt_aead(Cipher::aes_256_gcm(), key, Some(&iv), aad, &data, &mut tag)?;
+        Ok(Self { iv, tag, data })
+    }
+
+    fn open(&self, key: &AesKey, aad: &[u8]) -> Result<Message, GroupError> {
+        let data = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            key,
+            Some(&self.iv),
+            aad,
+            &self.data,
+            &self.tag,
+        )
+        .map_err(|_| GroupError::VerificationError)?;
+        Ok(bitcode::deserialize(&data)?)
+    }
+}
+
+/// What is sent under [GROUP_TAG]. Groups are run by their creator: members
+/// send their messages to the creator, who relays them to everyone else.
+#[derive(Serialize, Deserialize)]
+enum GroupFrame {
+    /// The membership of the group, sent by the creator to every member
+    /// whenever it changes. Members still in the group get the key of the
+    /// new epoch along with it.
+    Roster {
+        /// PEM encoded public key, the [GroupId] is its fingerprint
+        public_key: Vec<u8>,
+        /// The [Roster], as a signed [Packet]
+        roster: Vec<u8>,
+        key: Option<AesKey>,
+    },
+    /// A message of a member, for the creator to relay
+    Post {
+        group: GroupId,
+        epoch: u64,
+        sealed: Sealed,
+    },
+    /// A message relayed by the creator
+    Relay {
+        group: GroupId,
+        author: Fingerprint,
+        epoch: u64,
+        sealed: Sealed,
+    },
+    /// The sender leaves the group
+    Leave { group: GroupId },
+}
+
+/// Where a group is at, from our point of view
+struct GroupState {
+    roster: Roster,
+    /// Key of the current epoch, [None] once we are out of the group
+    key: Option<AesKey>,
+}
+
+/// A conversation with more than two participants, run over the channels
+/// between its creator and each of the members
+pub struct Group {
+    id: GroupId,
+    /// Our id within the group
+    me: Fingerprint,
+    /// The fingerprint of the creator on the channel with them, or [None]
+    /// if we are the creator
+    creator: Option<Fingerprint>,
+    /// Signs the membership, if we are the creator
+    signing_key: Option<PKey<Private>>,
+    state: Mutex<GroupState>,
+    messages: Mutex<Vec<GroupMessage>>,
+}
+
+impl Group {
+    /// Get the id of the group
+    pub fn id(&self) -> &GroupId {
+        &self.id
+    }
+
+    /// Get the name the creator gave the group
+    pub fn name(&self) -> String {
+        self.state.lock().unwrap().roster.name.clone()
+    }
+
+    /// Get the current members, including the creator and us
+    pub fn members(&self) -> Vec<Member> {
+        self.state.lock().unwrap().roster.members.clone()
+    }
+
+    /// Find a current member by their id
+    pub fn member(&self, id: &Fingerprint) -> Option<Member> {
+        self.state
+            .lock()
+            .unwrap()
+            .roster
+            .members
+            .iter()
+            .find(|member| &member.id == id)
+            .cloned()
+    }
+
+    /// Get our id within the group
+    pub fn me(&self) -> &Fingerprint {
+        &self.me
+    }
+
+    /// Check if we created the group, and so run it
+    pub fn is_creator(&self) -> bool {
+        self.creator.is_none()
+    }
+
+    /// Check if we are still in the group. Groups we left, were removed from,
+    /// or whose creator left are kept with their history, until removed.
+    pub fn is_active(&self) -> bool {
+        self.state.lock().unwrap().key.is_some()
+    }
+
+    /// Get how many times the key has changed
+    pub fn epoch(&self) -> u64 {
+        self.state.lock().unwrap().roster.epoch
+    }
+
+    /// Get the messages of the group
+    pub fn messages(&self) -> &Mutex<Vec<GroupMessage>> {
+        &self.messages
+    }
+
+    /// Changes the membership, and moves on to a new key
+    fn rekey(&self, change: impl FnOnce(&mut Vec<Member>)) -> Result<(), GroupError> {
+        let mut state = self.state.lock().unwrap();
+        if state.key.is_none() {
+            return Err(GroupError::Left);
+        }
+        change(&mut state.roster.members);
+        state.roster.epoch += 1;
+        state.key = Some(new_aes_key()?);
+        Ok(())
+    }
+
+    /// Signs the current membership, for the creator to send out
+    fn roster_frame(&self, with_key: bool) -> Result<GroupFrame, GroupError> {
+        let signing_key = self.signing_key.as_ref().ok_or(GroupError::NotCreator)?;
+        let state = self.state.lock().unwrap();
+        let mut roster = Vec::new();
+        state
+            .roster
+            .into_packet(signing_key)?
+            .to_writer(&mut roster)?;
+        Ok(GroupFrame::Roster {
+            public_key: signing_key.public_key_to_pem()?,
+            roster,
+            key: state.key.filter(|_| with_key),
+        })
+    }
+
+    /// Takes on a newer membership from the creator
+    ///
+    /// ## Returns
+    ///
+    /// Whether the membership was newer
+    fn update(&self, roster: Roster, key: Option<AesKey>) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if roster.epoch <= state.roster.epoch {
+            return false;
+        }
+        state.roster = roster;
+        state.key = key;
+        true
+    }
+
+    /// Seals our message with the current key
+    fn seal(&self, message: &Message) -> Result<(u64, Sealed), GroupError> {
+        let state = self.state.lock().unwrap();
+        let key = state.key.as_ref().ok_or(GroupError::Left)?;
+        let epoch = state.roster.epoch;
+        let sealed = Sealed::seal(message, key, &associated_data(&self.id, epoch, &self.me))?;
+        Ok((epoch, sealed))
+    }
+
+    /// Opens a message of the member, sealed with the current key
+    fn open(
+        &self,
+        epoch: u64,
+        author: &Fingerprint,
+        sealed: &Sealed,
+    ) -> Result<Message, GroupError> {
+        let state = self.state.lock().unwrap();
+        let key = state.key.as_ref().ok_or(GroupError::Left)?;
+        if epoch != state.roster.epoch {
+            return Err(GroupError::OutdatedKey);
+        }
+        sealed.open(key, &associated_data(&self.id, epoch, author))
+    }
+
+    /// Adds the message to the history
+    fn record(&self, author: Member, message: Message) -> GroupMessage {
+        let message = GroupMessage { author, message };
+        self.messages.lock().unwrap().push(message.clone());
+        message
+    }
+}
+
+/// The groups of an app, run on top of the [Payload]s of its channels
+pub(crate) struct Groups<T: Transport> {
+    groups: Shared<Vec<Arc<Group>>>,
+    channels: Shared<Vec<Arc<Channel<T>>>>,
+    /// What the event callback leaves to a task
+    actions: mpsc::UnboundedSender<Deferred<T>>,
+}
+
+impl<T: Transport> Groups<T> {
+    /// Creates the groups, along with the task sending their frames
+    pub fn new(
+        channels: Shared<Vec<Arc<Channel<T>>>>,
+        handler: Shared<EventHandler<T>>,
+        runtime: &Handle,
+    ) -> (Arc<Self>, JoinHandle<()>) {
+        let (actions, queue) = mpsc::unbounded_channel();
+        let groups = Arc::new(Self {
+            groups: Arc::new(Mutex::new(Vec::new())),
+            channels,
+            actions,
+        });
+        (groups, runtime.spawn(run_deferred(queue, handler)))
+    }
+
+    pub fn groups(&self) -> &Shared<Vec<Arc<Group>>> {
+        &self.groups
+    }
+
+    /// Creates a new group, with us as its only member
+    pub async fn create(&self, name: String, our_name: String) -> Result<Arc<Group>, GroupError> {
+        let signing_key = tokio::task::spawn_blocking(|| -> Result<_, ErrorStack> {
+            PKey::from_rsa(Rsa::generate(RSA_KEY_SIZE)?)
+        })
+        .await
+        .map_err(io::Error::other)??;
+        let id = Fingerprint::of(&signing_key);
+
+        let group = Arc::new(Group {
+            id,
+            me: id,
+            creator: None,
+            signing_key: Some(signing_key),
+            state: Mutex::new(GroupState {
+                roster: Roster {
+                    name,
+                    epoch: 0,
+                    members: vec![Member { id, name: our_name }],
+                },
+                key: Some(new_aes_key()?),
+            }),
+            messages: Mutex::new(Vec::new()),
+        });
+        self.groups.lock().unwrap().push(group.clone());
+        Ok(group)
+    }
+
+    /// Adds the other party of the channel to the group we created
+    pub fn invite(&self, group: &Group, channel: &Arc<Channel<T>>) -> Result<(), GroupError> {
+        if !group.is_creator() {
+            return Err(GroupError::NotCreator);
+        }
+        if !matches!(channel.state(), ChannelState::Open) {
+            return Err(GroupError::NotConnected);
+        }
+        if group.member(channel.fingerprint()).is_some() {
+            // they may have missed something
+            return self.push(channel, &group.roster_frame(true)?);
+        }
+        group.rekey(|members| {
+            members.push(Member {
+                id: *channel.fingerprint(),
+                name: channel.name().to_string(),
+            })
+        })?;
+        self.publish(group)
+    }
+
+    /// Removes a member from the group we created
+    pub fn remove(&self, group: &Group, member: &Fingerprint) -> Result<(), GroupError> {
+        if !group.is_creator() {
+            return Err(GroupError::NotCreator);
+        }
+        if member == group.me() || group.member(member).is_none() {
+            return Err(GroupError::NotMember);
+        }
+        group.rekey(|members| members.retain(|other| &other.id != member))?;
+        self.publish(group)?;
+        // letting them know they are out
+        if let Some(channel) = self.channel_with(member) {
+            self.push(&channel, &group.roster_frame(false)?)?;
+        }
+        Ok(())
+    }
+
+    /// Leaves the group. The group ends, once its creator leaves.
+    pub fn leave(&self, group: &Arc<Group>) -> Result<(), GroupError> {
+        if !group.is_active() {
+            return Err(GroupError::Left);
+        }
+        if group.is_creator() {
+            let members = group.members();
+            group.rekey(Vec::clear)?;
+            group.state.lock().unwrap().key = None;
+            let ended = group.roster_frame(false)?;
+            for member in members.iter().filter(|member| member.id != group.me) {
+                if let Some(channel) = self.channel_with(&member.id) {
+                    self.push(&channel, &ended)?;
+                }
+            }
+        } else {
+            if let Some(channel) = self.creator_channel(group) {
+                self.push(&channel, &GroupFrame::Leave { group: group.id })?;
+            }
+            group.state.lock().unwrap().key = None;
+        }
+        self.emit(Event::GroupUpdated {
+            group: group.clone(),
+        });
+        Ok(())
+    }
+
+    /// Sends the message to every member of the group
+    pub fn send(&self, group: &Group, message: Message) -> Result<(), GroupError> {
+        let me = group.member(group.me()).ok_or(GroupError::Left)?;
+        let (epoch, sealed) = group.seal(&message)?;
+        if group.is_creator() {
+            self.relay(group, &me, epoch, sealed)?;
+        } else {
+            let channel = self
+                .creator_channel(group)
+                .ok_or(GroupError::NotConnected)?;
+            self.push(
+                &channel,
+                &GroupFrame::Post {
+                    group: group.id,
+                    epoch,
+                    sealed,
+                },
+            )?;
+        }
+        group.record(me, message);
+        Ok(())
+    }
+
+    /// Handles the frames of the groups, and catches up members who
+    /// reconnect. Runs as an event callback.
+    pub fn on_event(&self, event: &Event<T>) {
+        match event {
+            Event::ChannelOpened { channel } => {
+                let groups = self.groups.lock().unwrap().clone();
+                for group in groups {
+                    if group.is_creator()
+                        && group.is_active()
+                        && group.member(channel.fingerprint()).is_some()
+                        && let Ok(frame) = group.roster_frame(true)
+                    {
+                        let _ = self.push(channel, &frame);
+                    }
+                }
+            }
+            Event::PayloadReceived { channel, payload } if payload.tag() == GROUP_TAG => {
+                if let Ok(frame) = payload.decode() {
+                    // frames that don't check out are dropped
+                    let _ = self.on_frame(channel, frame);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_frame(&self, channel: &Arc<Channel<T>>, frame: GroupFrame) -> Result<(), GroupError> {
+        match frame {
+            GroupFrame::Roster {
+                public_key,
+                roster,
+                key,
+            } => self.on_roster(channel, &public_key, &roster, key),
+            GroupFrame::Post {
+                group,
+                epoch,
+                sealed,
+            } => {
+                let group = self.find(&group)?;
+                if !group.is_creator() {
+                    return Err(GroupError::NotCreator);
+                }
+                let author = group
+                    .member(channel.fingerprint())
+                    .ok_or(GroupError::NotMember)?;
+                let message = group.open(epoch, &author.id, &sealed)?;
+                self.relay(&group, &author, epoch, sealed)?;
+                self.received(group, author, message);
+                Ok(())
+            }
+            GroupFrame::Relay {
+                group,
+                author,
+                epoch,
+                sealed,
+            } => {
+                let group = self.find(&group)?;
+                if group.creator.as_ref() != Some(channel.fingerprint()) {
+                    return Err(GroupError::VerificationError);
+                }
+                let author = group.member(&author).ok_or(GroupError::NotMember)?;
+                let message = group.open(epoch, &author.id, &sealed)?;
+                self.received(group, author, message);
+                Ok(())
+            }
+            GroupFrame::Leave { group } => {
+                let group = self.find(&group)?;
+                if !group.is_creator() {
+                    return Err(GroupError::NotCreator);
+                }
+                let leaving = *channel.fingerprint();
+                if group.member(&leaving).is_none() {
+                    return Err(GroupError::NotMember);
+                }
+                group.rekey(|members| members.retain(|member| member.id != leaving))?;
+                self.publish(&group)?;
+                self.emit(Event::GroupUpdated { group });
+                Ok(())
+            }
+        }
+    }
+
+    /// Joins the group, or takes on its new membership
+    fn on_roster(
+        &self,
+        channel: &Arc<Channel<T>>,
+        public_key: &[u8],
+        roster: &[u8],
+        key: Option<AesKey>,
+    ) -> Result<(), GroupError> {
+        let public_key = PKey::public_key_from_pem(public_key)?;
+        let packet = Packet::from_reader(&mut &roster[..])?;
+        if !packet.verify(&public_key) {
+            return Err(GroupError::VerificationError);
+        }
+        let roster = Roster::from_packet(&packet)?;
+        // the creator knows us by our key on the channel with them
+        let me = channel.desc().our_fingerprint();
+        let key = key.filter(|_| roster.members.iter().any(|member| member.id == me));
+
+        let id = Fingerprint::of(&public_key);
+        let group = match self.find(&id) {
+            Ok(group) => {
+                if group.creator.as_ref() != Some(channel.fingerprint()) {
+                    return Err(GroupError::VerificationError);
+                }
+                if !group.update(roster, key) {
+                    return Ok(());
+                }
+                group
+            }
+            Err(_) => {
+                // we were invited, or we weren't and there is nothing to do
+                if key.is_none() {
+                    return Err(GroupError::NotMember);
+                }
+                let group = Arc::new(Group {
+                    id,
+                    me,
+                    creator: Some(*channel.fingerprint()),
+                    signing_key: None,
+                    state: Mutex::new(GroupState { roster, key }),
+                    messages: Mutex::new(Vec::new()),
+                });
+                self.groups.lock().unwrap().push(group.clone());
+                group
+            }
+        };
+        self.emit(Event::GroupUpdated { group });
+        Ok(())
+    }
+
+    /// Sends the new membership to every member. Members who aren't
+    /// connected get it once they reconnect.
+    fn publish(&self, group: &Group) -> Result<(), GroupError> {
+        let frame = group.roster_frame(true)?;
+        for member in group.members() {
+            if member.id != group.me
+                && let Some(channel) = self.channel_with(&member.id)
+            {
+                self.push(&channel, &frame)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Passes the message on to every connected member, other than its author
+    fn relay(
+        &self,
+        group: &Group,
+        author: &Member,
+        epoch: u64,
+        sealed: Sealed,
+    ) -> Result<(), GroupError> {
+        let frame = GroupFrame::Relay {
+            group: group.id,
+            author: author.id,
+            epoch,
+            sealed,
+        };
+        for member in group.members() {
+            if member.id != group.me
+                && member.id != author.id
+                && let Some(channel) = self.channel_with(&member.id)
+            {
+                self.push(&channel, &frame)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records a message of someone else, and lets everyone know
+    fn received(&self, group: Arc<Group>, author: Member, message: Message) {
+        let message = group.record(author, message);
+        self.emit(Event::GroupMessageReceived { group, message });
+    }
+
+    fn find(&self, id: &GroupId) -> Result<Arc<Group>, GroupError> {
+        self.groups
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|group| &group.id == id)
+            .cloned()
+            .ok_or(GroupError::UnknownGroup)
+    }
+
+    /// Finds the open channel with the party
+    fn channel_with(&self, id: &Fingerprint) -> Option<Arc<Channel<T>>> {
+        self.channels
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|channel| {
+                channel.fingerprint() == id && matches!(channel.state(), ChannelState::Open)
+            })
+            .cloned()
+    }
+
+    fn creator_channel(&self, group: &Group) -> Option<Arc<Channel<T>>> {
+        self.channel_with(group.creator.as_ref()?)
+    }
+
+    fn push(&self, channel: &Arc<Channel<T>>, frame: &GroupFrame) -> Result<(), GroupError> {
+        let payload = Payload::new(GROUP_TAG, frame)?;
+        // the task only stops along with the app
+        let _ = self.actions.send(Deferred::Send(channel.clone(), payload));
+        Ok(())
+    }
+
+    fn emit(&self, event: Event<T>) {
+        let _ = self.actions.send(Deferred::Emit(event));
+    }
+}
@@ -1,91 +1,114 @@
-use std::{ops::DerefMut, sync::Arc};
+use std::sync::Arc;
+
+use tokio::{net::TcpStream, sync::mpsc};
 
 use super::{
     Shared,
-    channel::{Channel, ProtocolError},
-    events::*,
-    protocol::Message,
+    channel::Channel,
+    events::{Event, EventReceiver, HandleMessage},
+    protocol::{Message, Payload},
+    transport::Transport,
 };
 
-/// An internal app wide event handler.
-/// Separated out of the app, so that it can be shared between threads
-pub struct EventHandler {
-    channels: Shared<Vec<Arc<Channel>>>,
-    recipients: Vec<Shared<dyn EventRecipient>>,
-}
-
-/// Can listen for events
-pub trait EventRecipient: Send {
-    fn info(&mut self, message: &str);
-
-    fn warn(&mut self, message: &str);
-
-    fn error(&mut self, message: &str);
+/// Callback invoked on every [Event]
+type EventCallback<T> = Box<dyn FnMut(&Event<T>) + Send>;
 
-    fn success(&mut self, message: &str);
+/// An internal app wide event handler, delivering [Event]s to whoever is
+/// interested. Separated out of the app, so that it can be shared between
+/// tasks.
+pub struct EventHandler<T: Transport = TcpStream> {
+    channels: Shared<Vec<Arc<Channel<T>>>>,
+    subscribers: Vec<mpsc::UnboundedSender<Event<T>>>,
+    callbacks: Vec<EventCallback<T>>,
 }
 
-impl EventHandler {
+impl<T: Transport> EventHandler<T> {
     /// Creates a new [EventHandler], with a shared ownership of the app's channels
-    pub fn new(channels: Shared<Vec<Arc<Channel>>>) -> Self {
+    pub fn new(channels: Shared<Vec<Arc<Channel<T>>>>) -> Self {
         Self {
             channels,
-            recipients: Vec::new(),
-        }
-    }
-
-    /// Adds a recipient, towards which all events will be forwarded to
-    pub fn add_recipient(&mut self, recipient: Shared<dyn EventRecipient>) {
-        self.recipients.push(recipient);
-    }
-
-    /// Convenience method that does some work on all recipients
-    fn recipient_invoke(&mut self, f: impl Fn(&mut dyn EventRecipient) -> ()) {
-        for recipient in &self.recipients {
-            f(recipient.lock().unwrap().deref_mut())
+            subscribers: Vec::new(),
+            callbacks: Vec::new(),
         }
     }
-}
 
-impl EventRecipient for EventHandler {
-    fn info(&mut self, message: &str) {
-        self.recipient_invoke(|recipient| recipient.info(message));
+    /// Creates a new receiver, to which all future events will be sent
+    pub fn subscribe(&mut self) -> EventReceiver<T> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers.push(sender);
+        receiver
     }
 
-    fn warn(&mut self, message: &str) {
-        self.recipient_invoke(|recipient| recipient.warn(message));
+    /// Adds a callback, which will be invoked on all future events.
+    /// The callback runs with the handler locked, so it must not call back
+    /// into the app.
+    pub fn add_callback(&mut self, callback: impl FnMut(&Event<T>) + Send + 'static) {
+        self.callbacks.push(Box::new(callback));
     }
 
-    fn error(&mut self, message: &str) {
-        self.recipient_invoke(|recipient| recipient.error(message));
+    /// Delivers the event to all subscribers and callbacks. Subscribers who
+    /// dropped their receivers are forgotten.
+    pub fn emit(&mut self, event: Event<T>) {
+        for callback in &mut self.callbacks {
+            callback(&event);
+        }
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
     }
+}
 
-    fn success(&mut self, message: &str) {
-        self.recipient_invoke(|recipient| recipient.success(message));
+impl<T: Transport> EventHandler<T> {
+    /// Finds the app's handle of the channel. The channel is always shared
+    /// by the app, by the time it listens.
+    fn shared(&self, channel: &Channel<T>) -> Option<Arc<Channel<T>>> {
+        self.channels
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|other| std::ptr::eq(Arc::as_ptr(other), channel))
+            .cloned()
     }
 }
 
-impl HandleMessage for EventHandler {
-    fn on_message(&mut self, _message: &Message, channel: &Channel) {
-        self.info(&format!("Received message on {}", channel.name()))
-    }
+/// Something an event callback wants done. Callbacks can't emit events,
+/// nor wait for sending, so both are left to [run_deferred].
+pub(crate) enum Deferred<T: Transport> {
+    Send(Arc<Channel<T>>, Payload),
+    Emit(Event<T>),
 }
 
-impl HandleThreadError for EventHandler {
-    fn on_thread_error(&mut self, error: &ProtocolError, channel: &Arc<Channel>) {
-        self.channels.lock().unwrap().retain(|c| c != channel);
-        self.error(&format!("Thread error on {}: {}", channel.name(), error))
+/// Sends the payloads and emits the events, in the order they were deferred
+pub(crate) async fn run_deferred<T: Transport>(
+    mut deferred: mpsc::UnboundedReceiver<Deferred<T>>,
+    handler: Shared<EventHandler<T>>,
+) {
+    while let Some(action) = deferred.recv().await {
+        match action {
+            Deferred::Send(channel, payload) => {
+                // the channel may have closed in the meantime
+                let _ = channel.send_payload(payload).await;
+            }
+            Deferred::Emit(event) => handler.lock().unwrap().emit(event),
+        }
     }
 }
 
-impl HandleChannelCreationError for EventHandler {
-    fn on_channel_creation_error(&mut self, error: &ProtocolError) {
-        self.warn(&format!("Failed to create thread: {}", error))
+impl<T: Transport> HandleMessage<T> for EventHandler<T> {
+    fn on_message(&mut self, message: &Message, channel: &Channel<T>) {
+        if let Some(channel) = self.shared(channel) {
+            self.emit(Event::MessageReceived {
+                channel,
+                message: message.clone(),
+            });
+        }
     }
-}
 
-impl HandleNewChannel for EventHandler {
-    fn on_new_channel(&mut self, channel: &Arc<Channel>) {
-        self.success(&format!("New channel: {}", channel.name()));
+    fn on_payload(&mut self, payload: &Payload, channel: &Channel<T>) {
+        if let Some(channel) = self.shared(channel) {
+            self.emit(Event::PayloadReceived {
+                channel,
+                payload: payload.clone(),
+            });
+        }
     }
 }
@@ -1,26 +1,118 @@
+/// Peer addresses, which may be hostnames
+mod address;
+pub use address::{HostAddr, HostAddrParseError};
+
+/// Byte streams channels can run over
+mod transport;
+pub use transport::Endpoint;
+#[cfg(feature = "async")]
+pub use transport::{Acceptor, MemoryConnector, MemoryListener, MemoryTransport, Transport};
 /// Basic messaging protocol functionality
 mod protocol;
-pub use protocol::Message;
+#[cfg(feature = "async")]
+pub use protocol::ProtocolPath;
+pub use protocol::{Fingerprint, FingerprintParseError, Message, Payload};
+// wire level building blocks, for the benchmarks alone
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub use protocol::{AesKey, FromPacket, IntoPacket, Opener, Packet, Sealer, new_aes_key};
 
-/// [std::net::TcpStream] handling functionality through the [Channel] class
+/// [Transport] handling functionality through the [Channel] class
 mod channel;
-pub use channel::{Channel, ChannelDesc};
+#[cfg(feature = "async")]
+pub use channel::Channel;
+pub use channel::{ChannelDesc, ProtocolError};
+
+/// Outgoing messages, waiting for the writer task of their channel
+#[cfg(feature = "async")]
+mod outbox;
+#[cfg(feature = "async")]
+pub use outbox::{Backpressure, QueueConfig, SendError, SendHandle, SendStatus};
+
+/// Lifecycle of channels, including the ones still being established
+#[cfg(feature = "async")]
+mod state;
+#[cfg(feature = "async")]
+pub use state::{ChannelState, ConnectionAttempt, Timeouts};
 
+/// Persistent knowledge about peers
+mod contacts;
+pub use contacts::{Contact, ContactBook};
+
+/// Rules deciding who may connect to us
+mod firewall;
+pub use firewall::{
+    AccessRules, Firewall, IpNet, IpNetParseError, RateLimit, Rejection, SecurityEvent,
+};
 /// Library-wide events
+#[cfg(feature = "async")]
 mod events;
+#[cfg(feature = "async")]
+pub use events::{CloseReason, Event, EventReceiver, HandleMessage};
 
 /// Handler for the events laid out in [events]
+#[cfg(feature = "async")]
 mod handler;
-pub use handler::EventRecipient;
 
-/// Server thread functionality
+/// Server task functionality
+#[cfg(feature = "async")]
 mod listener;
-pub use listener::{PendingAesHandshake, PendingConnection, PendingRsaHandshake};
+#[cfg(feature = "async")]
+pub use listener::{
+    AutoAccept, ListenerConfig, ListenerPolicy, PendingAesHandshake, PendingConnection,
+    PendingRsaHandshake,
+};
+
+/// Requests to peers, awaiting their responses
+#[cfg(feature = "async")]
+mod rpc;
+#[cfg(feature = "async")]
+pub use rpc::{CallError, RPC_TAG, RemoteError};
+
+/// Conversations with more than two participants
+#[cfg(feature = "async")]
+mod group;
+#[cfg(feature = "async")]
+pub use group::{GROUP_TAG, Group, GroupError, GroupId, GroupMessage, Member};
 
-/// Core self contained app.
+/// Messages travelling through peers, to contacts we aren't connected with
+#[cfg(feature = "async")]
+mod gossip;
+#[cfg(feature = "async")]
+pub use gossip::{GOSSIP_TAG, GossipConfig, MessageId};
+
+/// Connecting peers who can't reach each other directly
+#[cfg(feature = "async")]
+mod relay;
+#[cfg(feature = "async")]
+pub use relay::{Relay, RelayListener};
+
+/// Finding peers on the local network
+#[cfg(feature = "async")]
+mod discovery;
+#[cfg(feature = "async")]
+pub use discovery::{Announcement, DISCOVERY_ADDR, DiscoveredPeer};
+
+/// Core self contained app, running on tokio
+#[cfg(feature = "async")]
+mod async_app;
+#[cfg(feature = "async")]
+pub use async_app::AsyncGrapevineApp;
+
+/// Blocking facade over the [AsyncGrapevineApp]
+#[cfg(feature = "async")]
 mod app;
-pub use app::GrapevineApp;
+#[cfg(feature = "async")]
+pub use app::{BlockingError, GrapevineApp};
+
+/// High level API for automated peers
+#[cfg(feature = "bot")]
+mod bot;
+#[cfg(feature = "bot")]
+pub use bot::{AcceptPolicy, Bot, Context};
 
+#[cfg(feature = "async")]
 use std::sync::{Arc, Mutex};
 
+#[cfg(feature = "async")]
 type Shared<T> = Arc<Mutex<T>>;
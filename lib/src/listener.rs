@@ -1,32 +1,155 @@
 use std::{
-    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    net::{IpAddr, SocketAddr},
     sync::{
         Arc,
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicU64, Ordering},
     },
-    thread::sleep,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use openssl::pkey::{PKey, Private, Public};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::{TcpStream, lookup_host},
+    sync::{oneshot, watch},
+    time::{sleep, timeout},
+};
 
 use super::{
     Shared,
+    address::HostAddr,
+    async_app::ChannelSpawner,
     channel::{Channel, ProtocolError},
-    events::HandleMessage,
-    protocol::{Handshake, ProtocolPath},
+    contacts::{Contact, ContactBook},
+    events::{Event, HandleMessage},
+    firewall::{Firewall, Rejection},
+    handler::EventHandler,
+    protocol::{Fingerprint, Handshake, ProtocolPath},
+    transport::{Acceptor, Endpoint, Transport},
 };
 
+/// Which incoming connections get accepted without asking the user
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum AutoAccept {
+    /// Every connection waits for the user
+    #[default]
+    Never,
+    /// Contacts reconnecting under the keys we have for them are accepted
+    Contacts,
+    /// All RSA exchanges are accepted, alongside known contacts
+    Everyone,
+}
+
+/// Rules a listener applies to incoming connections
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ListenerPolicy {
+    /// Protocol paths peers may take, others are dropped right away
+    allowed_paths: Vec<ProtocolPath>,
+    /// Whether peers may exchange new RSA keys. Whoever they claim to be,
+    /// they are strangers until then.
+    rsa_from_unknown: bool,
+    auto_accept: AutoAccept,
+}
+
+/// How long a peer has to send its [Handshake], after connecting
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a [PendingConnection] may wait for the user, before being dropped
+pub(crate) const PENDING_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// How long a listener waits before accepting again, after failing to accept
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+impl Default for ListenerPolicy {
+    fn default() -> Self {
+        Self::new(
+            vec![ProtocolPath::RsaExchange, ProtocolPath::AesExchange],
+            true,
+            AutoAccept::default(),
+        )
+    }
+}
+
+impl ListenerPolicy {
+    pub fn new(
+        allowed_paths: Vec<ProtocolPath>,
+        rsa_from_unknown: bool,
+        auto_accept: AutoAccept,
+    ) -> Self {
+        Self {
+            allowed_paths,
+            rsa_from_unknown,
+            auto_accept,
+        }
+    }
+
+    /// Check if the protocol path is permitted
+    pub fn allows(&self, path: &ProtocolPath) -> bool {
+        self.allowed_paths.contains(path)
+    }
+
+    /// Get the permitted protocol paths
+    pub fn allowed_paths(&self) -> &[ProtocolPath] {
+        &self.allowed_paths
+    }
+
+    /// Check if peers may exchange new RSA keys
+    pub fn rsa_from_unknown(&self) -> bool {
+        self.rsa_from_unknown
+    }
+
+    /// Get the auto-accept rule
+    pub fn auto_accept(&self) -> AutoAccept {
+        self.auto_accept
+    }
+}
+
+/// Where a listener should be bound, and how it should behave
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ListenerConfig {
+    addr: HostAddr,
+    policy: ListenerPolicy,
+}
+
+impl ListenerConfig {
+    pub fn new(addr: HostAddr, policy: ListenerPolicy) -> Self {
+        Self { addr, policy }
+    }
+
+    /// Get the address to bind to
+    pub fn addr(&self) -> &HostAddr {
+        &self.addr
+    }
+
+    /// Get the policy of the listener
+    pub fn policy(&self) -> &ListenerPolicy {
+        &self.policy
+    }
+}
+
+/// Tells the pending connections apart, see [PendingConnection::id]
+static NEXT_PENDING_ID: AtomicU64 = AtomicU64::new(0);
+
 /// Generic pending connection
-struct PendingHandshake {
-    stream: TcpStream,
+struct PendingHandshake<T: Transport> {
+    transport: T,
     name: String,
+    peer: Endpoint,
+    /// When the [Handshake] was received
+    arrived: Instant,
+    id: u64,
+    /// Dropped along with the connection, letting the task that queued it
+    /// know it no longer has to expire it
+    _queued: oneshot::Sender<()>,
 }
 
-impl PendingHandshake {
+impl<T: Transport> PendingHandshake<T> {
     /// Close the connection
     pub fn reject(self) {
-        self.stream.shutdown(Shutdown::Both).unwrap();
+        // dropping the transport closes it
+    }
+
+    /// Checks if the connection waited for too long, or the peer went away
+    pub fn is_stale(&self) -> bool {
+        self.arrived.elapsed() > PENDING_TIMEOUT || self.transport.is_closed()
     }
 
     /// Get the name of the incoming connection
@@ -36,20 +159,57 @@ impl PendingHandshake {
 }
 
 /// [PendingHandshake] but with the context of having received a [Handshake] with [ProtocolPath::AesExchange]
-pub struct PendingAesHandshake {
-    inner: PendingHandshake,
+pub struct PendingAesHandshake<T: Transport = TcpStream> {
+    inner: PendingHandshake<T>,
+    /// The saved contact matching the [Handshake::key_hint]
+    contact: Option<Contact>,
 }
 
-impl PendingAesHandshake {
+impl<T: Transport> PendingAesHandshake<T> {
+    /// Gets the saved contact the peer claims to be
+    pub fn contact(&self) -> Option<&Contact> {
+        self.contact.as_ref()
+    }
+
+    /// Accept the pending connection, with the keys of the matching
+    /// [Self::contact].
+    ///
+    /// ## Returns
+    ///
+    /// None if there is no matching contact, or the verification fails
+    pub async fn accept_known(
+        self,
+        message_handler: Shared<dyn HandleMessage<T>>,
+    ) -> Result<Option<Channel<T>>, ProtocolError> {
+        match self.contact {
+            Some(contact) => {
+                Channel::from_desc(
+                    self.inner.transport,
+                    contact.desc().clone(),
+                    message_handler,
+                )
+                .await
+            }
+            None => Ok(None),
+        }
+    }
+
     /// Accept the pending connection, with the provided keys
-    pub fn accept(
+    pub async fn accept(
         self,
         name: Option<String>,
         our_key: PKey<Private>,
         their_key: PKey<Public>,
-        message_handler: Shared<dyn HandleMessage>,
-    ) -> Result<Option<Channel>, ProtocolError> {
-        Channel::with_keys(self.inner.stream, our_key, their_key, name, message_handler)
+        message_handler: Shared<dyn HandleMessage<T>>,
+    ) -> Result<Option<Channel<T>>, ProtocolError> {
+        Channel::with_keys(
+            self.inner.transport,
+            our_key,
+            their_key,
+            name,
+            message_handler,
+        )
+        .await
     }
 
     /// Close the connection
@@ -61,21 +221,28 @@ impl PendingAesHandshake {
     pub fn name(&self) -> &str {
         self.inner.name()
     }
+
+    /// Where the connection came from
+    pub fn peer(&self) -> &Endpoint {
+        &self.inner.peer
+    }
 }
 
 /// [PendingHandshake] but with the context of having received a [Handshake] with [ProtocolPath::RsaExchange]
-pub struct PendingRsaHandshake {
-    inner: PendingHandshake,
+pub struct PendingRsaHandshake<T: Transport = TcpStream> {
+    inner: PendingHandshake<T>,
+    /// The key of ours the peer expects, see [Acceptor::identity]
+    identity: Option<PKey<Private>>,
 }
 
-impl PendingRsaHandshake {
+impl<T: Transport> PendingRsaHandshake<T> {
     /// Accept the incoming connection. Will perform the RSA handshake
-    pub fn accept(
+    pub async fn accept(
         self,
         name: Option<String>,
-        message_handler: Shared<dyn HandleMessage>,
-    ) -> Result<Option<Channel>, ProtocolError> {
-        Channel::new(self.inner.stream, name, message_handler)
+        message_handler: Shared<dyn HandleMessage<T>>,
+    ) -> Result<Option<Channel<T>>, ProtocolError> {
+        Channel::new_as(self.inner.transport, self.identity, name, message_handler).await
     }
 
     /// Rejects the incoming connection
@@ -87,15 +254,20 @@ impl PendingRsaHandshake {
     pub fn name(&self) -> &str {
         self.inner.name()
     }
+
+    /// Gets where the connection came from
+    pub fn peer(&self) -> &Endpoint {
+        &self.inner.peer
+    }
 }
 
 /// Unspecified type of incoming connection
-pub enum PendingConnection {
-    Rsa(PendingRsaHandshake),
-    Aes(PendingAesHandshake),
+pub enum PendingConnection<T: Transport = TcpStream> {
+    Rsa(PendingRsaHandshake<T>),
+    Aes(PendingAesHandshake<T>),
 }
 
-impl PendingConnection {
+impl<T: Transport> PendingConnection<T> {
     pub fn name(&self) -> &str {
         match self {
             PendingConnection::Aes(p) => p.name(),
@@ -103,6 +275,42 @@ impl PendingConnection {
         }
     }
 
+    /// Gets where the connection came from
+    pub fn peer(&self) -> &Endpoint {
+        match self {
+            PendingConnection::Aes(p) => p.peer(),
+            PendingConnection::Rsa(p) => p.peer(),
+        }
+    }
+
+    /// Checks if the connection should be cleaned up, because it has waited
+    /// for too long, or the peer has disconnected in the meantime
+    pub fn is_stale(&self) -> bool {
+        match self {
+            PendingConnection::Aes(p) => p.inner.is_stale(),
+            PendingConnection::Rsa(p) => p.inner.is_stale(),
+        }
+    }
+
+    fn id(&self) -> u64 {
+        match self {
+            PendingConnection::Aes(p) => p.inner.id,
+            PendingConnection::Rsa(p) => p.inner.id,
+        }
+    }
+
+    /// Closes the connection, letting everyone know it was left waiting
+    pub(crate) fn expire(self, handler: &Shared<EventHandler<T>>) {
+        handler
+            .lock()
+            .unwrap()
+            .emit(Event::PendingConnectionExpired {
+                name: self.name().to_string(),
+                peer: self.peer().clone(),
+            });
+        self.reject();
+    }
+
     pub fn reject(self) {
         match self {
             PendingConnection::Aes(p) => p.reject(),
@@ -111,44 +319,294 @@ impl PendingConnection {
     }
 }
 
-/// 'Server' thread, that listens for incoming connections and creates new channels for each connection.
-pub fn listener_thread<A: ToSocketAddrs>(
-    addr: A,
-    pending: Shared<Vec<PendingConnection>>,
-    listening: Arc<AtomicBool>,
-) {
-    let listener = TcpListener::bind(addr).unwrap();
+/// Everything a listener needs for handling incoming connections
+pub struct ListenerContext<T: Transport> {
+    pub policy: ListenerPolicy,
+    pub pending: Shared<Vec<PendingConnection<T>>>,
+    pub contacts: Shared<ContactBook>,
+    pub firewall: Shared<Firewall>,
+    pub spawner: ChannelSpawner<T>,
+}
+
+// derived Clone would needlessly require T: Clone
+impl<T: Transport> Clone for ListenerContext<T> {
+    fn clone(&self) -> Self {
+        Self {
+            policy: self.policy.clone(),
+            pending: self.pending.clone(),
+            contacts: self.contacts.clone(),
+            firewall: self.firewall.clone(),
+            spawner: self.spawner.clone(),
+        }
+    }
+}
 
-    listener.set_nonblocking(true).unwrap();
+/// Disconnects the freshly created [Channel] if the other party's key is
+/// blocked by the [Firewall]
+pub fn check_key<T: Transport>(
+    firewall: &Shared<Firewall>,
+    peer: &Endpoint,
+    channel: Option<Channel<T>>,
+) -> Result<Option<Channel<T>>, ProtocolError> {
+    let Some(channel) = channel else {
+        return Ok(None);
+    };
 
-    for stream in listener.incoming() {
-        if !listening.load(Ordering::Relaxed) {
-            break;
+    let mut firewall = firewall.lock().unwrap();
+    if firewall.rules().is_key_blocked(channel.fingerprint()) {
+        firewall.log(peer, Rejection::BlockedKey(*channel.fingerprint()));
+        let _ = channel.close();
+        Err(ProtocolError::Blocked)
+    } else {
+        Ok(Some(channel))
+    }
+}
+
+/// Records the address of the peer, if they connected to us from somewhere
+/// new, both in the [ContactBook] and the [Channel]
+pub async fn note_roaming<T: Transport>(
+    contacts: &Shared<ContactBook>,
+    peer: &Endpoint,
+    channel: Option<Channel<T>>,
+) -> Option<Channel<T>> {
+    let channel = channel?;
+    let Some(ip) = peer.ip().map(|ip| ip.to_canonical()) else {
+        return Some(channel);
+    };
+    let Some((addresses, port)) =
+        contacts
+            .lock()
+            .unwrap()
+            .get(channel.fingerprint())
+            .map(|contact| {
+                (
+                    contact.addresses().to_vec(),
+                    contact.desc().last_addr().port(),
+                )
+            })
+    else {
+        return Some(channel);
+    };
+
+    // resolving hostnames may take a while, so neither the lock, nor the
+    // runtime is held up meanwhile
+    for addr in &addresses {
+        let known = match addr.host().parse::<IpAddr>() {
+            Ok(known) => known.to_canonical() == ip,
+            Err(_) => lookup_host((addr.host(), addr.port()))
+                .await
+                .is_ok_and(|mut resolved| resolved.any(|known| known.ip().to_canonical() == ip)),
+        };
+        if known {
+            return Some(channel);
         }
+    }
 
-        if let Ok(mut stream) = stream {
-            let name = stream.peer_addr().unwrap().to_string();
+    // the port they connected from is ephemeral, so the last known one is kept
+    let addr: HostAddr = SocketAddr::new(ip, port).into();
+    if let Some(contact) = contacts.lock().unwrap().get_mut(channel.fingerprint()) {
+        contact.add_address(addr.clone());
+    }
+    Some(channel.with_addr(addr))
+}
 
-            if let Ok(handshake) = Handshake::from_reader(&mut stream) {
-                if !handshake.version_ok() {
-                    continue;
-                }
+/// Handles a single incoming connection, from receiving the [Handshake], up
+/// to either turning it into a pending connection, or accepting it right
+/// away, depending on the [ListenerPolicy]. Pending connections are expired
+/// once they have waited for too long, unless taken care of before.
+/// Meant to be spawned as a separate task, so that a stalling peer doesn't
+/// hold up others.
+async fn handle_incoming<T: Transport>(
+    mut transport: T,
+    peer: Endpoint,
+    identity: Option<PKey<Private>>,
+    reached: Option<Fingerprint>,
+    ctx: ListenerContext<T>,
+) -> Result<(), Rejection> {
+    let handshake = timeout(
+        HANDSHAKE_TIMEOUT,
+        Handshake::from_async_reader(&mut transport),
+    )
+    .await
+    .ok()
+    .and_then(Result::ok)
+    .ok_or(Rejection::BadHandshake)?;
+
+    if !handshake.version_ok() {
+        return Err(Rejection::BadVersion);
+    }
+
+    let key_hint = handshake.key_hint().copied();
+    let path = handshake.next();
+    if !ctx.policy.allows(&path) {
+        return Err(Rejection::PathNotAllowed);
+    }
 
-                let inner = PendingHandshake { stream, name };
+    // an address says nothing about who is connecting, only the key hint
+    // does, and only once the AES handshake proves it. Every RSA exchange
+    // comes from a stranger, with a key nobody has seen yet.
+    let contact = match (path, key_hint) {
+        (ProtocolPath::AesExchange, Some(hint)) => ctx.contacts.lock().unwrap().get(&hint).cloned(),
+        _ => None,
+    };
 
-                let conn = match handshake.next() {
+    // see [Acceptor::reached]
+    if let Some(reached) = reached {
+        let meant_for = match (path, &contact) {
+            (ProtocolPath::RsaExchange, _) => identity
+                .as_ref()
+                .is_some_and(|key| Fingerprint::of(key) == reached),
+            (ProtocolPath::AesExchange, Some(contact)) => {
+                contact.desc().our_fingerprint() == reached
+            }
+            (ProtocolPath::AesExchange, None) => false,
+        };
+        if !meant_for {
+            return Err(Rejection::WrongKey);
+        }
+    }
+
+    let auto_accept = match ctx.policy.auto_accept() {
+        AutoAccept::Never => false,
+        AutoAccept::Contacts => contact.is_some(),
+        AutoAccept::Everyone => contact.is_some() || matches!(path, ProtocolPath::RsaExchange),
+    };
+
+    let name = contact
+        .as_ref()
+        .map(|contact| contact.name().to_string())
+        .unwrap_or_else(|| peer.to_string());
+
+    let firewall = ctx.firewall.clone();
+    match (path, contact) {
+        (ProtocolPath::RsaExchange, _) if !ctx.policy.rsa_from_unknown() => {
+            return Err(Rejection::StrangerRsa);
+        }
+        (ProtocolPath::RsaExchange, _) if auto_accept => {
+            ctx.spawner.spawn(move |handler| async move {
+                let channel = Channel::new_as(transport, identity, Some(name), handler).await?;
+                check_key(&firewall, &peer, channel)
+            });
+        }
+        (ProtocolPath::AesExchange, Some(contact)) if auto_accept => {
+            let desc = contact.desc().clone();
+            let contacts = ctx.contacts.clone();
+            ctx.spawner.spawn(move |handler| async move {
+                let channel = Channel::from_desc(transport, desc, handler).await?;
+                check_key(
+                    &firewall,
+                    &peer,
+                    note_roaming(&contacts, &peer, channel).await,
+                )
+            });
+        }
+        (path, contact) => {
+            let max_pending = ctx.firewall.lock().unwrap().rules().max_pending();
+            let event = Event::PendingConnectionArrived {
+                name: name.clone(),
+                peer: peer.clone(),
+            };
+            let id = NEXT_PENDING_ID.fetch_add(1, Ordering::Relaxed);
+            let (queued, taken) = oneshot::channel();
+            let inner = PendingHandshake {
+                transport,
+                name,
+                peer,
+                arrived: Instant::now(),
+                id,
+                _queued: queued,
+            };
+
+            {
+                let mut pending = ctx.pending.lock().unwrap();
+                if pending.len() >= max_pending {
+                    return Err(Rejection::QueueFull);
+                }
+                pending.push(match path {
                     ProtocolPath::AesExchange => {
-                        PendingConnection::Aes(PendingAesHandshake { inner })
+                        PendingConnection::Aes(PendingAesHandshake { inner, contact })
                     }
                     ProtocolPath::RsaExchange => {
-                        PendingConnection::Rsa(PendingRsaHandshake { inner })
+                        PendingConnection::Rsa(PendingRsaHandshake { inner, identity })
                     }
-                };
+                });
+            }
+            let handler = ctx.spawner.handler().clone();
+            handler.lock().unwrap().emit(event);
 
-                pending.lock().unwrap().push(conn);
+            tokio::select! {
+                _ = sleep(PENDING_TIMEOUT) => {}
+                // accepted or rejected in the meantime
+                _ = taken => return Ok(()),
+            }
+            // one taken out at the moment gets expired once it's added back
+            let expired = ctx
+                .pending
+                .lock()
+                .unwrap()
+                .extract_if(.., |pending| pending.id() == id)
+                .collect::<Vec<_>>();
+            for pending in expired {
+                pending.expire(&handler);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 'Server' task, that waits for incoming connections, and hands each of
+/// them off to a separate task running [handle_incoming], until told to
+/// stop. Rejected connections are dropped, and logged by the [Firewall].
+pub async fn listener_task<A: Acceptor>(
+    mut acceptor: A,
+    ctx: ListenerContext<A::Transport>,
+    mut stop: watch::Receiver<bool>,
+) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = acceptor.accept() => accepted,
+            _ = stop.wait_for(|stop| *stop) => return,
+        };
+        match accepted.and_then(|transport| Ok((acceptor.peer(&transport)?, transport))) {
+            Ok((peer, transport)) => {
+                let identity = acceptor.identity(&transport);
+                let reached = acceptor.reached(&transport);
+                let admitted = ctx.firewall.lock().unwrap().admit(&peer);
+                if let Err(reason) = admitted {
+                    let event = Event::ConnectionRejected { peer, reason };
+                    ctx.spawner.handler().lock().unwrap().emit(event);
+                    continue;
+                }
+
+                let ctx = ctx.clone();
+                tokio::spawn(async move {
+                    let firewall = ctx.firewall.clone();
+                    let handler = ctx.spawner.handler().clone();
+                    if let Err(reason) =
+                        handle_incoming(transport, peer.clone(), identity, reached, ctx).await
+                    {
+                        firewall.lock().unwrap().log(&peer, reason);
+                        handler
+                            .lock()
+                            .unwrap()
+                            .emit(Event::ConnectionRejected { peer, reason });
+                    }
+                });
+            }
+            Err(e) => {
+                if let Ok(local) = acceptor.local() {
+                    ctx.spawner
+                        .handler()
+                        .lock()
+                        .unwrap()
+                        .emit(Event::ListenerError {
+                            listener: local.to_string(),
+                            error: Arc::new(e),
+                        });
+                }
+                // errors like running out of file descriptors tend to persist
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
             }
-        } else {
-            sleep(Duration::from_millis(100));
         }
     }
 }
@@ -0,0 +1,471 @@
+use std::{fmt, sync::Arc};
+
+use derive_more::{Display, Error};
+use openssl::{
+    error::ErrorStack,
+    pkey::{PKey, Private},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    runtime::Handle,
+    sync::{mpsc, watch},
+};
+
+use super::{
+    app::{BlockingError, block_on},
+    channel::ProtocolError,
+    protocol::{Checkpoint, Frame, Sealer, Transcript},
+};
+
+const DEFAULT_CAPACITY: usize = 32;
+
+/// What happens to a message, when the send queue is full
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum Backpressure {
+    /// Wait for there to be room
+    #[default]
+    Block,
+    /// Give up on the message, see [SendStatus::Dropped]
+    Drop,
+    /// Refuse the message with [SendError::QueueFull]
+    Error,
+}
+
+/// How messages wait for being written to a [Channel](super::Channel)
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    /// How many messages may wait at once
+    capacity: usize,
+    backpressure: Backpressure,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, Backpressure::default())
+    }
+}
+
+impl QueueConfig {
+    /// The capacity is at least one
+    pub fn new(capacity: usize, backpressure: Backpressure) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            backpressure,
+        }
+    }
+
+    /// Get how many messages may wait at once
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get what happens to messages, when the queue is full
+    pub fn backpressure(&self) -> Backpressure {
+        self.backpressure
+    }
+}
+
+/// What happened to a message handed to [Channel::send](super::Channel::send)
+#[derive(Clone, Debug)]
+pub enum SendStatus {
+    /// Waiting for its turn
+    Queued,
+    /// Written to the transport
+    Sent,
+    /// The queue was full, see [Backpressure::Drop]
+    Dropped,
+    /// The channel stopped before the message got its turn
+    Cancelled,
+    /// Writing the message failed
+    Failed(Arc<ProtocolError>),
+}
+
+impl SendStatus {
+    /// Checks whether the status is final
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, SendStatus::Queued)
+    }
+}
+
+impl fmt::Display for SendStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendStatus::Queued => write!(f, "queued"),
+            SendStatus::Sent => write!(f, "sent"),
+            SendStatus::Dropped => write!(f, "dropped, the queue was full"),
+            SendStatus::Cancelled => write!(f, "cancelled, the channel stopped"),
+            SendStatus::Failed(error) => write!(f, "failed: {}", error),
+        }
+    }
+}
+
+/// Why a message didn't make it
+#[derive(Debug, Display, Error)]
+pub enum SendError {
+    #[display("the send queue is full")]
+    QueueFull,
+    #[display("dropped, the send queue was full")]
+    Dropped,
+    #[display("the channel stopped")]
+    Cancelled,
+    Failed(Arc<ProtocolError>),
+    /// Waiting was attempted from within the current thread runtime, that
+    /// the channel runs on. The async methods are the way to go there.
+    Blocking(BlockingError),
+}
+
+/// Lets the caller of [Channel::send](super::Channel::send) follow the
+/// message, either by polling [Self::status], or by waiting for it to be
+/// written
+pub struct SendHandle {
+    status: watch::Receiver<SendStatus>,
+    /// Used by [Self::wait]
+    runtime: Handle,
+}
+
+impl SendHandle {
+    fn new(status: SendStatus, runtime: Handle) -> (watch::Sender<SendStatus>, Self) {
+        let (sender, status) = watch::channel(status);
+        (sender, Self { status, runtime })
+    }
+
+    /// Get the current status of the message
+    pub fn status(&self) -> SendStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Waits until the message is written, or turns out it won't be
+    pub async fn sent(&self) -> Result<(), SendError> {
+        let mut status = self.status.clone();
+        // the writer only lets go of the sender, once the status is final
+        let finished = status
+            .wait_for(SendStatus::is_finished)
+            .await
+            .map(|status| status.clone())
+            .unwrap_or(SendStatus::Cancelled);
+        match finished {
+            SendStatus::Sent => Ok(()),
+            SendStatus::Dropped => Err(SendError::Dropped),
+            SendStatus::Queued | SendStatus::Cancelled => Err(SendError::Cancelled),
+            SendStatus::Failed(error) => Err(SendError::Failed(error)),
+        }
+    }
+
+    /// Blocks until the message is written. The blocking counterpart of
+    /// [Self::sent].
+    pub fn wait(&self) -> Result<(), SendError> {
+        block_on(&self.runtime, self.sent()).map_err(SendError::Blocking)?
+    }
+}
+
+/// A frame waiting for the writer
+pub(crate) struct Outgoing {
+    frame: Frame,
+    status: watch::Sender<SendStatus>,
+}
+
+/// Sending end of the queue, owned by the [Channel](super::Channel)
+pub(crate) struct Outbox {
+    queue: mpsc::Sender<Outgoing>,
+    backpressure: Backpressure,
+    runtime: Handle,
+}
+
+impl Outbox {
+    /// Creates the queue, the receiving end of which goes to [write_queue]
+    pub fn new(config: QueueConfig) -> (Self, mpsc::Receiver<Outgoing>) {
+        let (queue, receiver) = mpsc::channel(config.capacity());
+        let outbox = Self {
+            queue,
+            backpressure: config.backpressure(),
+            runtime: Handle::current(),
+        };
+        (outbox, receiver)
+    }
+
+    /// Queues the frame, acting on a full queue according to the
+    /// [Backpressure]
+    pub async fn push(&self, frame: Frame) -> Result<SendHandle, SendError> {
+        self.push_with(frame, self.backpressure).await
+    }
+
+    /// [Self::push], regardless of the configured [Backpressure]
+    pub async fn push_with(
+        &self,
+        frame: Frame,
+        backpressure: Backpressure,
+    ) -> Result<SendHandle, SendError> {
+        let (status, handle) = SendHandle::new(SendStatus::Queued, self.runtime.clone());
+        let outgoing = Outgoing { frame, status };
+        match backpressure {
+            Backpressure::Block => self
+                .queue
+                .send(outgoing)
+                .await
+                .map_err(|_| SendError::Cancelled)?,
+            Backpressure::Drop | Backpressure::Error => match self.queue.try_send(outgoing) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Closed(_)) => return Err(SendError::Cancelled),
+                Err(mpsc::error::TrySendError::Full(outgoing)) => {
+                    if backpressure == Backpressure::Error {
+                        return Err(SendError::QueueFull);
+                    }
+                    outgoing.status.send_replace(SendStatus::Dropped);
+                }
+            },
+        }
+        Ok(handle)
+    }
+}
+
+/// How the writer task authenticates the frames it writes
+pub(crate) struct WriterSession {
+    sealer: Sealer,
+    /// What the checkpoints vouch for
+    transcript: Transcript,
+    /// Our identity key, signing the checkpoints
+    key: PKey<Private>,
+    /// How many frames go between checkpoints, if any
+    checkpoints: Option<u64>,
+}
+
+impl WriterSession {
+    pub fn new(
+        sealer: Sealer,
+        key: PKey<Private>,
+        checkpoints: Option<u64>,
+    ) -> Result<Self, ErrorStack> {
+        Ok(Self {
+            sealer,
+            transcript: Transcript::new()?,
+            key,
+            checkpoints: checkpoints.filter(|every| *every > 0),
+        })
+    }
+
+    /// Seals the frame, followed by a checkpoint if one is due
+    fn seal(&mut self, frame: &Frame) -> Result<Vec<u8>, ProtocolError> {
+        let data = bitcode::serialize(frame)?;
+        self.transcript.record(&data)?;
+        let mut sealed = Vec::new();
+        self.sealer.seal(data)?.to_writer(&mut sealed)?;
+
+        // nobody reads what comes after a goodbye
+        if let Some(every) = self.checkpoints
+            && self.transcript.frames().is_multiple_of(every)
+            && !matches!(frame, Frame::Close { .. })
+        {
+            let checkpoint = Frame::Checkpoint(Checkpoint::new(&self.transcript, &self.key)?);
+            self.sealer
+                .seal(bitcode::serialize(&checkpoint)?)?
+                .to_writer(&mut sealed)?;
+        }
+        Ok(sealed)
+    }
+}
+
+/// Seals and writes the frame
+async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    frame: &Frame,
+    session: &mut WriterSession,
+) -> Result<(), ProtocolError> {
+    let sealed = session.seal(frame)?;
+    writer.write_all(&sealed).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Task writing the queued frames one by one, until the goodbye is written,
+/// told to stop, or a frame fails to be written. The writer is shut down
+/// either way, and whatever is left in the queue gets cancelled.
+///
+/// ## Returns
+///
+/// The error that stopped the writing, if any
+pub(crate) async fn write_queue<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut queue: mpsc::Receiver<Outgoing>,
+    mut stop: watch::Receiver<bool>,
+    mut session: WriterSession,
+) -> Result<(), Arc<ProtocolError>> {
+    let mut result = Ok(());
+    loop {
+        let outgoing = tokio::select! {
+            biased;
+            _ = stop.wait_for(|stop| *stop) => break,
+            outgoing = queue.recv() => outgoing,
+        };
+        let Some(outgoing) = outgoing else {
+            break;
+        };
+        // a slow peer mustn't keep the channel from stopping
+        let written = tokio::select! {
+            biased;
+            _ = stop.wait_for(|stop| *stop) => None,
+            written = write_frame(&mut writer, &outgoing.frame, &mut session) => {
+                Some(written)
+            }
+        };
+        match written {
+            Some(Ok(())) => {
+                outgoing.status.send_replace(SendStatus::Sent);
+                if let Frame::Close { .. } = outgoing.frame {
+                    break;
+                }
+            }
+            Some(Err(error)) => {
+                // the stream is in an unknown state, nothing after it can go out
+                let error = Arc::new(error);
+                outgoing
+                    .status
+                    .send_replace(SendStatus::Failed(error.clone()));
+                result = Err(error);
+                break;
+            }
+            None => {
+                outgoing.status.send_replace(SendStatus::Cancelled);
+                break;
+            }
+        }
+    }
+
+    queue.close();
+    while let Ok(outgoing) = queue.try_recv() {
+        outgoing.status.send_replace(SendStatus::Cancelled);
+    }
+    // lets the other party know we are gone
+    let _ = writer.shutdown().await;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{FromPacket, Message, Opener, Packet, new_aes_key};
+    use openssl::rsa::Rsa;
+    use tokio::io::{duplex, sink};
+
+    fn message() -> Frame {
+        Frame::Message(Message::new("hello".to_string()))
+    }
+
+    fn key() -> PKey<Private> {
+        PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap()
+    }
+
+    fn session() -> WriterSession {
+        let sealer = Sealer::new(new_aes_key().unwrap(), &new_aes_key().unwrap()).unwrap();
+        WriterSession::new(sealer, key(), None).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_backpressure() {
+        let (outbox, _queue) = Outbox::new(QueueConfig::new(1, Backpressure::Error));
+        let first = outbox.push(message()).await.unwrap();
+        assert!(matches!(first.status(), SendStatus::Queued));
+        assert!(matches!(
+            outbox.push(message()).await,
+            Err(SendError::QueueFull)
+        ));
+
+        let dropped = outbox
+            .push_with(message(), Backpressure::Drop)
+            .await
+            .unwrap();
+        assert!(matches!(dropped.status(), SendStatus::Dropped));
+        assert!(matches!(dropped.sent().await, Err(SendError::Dropped)));
+    }
+
+    #[tokio::test]
+    async fn test_writer_sends_until_goodbye() {
+        let (outbox, queue) = Outbox::new(QueueConfig::default());
+        let sent = outbox.push(message()).await.unwrap();
+        let goodbye = outbox.push(Frame::Close { reason: None }).await.unwrap();
+        let late = outbox.push(message()).await.unwrap();
+
+        let (_stop, stopped) = watch::channel(false);
+        write_queue(sink(), queue, stopped, session())
+            .await
+            .unwrap();
+        sent.sent().await.unwrap();
+        goodbye.sent().await.unwrap();
+        // nothing goes out after the goodbye
+        assert!(matches!(late.sent().await, Err(SendError::Cancelled)));
+        assert!(matches!(
+            outbox.push(message()).await,
+            Err(SendError::Cancelled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stopping_cancels_the_queue() {
+        let (outbox, queue) = Outbox::new(QueueConfig::default());
+        let queued = outbox.push(message()).await.unwrap();
+
+        let (_stop, stopped) = watch::channel(true);
+        write_queue(sink(), queue, stopped, session())
+            .await
+            .unwrap();
+        assert!(matches!(queued.status(), SendStatus::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn test_write_error_cancels_the_queue() {
+        let (outbox, queue) = Outbox::new(QueueConfig::default());
+        let failed = outbox.push(message()).await.unwrap();
+        let queued = outbox.push(message()).await.unwrap();
+
+        // the other end is gone, so nothing can be written
+        let (writer, _) = duplex(64);
+        let (_stop, stopped) = watch::channel(false);
+        assert!(
+            write_queue(writer, queue, stopped, session())
+                .await
+                .is_err()
+        );
+        assert!(matches!(failed.status(), SendStatus::Failed(_)));
+        assert!(matches!(queued.status(), SendStatus::Cancelled));
+        assert!(matches!(
+            outbox.push(message()).await,
+            Err(SendError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn test_checkpoints_follow_every_few_frames() {
+        let ours = new_aes_key().unwrap();
+        let theirs = new_aes_key().unwrap();
+        let private = key();
+        let public = PKey::public_key_from_pem(&private.public_key_to_pem().unwrap()).unwrap();
+        let sealer = Sealer::new(theirs, &ours).unwrap();
+        let mut session = WriterSession::new(sealer, private, Some(2)).unwrap();
+        let mut written = Vec::new();
+        for _ in 0..2 {
+            written.extend(session.seal(&message()).unwrap());
+        }
+
+        // the other party reads both messages, followed by the checkpoint
+        let mut opener = Opener::new(theirs, &ours).unwrap();
+        let mut transcript = Transcript::new().unwrap();
+        let mut reader = written.as_slice();
+        for _ in 0..2 {
+            let mut packet = Packet::from_reader(&mut reader).unwrap();
+            assert!(opener.open(&mut packet).unwrap());
+            assert!(matches!(
+                Frame::from_packet(&packet).unwrap(),
+                Frame::Message(_)
+            ));
+            transcript.record(packet.data()).unwrap();
+        }
+        let mut packet = Packet::from_reader(&mut reader).unwrap();
+        assert!(opener.open(&mut packet).unwrap());
+        let Frame::Checkpoint(checkpoint) = Frame::from_packet(&packet).unwrap() else {
+            panic!("expected a checkpoint");
+        };
+        assert!(checkpoint.verify(&transcript, &public));
+        assert!(reader.is_empty());
+    }
+}
@@ -34,9 +34,14 @@ impl AesHandshake {
         let mut decryptor = Decrypter::new(private_key)?;
         decryptor.set_rsa_padding(PADDING)?;
         let mut decrypted_buff = vec![0; decryptor.decrypt_len(&self.encrypted_aes_key)?];
-        decryptor.decrypt(&self.encrypted_aes_key, &mut decrypted_buff)?;
+        let len = decryptor.decrypt(&self.encrypted_aes_key, &mut decrypted_buff)?;
 
-        Ok(decrypted_buff[..AES_KEY_SIZE].try_into().unwrap())
+        decrypted_buff[..len].try_into().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected a key of {} bytes", AES_KEY_SIZE),
+            )
+        })
     }
 }
 
@@ -57,4 +62,26 @@ mod tests {
         let decrypted_key = handshake.decrypt_key(&private_key).unwrap();
         assert_eq!(decrypted_key, aes_key);
     }
+
+    #[test]
+    fn test_decrypt_malformed_key() {
+        let private_key = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let public_raw = private_key.public_key_to_pem().unwrap();
+        let public_key = PKey::public_key_from_pem(&public_raw).unwrap();
+
+        // too short a key
+        let mut encryptor = Encrypter::new(&public_key).unwrap();
+        encryptor.set_rsa_padding(PADDING).unwrap();
+        let mut encrypted_aes_key = vec![0; encryptor.encrypt_len(&[1; 3]).unwrap()];
+        let len = encryptor.encrypt(&[1; 3], &mut encrypted_aes_key).unwrap();
+        encrypted_aes_key.truncate(len);
+        let handshake = AesHandshake { encrypted_aes_key };
+        assert!(handshake.decrypt_key(&private_key).is_err());
+
+        // garbage
+        let handshake = AesHandshake {
+            encrypted_aes_key: vec![7; 3],
+        };
+        assert!(handshake.decrypt_key(&private_key).is_err());
+    }
 }
@@ -0,0 +1,138 @@
+use std::{fmt, str::FromStr};
+
+use derive_more::{Display, Error};
+use openssl::{
+    hash::{MessageDigest, hash},
+    pkey::{HasPublic, PKey},
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const FINGERPRINT_SIZE: usize = 256 / 8;
+
+/// A stable identifier of a public key.
+/// It's the SHA-256 digest of the DER encoded public key, meaning the same
+/// key always yields the same fingerprint, regardless of its PEM formatting.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Fingerprint([u8; FINGERPRINT_SIZE]);
+
+impl Fingerprint {
+    /// Computes the fingerprint of the public part of the given key
+    pub fn of<T: HasPublic>(key: &PKey<T>) -> Self {
+        let der = key.public_key_to_der().unwrap();
+        let digest = hash(MessageDigest::sha256(), &der).unwrap();
+        Self(digest.as_ref().try_into().unwrap())
+    }
+
+    /// Abbreviated form, suitable for displaying next to a name
+    pub fn short(&self) -> String {
+        self.to_string()[..16].to_string()
+    }
+
+    /// Raw digest bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Takes the bytes as the digest, as is
+impl From<[u8; FINGERPRINT_SIZE]> for Fingerprint {
+    fn from(bytes: [u8; FINGERPRINT_SIZE]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fingerprint({})", self)
+    }
+}
+
+/// The provided string isn't a valid hex encoded [Fingerprint]
+#[derive(Debug, Display, Error)]
+#[display("invalid fingerprint")]
+pub struct FingerprintParseError;
+
+impl FromStr for Fingerprint {
+    type Err = FingerprintParseError;
+
+    /// Parses a hex encoded fingerprint. Colons between bytes are permitted.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.chars().filter(|c| *c != ':').collect::<Vec<_>>();
+        if digits.len() != FINGERPRINT_SIZE * 2 {
+            return Err(FingerprintParseError);
+        }
+
+        let mut bytes = [0; FINGERPRINT_SIZE];
+        for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+            let high = pair[0].to_digit(16).ok_or(FingerprintParseError)?;
+            let low = pair[1].to_digit(16).ok_or(FingerprintParseError)?;
+            *byte = (high * 16 + low) as u8;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl Serialize for Fingerprint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Fingerprint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let s = String::deserialize(deserializer)?;
+        Fingerprint::from_str(&s).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::rsa::Rsa;
+
+    #[test]
+    fn test_fingerprint_matches_public_part() {
+        let private_key = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let public_key =
+            PKey::public_key_from_pem(&private_key.public_key_to_pem().unwrap()).unwrap();
+
+        assert_eq!(Fingerprint::of(&private_key), Fingerprint::of(&public_key));
+    }
+
+    #[test]
+    fn test_fingerprint_differs_between_keys() {
+        let a = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let b = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+
+        assert_ne!(Fingerprint::of(&a), Fingerprint::of(&b));
+    }
+
+    #[test]
+    fn test_fingerprint_display_parse_roundtrip() {
+        let key = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let fingerprint = Fingerprint::of(&key);
+
+        let parsed = Fingerprint::from_str(&fingerprint.to_string()).unwrap();
+        assert_eq!(parsed, fingerprint);
+        let bytes: [u8; FINGERPRINT_SIZE] = fingerprint.as_bytes().try_into().unwrap();
+        assert_eq!(Fingerprint::from(bytes), fingerprint);
+        assert!(fingerprint.to_string().starts_with(&fingerprint.short()));
+    }
+
+    #[test]
+    fn test_fingerprint_parse_rejects_garbage() {
+        assert!(Fingerprint::from_str("abc").is_err());
+        assert!(Fingerprint::from_str(&"zz".repeat(FINGERPRINT_SIZE)).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+use super::{Checkpoint, Message, Payload};
+
+/// Everything that may be sent over an established channel
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Frame {
+    /// A message for the other party to read
+    Message(Message),
+    /// The sender is closing the channel, and won't send anything else
+    Close { reason: Option<String> },
+    /// The sender vouches for everything they sent before
+    Checkpoint(Checkpoint),
+    /// Application defined data
+    Payload(Payload),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{FromPacket, IntoPacket};
+    use openssl::{pkey::PKey, rsa::Rsa};
+
+    #[test]
+    fn test_frame_packet_roundtrip() {
+        let private = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let packet = Frame::Message(Message::new("hello".to_string()))
+            .into_packet(&private)
+            .unwrap();
+        let Frame::Message(message) = Frame::from_packet(&packet).unwrap() else {
+            panic!("expected a message");
+        };
+        assert_eq!(message.content(), "hello");
+        assert!(!message.is_ours());
+
+        let packet = Frame::Close {
+            reason: Some("bye".to_string()),
+        }
+        .into_packet(&private)
+        .unwrap();
+        assert!(matches!(
+            Frame::from_packet(&packet).unwrap(),
+            Frame::Close { reason: Some(reason) } if reason == "bye"
+        ));
+
+        let payload = Payload::new("app", &(1u8, "two")).unwrap();
+        let packet = Frame::Payload(payload.clone())
+            .into_packet(&private)
+            .unwrap();
+        assert!(matches!(
+            Frame::from_packet(&packet).unwrap(),
+            Frame::Payload(received) if received == payload
+        ));
+    }
+}
@@ -2,24 +2,26 @@ use std::io::{self, Read, Write};
 
 use bitcode::{deserialize, serialize};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
-use super::io::{read_buffer, write_buffer};
+#[cfg(feature = "async")]
+use super::io::read_frame_async;
+use super::{
+    fingerprint::Fingerprint,
+    io::{read_buffer, write_buffer},
+};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ProtocolPath {
     /// I don't know you, I would like to exchange RSA keys
+    #[default]
     RsaExchange,
     /// I know you, you should know me
     AesExchange,
 }
 
-impl Default for ProtocolPath {
-    fn default() -> Self {
-        ProtocolPath::RsaExchange
-    }
-}
-
-const PROTOCOL_V: u16 = 1;
+const PROTOCOL_V: u16 = 4;
 
 /// Intended to be the first sent "packet". Unsigned nor encrypted.
 /// Meant to point the recipient towards what we want to do next.
@@ -27,6 +29,10 @@ const PROTOCOL_V: u16 = 1;
 pub struct Handshake {
     path: ProtocolPath,
     version: u16,
+    /// [Fingerprint] of our public key, so that the recipient can find out
+    /// which keys to use for [ProtocolPath::AesExchange].
+    /// Just a hint, the keys are verified during the exchange itself.
+    key_hint: Option<Fingerprint>,
 }
 
 impl Handshake {
@@ -34,9 +40,23 @@ impl Handshake {
         Self {
             path,
             version: PROTOCOL_V,
+            key_hint: None,
         }
     }
 
+    /// Creates a new [Handshake], that hints at which key we will be using
+    pub fn with_hint(path: ProtocolPath, key_hint: Fingerprint) -> Self {
+        Self {
+            key_hint: Some(key_hint),
+            ..Self::new(path)
+        }
+    }
+
+    /// Gets the [Fingerprint] of the sender's public key, if they sent one
+    pub fn key_hint(&self) -> Option<&Fingerprint> {
+        self.key_hint.as_ref()
+    }
+
     /// Checks if the protocol versions match
     pub fn version_ok(&self) -> bool {
         self.version == PROTOCOL_V
@@ -54,7 +74,30 @@ impl Handshake {
 
     /// Deserializes and returns the message
     pub fn from_reader<R: Read>(reader: &mut R) -> Result<Self, io::Error> {
-        Ok(deserialize(&read_buffer(reader)?).unwrap())
+        deserialize(&read_buffer(reader)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Serializes and sends the message over an asynchronous stream
+    #[cfg(feature = "async")]
+    pub async fn to_async_writer<W: AsyncWrite + Unpin>(
+        &self,
+        stream: &mut W,
+    ) -> Result<(), io::Error> {
+        let mut buf = Vec::new();
+        self.to_writer(&mut buf)?;
+        stream.write_all(&buf).await?;
+        stream.flush().await
+    }
+
+    /// Deserializes and returns the message from an asynchronous stream
+    #[cfg(feature = "async")]
+    pub async fn from_async_reader<R: AsyncRead + Unpin>(
+        reader: &mut R,
+    ) -> Result<Self, io::Error> {
+        let mut frame = Vec::new();
+        read_frame_async(reader, &mut frame).await?;
+        Self::from_reader(&mut frame.as_slice())
     }
 }
 
@@ -67,7 +110,7 @@ impl Default for Handshake {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
+    use std::{io::Cursor, str::FromStr};
 
     #[test]
     fn test_handshake_default() {
@@ -100,4 +143,23 @@ mod tests {
         assert!(matches!(deserialized.path, ProtocolPath::AesExchange));
         assert_eq!(deserialized.version, handshake.version);
     }
+
+    #[test]
+    fn test_handshake_key_hint() {
+        let fingerprint = Fingerprint::from_str(&"ab".repeat(32)).unwrap();
+        let handshake = Handshake::with_hint(ProtocolPath::AesExchange, fingerprint);
+        let mut buf = Vec::new();
+        handshake.to_writer(&mut buf).unwrap();
+
+        let deserialized = Handshake::from_reader(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(deserialized.key_hint(), Some(&fingerprint));
+        assert!(Handshake::default().key_hint().is_none());
+    }
+
+    #[test]
+    fn test_handshake_garbage() {
+        let mut buf = Vec::new();
+        write_buffer(&mut buf, &[0xff; 3]).unwrap();
+        assert!(Handshake::from_reader(&mut Cursor::new(buf)).is_err());
+    }
 }
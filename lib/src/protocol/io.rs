@@ -1,11 +1,30 @@
 use std::io::{self, Read, Write};
 
 use integer_encoding::{VarIntReader, VarIntWriter};
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// How many bytes a VarInt encoded [u32] may take up
+#[cfg(feature = "async")]
+const MAX_VARINT_LEN: usize = 5;
+/// The longest buffer we read, so that a peer can't make us allocate
+/// whatever length it claims before anything is authenticated
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Checks the length a buffer claims, before allocating for it
+fn checked_len(length: u32) -> Result<usize, io::Error> {
+    match length as usize {
+        length if length > MAX_FRAME_LEN => {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"))
+        }
+        length => Ok(length),
+    }
+}
 
 /// Reads a buffer from a stream
 pub fn read_buffer<R: Read>(reader: &mut R) -> Result<Vec<u8>, io::Error> {
-    let length = reader.read_varint::<u32>()?;
-    let mut data = vec![0; length as usize];
+    let length = checked_len(reader.read_varint::<u32>()?)?;
+    let mut data = vec![0; length];
     reader.read_exact(&mut data)?;
 
     Ok(data)
@@ -17,6 +36,36 @@ pub fn write_buffer<W: Write>(writer: &mut W, data: &[u8]) -> std::io::Result<()
     writer.write_all(data)
 }
 
+/// Reads a whole buffer, length prefix included, from an asynchronous
+/// stream, appending it to `frame`. Parsing is left to [read_buffer], once
+/// everything has arrived.
+#[cfg(feature = "async")]
+pub async fn read_frame_async<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    frame: &mut Vec<u8>,
+) -> Result<(), io::Error> {
+    let start = frame.len();
+    loop {
+        let byte = reader.read_u8().await?;
+        frame.push(byte);
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if frame.len() - start >= MAX_VARINT_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "length overflow",
+            ));
+        }
+    }
+
+    let length = checked_len((&frame[start..]).read_varint::<u32>()?)?;
+    let data = frame.len();
+    frame.resize(data + length, 0);
+    reader.read_exact(&mut frame[data..]).await?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -52,4 +101,50 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().kind(), ErrorKind::UnexpectedEof);
     }
+
+    #[test]
+    fn test_read_buffer_too_long() {
+        let mut buf = Vec::new();
+        buf.write_varint(u32::MAX).unwrap();
+        let result = read_buffer(&mut Cursor::new(buf));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_read_frame_async_too_long() {
+        let mut buf = Vec::new();
+        buf.write_varint(u32::MAX).unwrap();
+        let mut frame = Vec::new();
+        let result = read_frame_async(&mut buf.as_slice(), &mut frame).await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+        // nothing was allocated for the claimed length
+        assert!(frame.capacity() < MAX_FRAME_LEN);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_read_frame_async_matches_sync() {
+        let data = vec![7; 300];
+        let mut buf = Vec::new();
+        write_buffer(&mut buf, &data).unwrap();
+        write_buffer(&mut buf, &[]).unwrap();
+
+        let mut reader = buf.as_slice();
+        let mut frames = Vec::new();
+        read_frame_async(&mut reader, &mut frames).await.unwrap();
+        read_frame_async(&mut reader, &mut frames).await.unwrap();
+        assert_eq!(frames, buf);
+        assert_eq!(
+            read_frame_async(&mut reader, &mut frames)
+                .await
+                .unwrap_err()
+                .kind(),
+            ErrorKind::UnexpectedEof
+        );
+
+        let mut frames = frames.as_slice();
+        assert_eq!(read_buffer(&mut frames).unwrap(), data);
+        assert!(read_buffer(&mut frames).unwrap().is_empty());
+    }
 }
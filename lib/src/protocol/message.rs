@@ -2,6 +2,7 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize, ser::SerializeStruct};
 
 /// General purpose message packet
+#[derive(Clone, Debug)]
 pub struct Message {
     content: String,
     timestamp: DateTime<Utc>,
@@ -70,7 +71,6 @@ impl<'de> Deserialize<'de> for Message {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json;
 
     #[test]
     fn test_message_new_sets_fields() {
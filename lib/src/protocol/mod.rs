@@ -1,42 +1,90 @@
-/// Low level serialization for [RsaHandshake], [AesHandshake], [Message]
+/// Low level serialization for [RsaHandshake], [AesHandshake], [Frame]
+#[cfg(feature = "async")]
 mod packet;
+#[cfg(feature = "async")]
 pub use packet::{FromPacket, IntoPacket, Packet};
 
 /// Basic connection initialization
+#[cfg(feature = "async")]
 mod handshake;
+#[cfg(feature = "async")]
 pub use handshake::{Handshake, ProtocolPath};
 
+#[cfg(feature = "async")]
 mod rsa_handshake;
+#[cfg(feature = "async")]
 pub use rsa_handshake::RsaHandshake;
 
+#[cfg(feature = "async")]
 mod aes_handshake;
+#[cfg(feature = "async")]
 pub use aes_handshake::AesHandshake;
 
 mod message;
 pub use message::Message;
 
+/// Application defined data, sent alongside messages
+mod payload;
+pub use payload::Payload;
+
+/// What established channels exchange
+#[cfg(feature = "async")]
+mod frame;
+#[cfg(feature = "async")]
+pub use frame::Frame;
+
+/// Authentication of frames within an established channel
+#[cfg(feature = "async")]
+mod session;
+#[cfg(feature = "async")]
+pub use session::{Checkpoint, Opener, Sealer, Transcript};
+
+/// What clients tell a relay, and what it answers
+#[cfg(feature = "async")]
+mod relay;
+#[cfg(feature = "async")]
+pub use relay::{CHALLENGE_SIZE, REGISTRATION_LABEL, RelayRequest, RelayResponse};
+
+/// Signatures proving who holds a key
+#[cfg(feature = "async")]
+mod proof;
+#[cfg(feature = "async")]
+pub use proof::KeyProof;
+
+/// Stable identification of public keys
+mod fingerprint;
+pub use fingerprint::{Fingerprint, FingerprintParseError};
+
 /// Routines for serializing buffers.
 ///
 /// We serialize buffers by prepending them with VarInt encoded length, followed
 /// with the buffer contents.
+#[cfg(feature = "async")]
 mod io;
 
+#[cfg(feature = "async")]
 const AES_KEY_SIZE: usize = 256 / 8;
+#[cfg(feature = "async")]
 const AES_IV_SIZE: usize = 128 / 8;
 
+#[cfg(feature = "async")]
 pub type AesKey = [u8; AES_KEY_SIZE];
+#[cfg(feature = "async")]
 pub type AesIv = [u8; AES_IV_SIZE];
 
+#[cfg(feature = "async")]
 use openssl::{error::ErrorStack, rand::rand_bytes};
 
+#[cfg(feature = "async")]
 pub fn new_aes_key() -> Result<AesKey, ErrorStack> {
     let mut aes_key = [0; AES_KEY_SIZE];
     rand_bytes(&mut aes_key)?;
-    return Ok(aes_key);
+    Ok(aes_key)
 }
 
+#[cfg(feature = "async")]
 pub fn new_aes_iv() -> Result<AesIv, ErrorStack> {
     let mut iv = [0; AES_IV_SIZE];
     rand_bytes(&mut iv)?;
-    return Ok(iv);
+    Ok(iv)
 }
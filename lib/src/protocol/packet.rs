@@ -5,24 +5,33 @@ use std::{
 
 use bitcode::{self, deserialize, serialize};
 use openssl::{
+    error::ErrorStack,
     hash::MessageDigest,
+    memcmp,
     pkey::{PKey, Private, Public},
     sign::{Signer, Verifier},
     symm::{Cipher, decrypt, encrypt},
 };
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
+#[cfg(feature = "async")]
+use super::io::read_frame_async;
 use super::{
     AesIv, AesKey,
     io::{read_buffer, write_buffer},
     new_aes_iv,
+    session::MacKey,
 };
 
 /// Structured primitive data carrier
 /// Each packet is signed, and optionally encrypted. If encrypted it also
-/// carries the `iv`.
+/// carries the `iv`. Packets of an established session are
+/// [sealed](Self::seal) instead, carrying a MAC in place of the signature.
 pub struct Packet {
     data: Vec<u8>,
+    /// Either the signature, or the MAC
     signature: Vec<u8>,
     iv: Option<AesIv>,
 }
@@ -48,7 +57,11 @@ impl Packet {
         let iv = if potential_iv.is_empty() {
             None
         } else {
-            Some(potential_iv.try_into().unwrap())
+            Some(
+                potential_iv
+                    .try_into()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid IV length"))?,
+            )
         };
 
         Ok(Packet {
@@ -58,6 +71,19 @@ impl Packet {
         })
     }
 
+    /// Reads a packet from an asynchronous reader.
+    #[cfg(feature = "async")]
+    pub async fn from_async_reader<R: AsyncRead + Unpin>(
+        reader: &mut R,
+    ) -> Result<Self, io::Error> {
+        // data, signature and iv
+        let mut frames = Vec::new();
+        for _ in 0..3 {
+            read_frame_async(reader, &mut frames).await?;
+        }
+        Self::from_reader(&mut frames.as_slice())
+    }
+
     /// Writes a packet to a writer.
     pub fn to_writer<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
         write_buffer(writer, &self.data)?;
@@ -70,6 +96,18 @@ impl Packet {
         Ok(())
     }
 
+    /// Writes a packet to an asynchronous writer, all at once.
+    #[cfg(feature = "async")]
+    pub async fn to_async_writer<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+    ) -> Result<(), io::Error> {
+        let mut buf = Vec::new();
+        self.to_writer(&mut buf)?;
+        writer.write_all(&buf).await?;
+        writer.flush().await
+    }
+
     /// Encrypts the packet
     pub fn encrypt(&mut self, other_aes_key: &AesKey) -> Result<(), io::Error> {
         let cipher = Cipher::aes_256_cbc();
@@ -102,8 +140,50 @@ impl Packet {
             .verify_oneshot(&self.signature, &self.data)
             .unwrap_or(false)
     }
+
+    /// Encrypts the data, and authenticates it as the given frame of the
+    /// session, rather than signing it
+    pub fn seal(
+        data: Vec<u8>,
+        other_aes_key: &AesKey,
+        mac_key: &MacKey,
+        sequence: u64,
+    ) -> Result<Self, ErrorStack> {
+        let iv = new_aes_iv()?;
+        let data = encrypt(Cipher::aes_256_cbc(), other_aes_key, Some(&iv), &data)?;
+        Ok(Packet {
+            signature: mac_key.tag(sequence, &iv, &data)?,
+            data,
+            iv: Some(iv),
+        })
+    }
+
+    /// Checks the packet was sealed as the given frame of the session, and
+    /// decrypts it. Returns false, leaving the packet as is, if it wasn't.
+    pub fn open(
+        &mut self,
+        our_aes_key: &AesKey,
+        mac_key: &MacKey,
+        sequence: u64,
+    ) -> Result<bool, ErrorStack> {
+        let Some(iv) = &self.iv else {
+            return Ok(false);
+        };
+        let tag = mac_key.tag(sequence, iv, &self.data)?;
+        if tag.len() != self.signature.len() || !memcmp::eq(&tag, &self.signature) {
+            return Ok(false);
+        }
+        self.data = decrypt(Cipher::aes_256_cbc(), our_aes_key, Some(iv), &self.data)?;
+        Ok(true)
+    }
+
+    /// Get the serialized contents
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
 }
 
+#[allow(clippy::wrong_self_convention)]
 pub trait IntoPacket<E: error::Error> {
     fn into_packet(&self, private_key: &PKey<Private>) -> Result<Packet, E>;
 }
@@ -149,6 +229,17 @@ mod tests {
         assert_eq!(read_packet.iv, packet.iv);
     }
 
+    #[test]
+    fn test_packet_read_invalid_iv() {
+        let mut buf = Vec::new();
+        write_buffer(&mut buf, b"data").unwrap();
+        write_buffer(&mut buf, b"signature").unwrap();
+        write_buffer(&mut buf, &[0; 3]).unwrap();
+
+        let result = Packet::from_reader(&mut Cursor::new(buf));
+        assert!(matches!(result, Err(e) if e.kind() == io::ErrorKind::InvalidData));
+    }
+
     #[test]
     fn test_packet_signature_verification() {
         let rsa = Rsa::generate(2048).unwrap();
@@ -0,0 +1,62 @@
+use bitcode::{deserialize, serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+/// Application defined data, sent over a channel alongside the messages.
+/// The tag tells applications apart, and what the data should be decoded as.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Payload {
+    tag: String,
+    data: Vec<u8>,
+}
+
+impl Payload {
+    /// Serializes the value, under the given tag
+    pub fn new<P: Serialize + ?Sized>(
+        tag: impl Into<String>,
+        value: &P,
+    ) -> Result<Self, bitcode::Error> {
+        Ok(Self {
+            tag: tag.into(),
+            data: serialize(value)?,
+        })
+    }
+
+    /// Get the tag of the application the payload is meant for
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Get the serialized value
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Deserializes the value, which needs to be of the type it was
+    /// created from
+    pub fn decode<P: DeserializeOwned>(&self) -> Result<P, bitcode::Error> {
+        deserialize(&self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Status {
+        build: u32,
+        passing: bool,
+    }
+
+    #[test]
+    fn test_payload_roundtrip() {
+        let status = Status {
+            build: 42,
+            passing: true,
+        };
+        let payload = Payload::new("ci/status", &status).unwrap();
+        assert_eq!(payload.tag(), "ci/status");
+        assert_eq!(payload.decode::<Status>().unwrap(), status);
+        assert!(payload.decode::<Vec<String>>().is_err());
+    }
+}
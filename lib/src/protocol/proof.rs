@@ -0,0 +1,71 @@
+use openssl::{
+    error::ErrorStack,
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    sign::{Signer, Verifier},
+};
+use serde::{Deserialize, Serialize};
+
+use super::fingerprint::Fingerprint;
+
+/// Proof of holding the private key behind a fingerprint, by signing
+/// a message. The label tells what the message is, so that a signature
+/// made for one purpose can't be passed off for another.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct KeyProof {
+    /// DER encoded public key
+    public_key: Vec<u8>,
+    /// Signature of the label, followed by the message
+    signature: Vec<u8>,
+}
+
+impl KeyProof {
+    /// Signs the message with the key
+    pub fn new(key: &PKey<Private>, label: &[u8], message: &[u8]) -> Result<Self, ErrorStack> {
+        let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+        signer.update(label)?;
+        signer.update(message)?;
+        Ok(Self {
+            public_key: key.public_key_to_der()?,
+            signature: signer.sign_to_vec()?,
+        })
+    }
+
+    /// Gets the [Fingerprint] of the key, if it signed the message
+    pub fn verify(&self, label: &[u8], message: &[u8]) -> Option<Fingerprint> {
+        let key = PKey::public_key_from_der(&self.public_key).ok()?;
+        let signed = || -> Result<bool, ErrorStack> {
+            let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+            verifier.update(label)?;
+            verifier.update(message)?;
+            verifier.verify(&self.signature)
+        };
+        signed().unwrap_or(false).then(|| Fingerprint::of(&key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::rsa::Rsa;
+
+    #[test]
+    fn test_key_proof() {
+        let key = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let proof = KeyProof::new(&key, b"label", b"message").unwrap();
+        assert_eq!(
+            proof.verify(b"label", b"message"),
+            Some(Fingerprint::of(&key))
+        );
+        // a proof is only good for what it was made for
+        assert_eq!(proof.verify(b"label", b"another message"), None);
+        assert_eq!(proof.verify(b"another label", b"message"), None);
+
+        let other = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+        let forged = KeyProof {
+            public_key: other.public_key_to_der().unwrap(),
+            ..proof
+        };
+        assert_eq!(forged.verify(b"label", b"message"), None);
+    }
+}
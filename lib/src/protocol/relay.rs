@@ -0,0 +1,101 @@
+use std::io;
+
+use bitcode::{deserialize, serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use super::{
+    fingerprint::Fingerprint,
+    io::{read_buffer, read_frame_async, write_buffer},
+    proof::KeyProof,
+};
+
+/// How many random bytes the keys registered under have to sign
+pub const CHALLENGE_SIZE: usize = 32;
+/// What the challenges are signed as, see [KeyProof]
+pub const REGISTRATION_LABEL: &[u8] = b"grapevine relay registration";
+
+/// What a relay is told. Unsigned nor encrypted, the relay has no business
+/// knowing more than who to connect.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+pub enum RelayRequest {
+    /// Wait to be connected with whoever asks for us. The relay answers
+    /// with a [RelayResponse::Challenge], which is answered with
+    /// [RelayRequest::Prove].
+    Register,
+    /// The keys we want to be reached by, vouching for the challenge
+    Prove(Vec<KeyProof>),
+    /// Connect us with whoever is registered under the fingerprint
+    Connect(Fingerprint),
+}
+
+/// What the relay answers, once it has something to say. Whatever follows
+/// [RelayResponse::Connected] or [RelayResponse::Reached] comes from the
+/// other party.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RelayResponse {
+    /// What the keys registered under have to sign
+    Challenge([u8; CHALLENGE_SIZE]),
+    /// We are still registered, and the relay is still around
+    Ping,
+    /// Someone asked for us under the fingerprint. From now on, the stream
+    /// goes to them.
+    Reached(Fingerprint),
+    /// From now on, the stream goes to the other party
+    Connected,
+    /// Nobody is registered under the fingerprint
+    Unknown,
+}
+
+async fn write_async<W: AsyncWrite + Unpin, T: Serialize>(
+    stream: &mut W,
+    value: &T,
+) -> Result<(), io::Error> {
+    let mut buf = Vec::new();
+    write_buffer(&mut buf, &serialize(value).unwrap())?;
+    stream.write_all(&buf).await?;
+    stream.flush().await
+}
+
+async fn read_async<R: AsyncRead + Unpin, T: DeserializeOwned>(
+    reader: &mut R,
+) -> Result<T, io::Error> {
+    let mut frame = Vec::new();
+    read_frame_async(reader, &mut frame).await?;
+    deserialize(&read_buffer(&mut frame.as_slice())?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl RelayRequest {
+    /// Serializes and sends the request over an asynchronous stream
+    pub async fn to_async_writer<W: AsyncWrite + Unpin>(
+        &self,
+        stream: &mut W,
+    ) -> Result<(), io::Error> {
+        write_async(stream, self).await
+    }
+
+    /// Deserializes and returns the request from an asynchronous stream
+    pub async fn from_async_reader<R: AsyncRead + Unpin>(
+        reader: &mut R,
+    ) -> Result<Self, io::Error> {
+        read_async(reader).await
+    }
+}
+
+impl RelayResponse {
+    /// Serializes and sends the response over an asynchronous stream
+    pub async fn to_async_writer<W: AsyncWrite + Unpin>(
+        self,
+        stream: &mut W,
+    ) -> Result<(), io::Error> {
+        write_async(stream, &self).await
+    }
+
+    /// Deserializes and returns the response from an asynchronous stream
+    pub async fn from_async_reader<R: AsyncRead + Unpin>(
+        reader: &mut R,
+    ) -> Result<Self, io::Error> {
+        read_async(reader).await
+    }
+}
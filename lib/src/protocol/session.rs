@@ -0,0 +1,252 @@
+use openssl::{
+    error::ErrorStack,
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    sign::Signer,
+};
+#[cfg(feature = "async")]
+use openssl::{hash::Hasher, pkey::Public, sign::Verifier};
+#[cfg(feature = "async")]
+use serde::{Deserialize, Serialize};
+
+use super::{AesKey, Packet};
+
+/// Keeps the keys of either direction apart
+const MAC_LABEL: &[u8] = b"grapevine frame mac";
+
+/// Key authenticating the frames sent in one direction of a session.
+/// Derived from both AES keys exchanged during the handshake, so that only
+/// the two parties know it.
+#[derive(Clone)]
+pub struct MacKey(PKey<Private>);
+
+impl MacKey {
+    /// Derives the key for frames encrypted with the recipient's AES key,
+    /// and sent by the owner of the sender's AES key
+    pub fn derive(recipient_key: &AesKey, sender_key: &AesKey) -> Result<Self, ErrorStack> {
+        let key = PKey::hmac(recipient_key)?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(MAC_LABEL)?;
+        signer.update(sender_key)?;
+        Ok(Self(PKey::hmac(&signer.sign_to_vec()?)?))
+    }
+
+    /// Computes the tag of the given frame of the session
+    pub(super) fn tag(&self, sequence: u64, iv: &[u8], data: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.0)?;
+        signer.update(&sequence.to_be_bytes())?;
+        signer.update(iv)?;
+        signer.update(data)?;
+        signer.sign_to_vec()
+    }
+}
+
+/// Our end of an established session, sealing the frames we send
+pub struct Sealer {
+    aes_key: AesKey,
+    mac_key: MacKey,
+    /// How many frames were sealed so far
+    sequence: u64,
+}
+
+impl Sealer {
+    pub fn new(their_aes_key: AesKey, our_aes_key: &AesKey) -> Result<Self, ErrorStack> {
+        Ok(Self {
+            mac_key: MacKey::derive(&their_aes_key, our_aes_key)?,
+            aes_key: their_aes_key,
+            sequence: 0,
+        })
+    }
+
+    /// Seals the serialized frame as the next one of the session
+    pub fn seal(&mut self, data: Vec<u8>) -> Result<Packet, ErrorStack> {
+        let packet = Packet::seal(data, &self.aes_key, &self.mac_key, self.sequence)?;
+        self.sequence += 1;
+        Ok(packet)
+    }
+}
+
+/// The other party's end of an established session, opening the frames
+/// they send
+pub struct Opener {
+    aes_key: AesKey,
+    mac_key: MacKey,
+    /// How many frames were opened so far
+    sequence: u64,
+}
+
+impl Opener {
+    pub fn new(our_aes_key: AesKey, their_aes_key: &AesKey) -> Result<Self, ErrorStack> {
+        Ok(Self {
+            mac_key: MacKey::derive(&our_aes_key, their_aes_key)?,
+            aes_key: our_aes_key,
+            sequence: 0,
+        })
+    }
+
+    /// Opens the packet, which has to be the next frame of the session.
+    /// Returns false if it isn't, be it forged, replayed or out of order.
+    pub fn open(&mut self, packet: &mut Packet) -> Result<bool, ErrorStack> {
+        let opened = packet.open(&self.aes_key, &self.mac_key, self.sequence)?;
+        if opened {
+            self.sequence += 1;
+        }
+        Ok(opened)
+    }
+}
+
+/// Running digest of the frames sent in one direction of a session, which
+/// [Checkpoint]s vouch for
+#[cfg(feature = "async")]
+pub struct Transcript {
+    hasher: Hasher,
+    /// How many frames were recorded so far
+    frames: u64,
+}
+
+#[cfg(feature = "async")]
+impl Transcript {
+    pub fn new() -> Result<Self, ErrorStack> {
+        Ok(Self {
+            hasher: Hasher::new(MessageDigest::sha256())?,
+            frames: 0,
+        })
+    }
+
+    /// Adds the serialized frame to the transcript
+    pub fn record(&mut self, data: &[u8]) -> Result<(), ErrorStack> {
+        self.hasher.update(data)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Get how many frames were recorded so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Get the digest of the frames so far, leaving the transcript going
+    fn digest(&self) -> Result<Vec<u8>, ErrorStack> {
+        Ok(self.hasher.clone().finish()?.to_vec())
+    }
+}
+
+/// The sender's identity key vouching for everything they sent so far.
+/// Frames are authenticated with the session keys, which both parties know,
+/// so these are what ties the conversation to the sender alone.
+#[cfg(feature = "async")]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Checkpoint {
+    frames: u64,
+    digest: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+#[cfg(feature = "async")]
+impl Checkpoint {
+    /// Signs the current state of the transcript
+    pub fn new(transcript: &Transcript, private_key: &PKey<Private>) -> Result<Self, ErrorStack> {
+        let frames = transcript.frames();
+        let digest = transcript.digest()?;
+        let mut signer = Signer::new(MessageDigest::sha256(), private_key)?;
+        signer.update(&frames.to_be_bytes())?;
+        signer.update(&digest)?;
+        Ok(Self {
+            frames,
+            digest,
+            signature: signer.sign_to_vec()?,
+        })
+    }
+
+    /// Checks the checkpoint is signed by the public key, and matches what
+    /// we have received
+    pub fn verify(&self, transcript: &Transcript, public_key: &PKey<Public>) -> bool {
+        let signed = || -> Result<bool, ErrorStack> {
+            let mut verifier = Verifier::new(MessageDigest::sha256(), public_key)?;
+            verifier.update(&self.frames.to_be_bytes())?;
+            verifier.update(&self.digest)?;
+            verifier.verify(&self.signature)
+        };
+        self.frames == transcript.frames()
+            && transcript
+                .digest()
+                .is_ok_and(|digest| digest == self.digest)
+            && signed().unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::new_aes_key, *};
+    #[cfg(feature = "async")]
+    use openssl::rsa::Rsa;
+
+    fn session() -> (Sealer, Opener) {
+        let ours = new_aes_key().unwrap();
+        let theirs = new_aes_key().unwrap();
+        (
+            Sealer::new(theirs, &ours).unwrap(),
+            Opener::new(theirs, &ours).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_sealed_frames_open_in_order() {
+        let (mut sealer, mut opener) = session();
+        let mut first = sealer.seal(b"first".to_vec()).unwrap();
+        let mut second = sealer.seal(b"second".to_vec()).unwrap();
+        assert_ne!(first.data(), b"first");
+
+        // a frame out of order doesn't open, and doesn't break the session
+        assert!(!opener.open(&mut second).unwrap());
+        assert!(opener.open(&mut first).unwrap());
+        assert_eq!(first.data(), b"first");
+        assert!(opener.open(&mut second).unwrap());
+        assert_eq!(second.data(), b"second");
+    }
+
+    #[test]
+    fn test_tampered_frame_doesnt_open() {
+        let (mut sealer, mut opener) = session();
+        let mut packet = sealer.seal(b"hello".to_vec()).unwrap();
+        let mut tampered = Packet::from_reader(&mut {
+            let mut buf = Vec::new();
+            packet.to_writer(&mut buf).unwrap();
+            let last = buf.len() - 1;
+            buf[last] ^= 0xFF;
+            std::io::Cursor::new(buf)
+        })
+        .unwrap();
+        assert!(!opener.open(&mut tampered).unwrap());
+        assert!(opener.open(&mut packet).unwrap());
+    }
+
+    #[test]
+    fn test_directions_have_different_keys() {
+        let ours = new_aes_key().unwrap();
+        let theirs = new_aes_key().unwrap();
+        let mut sealer = Sealer::new(theirs, &ours).unwrap();
+        // a frame reflected back at us
+        let mut opener = Opener::new(ours, &theirs).unwrap();
+        let mut packet = sealer.seal(b"hello".to_vec()).unwrap();
+        assert!(!opener.open(&mut packet).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn test_checkpoint_verification() {
+        let private = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let public = PKey::public_key_from_pem(&private.public_key_to_pem().unwrap()).unwrap();
+
+        let mut sent = Transcript::new().unwrap();
+        let mut received = Transcript::new().unwrap();
+        sent.record(b"hello").unwrap();
+        received.record(b"hello").unwrap();
+        let checkpoint = Checkpoint::new(&sent, &private).unwrap();
+        assert!(checkpoint.verify(&received, &public));
+
+        // the transcripts have diverged
+        received.record(b"injected").unwrap();
+        assert!(!checkpoint.verify(&received, &public));
+    }
+}
@@ -0,0 +1,547 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use openssl::{
+    pkey::{PKey, Private},
+    rand::rand_bytes,
+};
+use tokio::{
+    io::{AsyncReadExt, copy_bidirectional},
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+    task::spawn_blocking,
+    time::{Instant, interval_at, sleep, timeout},
+};
+
+use super::{
+    Shared,
+    address::{self, HostAddr},
+    protocol::{
+        CHALLENGE_SIZE, Fingerprint, KeyProof, REGISTRATION_LABEL, RelayRequest, RelayResponse,
+    },
+    transport::{Acceptor, Endpoint},
+};
+
+/// How long a client has to say what it wants, after connecting
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many fingerprints a single client may register under
+const MAX_REGISTERED: usize = 256;
+/// How many fingerprints the clients may be registered under altogether
+const MAX_REGISTRATIONS: usize = 64 * 1024;
+/// How many clients may be waiting to be connected at once
+const MAX_WAITING: usize = 4096;
+/// How many connections may be open from a single IP address at once
+const MAX_CONNECTIONS_PER_IP: usize = 64;
+/// How long a [RelayListener] waits before registering again, after failing
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+/// How often the relay lets the registered clients know it's still there,
+/// which also keeps NAT mappings from timing out
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a [RelayListener] waits to hear from the relay, before
+/// registering again
+const PING_TIMEOUT: Duration = Duration::from_secs(75);
+/// How long the relay waits before accepting again, after failing to accept
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Hands whoever connected to a registered client, along with the
+/// fingerprint they asked for
+type Handoff = oneshot::Sender<(Fingerprint, TcpStream, Connection)>;
+
+/// A registered client, waiting to be taken by whoever connects first
+type Waiting = Arc<Mutex<Option<Handoff>>>;
+
+/// What the clients of a [Relay] take up, kept within limits
+#[derive(Default)]
+struct Load {
+    /// Open connections, by the IP address they come from
+    connections: HashMap<IpAddr, usize>,
+    /// Clients waiting to be connected
+    waiting: usize,
+    /// Fingerprints the waiting clients are registered under
+    registrations: usize,
+}
+
+/// An open connection, counted against the [MAX_CONNECTIONS_PER_IP] until
+/// dropped
+struct Connection {
+    load: Shared<Load>,
+    ip: IpAddr,
+}
+
+impl Connection {
+    /// Counts the connection, unless there are too many from the IP
+    fn new(load: &Shared<Load>, ip: IpAddr) -> Option<Self> {
+        let ip = ip.to_canonical();
+        let mut locked = load.lock().unwrap();
+        let connections = locked.connections.entry(ip).or_default();
+        if *connections >= MAX_CONNECTIONS_PER_IP {
+            return None;
+        }
+        *connections += 1;
+        Some(Self {
+            load: load.clone(),
+            ip,
+        })
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut load = self.load.lock().unwrap();
+        if let Some(connections) = load.connections.get_mut(&self.ip) {
+            *connections -= 1;
+            if *connections == 0 {
+                load.connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// A waiting client, counted against the [MAX_WAITING], and its
+/// fingerprints against the [MAX_REGISTRATIONS], until dropped
+struct Registration {
+    load: Shared<Load>,
+    ids: usize,
+}
+
+impl Registration {
+    /// Counts the client, unless the relay is full
+    fn new(load: &Shared<Load>, ids: usize) -> Option<Self> {
+        let mut locked = load.lock().unwrap();
+        if locked.waiting >= MAX_WAITING || locked.registrations + ids > MAX_REGISTRATIONS {
+            return None;
+        }
+        locked.waiting += 1;
+        locked.registrations += ids;
+        Some(Self {
+            load: load.clone(),
+            ids,
+        })
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut load = self.load.lock().unwrap();
+        load.waiting -= 1;
+        load.registrations -= self.ids;
+    }
+}
+
+/// Connects clients who can't reach each other directly, by splicing
+/// their streams together. Clients register under the fingerprints they
+/// want to be reached by, proving they hold the keys behind them, and
+/// others connect to them by fingerprint.
+///
+/// The relay never sees more than the public keys. Everything after is the
+/// usual handshake and the encrypted frames of the channel, which the relay
+/// passes on as they are.
+///
+/// So that nobody can take it over, the relay limits how many connections
+/// an IP address may have open, and how many clients may wait, registered
+/// under how many fingerprints. Clients over the limits are hung up on.
+#[derive(Clone, Default)]
+pub struct Relay {
+    /// Registered clients, by the fingerprints they registered under
+    registered: Shared<HashMap<Fingerprint, Vec<Waiting>>>,
+    load: Shared<Load>,
+}
+
+impl Relay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serves the clients connecting to the listener. Failing to accept
+    /// doesn't stop the relay, the errors are handed to `on_error`.
+    pub async fn serve(&self, listener: TcpListener, mut on_error: impl FnMut(io::Error)) {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    on_error(e);
+                    // errors like running out of file descriptors tend to persist
+                    sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+            };
+            let Some(connection) = Connection::new(&self.load, peer.ip()) else {
+                continue;
+            };
+            let relay = self.clone();
+            tokio::spawn(async move {
+                // clients who misbehave are simply hung up on
+                let _ = relay.handle(stream, connection).await;
+            });
+        }
+    }
+
+    /// Get how many clients are waiting to be connected
+    pub fn waiting(&self) -> usize {
+        self.prune();
+        let mut waiting = self
+            .registered
+            .lock()
+            .unwrap()
+            .values()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>();
+        waiting.sort_by_key(Arc::as_ptr);
+        waiting.dedup_by(|a, b| Arc::ptr_eq(a, b));
+        waiting.len()
+    }
+
+    async fn handle(&self, mut stream: TcpStream, connection: Connection) -> io::Result<()> {
+        let request = read_request(&mut stream).await?;
+        self.prune();
+
+        match request {
+            RelayRequest::Register => self.register(stream).await,
+            RelayRequest::Connect(id) => {
+                if let Err((mut stream, _)) = self.hand_over(id, stream, connection) {
+                    RelayResponse::Unknown.to_async_writer(&mut stream).await?;
+                }
+                Ok(())
+            }
+            // proofs only ever answer a challenge
+            RelayRequest::Prove(_) => Err(io::ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Registers the client under the keys it proves to hold, and keeps it
+    /// waiting until someone connects to it, or it goes away
+    async fn register(&self, mut stream: TcpStream) -> io::Result<()> {
+        let mut challenge = [0; CHALLENGE_SIZE];
+        rand_bytes(&mut challenge).map_err(io::Error::other)?;
+        RelayResponse::Challenge(challenge)
+            .to_async_writer(&mut stream)
+            .await?;
+        let RelayRequest::Prove(proofs) = read_request(&mut stream).await? else {
+            return Err(io::ErrorKind::InvalidData.into());
+        };
+        if proofs.is_empty() || proofs.len() > MAX_REGISTERED {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        // counted before checking the proofs, which takes a while
+        let registration = Registration::new(&self.load, proofs.len())
+            .ok_or_else(|| io::Error::other("the relay is full"))?;
+        let ids = spawn_blocking(move || {
+            proofs
+                .iter()
+                .map(|proof| proof.verify(REGISTRATION_LABEL, &challenge))
+                .collect::<Option<Vec<_>>>()
+        })
+        .await
+        .map_err(io::Error::other)?
+        .ok_or(io::ErrorKind::PermissionDenied)?;
+
+        let (handoff, mut reached) = oneshot::channel();
+        let waiting = Arc::new(Mutex::new(Some(handoff)));
+        {
+            let mut registered = self.registered.lock().unwrap();
+            for id in ids {
+                registered.entry(id).or_default().push(waiting.clone());
+            }
+        }
+
+        let mut ping = interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+        let mut byte = [0; 1];
+        let (id, mut other, _connection) = loop {
+            tokio::select! {
+                reached = &mut reached => break reached.map_err(io::Error::other)?,
+                _ = ping.tick() => RelayResponse::Ping.to_async_writer(&mut stream).await?,
+                // registered clients have nothing more to say, but goodbye
+                read = stream.read(&mut byte) => {
+                    return match read? {
+                        0 => Ok(()),
+                        _ => Err(io::ErrorKind::InvalidData.into()),
+                    };
+                }
+            }
+        };
+        drop(registration);
+        RelayResponse::Reached(id)
+            .to_async_writer(&mut stream)
+            .await?;
+        RelayResponse::Connected.to_async_writer(&mut other).await?;
+        copy_bidirectional(&mut stream, &mut other).await?;
+        Ok(())
+    }
+
+    /// Hands the stream to the client registered under the fingerprint the
+    /// latest, who is still around, along with the [Connection] it counts
+    /// as. Both are handed back if there is nobody.
+    fn hand_over(
+        &self,
+        id: Fingerprint,
+        mut stream: TcpStream,
+        mut connection: Connection,
+    ) -> Result<(), (TcpStream, Connection)> {
+        let mut registered = self.registered.lock().unwrap();
+        let Some(waiting) = registered.get_mut(&id) else {
+            return Err((stream, connection));
+        };
+        while let Some(client) = waiting.pop() {
+            if let Some(handoff) = client.lock().unwrap().take() {
+                match handoff.send((id, stream, connection)) {
+                    Ok(()) => return Ok(()),
+                    Err((_, unsent, unused)) => (stream, connection) = (unsent, unused),
+                }
+            }
+        }
+        Err((stream, connection))
+    }
+
+    /// Forgets the clients, who were taken or went away
+    fn prune(&self) {
+        let mut registered = self.registered.lock().unwrap();
+        registered.retain(|_, waiting| {
+            waiting.retain(|client| {
+                let mut client = client.lock().unwrap();
+                if client.as_ref().is_some_and(Handoff::is_closed) {
+                    *client = None;
+                }
+                client.is_some()
+            });
+            !waiting.is_empty()
+        });
+    }
+}
+
+/// Reads what the client wants, as long as it doesn't take too long
+async fn read_request(stream: &mut TcpStream) -> io::Result<RelayRequest> {
+    timeout(REQUEST_TIMEOUT, RelayRequest::from_async_reader(stream))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+}
+
+/// Connects to whoever is registered under the fingerprint at the relay
+pub(crate) async fn connect_through(relay: &HostAddr, id: Fingerprint) -> io::Result<TcpStream> {
+    let mut stream = address::connect_async(relay).await?;
+    RelayRequest::Connect(id)
+        .to_async_writer(&mut stream)
+        .await?;
+    match RelayResponse::from_async_reader(&mut stream).await? {
+        RelayResponse::Connected => Ok(stream),
+        RelayResponse::Unknown => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("nobody is registered under {} at {}", id.short(), relay),
+        )),
+        _ => Err(io::ErrorKind::InvalidData.into()),
+    }
+}
+
+/// An [Acceptor] of the connections made to us through a [Relay]. Every
+/// accepted connection is replaced by registering anew.
+pub struct RelayListener {
+    relay: HostAddr,
+    /// What strangers exchange RSA keys with us under
+    key: PKey<Private>,
+    /// The keys our contacts have for us
+    contact_keys: Vec<PKey<Private>>,
+    /// Whether registering failed the last time
+    failed: bool,
+    /// The fingerprint the latest accepted connection asked for
+    reached: Option<Fingerprint>,
+}
+
+impl RelayListener {
+    /// We are registered under the fingerprints of all of the keys at once.
+    /// Strangers exchanging RSA keys with us get the key, while the contact
+    /// keys only let the contacts they are meant for through, see
+    /// [Acceptor::reached].
+    pub fn new(relay: HostAddr, key: PKey<Private>, contact_keys: Vec<PKey<Private>>) -> Self {
+        Self {
+            relay,
+            key,
+            contact_keys,
+            failed: false,
+            reached: None,
+        }
+    }
+
+    /// Registers at the relay, and waits for someone to connect to us
+    async fn wait(&self) -> io::Result<(Fingerprint, TcpStream)> {
+        let mut stream = address::connect_async(&self.relay).await?;
+        RelayRequest::Register.to_async_writer(&mut stream).await?;
+        let RelayResponse::Challenge(challenge) = timeout(
+            REQUEST_TIMEOUT,
+            RelayResponse::from_async_reader(&mut stream),
+        )
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??
+        else {
+            return Err(io::ErrorKind::InvalidData.into());
+        };
+        let proofs = [&self.key]
+            .into_iter()
+            .chain(&self.contact_keys)
+            .map(|key| KeyProof::new(key, REGISTRATION_LABEL, &challenge))
+            .collect::<Result<_, _>>()
+            .map_err(io::Error::other)?;
+        RelayRequest::Prove(proofs)
+            .to_async_writer(&mut stream)
+            .await?;
+
+        // a relay gone quiet may have restarted, or lost the way to us
+        loop {
+            let response = timeout(PING_TIMEOUT, RelayResponse::from_async_reader(&mut stream))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "the relay went quiet"))??;
+            match response {
+                RelayResponse::Ping => {}
+                RelayResponse::Reached(id) => return Ok((id, stream)),
+                _ => return Err(io::ErrorKind::InvalidData.into()),
+            }
+        }
+    }
+}
+
+impl Acceptor for RelayListener {
+    type Transport = TcpStream;
+
+    async fn accept(&mut self) -> io::Result<Self::Transport> {
+        // the relay going away tends to take a while to come back
+        if self.failed {
+            sleep(RETRY_DELAY).await;
+        }
+        let reached = self.wait().await;
+        self.failed = reached.is_err();
+        let (id, stream) = reached?;
+        self.reached = Some(id);
+        Ok(stream)
+    }
+
+    fn local(&self) -> io::Result<Endpoint> {
+        Ok(Endpoint::Local(format!("relay {}", self.relay)))
+    }
+
+    /// The relay's address says nothing about who connected through it, so
+    /// it's kept out of address based decisions
+    fn peer(&self, _transport: &Self::Transport) -> io::Result<Endpoint> {
+        Ok(Endpoint::Local(format!("through relay {}", self.relay)))
+    }
+
+    fn identity(&self, _transport: &Self::Transport) -> Option<PKey<Private>> {
+        Some(self.key.clone())
+    }
+
+    fn reached(&self, _transport: &Self::Transport) -> Option<Fingerprint> {
+        self.reached
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::rsa::Rsa;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_registering_takes_the_keys() {
+        let relay = Relay::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr: HostAddr = listener.local_addr().unwrap().into();
+        tokio::spawn({
+            let relay = relay.clone();
+            async move { relay.serve(listener, |_| {}).await }
+        });
+        let key = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+
+        // a proof made for another challenge is hung up on
+        let mut stream = address::connect_async(&relay_addr).await.unwrap();
+        RelayRequest::Register
+            .to_async_writer(&mut stream)
+            .await
+            .unwrap();
+        let response = RelayResponse::from_async_reader(&mut stream).await;
+        assert!(matches!(response, Ok(RelayResponse::Challenge(_))));
+        RelayRequest::Prove(vec![
+            KeyProof::new(&key, REGISTRATION_LABEL, b"replayed").unwrap(),
+        ])
+        .to_async_writer(&mut stream)
+        .await
+        .unwrap();
+        assert!(RelayResponse::from_async_reader(&mut stream).await.is_err());
+        assert_eq!(relay.waiting(), 0);
+
+        let mut listener = RelayListener::new(relay_addr.clone(), key.clone(), Vec::new());
+        let accepting = tokio::spawn(async move {
+            let stream = listener.accept().await.unwrap();
+            let reached = listener.reached(&stream);
+            (stream, reached)
+        });
+        timeout(Duration::from_secs(5), async {
+            while relay.waiting() != 1 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let id = Fingerprint::of(&key);
+        connect_through(&relay_addr, id).await.unwrap();
+        let (_stream, reached) = accepting.await.unwrap();
+        assert_eq!(reached, Some(id));
+    }
+
+    #[test]
+    fn test_registrations_are_limited() {
+        let load = Shared::default();
+        let all = Registration::new(&load, MAX_REGISTRATIONS).unwrap();
+        assert!(Registration::new(&load, 1).is_none());
+        drop(all);
+
+        let waiting: Vec<_> = (0..MAX_WAITING)
+            .map(|_| Registration::new(&load, 1).unwrap())
+            .collect();
+        assert!(Registration::new(&load, 1).is_none());
+        drop(waiting);
+        assert!(Registration::new(&load, MAX_REGISTRATIONS).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_connections_are_limited_per_ip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr: HostAddr = listener.local_addr().unwrap().into();
+        tokio::spawn(async move { Relay::new().serve(listener, |_| {}).await });
+        let id = Fingerprint::of(&PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap());
+
+        let mut open = Vec::new();
+        for _ in 0..MAX_CONNECTIONS_PER_IP {
+            open.push(address::connect_async(&relay_addr).await.unwrap());
+        }
+        // one too many is hung up on right away
+        let mut extra = address::connect_async(&relay_addr).await.unwrap();
+        let read = timeout(Duration::from_secs(5), extra.read(&mut [0; 1]))
+            .await
+            .unwrap();
+        assert!(read.is_err() || read.unwrap() == 0);
+
+        // while the others are still served
+        RelayRequest::Connect(id)
+            .to_async_writer(&mut open[0])
+            .await
+            .unwrap();
+        let response = RelayResponse::from_async_reader(&mut open[0]).await;
+        assert!(matches!(response, Ok(RelayResponse::Unknown)));
+
+        // and hanging up makes room again
+        drop(open);
+        timeout(Duration::from_secs(5), async {
+            while connect_through(&relay_addr, id)
+                .await
+                .is_err_and(|e| e.kind() != io::ErrorKind::NotFound)
+            {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bitcode::{deserialize, serialize};
+use derive_more::{Display, Error, From};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::{runtime::Handle, sync::oneshot, task::AbortHandle, time::timeout};
+
+use super::{
+    channel::Channel, events::Event, outbox::SendError, protocol::Payload, transport::Transport,
+};
+
+/// Tag of the [Payload]s requests and responses travel in
+pub const RPC_TAG: &str = "grapevine/rpc";
+
+/// What is sent under [RPC_TAG]
+#[derive(Serialize, Deserialize)]
+enum Call {
+    Request {
+        id: u64,
+        method: String,
+        params: Vec<u8>,
+    },
+    Response {
+        id: u64,
+        result: Result<Vec<u8>, RemoteError>,
+    },
+    /// The caller is no longer interested
+    Cancel { id: u64 },
+}
+
+/// Why the peer didn't answer a request
+#[derive(Debug, Display, Error, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RemoteError {
+    #[display("no method named {_0}")]
+    UnknownMethod(#[error(not(source))] String),
+    #[display("invalid parameters: {_0}")]
+    InvalidParams(#[error(not(source))] String),
+    /// The handler returned an error
+    #[display("{_0}")]
+    Failed(#[error(not(source))] String),
+}
+
+/// Why a call didn't get a response
+#[derive(Debug, Display, From, Error)]
+pub enum CallError {
+    Remote(RemoteError),
+    #[display("the peer didn't answer in time")]
+    TimedOut,
+    #[display("the channel stopped")]
+    ChannelClosed,
+    Send(SendError),
+    Encoding(bitcode::Error),
+}
+
+type Answer = Pin<Box<dyn Future<Output = Result<Vec<u8>, RemoteError>> + Send>>;
+
+/// Type erased method handler, decoding the parameters and encoding the result
+type Method<T> = Box<dyn Fn(Arc<Channel<T>>, &[u8]) -> Answer + Send>;
+
+/// Calls are told apart by the channel they were made over, and their id
+type CallKey = (usize, u64);
+
+fn key<T: Transport>(channel: &Arc<Channel<T>>, id: u64) -> CallKey {
+    (Arc::as_ptr(channel) as usize, id)
+}
+
+struct Calls<T: Transport> {
+    methods: HashMap<String, Method<T>>,
+    /// Our requests, waiting for a response
+    waiting: HashMap<CallKey, oneshot::Sender<Result<Vec<u8>, RemoteError>>>,
+    /// Requests of the peers, being handled
+    running: HashMap<CallKey, AbortHandle>,
+    next_id: u64,
+}
+
+/// Request/response layer on top of the [Payload]s of all channels of an app
+pub(crate) struct Rpc<T: Transport> {
+    calls: Mutex<Calls<T>>,
+    /// Where the handlers run
+    runtime: Handle,
+}
+
+impl<T: Transport> Rpc<T> {
+    pub fn new(runtime: Handle) -> Arc<Self> {
+        Arc::new(Self {
+            calls: Mutex::new(Calls {
+                methods: HashMap::new(),
+                waiting: HashMap::new(),
+                running: HashMap::new(),
+                next_id: 0,
+            }),
+            runtime,
+        })
+    }
+
+    /// Replaces the handler of the method
+    pub fn add_method<P, R, F, Fut>(&self, name: String, handler: F)
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: Fn(Arc<Channel<T>>, P) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<R, String>> + Send + 'static,
+    {
+        let method: Method<T> = Box::new(move |channel, params| {
+            let answer = deserialize::<P>(params).map(|params| handler(channel, params));
+            Box::pin(async move {
+                let answer = answer.map_err(|e| RemoteError::InvalidParams(e.to_string()))?;
+                let result = answer.await.map_err(RemoteError::Failed)?;
+                serialize(&result).map_err(|e| RemoteError::Failed(e.to_string()))
+            })
+        });
+        self.calls.lock().unwrap().methods.insert(name, method);
+    }
+
+    /// Handles the frames of the calls. Runs as an event callback.
+    pub fn on_event(self: &Arc<Self>, event: &Event<T>) {
+        let Event::PayloadReceived { channel, payload } = event else {
+            return;
+        };
+        if payload.tag() != RPC_TAG {
+            return;
+        }
+        let Ok(call) = payload.decode::<Call>() else {
+            return;
+        };
+
+        let mut calls = self.calls.lock().unwrap();
+        match call {
+            Call::Request { id, method, params } => {
+                let key = key(channel, id);
+                if calls.running.contains_key(&key) {
+                    return;
+                }
+                let answer = calls
+                    .methods
+                    .get(&method)
+                    .map(|handler| handler(channel.clone(), &params));
+                let rpc = self.clone();
+                let channel = channel.clone();
+                // the lock is held until the task is registered, so that it
+                // can't unregister itself before
+                let task = self.runtime.spawn(async move {
+                    let result = match answer {
+                        Some(answer) => answer.await,
+                        None => Err(RemoteError::UnknownMethod(method)),
+                    };
+                    rpc.calls.lock().unwrap().running.remove(&key);
+                    let _ = send(&channel, &Call::Response { id, result }).await;
+                });
+                calls.running.insert(key, task.abort_handle());
+            }
+            Call::Response { id, result } => {
+                if let Some(waiting) = calls.waiting.remove(&key(channel, id)) {
+                    let _ = waiting.send(result);
+                }
+            }
+            Call::Cancel { id } => {
+                if let Some(task) = calls.running.remove(&key(channel, id)) {
+                    task.abort();
+                }
+            }
+        }
+    }
+
+    /// Sends the request, and waits for the response
+    pub async fn call(
+        self: &Arc<Self>,
+        channel: &Arc<Channel<T>>,
+        method: String,
+        params: Vec<u8>,
+        wait: Duration,
+    ) -> Result<Vec<u8>, CallError> {
+        let (sender, response) = oneshot::channel();
+        let id = {
+            let mut calls = self.calls.lock().unwrap();
+            calls.next_id += 1;
+            let id = calls.next_id;
+            calls.waiting.insert(key(channel, id), sender);
+            id
+        };
+        // cancels the request on the way out, unless it got answered
+        let _pending = Pending {
+            rpc: self.clone(),
+            channel: channel.clone(),
+            id,
+        };
+        send(channel, &Call::Request { id, method, params }).await?;
+
+        let mut state = channel.watch_state();
+        tokio::select! {
+            response = timeout(wait, response) => match response {
+                Ok(Ok(result)) => Ok(result?),
+                Ok(Err(_)) => Err(CallError::ChannelClosed),
+                Err(_) => Err(CallError::TimedOut),
+            },
+            _ = state.wait_for(|state| state.is_finished()) => Err(CallError::ChannelClosed),
+        }
+    }
+}
+
+async fn send<T: Transport>(channel: &Channel<T>, call: &Call) -> Result<(), CallError> {
+    channel.send_payload(Payload::new(RPC_TAG, call)?).await?;
+    Ok(())
+}
+
+/// A request we are waiting on
+struct Pending<T: Transport> {
+    rpc: Arc<Rpc<T>>,
+    channel: Arc<Channel<T>>,
+    id: u64,
+}
+
+impl<T: Transport> Drop for Pending<T> {
+    fn drop(&mut self) {
+        let unanswered = self
+            .rpc
+            .calls
+            .lock()
+            .unwrap()
+            .waiting
+            .remove(&key(&self.channel, self.id))
+            .is_some();
+        if unanswered && !self.channel.state().is_finished() {
+            let channel = self.channel.clone();
+            let id = self.id;
+            self.rpc.runtime.spawn(async move {
+                let _ = send(&channel, &Call::Cancel { id }).await;
+            });
+        }
+    }
+}
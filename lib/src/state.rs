@@ -0,0 +1,161 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use super::{
+    channel::{Channel, ProtocolError},
+    events::CloseReason,
+    firewall::secs,
+    transport::Transport,
+};
+
+/// Where a [Channel](super::Channel) is in its lifecycle.
+/// Outgoing connections go through every state in order, while channels
+/// accepted from others start out as [ChannelState::Open].
+#[derive(Clone, Debug)]
+pub enum ChannelState {
+    /// Establishing the transport
+    Connecting,
+    /// Waiting for the other party to accept the connection
+    AwaitingAccept,
+    /// Exchanging keys
+    Handshaking,
+    /// Ready for messages
+    Open,
+    /// Told to close, but not closed just yet
+    Closing,
+    /// Closed by either party
+    Closed(CloseReason),
+    /// Stopped because something went wrong
+    Failed(Arc<ProtocolError>),
+}
+
+impl ChannelState {
+    /// Checks whether the state is final
+    pub fn is_finished(&self) -> bool {
+        matches!(self, ChannelState::Closed(_) | ChannelState::Failed(_))
+    }
+}
+
+impl From<CloseReason> for ChannelState {
+    fn from(reason: CloseReason) -> Self {
+        match reason {
+            CloseReason::Failed(error) => ChannelState::Failed(error),
+            reason => ChannelState::Closed(reason),
+        }
+    }
+}
+
+impl fmt::Display for ChannelState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelState::Connecting => write!(f, "connecting"),
+            ChannelState::AwaitingAccept => write!(f, "waiting to be accepted"),
+            ChannelState::Handshaking => write!(f, "exchanging keys"),
+            ChannelState::Open => write!(f, "open"),
+            ChannelState::Closing => write!(f, "closing"),
+            ChannelState::Closed(reason) => write!(f, "{}", reason),
+            ChannelState::Failed(error) => write!(f, "failed: {}", error),
+        }
+    }
+}
+
+/// How long establishing a [Channel](super::Channel) may take
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    /// For connecting to the address
+    #[serde(with = "secs")]
+    connect: Duration,
+    /// For the key exchange, counted from when the other party accepts us
+    #[serde(with = "secs")]
+    handshake: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(10), Duration::from_secs(30))
+    }
+}
+
+impl Timeouts {
+    pub fn new(connect: Duration, handshake: Duration) -> Self {
+        Self { connect, handshake }
+    }
+
+    /// Get how long connecting to an address may take
+    pub fn connect(&self) -> Duration {
+        self.connect
+    }
+
+    /// Get how long the key exchange may take. Waiting for the other party
+    /// to accept us doesn't count, as that's up to them.
+    pub fn handshake(&self) -> Duration {
+        self.handshake
+    }
+}
+
+/// A [Channel](super::Channel) we are trying to establish, either by
+/// connecting to someone, or by accepting their connection. Lives in
+/// [AsyncGrapevineApp::connecting](super::AsyncGrapevineApp::connecting)
+/// until it either opens, fails or gets cancelled.
+pub struct ConnectionAttempt {
+    name: String,
+    /// Who we are connecting to
+    target: String,
+    /// Handed over to the channel once it's created
+    state: watch::Sender<ChannelState>,
+    /// Tells the task establishing the channel to give up
+    cancel: watch::Sender<bool>,
+}
+
+impl ConnectionAttempt {
+    pub(crate) fn new(name: String, target: String, state: ChannelState) -> Self {
+        Self {
+            name,
+            target,
+            state: watch::Sender::new(state),
+            cancel: watch::Sender::new(false),
+        }
+    }
+
+    /// Get the name the channel will have
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the description of who we are connecting to
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Get the current state of the attempt
+    pub fn state(&self) -> ChannelState {
+        self.state.borrow().clone()
+    }
+
+    /// Checks if the channel is the one the attempt opened, as it takes over
+    /// the state of the attempt. Names are no telling, as anyone can pick
+    /// the same one.
+    pub fn opened<T: Transport>(&self, channel: &Channel<T>) -> bool {
+        self.state.subscribe().same_channel(&channel.watch_state())
+    }
+
+    /// Gives up on the attempt, dropping the transport.
+    /// Has no effect once the channel is open.
+    pub fn cancel(&self) {
+        self.cancel.send_replace(true);
+    }
+
+    /// Gets the state, that will be handed over to the [Channel](super::Channel)
+    pub(crate) fn state_sender(&self) -> &watch::Sender<ChannelState> {
+        &self.state
+    }
+
+    /// Resolves once [Self::cancel] is called
+    pub(crate) async fn cancelled(&self) {
+        // the sender lives in self, so this never errors
+        let _ = self.cancel.subscribe().wait_for(|cancel| *cancel).await;
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf, ReadHalf, WriteHalf},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+
+use super::{Acceptor, Endpoint, Transport};
+
+/// How many bytes may be in flight in one direction, before writes wait
+const BUFFER_SIZE: usize = 64 * 1024;
+
+fn endpoint() -> Endpoint {
+    Endpoint::Local("memory".to_string())
+}
+
+/// One end of an in-memory duplex pipe, mostly useful for testing without
+/// sockets. Dropping an end closes it, like it would with a socket.
+pub struct MemoryTransport(DuplexStream);
+
+impl MemoryTransport {
+    /// Creates both ends of a new duplex pipe
+    pub fn pair() -> (Self, Self) {
+        let (a, b) = tokio::io::duplex(BUFFER_SIZE);
+        (Self(a), Self(b))
+    }
+}
+
+impl AsyncRead for MemoryTransport {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MemoryTransport {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl Transport for MemoryTransport {
+    type Reader = ReadHalf<DuplexStream>;
+    type Writer = WriteHalf<DuplexStream>;
+
+    fn split(self) -> (Self::Reader, Self::Writer) {
+        tokio::io::split(self.0)
+    }
+
+    fn peer(&self) -> io::Result<Endpoint> {
+        Ok(endpoint())
+    }
+
+    /// Pipes can't be peeked at, so a peer going away is only noticed once
+    /// we try to use the pipe
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+/// Accepts [MemoryTransport]s dialed through a [MemoryConnector]
+pub struct MemoryListener {
+    incoming: UnboundedReceiver<MemoryTransport>,
+}
+
+/// Dials a [MemoryListener]
+#[derive(Clone)]
+pub struct MemoryConnector {
+    outgoing: UnboundedSender<MemoryTransport>,
+}
+
+impl MemoryListener {
+    /// Creates a new listener, alongside a connector that can reach it
+    pub fn new() -> (Self, MemoryConnector) {
+        let (outgoing, incoming) = mpsc::unbounded_channel();
+        (Self { incoming }, MemoryConnector { outgoing })
+    }
+}
+
+impl MemoryConnector {
+    /// Connects to the [MemoryListener]
+    pub fn connect(&self) -> io::Result<MemoryTransport> {
+        let (ours, theirs) = MemoryTransport::pair();
+        self.outgoing
+            .send(theirs)
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+        Ok(ours)
+    }
+}
+
+impl Acceptor for MemoryListener {
+    type Transport = MemoryTransport;
+
+    async fn accept(&mut self) -> io::Result<Self::Transport> {
+        self.incoming
+            .recv()
+            .await
+            .ok_or_else(|| io::ErrorKind::NotConnected.into())
+    }
+
+    fn local(&self) -> io::Result<Endpoint> {
+        Ok(endpoint())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_memory_duplex() {
+        let (mut a, mut b) = MemoryTransport::pair();
+        a.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        b.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        b.write_all(b"pong").await.unwrap();
+        a.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[tokio::test]
+    async fn test_memory_close_wakes_reader() {
+        let (a, b) = MemoryTransport::pair();
+        let (mut reader, _writer) = a.split();
+
+        let task = tokio::spawn(async move { reader.read(&mut [0; 1]).await.unwrap() });
+        drop(b);
+        assert_eq!(task.await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_memory_listener() {
+        let (mut listener, connector) = MemoryListener::new();
+
+        let mut ours = connector.connect().unwrap();
+        let mut theirs = listener.accept().await.unwrap();
+        ours.write_all(b"!").await.unwrap();
+        let mut buf = [0; 1];
+        theirs.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"!");
+
+        drop(connector);
+        assert_eq!(
+            listener.accept().await.err().unwrap().kind(),
+            io::ErrorKind::NotConnected
+        );
+    }
+}
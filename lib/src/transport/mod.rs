@@ -0,0 +1,166 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+};
+#[cfg(feature = "async")]
+use std::{
+    io,
+    task::{Context, Poll, Waker},
+};
+
+#[cfg(feature = "async")]
+use openssl::pkey::{PKey, Private};
+#[cfg(feature = "async")]
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{
+        TcpListener, TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+};
+
+use super::address::HostAddr;
+#[cfg(feature = "async")]
+use super::protocol::Fingerprint;
+
+/// Transport over [tokio::net::UnixStream] sockets
+#[cfg(all(unix, feature = "async"))]
+mod unix;
+
+/// Transport between two ends within the same process
+#[cfg(feature = "async")]
+mod memory;
+#[cfg(feature = "async")]
+pub use memory::{MemoryConnector, MemoryListener, MemoryTransport};
+
+/// Description of either end of a [Transport]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Endpoint {
+    /// Reachable over the network
+    Net(SocketAddr),
+    /// Reachable some other way, like through a Unix socket
+    Local(String),
+}
+
+impl Endpoint {
+    /// Get the IP address, if the endpoint is on the network
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Endpoint::Net(addr) => Some(addr.ip()),
+            Endpoint::Local(_) => None,
+        }
+    }
+
+    /// Get the address we could reach the endpoint under, if the endpoint
+    /// is on the network
+    pub fn host_addr(&self) -> Option<HostAddr> {
+        match self {
+            Endpoint::Net(addr) => Some((*addr).into()),
+            Endpoint::Local(_) => None,
+        }
+    }
+}
+
+impl From<SocketAddr> for Endpoint {
+    fn from(addr: SocketAddr) -> Self {
+        Endpoint::Net(addr)
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Net(addr) => write!(f, "{}", addr),
+            Endpoint::Local(desc) => write!(f, "{}", desc),
+        }
+    }
+}
+
+/// A bidirectional byte stream [Channel](super::Channel)s can run over.
+/// Handshakes happen on the whole transport, after which it gets split,
+/// so that reading and writing can happen in separate tasks.
+#[cfg(feature = "async")]
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin + Sized + 'static {
+    type Reader: AsyncRead + Send + Unpin + 'static;
+    type Writer: AsyncWrite + Send + Unpin + 'static;
+
+    /// Splits the transport into independently usable halves
+    fn split(self) -> (Self::Reader, Self::Writer);
+
+    /// Describes the other end
+    fn peer(&self) -> io::Result<Endpoint>;
+
+    /// Checks, without waiting or consuming anything, whether the other end
+    /// went away
+    fn is_closed(&self) -> bool;
+}
+
+/// Source of incoming [Transport]s, like a bound socket
+#[cfg(feature = "async")]
+pub trait Acceptor: Send + 'static {
+    type Transport: Transport;
+
+    /// Waits for a new connection
+    fn accept(&mut self) -> impl Future<Output = io::Result<Self::Transport>> + Send;
+
+    /// Describes our end
+    fn local(&self) -> io::Result<Endpoint>;
+
+    /// Describes the other end of a transport we accepted, which is what
+    /// the transport says by default
+    fn peer(&self, transport: &Self::Transport) -> io::Result<Endpoint> {
+        transport.peer()
+    }
+
+    /// Gets our key the other end of a transport we accepted expects to
+    /// exchange RSA keys with, if any. Every exchange gets a new key
+    /// otherwise.
+    fn identity(&self, _transport: &Self::Transport) -> Option<PKey<Private>> {
+        None
+    }
+
+    /// Gets the fingerprint of ours the other end of a transport we accepted
+    /// asked for, if it could ask for one. They only get through if it's
+    /// meant for them then: strangers have to ask for the [Self::identity],
+    /// and contacts for the key they have for us.
+    fn reached(&self, _transport: &Self::Transport) -> Option<Fingerprint> {
+        None
+    }
+}
+
+#[cfg(feature = "async")]
+impl Transport for TcpStream {
+    type Reader = OwnedReadHalf;
+    type Writer = OwnedWriteHalf;
+
+    fn split(self) -> (Self::Reader, Self::Writer) {
+        self.into_split()
+    }
+
+    fn peer(&self) -> io::Result<Endpoint> {
+        Ok(self.peer_addr()?.into())
+    }
+
+    fn is_closed(&self) -> bool {
+        let mut buf = [0; 1];
+        let mut buf = ReadBuf::new(&mut buf);
+        match self.poll_peek(&mut Context::from_waker(Waker::noop()), &mut buf) {
+            Poll::Ready(Ok(read)) => read == 0,
+            Poll::Ready(Err(_)) => true,
+            Poll::Pending => false,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Acceptor for TcpListener {
+    type Transport = TcpStream;
+
+    async fn accept(&mut self) -> io::Result<Self::Transport> {
+        Ok(TcpListener::accept(self).await?.0)
+    }
+
+    fn local(&self) -> io::Result<Endpoint> {
+        Ok(self.local_addr()?.into())
+    }
+}
@@ -0,0 +1,67 @@
+use std::io;
+
+use tokio::net::{
+    UnixListener, UnixStream,
+    unix::{OwnedReadHalf, OwnedWriteHalf, SocketAddr},
+};
+
+use super::{Acceptor, Endpoint, Transport};
+
+fn describe(addr: SocketAddr) -> Endpoint {
+    match addr.as_pathname() {
+        Some(path) => Endpoint::Local(format!("unix:{}", path.display())),
+        None => Endpoint::Local("unix:unnamed".to_string()),
+    }
+}
+
+impl Transport for UnixStream {
+    type Reader = OwnedReadHalf;
+    type Writer = OwnedWriteHalf;
+
+    fn split(self) -> (Self::Reader, Self::Writer) {
+        self.into_split()
+    }
+
+    fn peer(&self) -> io::Result<Endpoint> {
+        Ok(describe(self.peer_addr()?))
+    }
+
+    /// Unix sockets can't be peeked at, so a peer going away is only
+    /// noticed once we try to use the socket
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+impl Acceptor for UnixListener {
+    type Transport = UnixStream;
+
+    async fn accept(&mut self) -> io::Result<Self::Transport> {
+        Ok(UnixListener::accept(self).await?.0)
+    }
+
+    fn local(&self) -> io::Result<Endpoint> {
+        Ok(describe(self.local_addr()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_unix_split_and_close() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let (mut reader, _writer) = Transport::split(a);
+        let (_, mut b_writer) = Transport::split(b);
+
+        b_writer.write_all(b"hi").await.unwrap();
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hi");
+
+        b_writer.shutdown().await.unwrap();
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+    }
+}
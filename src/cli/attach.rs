@@ -0,0 +1,367 @@
+use std::{
+    io::{self, BufRead},
+    path::Path,
+    sync::mpsc,
+    thread,
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use grapevine::rpc::{
+    AcceptParams, AttemptInfo, ChannelInfo, ChannelParams, Client, ClientError, CloseParams,
+    ConnectParams, EventInfo, HistoryEntry, KeyPaths, PendingInfo, PendingKind, PendingParams,
+    SaveParams, SendParams,
+};
+
+use super::session::{index, joined};
+
+const HELP: &str = "\
+Lines are sent to the current channel, with a leading // sent as /, unless
+they are one of:
+  /connect <contact|address> [name]    connect to a contact, or exchange keys
+  /pending                             list connections waiting to be accepted
+  /accept <n> [name]                   accept a key exchange, or a saved contact
+  /accept <n> <private> <public> [name]
+                                       accept a connection using the given keys
+  /reject <n>                          reject a connection
+  /channels                            list channels
+  /switch <n>                          make a channel the current one
+  /history                             print the messages of the current channel
+  /save [name]                         save the current channel as a contact
+  /close [reason]                      close the current channel
+  /reconnect                           reconnect the current channel
+  /detach                              exit, leaving everything open
+  /shutdown                            close everything, and stop the daemon";
+
+/// What the attached session may be woken up by
+enum Input {
+    Event(EventInfo),
+    /// The daemon went away
+    Detached(ClientError),
+    Line(String),
+    Eof,
+}
+
+fn connect(socket: &Path) -> Result<Client, String> {
+    Client::connect(socket).map_err(|e| format!("couldn't attach to {}: {}", socket.display(), e))
+}
+
+/// Lists the connections waiting at the daemon
+pub fn pending(socket: &Path) -> Result<(), String> {
+    let pending = connect(socket)?
+        .call("pending", ())
+        .map_err(|e| e.to_string())?;
+    print_pending(pending);
+    Ok(())
+}
+
+/// Accepts a connection waiting at the daemon, with the keys if it needs
+/// them
+pub fn accept(
+    socket: &Path,
+    pending: usize,
+    name: Option<String>,
+    keys: Option<KeyPaths>,
+) -> Result<(), String> {
+    let params = AcceptParams {
+        pending,
+        name,
+        keys,
+    };
+    let attempt: AttemptInfo = connect(socket)?
+        .call("accept", params)
+        .map_err(|e| e.to_string())?;
+    println!("* accepting {}", attempt.target);
+    Ok(())
+}
+
+/// Rejects a connection waiting at the daemon
+pub fn reject(socket: &Path, pending: usize) -> Result<(), String> {
+    connect(socket)?
+        .call("reject", PendingParams { pending })
+        .map_err(|e| e.to_string())
+}
+
+/// Chats through the daemon, until the user detaches. Nothing closes with us.
+pub fn attach(socket: &Path) -> Result<(), String> {
+    let client = connect(socket)?;
+    let mut events = connect(socket)?;
+    events.subscribe().map_err(|e| e.to_string())?;
+
+    let (sender, inputs) = mpsc::channel();
+    let forward = sender.clone();
+    thread::spawn(move || {
+        loop {
+            let input = match events.next_event() {
+                Ok(event) => Input::Event(event),
+                Err(e) => Input::Detached(e),
+            };
+            let detached = matches!(input, Input::Detached(_));
+            if forward.send(input).is_err() || detached {
+                break;
+            }
+        }
+    });
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(Input::Line(line)).is_err() {
+                return;
+            }
+        }
+        let _ = sender.send(Input::Eof);
+    });
+
+    let mut attached = Attached {
+        client,
+        current: None,
+    };
+    let channels = attached.channels()?;
+    for channel in &channels {
+        print_channel(channel, false);
+    }
+    attached.current = channels
+        .iter()
+        .find(|channel| !channel.finished)
+        .map(|channel| channel.id);
+    println!("* attached to {}, see /help", socket.display());
+
+    // we hold a sender ourselves
+    while let Ok(input) = inputs.recv() {
+        match input {
+            Input::Event(event) => attached.on_event(event),
+            Input::Detached(ClientError::Closed) => return Ok(()),
+            Input::Detached(e) => return Err(e.to_string()),
+            Input::Line(line) => match attached.command(line.trim()) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(e) => eprintln!("! {}", e),
+            },
+            Input::Eof => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+struct Attached {
+    client: Client,
+    /// Channel the typed lines are sent to
+    current: Option<usize>,
+}
+
+impl Attached {
+    fn call<R: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        params: impl Serialize,
+    ) -> Result<R, String> {
+        self.client.call(method, params).map_err(|e| e.to_string())
+    }
+
+    fn channels(&mut self) -> Result<Vec<ChannelInfo>, String> {
+        self.call("channels", ())
+    }
+
+    fn current(&self) -> Result<usize, String> {
+        self.current
+            .ok_or_else(|| "no channel selected, see /channels".to_string())
+    }
+
+    fn on_event(&mut self, event: EventInfo) {
+        match event {
+            EventInfo::MessageReceived { name, message, .. } => {
+                println!("{}: {}", name, message.content);
+            }
+            // meant for other applications
+            EventInfo::PayloadReceived { .. } => {}
+            EventInfo::ChannelOpened { channel } => {
+                println!("* {} is open ({})", channel.name, channel.peer);
+                if self.current.is_none() {
+                    self.current = Some(channel.id);
+                }
+            }
+            EventInfo::ChannelClosed { name, reason, .. } => println!("* {}: {}", name, reason),
+            EventInfo::PendingConnectionArrived { name, peer } => {
+                println!("* {} ({}) wants to connect, see /pending", name, peer);
+            }
+            EventInfo::PendingConnectionExpired { name, peer } => {
+                println!("* {} ({}) is no longer waiting", name, peer);
+            }
+            EventInfo::HandshakeFailed { error } => eprintln!("! handshake failed: {}", error),
+            EventInfo::ConnectionFailed { attempt, error } => {
+                eprintln!("! connecting to {} failed: {}", attempt.target, error);
+            }
+            EventInfo::ListenerError { listener, error } => {
+                eprintln!("! listener {}: {}", listener, error);
+            }
+            EventInfo::ConnectionRejected { peer, reason } => {
+                println!("* turned {} away: {}", peer, reason);
+            }
+            EventInfo::GroupUpdated { name, active, .. } if !active => {
+                println!("* no longer in group {}", name);
+            }
+            EventInfo::GroupUpdated { name, members, .. } => {
+                println!("* group {}: {}", name, members.join(", "));
+            }
+            EventInfo::GroupMessageReceived {
+                name,
+                author,
+                message,
+                ..
+            } => println!("[{}] {}: {}", name, author, message.content),
+            EventInfo::RelayedMessageReceived { name, message, .. } => {
+                println!("{} (through peers): {}", name, message.content);
+            }
+            EventInfo::PeerDiscovered { name, addr, .. } => {
+                println!("* found {} at {}", name, addr);
+            }
+        }
+    }
+
+    /// Handles a typed line. Returns false once the user wants to leave.
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let Some(command) = line
+            .strip_prefix('/')
+            .filter(|command| !command.starts_with('/'))
+        else {
+            // a doubled slash sends a single one, like for commands of bots
+            let line = line.strip_prefix('/').unwrap_or(line);
+            if !line.is_empty() {
+                let channel = self.current()?;
+                let params = SendParams {
+                    channel,
+                    content: line.to_string(),
+                };
+                self.call::<()>("send", params)?;
+            }
+            return Ok(true);
+        };
+
+        let mut args = command.split_whitespace();
+        match args.next().unwrap_or_default() {
+            "help" => println!("{}", HELP),
+            "connect" => {
+                let target = args.next().ok_or("missing contact or address")?.to_string();
+                let rest: Vec<&str> = args.collect();
+                let params = ConnectParams {
+                    target,
+                    name: joined(&rest),
+                };
+                let attempt: AttemptInfo = self.call("connect", params)?;
+                println!("* connecting to {}", attempt.target);
+            }
+            "pending" => print_pending(self.call("pending", ())?),
+            "accept" => {
+                let pending = index(args.next())?;
+                let rest: Vec<&str> = args.collect();
+                // keys are only looked for where they are needed
+                let pendings: Vec<PendingInfo> = self.call("pending", ())?;
+                let needs_keys = pendings
+                    .iter()
+                    .any(|p| p.id == pending && p.kind == PendingKind::NeedsKeys);
+                let params = match rest.as_slice() {
+                    [private, public, rest @ ..] if needs_keys => AcceptParams {
+                        pending,
+                        name: joined(rest),
+                        keys: Some(KeyPaths {
+                            private: private.into(),
+                            public: public.into(),
+                        }),
+                    },
+                    rest => AcceptParams {
+                        pending,
+                        name: joined(rest),
+                        keys: None,
+                    },
+                };
+                let attempt: AttemptInfo = self.call("accept", params)?;
+                println!("* accepting {}", attempt.target);
+            }
+            "reject" => {
+                let pending = index(args.next())?;
+                self.call::<()>("reject", PendingParams { pending })?;
+            }
+            "channels" => {
+                let current = self.current;
+                for channel in self.channels()? {
+                    print_channel(&channel, current == Some(channel.id));
+                }
+            }
+            "switch" => {
+                let id = index(args.next())?;
+                if !self.channels()?.iter().any(|channel| channel.id == id) {
+                    return Err("no such channel".to_string());
+                }
+                self.current = Some(id);
+            }
+            "history" => {
+                let channel = self.current()?;
+                let history: Vec<HistoryEntry> = self.call("history", ChannelParams { channel })?;
+                for message in history {
+                    let author = if message.ours { ">" } else { "<" };
+                    println!(
+                        "{} {} {}",
+                        message.timestamp.format("%H:%M"),
+                        author,
+                        message.content
+                    );
+                }
+            }
+            "save" => {
+                let channel = self.current()?;
+                let rest: Vec<&str> = args.collect();
+                let params = SaveParams {
+                    channel,
+                    name: joined(&rest),
+                };
+                self.call::<()>("save_contact", params)?;
+                println!("* saved");
+            }
+            "close" => {
+                let channel = self.current()?;
+                let rest: Vec<&str> = args.collect();
+                let params = CloseParams {
+                    channel,
+                    reason: joined(&rest),
+                };
+                self.call::<()>("close", params)?;
+            }
+            "reconnect" => {
+                let channel = self.current()?;
+                let attempt: AttemptInfo = self.call("reconnect", ChannelParams { channel })?;
+                println!("* reconnecting to {}", attempt.target);
+            }
+            "detach" | "quit" => return Ok(false),
+            "shutdown" => {
+                self.call::<()>("shutdown", ())?;
+                return Ok(false);
+            }
+            other => return Err(format!("unknown command /{}, see /help", other)),
+        }
+        Ok(true)
+    }
+}
+
+fn print_pending(pending: Vec<PendingInfo>) {
+    for connection in pending {
+        let kind = match connection.kind {
+            PendingKind::KeyExchange => "key exchange",
+            PendingKind::Contact => "contact",
+            PendingKind::NeedsKeys => "needs keys",
+        };
+        println!(
+            "{}\t{}\t{}\t{}",
+            connection.id, connection.name, connection.peer, kind
+        );
+    }
+}
+
+fn print_channel(channel: &ChannelInfo, current: bool) {
+    println!(
+        "{}{}\t{}\t{}",
+        channel.id,
+        if current { "*" } else { "" },
+        channel.name,
+        channel.state
+    );
+}
@@ -0,0 +1,380 @@
+//! Headless grapevine client, for terminals and scripts. Shares its
+//! settings and contacts with the GUI.
+
+use std::{
+    io::{self, Read},
+    path::PathBuf,
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+#[cfg(unix)]
+use grapevine::rpc::{KeyPaths, socket_path};
+use grapevine::{
+    settings::Settings,
+    storage::{Storage, StorageError, find_contact},
+};
+use grapevine_lib::{
+    AutoAccept, Channel, ConnectionAttempt, ContactBook, Event, GrapevineApp, HostAddr,
+    ListenerConfig, ListenerPolicy, Message, ProtocolPath,
+};
+
+mod session;
+use session::{Input, Session};
+
+#[cfg(unix)]
+mod attach;
+
+/// How long exiting may wait for channels to say goodbye
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Storage file to use, instead of the one the GUI saves to
+    #[arg(long, global = true)]
+    store: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Waits for incoming connections, and chats over the accepted ones
+    Listen {
+        /// Addresses to listen on, instead of the listeners from the settings
+        addrs: Vec<HostAddr>,
+        /// Which connections to the given addresses get accepted without asking
+        #[arg(long, value_enum, default_value_t = Accept::Never)]
+        accept: Accept,
+    },
+    /// Connects to a saved contact, or exchanges keys with an address, and
+    /// chats over the channel
+    Connect {
+        /// Name or fingerprint of a contact, or an address
+        target: String,
+        /// Name of the channel, when connecting to an address
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Sends a single message to a saved contact
+    Send {
+        /// Name or fingerprint of the contact
+        contact: String,
+        /// The message. Read from the standard input, if missing
+        message: Vec<String>,
+        /// How long to wait for the contact to accept us, in seconds
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+    /// Prints the messages a saved contact sends, until they leave
+    Tail {
+        /// Name or fingerprint of the contact
+        contact: String,
+    },
+    /// Lists the saved contacts
+    Contacts,
+    /// Chats through a running grapevined, leaving everything open on exit
+    #[cfg(unix)]
+    Attach {
+        #[command(flatten)]
+        daemon: Daemon,
+    },
+    /// Lists the connections waiting to be accepted by a running grapevined
+    #[cfg(unix)]
+    Pending {
+        #[command(flatten)]
+        daemon: Daemon,
+    },
+    /// Accepts a connection waiting at a running grapevined
+    #[cfg(unix)]
+    Accept {
+        /// Number of the connection, see `pending`
+        id: usize,
+        /// Name of the channel
+        name: Vec<String>,
+        /// Our private key in PEM, for connections that need keys
+        #[arg(long, requires = "public")]
+        private: Option<PathBuf>,
+        /// Their public key in PEM, for connections that need keys
+        #[arg(long, requires = "private")]
+        public: Option<PathBuf>,
+        #[command(flatten)]
+        daemon: Daemon,
+    },
+    /// Rejects a connection waiting at a running grapevined
+    #[cfg(unix)]
+    Reject {
+        /// Number of the connection, see `pending`
+        id: usize,
+        #[command(flatten)]
+        daemon: Daemon,
+    },
+}
+
+/// Where to find a running grapevined
+#[cfg(unix)]
+#[derive(clap::Args)]
+struct Daemon {
+    /// Socket the daemon listens on
+    #[arg(long)]
+    socket: Option<PathBuf>,
+}
+
+#[cfg(unix)]
+impl Daemon {
+    fn socket(self) -> Result<PathBuf, String> {
+        self.socket
+            .or_else(socket_path)
+            .ok_or_else(|| "couldn't find the daemon's socket, pass --socket".to_string())
+    }
+}
+
+/// Command-line counterpart of [AutoAccept]
+#[derive(Clone, Copy, ValueEnum)]
+enum Accept {
+    Never,
+    Contacts,
+    Everyone,
+}
+
+impl From<Accept> for AutoAccept {
+    fn from(accept: Accept) -> Self {
+        match accept {
+            Accept::Never => AutoAccept::Never,
+            Accept::Contacts => AutoAccept::Contacts,
+            Accept::Everyone => AutoAccept::Everyone,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("grapevine-cli: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    // the daemon has storage of its own
+    #[cfg(unix)]
+    match args.command {
+        Command::Attach { daemon } => return attach::attach(&daemon.socket()?),
+        Command::Pending { daemon } => return attach::pending(&daemon.socket()?),
+        Command::Accept {
+            id,
+            name,
+            private,
+            public,
+            daemon,
+        } => {
+            let keys = private
+                .zip(public)
+                .map(|(private, public)| KeyPaths { private, public });
+            let name = (!name.is_empty()).then(|| name.join(" "));
+            return attach::accept(&daemon.socket()?, id, name, keys);
+        }
+        Command::Reject { id, daemon } => return attach::reject(&daemon.socket()?, id),
+        _ => {}
+    }
+
+    let storage = match args.store {
+        Some(path) => Storage::open(path),
+        None => Storage::open_default(),
+    }
+    .map_err(|e| storage_error(e, "open"))?;
+    let settings = storage
+        .settings()
+        .map_err(|e| storage_error(e, "read the settings from"))?;
+    let contacts = storage
+        .contacts()
+        .map_err(|e| storage_error(e, "read the contacts from"))?;
+
+    if let Command::Contacts = args.command {
+        list_contacts(&contacts);
+        return Ok(());
+    }
+
+    let mut app = GrapevineApp::new();
+    settings.apply(&mut app);
+    app.set_contacts(contacts);
+    let events = app.subscribe();
+
+    let mut session = Session::new(app, events, storage, settings.save_channels());
+    let result = match args.command {
+        Command::Listen { addrs, accept } => listen(&mut session, &settings, addrs, accept),
+        Command::Connect { target, name } => connect(&mut session, &target, name),
+        Command::Send {
+            contact,
+            message,
+            timeout,
+        } => send(&mut session, &contact, message, timeout),
+        Command::Tail { contact } => tail(&mut session, &contact),
+        Command::Contacts => unreachable!(),
+        #[cfg(unix)]
+        Command::Attach { .. }
+        | Command::Pending { .. }
+        | Command::Accept { .. }
+        | Command::Reject { .. } => unreachable!(),
+    };
+    let finished = session.finish(SHUTDOWN_TIMEOUT);
+    result.and(finished)
+}
+
+fn storage_error(error: StorageError, action: &str) -> String {
+    format!("couldn't {} the storage: {}", action, error)
+}
+
+fn list_contacts(contacts: &ContactBook) {
+    for contact in contacts.iter() {
+        println!(
+            "{}\t{}\t{}",
+            contact.name(),
+            contact.desc().last_addr(),
+            contact.id()
+        );
+    }
+}
+
+/// Starts connecting to a saved contact
+fn connect_contact(session: &mut Session, query: &str) -> Result<Arc<ConnectionAttempt>, String> {
+    let desc = find_contact(&session.app().contacts().lock().unwrap(), query)?
+        .desc()
+        .clone();
+    Ok(session
+        .app_mut()
+        .new_channel_from_desc(desc.last_addr().clone(), desc))
+}
+
+fn listen(
+    session: &mut Session,
+    settings: &Settings,
+    addrs: Vec<HostAddr>,
+    accept: Accept,
+) -> Result<(), String> {
+    let configs: Vec<ListenerConfig> = if addrs.is_empty() {
+        settings.listeners().to_vec()
+    } else {
+        let policy = ListenerPolicy::new(
+            vec![ProtocolPath::RsaExchange, ProtocolPath::AesExchange],
+            true,
+            accept.into(),
+        );
+        addrs
+            .into_iter()
+            .map(|addr| ListenerConfig::new(addr, policy.clone()))
+            .collect()
+    };
+    if configs.is_empty() {
+        return Err("no listeners in the settings, pass an address to listen on".to_string());
+    }
+
+    for config in configs {
+        let addr = config.addr().clone();
+        match session.app_mut().add_listener(config) {
+            Ok(bound) => println!("* listening on {}", bound),
+            Err(e) => return Err(format!("couldn't listen on {}: {}", addr, e)),
+        }
+    }
+    session.interact(false)
+}
+
+fn connect(session: &mut Session, target: &str, name: Option<String>) -> Result<(), String> {
+    let attempt = match connect_contact(session, target) {
+        Ok(attempt) => attempt,
+        Err(not_found) => match target.parse::<HostAddr>() {
+            Ok(addr) => session.app_mut().new_rsa_channel(addr, name),
+            Err(_) => return Err(not_found),
+        },
+    };
+    println!("* connecting to {}", attempt.target());
+    session.interact(true)
+}
+
+fn send(
+    session: &mut Session,
+    contact: &str,
+    message: Vec<String>,
+    timeout: Option<u64>,
+) -> Result<(), String> {
+    let content = if message.is_empty() {
+        let mut content = String::new();
+        io::stdin()
+            .read_to_string(&mut content)
+            .map_err(|e| format!("couldn't read the message: {}", e))?;
+        content.trim_end().to_string()
+    } else {
+        message.join(" ")
+    };
+
+    let attempt = connect_contact(session, contact)?;
+    let channel = wait_open(session, &attempt, timeout.map(Duration::from_secs))?;
+    let handle = channel
+        .send_message(Message::new(content))
+        .map_err(|e| format!("couldn't send the message: {}", e))?;
+    handle
+        .wait()
+        .map_err(|e| format!("couldn't send the message: {}", e))
+}
+
+fn tail(session: &mut Session, contact: &str) -> Result<(), String> {
+    let attempt = connect_contact(session, contact)?;
+    let channel = wait_open(session, &attempt, None)?;
+    loop {
+        match session.next() {
+            Input::Event(Event::MessageReceived {
+                channel: from,
+                message,
+            }) if Arc::ptr_eq(&from, &channel) => println!("{}", message.content()),
+            Input::Event(Event::ChannelClosed {
+                channel: closed, ..
+            }) if Arc::ptr_eq(&closed, &channel) => {
+                return Ok(());
+            }
+            Input::Interrupt | Input::Eof => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+/// Waits for the attempt to open a channel
+fn wait_open(
+    session: &mut Session,
+    attempt: &Arc<ConnectionAttempt>,
+    timeout: Option<Duration>,
+) -> Result<Arc<Channel>, String> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        match session.next_until(deadline) {
+            Some(Input::Event(Event::ChannelOpened { channel })) if attempt.opened(&channel) => {
+                return Ok(channel);
+            }
+            Some(Input::Event(Event::ConnectionFailed {
+                attempt: failed,
+                error,
+            })) if Arc::ptr_eq(&failed, attempt) => {
+                return Err(format!(
+                    "couldn't connect to {}: {}",
+                    attempt.target(),
+                    error
+                ));
+            }
+            Some(Input::Interrupt) => {
+                attempt.cancel();
+                return Err("interrupted".to_string());
+            }
+            None => {
+                attempt.cancel();
+                return Err(format!("{} didn't accept us in time", attempt.target()));
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use std::{
+    fs,
+    io::{self, BufRead},
+    sync::{Arc, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use openssl::pkey::{PKey, Private, Public};
+
+use grapevine::storage::Storage;
+use grapevine_lib::{
+    Channel, Contact, Event, EventReceiver, GrapevineApp, Message, PendingConnection,
+};
+
+const HELP: &str = "\
+Lines are sent to the current channel, with a leading // sent as /, unless
+they are one of:
+  /pending                             list connections waiting to be accepted
+  /accept <n> [name]                   accept a key exchange, or a saved contact
+  /accept <n> <private> <public> [name]
+                                       accept a connection using the given keys
+  /reject <n>                          reject a connection
+  /channels                            list channels
+  /switch <n>                          make a channel the current one
+  /save [name]                         save the current channel as a contact
+  /close [reason]                      close the current channel
+  /quit                                close everything and exit";
+
+/// What the session may be woken up by
+pub enum Input {
+    Event(Event),
+    /// A line typed in
+    Line(String),
+    /// The standard input was closed
+    Eof,
+    /// Ctrl-C was pressed
+    Interrupt,
+}
+
+/// The app, along with everything it's waiting on
+pub struct Session {
+    app: GrapevineApp,
+    storage: Storage,
+    /// Whether the contacts get written back to the storage on exit
+    save_contacts: bool,
+    inputs: mpsc::Receiver<Input>,
+    sender: mpsc::Sender<Input>,
+    /// Channel the typed lines are sent to
+    current: Option<Arc<Channel>>,
+    reading: bool,
+}
+
+impl Session {
+    pub fn new(
+        app: GrapevineApp,
+        mut events: EventReceiver,
+        storage: Storage,
+        save_contacts: bool,
+    ) -> Self {
+        let (sender, inputs) = mpsc::channel();
+
+        let forward = sender.clone();
+        thread::spawn(move || {
+            while let Some(event) = events.blocking_recv() {
+                if forward.send(Input::Event(event)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let interrupt = sender.clone();
+        app.runtime().spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if interrupt.send(Input::Interrupt).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            app,
+            storage,
+            save_contacts,
+            inputs,
+            sender,
+            current: None,
+            reading: false,
+        }
+    }
+
+    pub fn app(&self) -> &GrapevineApp {
+        &self.app
+    }
+
+    pub fn app_mut(&mut self) -> &mut GrapevineApp {
+        &mut self.app
+    }
+
+    /// Waits for whatever comes next
+    pub fn next(&mut self) -> Input {
+        // we hold a sender ourselves
+        self.inputs.recv().unwrap()
+    }
+
+    /// Waits for whatever comes next, giving up at the deadline
+    pub fn next_until(&mut self, deadline: Option<Instant>) -> Option<Input> {
+        match deadline {
+            Some(deadline) => self
+                .inputs
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .ok(),
+            None => Some(self.next()),
+        }
+    }
+
+    /// Chats until the user quits, or, if asked to, until no channels are
+    /// left open
+    pub fn interact(&mut self, until_closed: bool) -> Result<(), String> {
+        if !self.reading {
+            self.reading = true;
+            let lines = self.sender.clone();
+            thread::spawn(move || {
+                for line in io::stdin().lock().lines() {
+                    let Ok(line) = line else { break };
+                    if lines.send(Input::Line(line)).is_err() {
+                        return;
+                    }
+                }
+                let _ = lines.send(Input::Eof);
+            });
+        }
+
+        loop {
+            match self.next() {
+                Input::Event(event) => {
+                    let finished = matches!(
+                        event,
+                        Event::ChannelClosed { .. } | Event::ConnectionFailed { .. }
+                    );
+                    self.on_event(event);
+                    if until_closed && finished && self.is_idle() {
+                        return Ok(());
+                    }
+                }
+                Input::Line(line) => match self.command(line.trim()) {
+                    Ok(true) => {}
+                    Ok(false) => return Ok(()),
+                    Err(e) => eprintln!("! {}", e),
+                },
+                Input::Eof | Input::Interrupt => return Ok(()),
+            }
+        }
+    }
+
+    /// Checks if nothing is open, nor being established
+    fn is_idle(&self) -> bool {
+        let open = self
+            .app
+            .channels()
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|channel| !channel.state().is_finished());
+        let connecting = self
+            .app
+            .connecting()
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|attempt| !attempt.state().is_finished());
+        !open && !connecting
+    }
+
+    fn on_event(&mut self, event: Event) {
+        match event {
+            Event::MessageReceived { channel, message } => {
+                println!("{}: {}", channel.name(), message.content());
+            }
+            // meant for other applications
+            Event::PayloadReceived { .. } => {}
+            Event::ChannelOpened { channel } => {
+                println!("* {} is open ({})", channel.name(), channel.peer());
+                if self.current.is_none() {
+                    self.current = Some(channel);
+                }
+            }
+            Event::ChannelClosed { channel, reason } => {
+                // stays current, for it to be saved or reopened
+                println!("* {}: {}", channel.name(), reason);
+            }
+            Event::PendingConnectionArrived { name, peer } => {
+                println!("* {} ({}) wants to connect, see /pending", name, peer);
+            }
+            Event::PendingConnectionExpired { name, peer } => {
+                println!("* {} ({}) is no longer waiting", name, peer);
+            }
+            Event::HandshakeFailed { error } => eprintln!("! handshake failed: {}", error),
+            Event::ConnectionFailed { attempt, error } => {
+                eprintln!("! connecting to {} failed: {}", attempt.target(), error);
+            }
+            Event::ListenerError { listener, error } => {
+                eprintln!("! listener {}: {}", listener, error);
+            }
+            Event::ConnectionRejected { peer, reason } => {
+                println!("* turned {} away: {}", peer, reason);
+            }
+            Event::GroupUpdated { group } if !group.is_active() => {
+                println!("* no longer in group {}", group.name());
+            }
+            Event::GroupUpdated { group } => {
+                let members = group
+                    .members()
+                    .iter()
+                    .map(|member| member.name().to_string())
+                    .collect::<Vec<_>>();
+                println!("* group {}: {}", group.name(), members.join(", "));
+            }
+            Event::GroupMessageReceived { group, message } => {
+                println!(
+                    "[{}] {}: {}",
+                    group.name(),
+                    message.author().name(),
+                    message.message().content()
+                );
+            }
+            Event::RelayedMessageReceived { name, message, .. } => {
+                println!("{} (through peers): {}", name, message.content());
+            }
+            Event::PeerDiscovered { peer } => {
+                println!("* found {} at {}", peer.name(), peer.addr());
+            }
+        }
+    }
+
+    /// Handles a typed line. Returns false once the user wants to quit.
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let Some(command) = line
+            .strip_prefix('/')
+            .filter(|command| !command.starts_with('/'))
+        else {
+            // a doubled slash sends a single one, like for commands of bots
+            let line = line.strip_prefix('/').unwrap_or(line);
+            if !line.is_empty() {
+                self.send(line)?;
+            }
+            return Ok(true);
+        };
+
+        let mut args = command.split_whitespace();
+        match args.next().unwrap_or_default() {
+            "help" => println!("{}", HELP),
+            "pending" => {
+                let pending = self.app.inspect_pending();
+                for (i, connection) in pending.iter().enumerate() {
+                    let kind = match connection {
+                        PendingConnection::Rsa(_) => "key exchange",
+                        PendingConnection::Aes(aes) if aes.contact().is_some() => "contact",
+                        PendingConnection::Aes(_) => "needs keys",
+                    };
+                    println!(
+                        "{}\t{}\t{}\t{}",
+                        i,
+                        connection.name(),
+                        connection.peer(),
+                        kind
+                    );
+                }
+                for connection in pending {
+                    self.app.add_pending(connection);
+                }
+            }
+            "accept" => {
+                let pending = self.take_pending(args.next())?;
+                let rest: Vec<&str> = args.collect();
+                let attempt = match (pending, rest.as_slice()) {
+                    (PendingConnection::Rsa(rsa), rest) => {
+                        self.app.add_rsa_channel(rsa, joined(rest))
+                    }
+                    (PendingConnection::Aes(aes), []) if aes.contact().is_some() => {
+                        self.app.add_known_aes_channel(aes)
+                    }
+                    (PendingConnection::Aes(aes), [private, public, rest @ ..]) => {
+                        let (private, public) = match read_keys(private, public) {
+                            Ok(keys) => keys,
+                            Err(e) => {
+                                self.app.add_pending(PendingConnection::Aes(aes));
+                                return Err(e);
+                            }
+                        };
+                        self.app.add_aes_channel(aes, joined(rest), private, public)
+                    }
+                    (pending, _) => {
+                        self.app.add_pending(pending);
+                        return Err("this connection needs the keys to use".to_string());
+                    }
+                };
+                println!("* accepting {}", attempt.target());
+            }
+            "reject" => self.take_pending(args.next())?.reject(),
+            "channels" => {
+                for (i, channel) in self.app.channels().lock().unwrap().iter().enumerate() {
+                    let current = if self
+                        .current
+                        .as_ref()
+                        .is_some_and(|current| Arc::ptr_eq(current, channel))
+                    {
+                        "*"
+                    } else {
+                        ""
+                    };
+                    println!("{}{}\t{}\t{}", i, current, channel.name(), channel.state());
+                }
+            }
+            "switch" => {
+                let index = index(args.next())?;
+                let channel = self.app.channels().lock().unwrap().get(index).cloned();
+                self.current = Some(channel.ok_or("no such channel")?);
+            }
+            "save" => {
+                let channel = self.current.as_ref().ok_or("no channel selected")?;
+                let mut contact = Contact::from(channel.desc().clone());
+                let rest: Vec<&str> = args.collect();
+                if let Some(name) = joined(&rest) {
+                    contact.rename(name);
+                }
+                println!("* saved {}", contact.name());
+                self.app.contacts().lock().unwrap().insert(contact);
+                self.save_contacts = true;
+            }
+            "close" => {
+                let channel = self.current.as_ref().ok_or("no channel selected")?;
+                let rest: Vec<&str> = args.collect();
+                match joined(&rest) {
+                    Some(reason) => channel.close_with_reason(reason),
+                    None => channel.close(),
+                }
+                .map_err(|e| e.to_string())?;
+            }
+            "quit" => return Ok(false),
+            other => return Err(format!("unknown command /{}, see /help", other)),
+        }
+        Ok(true)
+    }
+
+    fn send(&mut self, content: &str) -> Result<(), String> {
+        let channel = self
+            .current
+            .as_ref()
+            .ok_or("no channel selected, see /channels")?;
+        channel
+            .send_message(Message::new(content.to_string()))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Takes the pending connection out of the app, leaving the others
+    fn take_pending(&mut self, arg: Option<&str>) -> Result<PendingConnection, String> {
+        let index = index(arg)?;
+        let mut pending = self.app.inspect_pending();
+        let taken = (index < pending.len()).then(|| pending.remove(index));
+        for connection in pending {
+            self.app.add_pending(connection);
+        }
+        taken.ok_or_else(|| "no such connection, see /pending".to_string())
+    }
+
+    /// Writes the contacts back if need be, and says goodbye everywhere
+    pub fn finish(mut self, timeout: Duration) -> Result<(), String> {
+        let saved = if self.save_contacts {
+            let contacts = self.app.contacts().lock().unwrap().clone();
+            self.storage
+                .set_contacts(&contacts)
+                .and_then(|_| self.storage.save())
+                .map_err(|e| format!("couldn't save the contacts: {}", e))
+        } else {
+            Ok(())
+        };
+        self.app
+            .shutdown(timeout)
+            .map_err(|e| format!("couldn't shut down cleanly: {}", e))?;
+        saved
+    }
+}
+
+pub fn index(arg: Option<&str>) -> Result<usize, String> {
+    arg.ok_or("missing number")?
+        .parse()
+        .map_err(|_| "not a number".to_string())
+}
+
+/// Joins what's left of the arguments
+pub fn joined(rest: &[&str]) -> Option<String> {
+    (!rest.is_empty()).then(|| rest.join(" "))
+}
+
+fn read_keys(private: &str, public: &str) -> Result<(PKey<Private>, PKey<Public>), String> {
+    let read = |path: &str| fs::read(path).map_err(|e| format!("couldn't read {}: {}", path, e));
+    Ok((
+        PKey::private_key_from_pem(&read(private)?).map_err(|e| e.to_string())?,
+        PKey::public_key_from_pem(&read(public)?).map_err(|e| e.to_string())?,
+    ))
+}
@@ -0,0 +1,125 @@
+//! Background grapevine daemon. Owns the listeners and channels, for the
+//! other clients to attach to and detach from over a Unix socket, without
+//! the conversations ending with them.
+
+use std::process::ExitCode;
+
+#[cfg(unix)]
+mod server;
+
+#[cfg(not(unix))]
+fn main() -> ExitCode {
+    eprintln!("grapevined: only Unix-like systems are supported");
+    ExitCode::FAILURE
+}
+
+#[cfg(unix)]
+fn main() -> ExitCode {
+    use clap::Parser;
+
+    let args = unix::Args::parse();
+    match unix::run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("grapevined: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{path::PathBuf, sync::Arc, time::Duration};
+
+    use clap::Parser;
+
+    use grapevine::{rpc::socket_path, storage::Storage};
+    use grapevine_lib::{GrapevineApp, ListenerPolicy};
+
+    use super::server::Daemon;
+
+    /// How long exiting may wait for channels to say goodbye
+    const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+    #[derive(Parser)]
+    #[command(version, about)]
+    pub struct Args {
+        /// Storage file to use, instead of the one the GUI saves to
+        #[arg(long)]
+        store: Option<PathBuf>,
+        /// Socket to listen for clients on
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    }
+
+    pub fn run(args: Args) -> Result<(), String> {
+        let storage = match args.store {
+            Some(path) => Storage::open(path),
+            None => Storage::open_default(),
+        }
+        .map_err(|e| format!("couldn't open the storage: {}", e))?;
+        let settings = storage
+            .settings()
+            .map_err(|e| format!("couldn't read the settings: {}", e))?;
+        let contacts = storage
+            .contacts()
+            .map_err(|e| format!("couldn't read the contacts: {}", e))?;
+        let socket = args
+            .socket
+            .or_else(socket_path)
+            .ok_or("couldn't find a directory for the socket, pass --socket")?;
+
+        let mut app = GrapevineApp::new();
+        settings.apply(&mut app);
+        app.set_contacts(contacts);
+        // like the GUI, a listener failing doesn't stop the rest
+        let mut port = None;
+        for config in settings.listeners() {
+            match app.add_listener(config.clone()) {
+                Ok(bound) => {
+                    eprintln!("listening on {}", bound);
+                    port.get_or_insert(bound.port());
+                }
+                Err(e) => eprintln!("couldn't listen on {}: {}", config.addr(), e),
+            }
+        }
+        if settings.discovery() {
+            let (announcement, keys) = {
+                let contacts = app.contacts().lock().unwrap();
+                (
+                    port.map(|port| settings.announcement(port, &contacts)),
+                    settings.announcement_keys(&contacts),
+                )
+            };
+            if let Err(e) = app.start_discovery(announcement, &keys) {
+                eprintln!("couldn't find peers on the local network: {}", e);
+            }
+        }
+        if let Some(relay) = settings.relay() {
+            let key = settings.relay_key().clone();
+            let contact_keys = settings.contact_keys(&app.contacts().lock().unwrap());
+            match app.listen_through_relay(
+                relay.clone(),
+                key,
+                contact_keys,
+                ListenerPolicy::default(),
+            ) {
+                Ok(_) => eprintln!("waiting at {}", relay),
+                Err(e) => eprintln!("couldn't wait at {}: {}", relay, e),
+            }
+        }
+
+        let daemon = Arc::new(Daemon::new(
+            app,
+            storage,
+            settings.save_channels(),
+            socket.clone(),
+        ));
+        let served = daemon
+            .clone()
+            .serve()
+            .map_err(|e| format!("couldn't serve on {}: {}", socket.display(), e));
+        let finished = daemon.finish(SHUTDOWN_TIMEOUT);
+        served.and(finished)
+    }
+}
@@ -0,0 +1,558 @@
+use std::{
+    fs::{self, DirBuilder, Permissions},
+    io::{self, BufRead, BufReader, Write},
+    net::Shutdown,
+    os::unix::{
+        fs::{DirBuilderExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use openssl::pkey::{PKey, Private, Public};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Value, from_str, from_value, to_value, to_vec};
+use tokio::signal::{
+    self,
+    unix::{SignalKind, signal},
+};
+
+use grapevine::{
+    rpc::{
+        AcceptParams, AttemptInfo, ChannelInfo, ChannelParams, CloseParams, ConnectParams, EVENT,
+        EventInfo, HistoryEntry, KeyPaths, ListenerInfo, Notification, PendingInfo, PendingKind,
+        PendingParams, Request, Response, RpcError, SaveParams, SendParams, VERSION,
+    },
+    storage::{Storage, find_contact},
+};
+use grapevine_lib::{
+    Channel, ConnectionAttempt, Contact, Event, GrapevineApp, HostAddr, ListenerConfig, Message,
+    PendingConnection,
+};
+
+type Writer = Arc<Mutex<UnixStream>>;
+type CallResult = Result<Value, RpcError>;
+
+/// The app, shared between the clients attached to it
+pub struct Daemon {
+    app: Mutex<GrapevineApp>,
+    storage: Mutex<Storage>,
+    /// Whether the contacts get written back to the storage on exit
+    save_contacts: bool,
+    socket: PathBuf,
+    stopping: AtomicBool,
+}
+
+impl Daemon {
+    pub fn new(app: GrapevineApp, storage: Storage, save_contacts: bool, socket: PathBuf) -> Self {
+        Self {
+            app: Mutex::new(app),
+            storage: Mutex::new(storage),
+            save_contacts,
+            socket,
+            stopping: AtomicBool::new(false),
+        }
+    }
+
+    /// Serves clients, each on its own thread, until stopped by one of them
+    /// or a signal
+    pub fn serve(self: Arc<Self>) -> io::Result<()> {
+        let listener = bind(&self.socket)?;
+        eprintln!("waiting for clients on {}", self.socket.display());
+
+        let runtime = self.app.lock().unwrap().runtime().clone();
+        let interrupted = self.clone();
+        runtime.spawn(async move {
+            if signal::ctrl_c().await.is_ok() {
+                interrupted.stop();
+            }
+        });
+        let mut terminate = {
+            let _guard = runtime.enter();
+            signal(SignalKind::terminate())?
+        };
+        let terminated = self.clone();
+        runtime.spawn(async move {
+            if terminate.recv().await.is_some() {
+                terminated.stop();
+            }
+        });
+
+        for stream in listener.incoming() {
+            if self.stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            match stream {
+                Ok(stream) => {
+                    let daemon = self.clone();
+                    thread::spawn(move || daemon.serve_client(stream));
+                }
+                Err(e) => eprintln!("couldn't accept a client: {}", e),
+            }
+        }
+        fs::remove_file(&self.socket)
+    }
+
+    /// Makes [Self::serve] return
+    fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        // wakes the listener up, for it to notice
+        let _ = UnixStream::connect(&self.socket);
+    }
+
+    /// Writes the contacts back if need be, and says goodbye everywhere
+    pub fn finish(&self, timeout: Duration) -> Result<(), String> {
+        let mut app = self.app.lock().unwrap();
+        let saved = if self.save_contacts {
+            self.save_contacts(&app)
+                .map_err(|e| format!("couldn't save the contacts: {}", e))
+        } else {
+            Ok(())
+        };
+        app.shutdown(timeout)
+            .map_err(|e| format!("couldn't shut down cleanly: {}", e))?;
+        saved
+    }
+
+    fn save_contacts(&self, app: &GrapevineApp) -> Result<(), grapevine::storage::StorageError> {
+        let contacts = app.contacts().lock().unwrap().clone();
+        let mut storage = self.storage.lock().unwrap();
+        storage.set_contacts(&contacts)?;
+        storage.save()
+    }
+
+    fn serve_client(&self, stream: UnixStream) {
+        let writer = match stream.try_clone() {
+            Ok(writer) => Arc::new(Mutex::new(writer)),
+            Err(e) => {
+                eprintln!("couldn't serve a client: {}", e);
+                return;
+            }
+        };
+
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (id, result, stop) = match parse(&line) {
+                Ok(request) => (
+                    request.id,
+                    self.call(&request.method, request.params, &writer),
+                    request.method == "shutdown",
+                ),
+                Err(error) => (Some(Value::Null), Err(error), false),
+            };
+            // notifications get no response
+            if let Some(id) = id
+                && write_line(&writer, &Response::new(id, result)).is_err()
+            {
+                break;
+            }
+            if stop {
+                self.stop();
+                break;
+            }
+        }
+        // ends the event subscription, if any
+        let _ = writer.lock().unwrap().shutdown(Shutdown::Both);
+    }
+
+    fn call(&self, method: &str, params: Value, writer: &Writer) -> CallResult {
+        match method {
+            "channels" => reply(self.channels()),
+            "connecting" => reply(self.connecting()),
+            "pending" => reply(self.pending()),
+            "contacts" => reply(self.app.lock().unwrap().contacts().lock().unwrap().clone()),
+            "listeners" => reply(self.listeners()),
+            "listen" => self.listen(parse_params(params)?),
+            "connect" => self.connect(parse_params(params)?),
+            "accept" => self.accept(parse_params(params)?),
+            "reject" => {
+                let PendingParams { pending } = parse_params(params)?;
+                self.take_pending(pending)?.reject();
+                reply(())
+            }
+            "reconnect" => self.reconnect(parse_params(params)?),
+            "history" => self.history(parse_params(params)?),
+            "send" => self.send(parse_params(params)?),
+            "close" => self.close(parse_params(params)?),
+            "save_contact" => self.save_contact(parse_params(params)?),
+            "subscribe" => {
+                self.subscribe(writer.clone());
+                reply(())
+            }
+            "shutdown" => reply(()),
+            _ => Err(RpcError::new(
+                RpcError::METHOD_NOT_FOUND,
+                format!("no method named {}", method),
+            )),
+        }
+    }
+
+    fn channels(&self) -> Vec<ChannelInfo> {
+        let app = self.app.lock().unwrap();
+        let channels = app.channels().lock().unwrap();
+        channels
+            .iter()
+            .enumerate()
+            .map(|(id, channel)| channel_info(id, channel))
+            .collect()
+    }
+
+    fn connecting(&self) -> Vec<AttemptInfo> {
+        let app = self.app.lock().unwrap();
+        let attempts = app.connecting().lock().unwrap();
+        attempts
+            .iter()
+            .map(|attempt| attempt_info(attempt))
+            .collect()
+    }
+
+    fn pending(&self) -> Vec<PendingInfo> {
+        let mut app = self.app.lock().unwrap();
+        let pending = app.inspect_pending();
+        let infos = pending
+            .iter()
+            .enumerate()
+            .map(|(id, connection)| PendingInfo {
+                id,
+                name: connection.name().to_string(),
+                peer: connection.peer().to_string(),
+                kind: match connection {
+                    PendingConnection::Rsa(_) => PendingKind::KeyExchange,
+                    PendingConnection::Aes(aes) if aes.contact().is_some() => PendingKind::Contact,
+                    PendingConnection::Aes(_) => PendingKind::NeedsKeys,
+                },
+            })
+            .collect();
+        for connection in pending {
+            app.add_pending(connection);
+        }
+        infos
+    }
+
+    fn listeners(&self) -> Vec<ListenerInfo> {
+        let app = self.app.lock().unwrap();
+        app.listeners()
+            .map(|(endpoint, policy)| ListenerInfo {
+                endpoint: endpoint.to_string(),
+                policy: policy.clone(),
+            })
+            .collect()
+    }
+
+    fn listen(&self, config: ListenerConfig) -> CallResult {
+        let bound = self
+            .app
+            .lock()
+            .unwrap()
+            .add_listener(config)
+            .map_err(RpcError::failed)?;
+        reply(bound.to_string())
+    }
+
+    fn connect(&self, params: ConnectParams) -> CallResult {
+        let mut app = self.app.lock().unwrap();
+        let found = find_contact(&app.contacts().lock().unwrap(), &params.target)
+            .map(|contact| contact.desc().clone());
+        let attempt = match found {
+            Ok(desc) => app.new_channel_from_desc(desc.last_addr().clone(), desc),
+            Err(not_found) => match HostAddr::from_str(&params.target) {
+                Ok(addr) => app.new_rsa_channel(addr, params.name),
+                Err(_) => return Err(RpcError::failed(not_found)),
+            },
+        };
+        reply(attempt_info(&attempt))
+    }
+
+    /// Takes the pending connection out of the app, leaving the others
+    fn take_pending(&self, id: usize) -> Result<PendingConnection, RpcError> {
+        let mut app = self.app.lock().unwrap();
+        let mut pending = app.inspect_pending();
+        let taken = (id < pending.len()).then(|| pending.remove(id));
+        for connection in pending {
+            app.add_pending(connection);
+        }
+        taken.ok_or_else(|| RpcError::failed("no such pending connection"))
+    }
+
+    fn accept(&self, params: AcceptParams) -> CallResult {
+        let pending = self.take_pending(params.pending)?;
+        let mut app = self.app.lock().unwrap();
+        let attempt = match (pending, params.keys) {
+            (PendingConnection::Rsa(rsa), _) => app.add_rsa_channel(rsa, params.name),
+            (PendingConnection::Aes(aes), None) if aes.contact().is_some() => {
+                app.add_known_aes_channel(aes)
+            }
+            (PendingConnection::Aes(aes), Some(keys)) => match read_keys(&keys) {
+                Ok((private, public)) => app.add_aes_channel(aes, params.name, private, public),
+                Err(e) => {
+                    app.add_pending(PendingConnection::Aes(aes));
+                    return Err(e);
+                }
+            },
+            (pending, None) => {
+                app.add_pending(pending);
+                return Err(RpcError::failed("this connection needs the keys to use"));
+            }
+        };
+        reply(attempt_info(&attempt))
+    }
+
+    fn channel(&self, id: usize) -> Result<Arc<Channel>, RpcError> {
+        let app = self.app.lock().unwrap();
+        let channel = app.channels().lock().unwrap().get(id).cloned();
+        channel.ok_or_else(|| RpcError::failed("no such channel"))
+    }
+
+    fn reconnect(&self, params: ChannelParams) -> CallResult {
+        let channel = self.channel(params.channel)?;
+        let attempt = self
+            .app
+            .lock()
+            .unwrap()
+            .reconnect(&channel)
+            .map_err(RpcError::failed)?;
+        reply(attempt_info(&attempt))
+    }
+
+    fn history(&self, params: ChannelParams) -> CallResult {
+        let channel = self.channel(params.channel)?;
+        let messages = channel.messages().lock().unwrap();
+        reply(messages.iter().map(history_entry).collect::<Vec<_>>())
+    }
+
+    /// Sends the message, returning once it's written out
+    fn send(&self, params: SendParams) -> CallResult {
+        let channel = self.channel(params.channel)?;
+        channel
+            .send_message(Message::new(params.content))
+            .map_err(RpcError::failed)?
+            .wait()
+            .map_err(RpcError::failed)?;
+        reply(())
+    }
+
+    fn close(&self, params: CloseParams) -> CallResult {
+        let channel = self.channel(params.channel)?;
+        match params.reason {
+            Some(reason) => channel.close_with_reason(reason),
+            None => channel.close(),
+        }
+        .map_err(RpcError::failed)?;
+        reply(())
+    }
+
+    /// Saves the channel as a contact, writing the contacts out right away
+    fn save_contact(&self, params: SaveParams) -> CallResult {
+        let channel = self.channel(params.channel)?;
+        let mut contact = Contact::from(channel.desc().clone());
+        if let Some(name) = params.name {
+            contact.rename(name);
+        }
+        let app = self.app.lock().unwrap();
+        app.contacts().lock().unwrap().insert(contact);
+        self.save_contacts(&app).map_err(RpcError::failed)?;
+        reply(())
+    }
+
+    /// Forwards all the events to the client, until it goes away
+    fn subscribe(&self, writer: Writer) {
+        let mut app = self.app.lock().unwrap();
+        let mut events = app.subscribe();
+        let channels = app.channels().clone();
+        thread::spawn(move || {
+            while let Some(event) = events.blocking_recv() {
+                let info = event_info(&event, &channels.lock().unwrap());
+                let notification = Notification {
+                    jsonrpc: VERSION.to_string(),
+                    method: EVENT.to_string(),
+                    params: to_value(info).unwrap(),
+                };
+                if write_line(&writer, &notification).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Binds the socket, only accessible to us
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "another daemon is already running",
+        ));
+    }
+    // left behind by a daemon that didn't exit cleanly
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    if let Some(dir) = path.parent() {
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+fn parse(line: &str) -> Result<Request, RpcError> {
+    let value: Value = from_str(line).map_err(|e| RpcError::new(RpcError::PARSE_ERROR, e))?;
+    let request: Request =
+        from_value(value).map_err(|e| RpcError::new(RpcError::INVALID_REQUEST, e))?;
+    if request.jsonrpc != VERSION {
+        return Err(RpcError::new(
+            RpcError::INVALID_REQUEST,
+            "unsupported JSON-RPC version",
+        ));
+    }
+    Ok(request)
+}
+
+fn parse_params<P: DeserializeOwned>(params: Value) -> Result<P, RpcError> {
+    from_value(params).map_err(|e| RpcError::new(RpcError::INVALID_PARAMS, e))
+}
+
+fn reply(result: impl Serialize) -> CallResult {
+    to_value(result).map_err(RpcError::failed)
+}
+
+fn write_line(writer: &Writer, message: &impl Serialize) -> io::Result<()> {
+    let mut line = to_vec(message)?;
+    line.push(b'\n');
+    writer.lock().unwrap().write_all(&line)
+}
+
+fn read_keys(keys: &KeyPaths) -> Result<(PKey<Private>, PKey<Public>), RpcError> {
+    let read = |path: &Path| {
+        fs::read(path)
+            .map_err(|e| RpcError::failed(format!("couldn't read {}: {}", path.display(), e)))
+    };
+    Ok((
+        PKey::private_key_from_pem(&read(&keys.private)?).map_err(RpcError::failed)?,
+        PKey::public_key_from_pem(&read(&keys.public)?).map_err(RpcError::failed)?,
+    ))
+}
+
+fn channel_info(id: usize, channel: &Channel) -> ChannelInfo {
+    let state = channel.state();
+    ChannelInfo {
+        id,
+        name: channel.name().to_string(),
+        peer: channel.peer().to_string(),
+        fingerprint: *channel.fingerprint(),
+        state: state.to_string(),
+        finished: state.is_finished(),
+    }
+}
+
+fn attempt_info(attempt: &ConnectionAttempt) -> AttemptInfo {
+    AttemptInfo {
+        name: attempt.name().to_string(),
+        target: attempt.target().to_string(),
+        state: attempt.state().to_string(),
+    }
+}
+
+fn history_entry(message: &Message) -> HistoryEntry {
+    HistoryEntry {
+        content: message.content().clone(),
+        timestamp: *message.timestamp(),
+        ours: message.is_ours(),
+    }
+}
+
+/// Finds the position of the channel, which is how clients refer to it
+fn channel_id(channels: &[Arc<Channel>], channel: &Arc<Channel>) -> usize {
+    channels
+        .iter()
+        .position(|other| Arc::ptr_eq(other, channel))
+        .unwrap_or(channels.len())
+}
+
+fn event_info(event: &Event, channels: &[Arc<Channel>]) -> EventInfo {
+    match event {
+        Event::MessageReceived { channel, message } => EventInfo::MessageReceived {
+            channel: channel_id(channels, channel),
+            name: channel.name().to_string(),
+            message: history_entry(message),
+        },
+        Event::PayloadReceived { channel, payload } => EventInfo::PayloadReceived {
+            channel: channel_id(channels, channel),
+            name: channel.name().to_string(),
+            tag: payload.tag().to_string(),
+            data: payload.data().to_vec(),
+        },
+        Event::ChannelOpened { channel } => EventInfo::ChannelOpened {
+            channel: channel_info(channel_id(channels, channel), channel),
+        },
+        Event::ChannelClosed { channel, reason } => EventInfo::ChannelClosed {
+            channel: channel_id(channels, channel),
+            name: channel.name().to_string(),
+            reason: reason.to_string(),
+        },
+        Event::PendingConnectionArrived { name, peer } => EventInfo::PendingConnectionArrived {
+            name: name.clone(),
+            peer: peer.to_string(),
+        },
+        Event::PendingConnectionExpired { name, peer } => EventInfo::PendingConnectionExpired {
+            name: name.clone(),
+            peer: peer.to_string(),
+        },
+        Event::HandshakeFailed { error } => EventInfo::HandshakeFailed {
+            error: error.to_string(),
+        },
+        Event::ConnectionFailed { attempt, error } => EventInfo::ConnectionFailed {
+            attempt: attempt_info(attempt),
+            error: error.to_string(),
+        },
+        Event::ListenerError { listener, error } => EventInfo::ListenerError {
+            listener: listener.clone(),
+            error: error.to_string(),
+        },
+        Event::ConnectionRejected { peer, reason } => EventInfo::ConnectionRejected {
+            peer: peer.to_string(),
+            reason: reason.to_string(),
+        },
+        Event::GroupUpdated { group } => EventInfo::GroupUpdated {
+            group: *group.id(),
+            name: group.name(),
+            members: group
+                .members()
+                .iter()
+                .map(|member| member.name().to_string())
+                .collect(),
+            active: group.is_active(),
+        },
+        Event::GroupMessageReceived { group, message } => EventInfo::GroupMessageReceived {
+            group: *group.id(),
+            name: group.name(),
+            author: message.author().name().to_string(),
+            message: history_entry(message.message()),
+        },
+        Event::RelayedMessageReceived {
+            name,
+            sender,
+            message,
+        } => EventInfo::RelayedMessageReceived {
+            name: name.clone(),
+            sender: *sender,
+            message: history_entry(message),
+        },
+        Event::PeerDiscovered { peer } => EventInfo::PeerDiscovered {
+            name: peer.name().to_string(),
+            addr: peer.addr().to_string(),
+            id: *peer.id(),
+        },
+    }
+}
@@ -3,7 +3,7 @@ use std::ops::{Deref, DerefMut};
 use egui::Context;
 use egui_notify::Toasts;
 
-use grapevine_lib::EventRecipient;
+use grapevine_lib::{CloseReason, Event};
 
 #[derive(Default)]
 pub struct UiEventHandler {
@@ -14,6 +14,42 @@ impl UiEventHandler {
     pub fn ui(&mut self, ctx: &Context) {
         self.toasts.show(ctx);
     }
+
+    /// Turns the app event into a toast, if it's worth one
+    pub fn on_event(&mut self, event: &Event) {
+        let message = event.to_string();
+        match event {
+            // the channel view shows these already
+            Event::MessageReceived { .. } => {}
+            // meant for other applications
+            Event::PayloadReceived { .. } => {}
+            Event::ChannelOpened { .. } => {
+                self.toasts.success(message);
+            }
+            Event::ChannelClosed {
+                reason: CloseReason::Failed(_),
+                ..
+            }
+            | Event::HandshakeFailed { .. }
+            | Event::ConnectionFailed { .. }
+            | Event::ListenerError { .. } => {
+                self.toasts.error(message);
+            }
+            Event::ChannelClosed { .. } | Event::PendingConnectionArrived { .. } => {
+                self.toasts.info(message);
+            }
+            Event::PendingConnectionExpired { .. } | Event::ConnectionRejected { .. } => {
+                self.toasts.warning(message);
+            }
+            // the group view shows these already
+            Event::GroupMessageReceived { .. } => {}
+            Event::GroupUpdated { .. }
+            | Event::RelayedMessageReceived { .. }
+            | Event::PeerDiscovered { .. } => {
+                self.toasts.info(message);
+            }
+        }
+    }
 }
 
 impl Deref for UiEventHandler {
@@ -29,21 +65,3 @@ impl DerefMut for UiEventHandler {
         &mut self.toasts
     }
 }
-
-impl EventRecipient for UiEventHandler {
-    fn info(&mut self, message: &str) {
-        self.toasts.info(message);
-    }
-
-    fn warn(&mut self, message: &str) {
-        self.toasts.warning(message);
-    }
-
-    fn error(&mut self, message: &str) {
-        self.toasts.error(message);
-    }
-
-    fn success(&mut self, message: &str) {
-        self.toasts.success(message);
-    }
-}
@@ -0,0 +1,11 @@
+//! What the grapevine frontends share, so that they all pick up the same
+//! settings and contacts.
+
+/// User preferences, as edited in the GUI
+pub mod settings;
+
+/// The key-value store the GUI persists its state in
+pub mod storage;
+
+/// The protocol of the daemon's control socket, and a client for it
+pub mod rpc;
@@ -1,21 +1,22 @@
-use std::any::type_name;
-
 mod handler;
 
 mod ui;
 use ui::GrapevineUI;
 
-mod settings;
-use settings::Settings;
+use grapevine::{
+    settings::Settings,
+    storage::{APP_ID, contacts_from, protect_storage_dir, settings_from},
+};
 
 mod modals;
 
-use grapevine_lib::ChannelDesc;
-use serde_json::from_str;
-
-const TITLE: &'static str = env!("CARGO_PKG_NAME");
+const TITLE: &str = APP_ID;
 
 fn main() -> eframe::Result {
+    // eframe writes the settings there, private keys included
+    if let Err(e) = protect_storage_dir() {
+        eprintln!("couldn't protect the storage directory: {}", e);
+    }
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([1000.0, 1000.0])
@@ -28,35 +29,24 @@ fn main() -> eframe::Result {
         Box::new(|cc| {
             Ok({
                 let mut settings = None;
-                let mut saved_channels = None;
+                let mut contacts = None;
 
                 if let Some(storage) = cc.storage {
-                    if let Some(serialized_settings) = storage.get_string(type_name::<Settings>()) {
-                        match from_str(serialized_settings.as_str()) {
-                            Ok(val) => {
-                                settings = Some(val);
-                            }
-                            Err(e) => {
-                                eprintln!("{}", e);
-                            }
-                        }
+                    let get = |key: &str| storage.get_string(key);
+                    match settings_from(get) {
+                        Ok(val) => settings = val,
+                        Err(e) => eprintln!("{}", e),
                     }
-
-                    if let Some(serialized_channels) =
-                        storage.get_string(type_name::<ChannelDesc>())
-                    {
-                        match from_str(serialized_channels.as_str()) {
-                            Ok(val) => saved_channels = Some(val),
-                            Err(e) => {
-                                eprintln!("{}", e);
-                            }
-                        }
+                    match contacts_from(get) {
+                        Ok(val) => contacts = val,
+                        Err(e) => eprintln!("{}", e),
                     }
                 }
 
                 Box::new(GrapevineUI::new(
                     settings.unwrap_or(Settings::default()),
-                    saved_channels.unwrap_or(Vec::default()),
+                    contacts.unwrap_or_default(),
+                    cc.egui_ctx.clone(),
                 ))
             })
         }),
@@ -1,9 +1,4 @@
-use std::{
-    fs, io, mem,
-    net::{AddrParseError, SocketAddr},
-    path::PathBuf,
-    str::FromStr,
-};
+use std::{fs, io, mem, path::PathBuf, str::FromStr};
 
 use derive_more::{Display, Error, From};
 use egui::{Frame, Ui};
@@ -13,24 +8,33 @@ use openssl::{
 };
 
 use egui_path_picker::PathPicker;
+use grapevine_lib::{Fingerprint, FingerprintParseError, HostAddr, HostAddrParseError};
 
 use super::modal::Form;
 
 #[derive(Debug, From, Display, Error)]
 pub enum ChannelFormError {
-    InvalidIp(AddrParseError),
+    InvalidAddr(HostAddrParseError),
+    InvalidFingerprint(FingerprintParseError),
     IoError(io::Error),
     OpenSSL(ErrorStack),
 }
 
 pub enum ChannelArgs {
-    Rsa((SocketAddr, Option<String>)),
-    Aes((SocketAddr, Option<String>, PKey<Private>, PKey<Public>)),
+    Rsa((HostAddr, Option<String>)),
+    Aes((HostAddr, Option<String>, PKey<Private>, PKey<Public>)),
+    /// Through the relay, to whoever waits there under the fingerprint
+    RelayedRsa((HostAddr, Fingerprint, Option<String>)),
+    /// Through the relay, to whoever waits there under their key
+    RelayedAes((HostAddr, Option<String>, PKey<Private>, PKey<Public>)),
 }
 
 pub struct ChannelForm {
     channel_name_input: String,
     channel_addr_input: String,
+    through_relay: bool,
+    relay_addr_input: String,
+    relay_id_input: String,
     aes_skip: bool,
     public_key_path: String,
     private_key_path: String,
@@ -38,16 +42,19 @@ pub struct ChannelForm {
 }
 
 impl ChannelForm {
-    pub fn new(default_key_path: PathBuf) -> Self {
+    pub fn new(default_key_path: PathBuf, relay: Option<&HostAddr>) -> Self {
         let default_key_path_str = default_key_path.to_string_lossy().to_string();
 
         Self {
             channel_addr_input: String::new(),
             channel_name_input: String::new(),
+            through_relay: false,
+            relay_addr_input: relay.map(|relay| relay.to_string()).unwrap_or_default(),
+            relay_id_input: String::new(),
             aes_skip: false,
             public_key_path: default_key_path_str.clone(),
             private_key_path: default_key_path_str,
-            default_key_path: default_key_path,
+            default_key_path,
         }
     }
 }
@@ -60,8 +67,19 @@ impl<'a> Form<'a> for ChannelForm {
         ui.label("Channel Name");
         ui.text_edit_singleline(&mut self.channel_name_input);
 
-        ui.label("Address");
-        ui.text_edit_singleline(&mut self.channel_addr_input);
+        ui.checkbox(&mut self.through_relay, "Through relay");
+        if self.through_relay {
+            ui.label("Relay address");
+            ui.text_edit_singleline(&mut self.relay_addr_input);
+            // with known keys, they wait under theirs
+            if !self.aes_skip {
+                ui.label("Fingerprint they wait under");
+                ui.text_edit_singleline(&mut self.relay_id_input);
+            }
+        } else {
+            ui.label("Address");
+            ui.text_edit_singleline(&mut self.channel_addr_input);
+        }
 
         ui.checkbox(&mut self.aes_skip, "Known keys");
         ui.add_enabled_ui(self.aes_skip, |ui| {
@@ -82,17 +100,33 @@ impl<'a> Form<'a> for ChannelForm {
 
         ui.horizontal(|ui| {
             if ui.button("Create").clicked() {
-                let addr = SocketAddr::from_str(&self.channel_addr_input)?;
+                let addr = HostAddr::from_str(match self.through_relay {
+                    false => &self.channel_addr_input,
+                    true => &self.relay_addr_input,
+                })?;
                 let name = Some(mem::take(&mut self.channel_name_input)).filter(|s| !s.is_empty());
+                let keys = || -> Result<_, ChannelFormError> {
+                    Ok((
+                        PKey::private_key_from_pem(&fs::read(&self.private_key_path)?)?,
+                        PKey::public_key_from_pem(&fs::read(&self.public_key_path)?)?,
+                    ))
+                };
 
-                Ok(Some(Some(match self.aes_skip {
-                    false => ChannelArgs::Rsa((addr, name)),
-                    true => ChannelArgs::Aes((
+                Ok(Some(Some(match (self.through_relay, self.aes_skip) {
+                    (false, false) => ChannelArgs::Rsa((addr, name)),
+                    (false, true) => {
+                        let (ours, theirs) = keys()?;
+                        ChannelArgs::Aes((addr, name, ours, theirs))
+                    }
+                    (true, false) => ChannelArgs::RelayedRsa((
                         addr,
+                        Fingerprint::from_str(self.relay_id_input.trim())?,
                         name,
-                        PKey::private_key_from_pem(&fs::read(&self.private_key_path)?)?,
-                        PKey::public_key_from_pem(&fs::read(&self.public_key_path)?)?,
                     )),
+                    (true, true) => {
+                        let (ours, theirs) = keys()?;
+                        ChannelArgs::RelayedAes((addr, name, ours, theirs))
+                    }
                 })))
             } else if ui.button("Cancel").clicked() {
                 Ok(Some(None))
@@ -1,24 +1,27 @@
-use std::{
-    net::{AddrParseError, SocketAddr},
-    str::FromStr,
-};
+use std::str::FromStr;
 
 use egui::Ui;
 
-use grapevine_lib::ChannelDesc;
+use grapevine_lib::{ChannelDesc, Contact, HostAddr, HostAddrParseError};
 
 use super::modal::Form;
 
 pub struct ChannelRecreationForm {
     desc: ChannelDesc,
+    known_addrs: Vec<HostAddr>,
     channel_addr_input: String,
+    through_relay: bool,
+    relay_addr_input: String,
 }
 
 impl ChannelRecreationForm {
-    pub fn new(desc: ChannelDesc) -> Self {
+    pub fn new(contact: &Contact, relay: Option<&HostAddr>) -> Self {
         Self {
-            channel_addr_input: desc.last_addr().to_string(),
-            desc,
+            channel_addr_input: contact.desc().last_addr().to_string(),
+            through_relay: false,
+            relay_addr_input: relay.map(|relay| relay.to_string()).unwrap_or_default(),
+            known_addrs: contact.addresses().to_vec(),
+            desc: contact.desc().clone(),
         }
     }
 
@@ -28,18 +31,41 @@ impl ChannelRecreationForm {
 }
 
 impl<'a> Form<'a> for ChannelRecreationForm {
-    type Ret = Option<SocketAddr>;
-    type Error = AddrParseError;
+    /// The address, and whether it's of a relay
+    type Ret = Option<(HostAddr, bool)>;
+    type Error = HostAddrParseError;
 
     fn show(&mut self, ui: &mut Ui) -> Result<Option<Self::Ret>, Self::Error> {
-        ui.label("Address");
-        ui.text_edit_singleline(&mut self.channel_addr_input);
+        ui.label(format!("Reconnecting to {}", self.desc.name()));
+
+        ui.checkbox(&mut self.through_relay, "Through relay");
+        if self.through_relay {
+            ui.label("Relay address");
+            ui.text_edit_singleline(&mut self.relay_addr_input);
+        } else if self.known_addrs.len() > 1 {
+            ui.label("Known addresses");
+            for addr in &self.known_addrs {
+                let addr = addr.to_string();
+                let selected = addr == self.channel_addr_input;
+                if ui.selectable_label(selected, addr.as_str()).clicked() {
+                    self.channel_addr_input = addr;
+                }
+            }
+        }
+
+        if !self.through_relay {
+            ui.label("Address");
+            ui.text_edit_singleline(&mut self.channel_addr_input);
+        }
 
         ui.horizontal(|ui| {
             if ui.button("Create").clicked() {
-                let addr = SocketAddr::from_str(&self.channel_addr_input)?;
+                let addr = HostAddr::from_str(match self.through_relay {
+                    false => &self.channel_addr_input,
+                    true => &self.relay_addr_input,
+                })?;
 
-                Ok(Some(Some(addr)))
+                Ok(Some(Some((addr, self.through_relay))))
             } else if ui.button("Cancel").clicked() {
                 Ok(Some(None))
             } else {
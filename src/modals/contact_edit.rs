@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+use egui::Ui;
+
+use grapevine_lib::{Contact, HostAddr, HostAddrParseError};
+
+use super::modal::Form;
+
+pub struct ContactEditForm {
+    name_input: String,
+    addresses_input: String,
+    notes_input: String,
+    verified: bool,
+    contact: Contact,
+}
+
+impl ContactEditForm {
+    pub fn new(contact: Contact) -> Self {
+        Self {
+            name_input: contact.name().to_owned(),
+            addresses_input: contact
+                .addresses()
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            notes_input: contact.notes().to_owned(),
+            verified: contact.is_verified(),
+            contact,
+        }
+    }
+
+    pub fn contact(self) -> Contact {
+        self.contact
+    }
+}
+
+impl<'a> Form<'a> for ContactEditForm {
+    type Ret = bool;
+    type Error = HostAddrParseError;
+
+    fn show(&mut self, ui: &mut Ui) -> Result<Option<Self::Ret>, Self::Error> {
+        ui.label("Name");
+        ui.text_edit_singleline(&mut self.name_input);
+
+        ui.label("Addresses (one per line, most recent first)");
+        ui.text_edit_multiline(&mut self.addresses_input);
+
+        ui.label("Notes");
+        ui.text_edit_multiline(&mut self.notes_input);
+
+        ui.label(format!("Fingerprint: {}", self.contact.id()));
+        ui.checkbox(&mut self.verified, "Fingerprint verified");
+
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked() {
+                let addresses = self
+                    .addresses_input
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(HostAddr::from_str)
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                for addr in addresses.iter().rev() {
+                    self.contact.add_address(addr.clone());
+                }
+                for addr in self.contact.addresses().to_vec() {
+                    if !addresses.contains(&addr) {
+                        self.contact.remove_address(&addr);
+                    }
+                }
+
+                self.contact.rename(self.name_input.clone());
+                self.contact.set_notes(self.notes_input.clone());
+                self.contact.set_verified(self.verified);
+
+                Ok(Some(true))
+            } else if ui.button("Cancel").clicked() {
+                Ok(Some(false))
+            } else {
+                Ok(None)
+            }
+        })
+        .inner
+    }
+}
@@ -0,0 +1,45 @@
+use std::io;
+
+use egui::Ui;
+
+use super::modal::Form;
+
+pub struct GroupCreationForm {
+    name_input: String,
+}
+
+impl GroupCreationForm {
+    pub fn new() -> Self {
+        GroupCreationForm {
+            name_input: String::new(),
+        }
+    }
+}
+
+impl Form<'_> for GroupCreationForm {
+    type Error = io::Error;
+    type Ret = Option<String>;
+
+    fn show(&mut self, ui: &mut Ui) -> Result<Option<Self::Ret>, Self::Error> {
+        ui.label("Group name");
+        ui.text_edit_singleline(&mut self.name_input);
+        ui.weak("Members are invited from the context menu of their channels");
+
+        ui.horizontal(|ui| {
+            if ui.button("Create").clicked() {
+                if self.name_input.trim().is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "The group needs a name",
+                    ));
+                }
+                Ok(Some(Some(self.name_input.trim().to_string())))
+            } else if ui.button("Cancel").clicked() {
+                Ok(Some(None))
+            } else {
+                Ok(None)
+            }
+        })
+        .inner
+    }
+}
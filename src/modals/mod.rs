@@ -16,5 +16,8 @@ pub use channel_accept_aes::ChannelAcceptAesForm;
 mod channel_recreation;
 pub use channel_recreation::ChannelRecreationForm;
 
-mod channel_desc_edit;
-pub use channel_desc_edit::ChannelDescEditForm;
+mod contact_edit;
+pub use contact_edit::ContactEditForm;
+
+mod group_creation;
+pub use group_creation::GroupCreationForm;
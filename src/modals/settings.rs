@@ -1,21 +1,215 @@
-use std::{
-    error, io,
-    net::{AddrParseError, SocketAddr},
-    ops::Not,
-    path::PathBuf,
-    str::FromStr,
-};
+use std::{error, io, ops::Not, path::PathBuf, str::FromStr, time::Duration};
 
 use derive_more::{Display, From};
-use egui::{Frame, Ui};
+use egui::{Checkbox, CollapsingHeader, ComboBox, DragValue, Frame, Ui};
+use openssl::pkey::{PKey, Private};
+
+use grapevine_lib::{
+    AccessRules, AutoAccept, Backpressure, Fingerprint, FingerprintParseError, GossipConfig,
+    HostAddr, HostAddrParseError, IpNetParseError, ListenerConfig, ListenerPolicy, ProtocolPath,
+    QueueConfig, RateLimit, Timeouts,
+};
+
+use grapevine::settings::Settings;
+
+use super::modal::Form;
+
+const DEFAULT_LISTENER_ADDR: &str = "0.0.0.0:0";
+const DEFAULT_CHECKPOINT_EVERY: u64 = 100;
+
+/// Inputs for a single [ListenerConfig]
+struct ListenerInput {
+    addr_input: String,
+    allow_rsa: bool,
+    allow_aes: bool,
+    rsa_from_unknown: bool,
+    auto_accept: AutoAccept,
+}
+
+impl Default for ListenerInput {
+    fn default() -> Self {
+        Self::new(&ListenerConfig::new(
+            HostAddr::from_str(DEFAULT_LISTENER_ADDR).unwrap(),
+            ListenerPolicy::default(),
+        ))
+    }
+}
+
+impl ListenerInput {
+    fn new(config: &ListenerConfig) -> Self {
+        let policy = config.policy();
+        Self {
+            addr_input: config.addr().to_string(),
+            allow_rsa: policy.allows(&ProtocolPath::RsaExchange),
+            allow_aes: policy.allows(&ProtocolPath::AesExchange),
+            rsa_from_unknown: policy.rsa_from_unknown(),
+            auto_accept: policy.auto_accept(),
+        }
+    }
+
+    fn config(&self) -> Result<ListenerConfig, HostAddrParseError> {
+        let mut allowed_paths = Vec::new();
+        if self.allow_rsa {
+            allowed_paths.push(ProtocolPath::RsaExchange);
+        }
+        if self.allow_aes {
+            allowed_paths.push(ProtocolPath::AesExchange);
+        }
+
+        Ok(ListenerConfig::new(
+            HostAddr::from_str(&self.addr_input)?,
+            ListenerPolicy::new(allowed_paths, self.rsa_from_unknown, self.auto_accept),
+        ))
+    }
+
+    /// Shows the inputs, returns whether the listener should be removed
+    fn show(&mut self, ui: &mut Ui, index: usize) -> bool {
+        let mut remove = false;
+        Frame::group(ui.style()).show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.addr_input);
+                remove = ui.small_button("✘").clicked();
+            });
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.allow_rsa, "Key exchange");
+                ui.add_enabled(
+                    self.allow_rsa,
+                    Checkbox::new(&mut self.rsa_from_unknown, "From strangers"),
+                );
+                ui.checkbox(&mut self.allow_aes, "Known keys");
+            });
+
+            ComboBox::from_id_salt(("auto_accept", index))
+                .selected_text(format!("Auto-accept: {:?}", self.auto_accept))
+                .show_ui(ui, |ui| {
+                    for rule in [
+                        AutoAccept::Never,
+                        AutoAccept::Contacts,
+                        AutoAccept::Everyone,
+                    ] {
+                        ui.selectable_value(&mut self.auto_accept, rule, format!("{:?}", rule));
+                    }
+                });
+        });
+        remove
+    }
+}
+
+/// Parses one item per line, skipping empty lines
+fn parse_lines<T: FromStr>(input: &str) -> Result<Vec<T>, T::Err> {
+    input
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| T::from_str(line.trim()))
+        .collect()
+}
+
+/// Writes one item per line
+fn join_lines<T: ToString>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Inputs for the [AccessRules]
+struct AccessRulesInput {
+    allowed_input: String,
+    blocked_input: String,
+    blocked_keys_input: String,
+    rate_limited: bool,
+    rate_limit_max: u32,
+    rate_limit_window: u64,
+    max_pending: usize,
+}
 
-use super::{super::settings::Settings, modal::Form};
+impl Default for AccessRulesInput {
+    fn default() -> Self {
+        Self::new(&AccessRules::default())
+    }
+}
+
+impl AccessRulesInput {
+    fn new(rules: &AccessRules) -> Self {
+        let rate_limit = rules.rate_limit().copied().unwrap_or_default();
+        Self {
+            allowed_input: join_lines(rules.allowed()),
+            blocked_input: join_lines(rules.blocked()),
+            blocked_keys_input: join_lines(rules.blocked_keys()),
+            rate_limited: rules.rate_limit().is_some(),
+            rate_limit_max: rate_limit.max(),
+            rate_limit_window: rate_limit.window().as_secs(),
+            max_pending: rules.max_pending(),
+        }
+    }
+
+    fn rules(&self) -> Result<AccessRules, SettingsFormError> {
+        Ok(AccessRules::new(
+            parse_lines(&self.allowed_input)?,
+            parse_lines(&self.blocked_input)?,
+            parse_lines(&self.blocked_keys_input)?,
+            self.rate_limited.then(|| {
+                RateLimit::new(
+                    self.rate_limit_max,
+                    Duration::from_secs(self.rate_limit_window),
+                )
+            }),
+            self.max_pending,
+        ))
+    }
+
+    fn show(&mut self, ui: &mut Ui) {
+        ui.label("Allowed networks (one per line, empty allows everyone)");
+        ui.text_edit_multiline(&mut self.allowed_input);
+
+        ui.label("Blocked networks");
+        ui.text_edit_multiline(&mut self.blocked_input);
+
+        ui.label("Blocked key fingerprints");
+        ui.text_edit_multiline(&mut self.blocked_keys_input);
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.rate_limited, "At most");
+            ui.add_enabled(
+                self.rate_limited,
+                DragValue::new(&mut self.rate_limit_max).range(1..=1000),
+            );
+            ui.label("connections per address every");
+            ui.add_enabled(
+                self.rate_limited,
+                DragValue::new(&mut self.rate_limit_window)
+                    .range(1..=3600)
+                    .suffix("s"),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Pending connections limit");
+            ui.add(DragValue::new(&mut self.max_pending).range(1..=1000));
+        });
+    }
+}
 
-#[derive(Default)]
 pub struct SettingsForm {
     uname_input: String,
-    server_active: bool,
-    server_addr_input: String,
+    listeners: Vec<ListenerInput>,
+    access_rules: AccessRulesInput,
+    connect_timeout: u64,
+    handshake_timeout: u64,
+    queue_capacity: usize,
+    backpressure: Backpressure,
+    checkpoints: bool,
+    checkpoint_every: u64,
+    relay: bool,
+    relay_hops: u8,
+    relay_hours: u64,
+    relay_capacity: usize,
+    relay_input: String,
+    relay_key: PKey<Private>,
+    discovery: bool,
+    announce_contacts: bool,
     default_key_path_input: String,
     save_channels: bool,
 }
@@ -24,11 +218,31 @@ impl SettingsForm {
     pub fn new(settings_base: &Settings) -> Self {
         Self {
             uname_input: settings_base.username().to_string(),
-            server_active: settings_base.listening().is_some(),
-            server_addr_input: settings_base
-                .listening()
-                .and_then(|addr| Some(addr.to_string()))
-                .unwrap_or(String::new()),
+            listeners: settings_base
+                .listeners()
+                .iter()
+                .map(ListenerInput::new)
+                .collect(),
+            access_rules: AccessRulesInput::new(settings_base.access_rules()),
+            connect_timeout: settings_base.timeouts().connect().as_secs(),
+            handshake_timeout: settings_base.timeouts().handshake().as_secs(),
+            queue_capacity: settings_base.send_queue().capacity(),
+            backpressure: settings_base.send_queue().backpressure(),
+            checkpoints: settings_base.checkpoints().is_some(),
+            checkpoint_every: settings_base
+                .checkpoints()
+                .unwrap_or(DEFAULT_CHECKPOINT_EVERY),
+            relay: settings_base.gossip().relay(),
+            relay_hops: settings_base.gossip().hops(),
+            relay_hours: settings_base.gossip().expiry().as_secs() / 3600,
+            relay_capacity: settings_base.gossip().capacity(),
+            relay_input: settings_base
+                .relay()
+                .map(|relay| relay.to_string())
+                .unwrap_or_default(),
+            relay_key: settings_base.relay_key().clone(),
+            discovery: settings_base.discovery(),
+            announce_contacts: settings_base.announce_contacts(),
             default_key_path_input: settings_base
                 .default_key_path()
                 .to_string_lossy()
@@ -39,8 +253,11 @@ impl SettingsForm {
 }
 
 #[derive(Debug, Display, From)]
+#[allow(clippy::enum_variant_names)]
 pub enum SettingsFormError {
-    AddrError(AddrParseError),
+    AddrError(HostAddrParseError),
+    NetError(IpNetParseError),
+    KeyError(FingerprintParseError),
     IoError(io::Error),
 }
 
@@ -48,6 +265,8 @@ impl error::Error for SettingsFormError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             Self::AddrError(e) => Some(e),
+            Self::NetError(e) => Some(e),
+            Self::KeyError(e) => Some(e),
             Self::IoError(e) => Some(e),
         }
     }
@@ -61,31 +280,142 @@ impl Form<'_> for SettingsForm {
         ui.label("Username");
         ui.text_edit_singleline(&mut self.uname_input);
 
-        ui.checkbox(&mut self.save_channels, "Save saved channels");
+        ui.checkbox(&mut self.save_channels, "Save contacts");
 
-        ui.label("Server");
-        ui.checkbox(&mut self.server_active, "Enabled");
-        ui.add_enabled_ui(self.server_active, |ui| {
-            Frame::group(ui.style()).show(ui, |ui| {
-                ui.text_edit_singleline(&mut self.server_addr_input);
-            });
+        ui.label("Listeners");
+        let mut index = 0;
+        self.listeners.retain_mut(|listener| {
+            index += 1;
+            !listener.show(ui, index)
+        });
+        if ui.button("Add listener").clicked() {
+            self.listeners.push(ListenerInput::default());
+        }
+
+        CollapsingHeader::new("Access rules").show(ui, |ui| self.access_rules.show(ui));
+
+        ui.horizontal(|ui| {
+            ui.label("Give up connecting after");
+            ui.add(
+                DragValue::new(&mut self.connect_timeout)
+                    .range(1..=600)
+                    .suffix("s"),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Give up exchanging keys after");
+            ui.add(
+                DragValue::new(&mut self.handshake_timeout)
+                    .range(1..=600)
+                    .suffix("s"),
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Queue up to");
+            ui.add(DragValue::new(&mut self.queue_capacity).range(1..=1000));
+            ui.label("messages, then");
+            ComboBox::from_id_salt("backpressure")
+                .selected_text(format!("{:?}", self.backpressure))
+                .show_ui(ui, |ui| {
+                    for policy in [Backpressure::Block, Backpressure::Drop, Backpressure::Error] {
+                        ui.selectable_value(
+                            &mut self.backpressure,
+                            policy,
+                            format!("{:?}", policy),
+                        );
+                    }
+                });
+        });
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.checkpoints, "Sign a checkpoint every");
+            ui.add_enabled(
+                self.checkpoints,
+                DragValue::new(&mut self.checkpoint_every).range(1..=10000),
+            );
+            ui.label("messages");
+        });
+
+        ui.checkbox(&mut self.relay, "Pass on messages for others");
+        ui.horizontal(|ui| {
+            ui.label("Messages travel through up to");
+            ui.add(DragValue::new(&mut self.relay_hops).range(0..=10));
+            ui.label("peers, for up to");
+            ui.add(
+                DragValue::new(&mut self.relay_hours)
+                    .range(1..=720)
+                    .suffix("h"),
+            );
+        });
+
+        ui.label("Wait at relay (empty for none)");
+        ui.text_edit_singleline(&mut self.relay_input);
+        let relay_id = Fingerprint::of(&self.relay_key);
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "New peers reach us there under {}",
+                relay_id.short()
+            ));
+            if ui.button("Copy").clicked() {
+                ui.ctx().copy_text(relay_id.to_string());
+            }
         });
 
+        ui.checkbox(&mut self.discovery, "Find peers on the local network")
+            .on_hover_text("Announces our name, address and fingerprint to everyone there");
+        ui.add_enabled(
+            self.discovery,
+            Checkbox::new(
+                &mut self.announce_contacts,
+                "Let contacts recognize us there",
+            ),
+        )
+        .on_hover_text("Announces the keys our contacts have for us, telling everyone who we know");
+
         ui.label("Default encryption key path");
         ui.text_edit_singleline(&mut self.default_key_path_input);
 
         if ui.button("Save").clicked() {
-            Ok(Some(Settings::new(
-                self.server_active
-                    .then(|| SocketAddr::from_str(&self.server_addr_input))
-                    .transpose()?,
-                self.uname_input
-                    .is_empty()
-                    .not()
-                    .then_some(self.uname_input.clone()),
-                Some(PathBuf::from(self.default_key_path_input.clone()).canonicalize()?),
-                self.save_channels,
-            )))
+            Ok(Some(
+                Settings::new(
+                    self.uname_input
+                        .is_empty()
+                        .not()
+                        .then_some(self.uname_input.clone()),
+                    Some(PathBuf::from(self.default_key_path_input.clone()).canonicalize()?),
+                    self.save_channels,
+                )
+                .with_listeners(
+                    self.listeners
+                        .iter()
+                        .map(ListenerInput::config)
+                        .collect::<Result<_, _>>()?,
+                )
+                .with_access_rules(self.access_rules.rules()?)
+                .with_timeouts(Timeouts::new(
+                    Duration::from_secs(self.connect_timeout),
+                    Duration::from_secs(self.handshake_timeout),
+                ))
+                .with_send_queue(QueueConfig::new(self.queue_capacity, self.backpressure))
+                .with_checkpoints(self.checkpoints.then_some(self.checkpoint_every))
+                .with_gossip(GossipConfig::new(
+                    self.relay,
+                    self.relay_hops,
+                    Duration::from_secs(self.relay_hours * 3600),
+                    self.relay_capacity,
+                ))
+                .with_relay(
+                    self.relay_input
+                        .is_empty()
+                        .not()
+                        .then(|| HostAddr::from_str(&self.relay_input))
+                        .transpose()?,
+                )
+                .with_relay_key(self.relay_key.clone())
+                .with_discovery(self.discovery)
+                .with_announce_contacts(self.announce_contacts),
+            ))
         } else {
             Ok(None)
         }
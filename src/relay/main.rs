@@ -0,0 +1,46 @@
+//! Relay for grapevine peers who can't reach each other directly, like when
+//! both are behind NAT. Splices the streams of its clients together, without
+//! ever seeing more than the fingerprints they ask for.
+
+use std::process::ExitCode;
+
+use clap::Parser;
+use tokio::{net::TcpListener, runtime::Runtime, signal};
+
+use grapevine_lib::{HostAddr, Relay};
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Address to accept clients on
+    addr: HostAddr,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("grapevine-relay: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let runtime = Runtime::new().map_err(|e| format!("couldn't start the runtime: {}", e))?;
+    runtime.block_on(async {
+        let listener = TcpListener::bind((args.addr.host(), args.addr.port()))
+            .await
+            .map_err(|e| format!("couldn't listen on {}: {}", args.addr, e))?;
+        if let Ok(bound) = listener.local_addr() {
+            eprintln!("relaying on {}", bound);
+        }
+
+        let relay = Relay::new();
+        tokio::select! {
+            _ = relay.serve(listener, |e| eprintln!("couldn't accept: {}", e)) => Ok(()),
+            _ = signal::ctrl_c() => Ok(()),
+        }
+    })
+}
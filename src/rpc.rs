@@ -0,0 +1,475 @@
+//! The protocol grapevined speaks over its control socket. It's JSON-RPC
+//! 2.0, one message per line.
+//!
+//! Channels and pending connections are referred to by their position, as
+//! listed by the daemon. Channels keep their position for as long as the
+//! daemon runs, reconnecting replaces them in place.
+//!
+//! | method         | params            | result              |
+//! |----------------|-------------------|---------------------|
+//! | `channels`     |                   | `[ChannelInfo]`     |
+//! | `connecting`   |                   | `[AttemptInfo]`     |
+//! | `pending`      |                   | `[PendingInfo]`     |
+//! | `contacts`     |                   | `ContactBook`       |
+//! | `listeners`    |                   | `[ListenerInfo]`    |
+//! | `listen`       | `ListenerConfig`  | bound address       |
+//! | `connect`      | [ConnectParams]   | [AttemptInfo]       |
+//! | `accept`       | [AcceptParams]    | [AttemptInfo]       |
+//! | `reject`       | [PendingParams]   |                     |
+//! | `reconnect`    | [ChannelParams]   | [AttemptInfo]       |
+//! | `history`      | [ChannelParams]   | `[HistoryEntry]`    |
+//! | `send`         | [SendParams]      |                     |
+//! | `close`        | [CloseParams]     |                     |
+//! | `save_contact` | [SaveParams]      |                     |
+//! | `subscribe`    |                   |                     |
+//! | `shutdown`     |                   |                     |
+//!
+//! Once subscribed, the connection also receives `event` notifications,
+//! carrying an [EventInfo].
+
+use std::{env, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use derive_more::{Display, Error, From};
+use grapevine_lib::{Fingerprint, ListenerPolicy};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::storage::{APP_ID, storage_dir};
+
+/// The only JSON-RPC version there is
+pub const VERSION: &str = "2.0";
+/// Method of the notifications carrying events
+pub const EVENT: &str = "event";
+/// Name of the socket within its directory
+const SOCKET_NAME: &str = "grapevined.sock";
+
+/// Gets where the daemon listens by default. That's the runtime directory
+/// if there is one, and the storage directory otherwise.
+pub fn socket_path() -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .map(|p| p.join(APP_ID))
+        .or_else(storage_dir)
+        .map(|p| p.join(SOCKET_NAME))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    /// Missing for notifications, which get no response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: VERSION.to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Notification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// Whatever a client may receive
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Incoming {
+    Notification(Notification),
+    Response(Response),
+}
+
+#[derive(Debug, Display, Error, Clone, Serialize, Deserialize)]
+#[display("{message}")]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    /// The call was understood, but the app couldn't carry it out
+    pub const FAILED: i64 = -32000;
+
+    pub fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn failed(message: impl ToString) -> Self {
+        Self::new(Self::FAILED, message)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChannelInfo {
+    pub id: usize,
+    pub name: String,
+    pub peer: String,
+    pub fingerprint: Fingerprint,
+    pub state: String,
+    /// Whether the channel stopped, for good or until reconnected
+    pub finished: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AttemptInfo {
+    pub name: String,
+    pub target: String,
+    pub state: String,
+}
+
+/// What accepting a pending connection takes
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PendingKind {
+    /// Accepting their public key
+    KeyExchange,
+    /// Nothing, they are a saved contact
+    Contact,
+    /// Our private key and their public key
+    NeedsKeys,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PendingInfo {
+    pub id: usize,
+    pub name: String,
+    pub peer: String,
+    pub kind: PendingKind,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ListenerInfo {
+    pub endpoint: String,
+    pub policy: ListenerPolicy,
+}
+
+/// A message of a channel, from either side
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub content: String,
+    pub timestamp: DateTime<Utc>,
+    pub ours: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConnectParams {
+    /// Name or fingerprint of a saved contact, or an address to exchange
+    /// keys with
+    pub target: String,
+    /// Name of the channel, when connecting to an address
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct KeyPaths {
+    /// Our private key, in PEM
+    pub private: PathBuf,
+    /// Their public key, in PEM
+    pub public: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AcceptParams {
+    pub pending: usize,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Required by [PendingKind::NeedsKeys]. Read by the daemon.
+    #[serde(default)]
+    pub keys: Option<KeyPaths>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PendingParams {
+    pub pending: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChannelParams {
+    pub channel: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SendParams {
+    pub channel: usize,
+    pub content: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CloseParams {
+    pub channel: usize,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveParams {
+    pub channel: usize,
+    /// Name to save the contact under, the channel's by default
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// An [grapevine_lib::Event], as told to subscribers
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventInfo {
+    MessageReceived {
+        channel: usize,
+        name: String,
+        message: HistoryEntry,
+    },
+    /// Application defined data, see [grapevine_lib::Payload]
+    PayloadReceived {
+        channel: usize,
+        name: String,
+        tag: String,
+        data: Vec<u8>,
+    },
+    ChannelOpened {
+        channel: ChannelInfo,
+    },
+    ChannelClosed {
+        channel: usize,
+        name: String,
+        reason: String,
+    },
+    PendingConnectionArrived {
+        name: String,
+        peer: String,
+    },
+    PendingConnectionExpired {
+        name: String,
+        peer: String,
+    },
+    HandshakeFailed {
+        error: String,
+    },
+    ConnectionFailed {
+        attempt: AttemptInfo,
+        error: String,
+    },
+    ListenerError {
+        listener: String,
+        error: String,
+    },
+    ConnectionRejected {
+        peer: String,
+        reason: String,
+    },
+    /// See [grapevine_lib::Group]
+    GroupUpdated {
+        group: Fingerprint,
+        name: String,
+        members: Vec<String>,
+        active: bool,
+    },
+    GroupMessageReceived {
+        group: Fingerprint,
+        name: String,
+        author: String,
+        message: HistoryEntry,
+    },
+    /// See [grapevine_lib::Event::RelayedMessageReceived]
+    RelayedMessageReceived {
+        name: String,
+        sender: Fingerprint,
+        message: HistoryEntry,
+    },
+    /// See [grapevine_lib::Event::PeerDiscovered]
+    PeerDiscovered {
+        name: String,
+        addr: String,
+        id: Fingerprint,
+    },
+}
+
+#[derive(Debug, Display, From, Error)]
+pub enum ClientError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Rpc(RpcError),
+    #[display("the daemon hung up")]
+    Closed,
+}
+
+#[cfg(unix)]
+pub use client::Client;
+
+#[cfg(unix)]
+mod client {
+    use std::{
+        collections::VecDeque,
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+        path::Path,
+    };
+
+    use serde::{Serialize, de::DeserializeOwned};
+    use serde_json::{from_str, from_value, to_value, to_vec};
+
+    use super::{ClientError, EVENT, EventInfo, Incoming, Request, VERSION};
+
+    /// Blocking connection to the daemon
+    pub struct Client {
+        writer: UnixStream,
+        reader: BufReader<UnixStream>,
+        next_id: u64,
+        /// Events that arrived while waiting for a response
+        events: VecDeque<EventInfo>,
+    }
+
+    impl Client {
+        pub fn connect(path: &Path) -> Result<Self, ClientError> {
+            let writer = UnixStream::connect(path)?;
+            let reader = BufReader::new(writer.try_clone()?);
+            Ok(Self {
+                writer,
+                reader,
+                next_id: 0,
+                events: VecDeque::new(),
+            })
+        }
+
+        /// Calls the method, waiting for its result
+        pub fn call<R: DeserializeOwned>(
+            &mut self,
+            method: &str,
+            params: impl Serialize,
+        ) -> Result<R, ClientError> {
+            let id = self.next_id;
+            self.next_id += 1;
+            let request = Request {
+                jsonrpc: VERSION.to_string(),
+                id: Some(id.into()),
+                method: method.to_string(),
+                params: to_value(params)?,
+            };
+            let mut line = to_vec(&request)?;
+            line.push(b'\n');
+            self.writer.write_all(&line)?;
+
+            loop {
+                match self.read()? {
+                    Incoming::Response(response) if response.id == id => {
+                        if let Some(error) = response.error {
+                            return Err(error.into());
+                        }
+                        return Ok(from_value(response.result.unwrap_or_default())?);
+                    }
+                    Incoming::Notification(notification) if notification.method == EVENT => {
+                        self.events.push_back(from_value(notification.params)?);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        /// Starts receiving events
+        pub fn subscribe(&mut self) -> Result<(), ClientError> {
+            self.call("subscribe", ())
+        }
+
+        /// Waits for the next event. Requires [Self::subscribe].
+        pub fn next_event(&mut self) -> Result<EventInfo, ClientError> {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            loop {
+                if let Incoming::Notification(notification) = self.read()?
+                    && notification.method == EVENT
+                {
+                    return Ok(from_value(notification.params)?);
+                }
+            }
+        }
+
+        fn read(&mut self) -> Result<Incoming, ClientError> {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(ClientError::Closed);
+            }
+            Ok(from_str(&line)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_str, json, to_value};
+
+    use super::*;
+
+    #[test]
+    fn test_incoming_tells_responses_from_notifications() {
+        let response = r#"{"jsonrpc":"2.0","id":3,"result":null}"#;
+        assert!(matches!(
+            from_str::<Incoming>(response).unwrap(),
+            Incoming::Response(Response { id, .. }) if id == json!(3)
+        ));
+
+        let notification = to_value(Notification {
+            jsonrpc: VERSION.to_string(),
+            method: EVENT.to_string(),
+            params: to_value(EventInfo::HandshakeFailed {
+                error: "oops".to_string(),
+            })
+            .unwrap(),
+        })
+        .unwrap();
+        let Incoming::Notification(notification) = serde_json::from_value(notification).unwrap()
+        else {
+            panic!("expected a notification");
+        };
+        assert!(matches!(
+            serde_json::from_value(notification.params).unwrap(),
+            EventInfo::HandshakeFailed { error } if error == "oops"
+        ));
+    }
+
+    #[test]
+    fn test_error_response() {
+        let response = Response::new(
+            json!("a"),
+            Err(RpcError::new(RpcError::METHOD_NOT_FOUND, "no such method")),
+        );
+        let value = to_value(&response).unwrap();
+        assert_eq!(value["error"]["code"], json!(-32601));
+        assert!(value.get("result").is_none());
+    }
+}
@@ -1,13 +1,132 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::path::PathBuf;
 
 const OUR_NAME: &str = "You";
-const DEFAULT_KEY_PATH: &'static str = ".";
+const DEFAULT_KEY_PATH: &str = ".";
 
-use serde::{Deserialize, Serialize};
+use grapevine_lib::{
+    AccessRules, Announcement, ContactBook, Fingerprint, GossipConfig, GrapevineApp, HostAddr,
+    ListenerConfig, ListenerPolicy, QueueConfig, Timeouts, Transport,
+};
+use openssl::{
+    pkey::{PKey, Private},
+    rsa::Rsa,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Makes up the key we are reached under at relays by those, who don't have
+/// a key of ours yet
+fn new_relay_key() -> PKey<Private> {
+    PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap()
+}
+
+fn serialize_relay_key<S: Serializer>(
+    key: &PKey<Private>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::Error;
+
+    let pem = key
+        .private_key_to_pem_pkcs8()
+        .map_err(|e| S::Error::custom(format!("Failed to serialize relay key: {}", e)))?;
+    serializer.serialize_str(&String::from_utf8_lossy(&pem))
+}
+
+fn deserialize_relay_key<'de, D: Deserializer<'de>>(de: D) -> Result<PKey<Private>, D::Error> {
+    use serde::de::Error;
+
+    let pem = String::deserialize(de)?;
+    PKey::private_key_from_pem(pem.as_bytes())
+        .map_err(|e| D::Error::custom(format!("Failed to parse relay key: {}", e)))
+}
+
+/// The keys the contacts have for us
+fn known_as(contacts: &ContactBook) -> Vec<Fingerprint> {
+    contacts
+        .iter()
+        .map(|contact| contact.desc().our_fingerprint())
+        .collect()
+}
+
+/// [Settings] as they may be stored, including fields of older versions
+#[derive(Deserialize)]
+struct StoredSettings {
+    /// Single listener, from before multiple listeners were supported
+    #[serde(default)]
+    listening: Option<HostAddr>,
+    #[serde(default)]
+    listeners: Vec<ListenerConfig>,
+    #[serde(default)]
+    access_rules: AccessRules,
+    #[serde(default)]
+    timeouts: Timeouts,
+    #[serde(default)]
+    send_queue: QueueConfig,
+    #[serde(default)]
+    checkpoints: Option<u64>,
+    #[serde(default)]
+    gossip: GossipConfig,
+    #[serde(default)]
+    relay: Option<HostAddr>,
+    /// Replaces the made up `relay_id` of older versions, which anyone could
+    /// have waited under
+    #[serde(default = "new_relay_key", deserialize_with = "deserialize_relay_key")]
+    relay_key: PKey<Private>,
+    #[serde(default)]
+    discovery: bool,
+    #[serde(default)]
+    announce_contacts: bool,
+    username: Option<String>,
+    default_key_path: PathBuf,
+    save_channels: bool,
+}
+
+impl From<StoredSettings> for Settings {
+    fn from(stored: StoredSettings) -> Self {
+        let mut listeners = stored.listeners;
+        if let Some(addr) = stored.listening {
+            listeners.push(ListenerConfig::new(addr, ListenerPolicy::default()));
+        }
+
+        Settings::new(
+            stored.username,
+            Some(stored.default_key_path),
+            stored.save_channels,
+        )
+        .with_listeners(listeners)
+        .with_access_rules(stored.access_rules)
+        .with_timeouts(stored.timeouts)
+        .with_send_queue(stored.send_queue)
+        .with_checkpoints(stored.checkpoints)
+        .with_gossip(stored.gossip)
+        .with_relay(stored.relay)
+        .with_relay_key(stored.relay_key)
+        .with_discovery(stored.discovery)
+        .with_announce_contacts(stored.announce_contacts)
+    }
+}
 
 #[derive(Serialize, Deserialize)]
+#[serde(from = "StoredSettings")]
 pub struct Settings {
-    listening: Option<SocketAddr>,
+    listeners: Vec<ListenerConfig>,
+    access_rules: AccessRules,
+    timeouts: Timeouts,
+    send_queue: QueueConfig,
+    /// How many messages go between signed checkpoints, if any
+    checkpoints: Option<u64>,
+    /// Whether, and how we pass on messages for others
+    gossip: GossipConfig,
+    /// Relay we wait at, for those who can't reach us directly
+    relay: Option<HostAddr>,
+    /// Who we are to those who don't have a key of ours, at the relay and
+    /// on the local network
+    #[serde(serialize_with = "serialize_relay_key")]
+    relay_key: PKey<Private>,
+    /// Whether we find, and announce ourselves to peers on the local network
+    discovery: bool,
+    /// Whether we announce the keys the contacts have for us, for them to
+    /// recognize us by, letting everyone on the network know who we know
+    announce_contacts: bool,
     username: Option<String>,
     default_key_path: PathBuf,
     save_channels: bool,
@@ -15,13 +134,14 @@ pub struct Settings {
 
 impl Default for Settings {
     fn default() -> Self {
-        Settings::new(None, None, None, false)
+        Settings::new(None, None, false)
     }
 }
 
 impl Settings {
+    /// Creates the settings, with the defaults for everything set through
+    /// the `with_*` methods
     pub fn new(
-        listening: Option<SocketAddr>,
         username: Option<String>,
         default_key_path: Option<PathBuf>,
         save_channels: bool,
@@ -30,13 +150,73 @@ impl Settings {
             .unwrap_or_else(|| PathBuf::from(DEFAULT_KEY_PATH).canonicalize().unwrap());
 
         Self {
-            listening,
+            listeners: Vec::new(),
+            access_rules: AccessRules::default(),
+            timeouts: Timeouts::default(),
+            send_queue: QueueConfig::default(),
+            checkpoints: None,
+            gossip: GossipConfig::default(),
+            relay: None,
+            relay_key: new_relay_key(),
+            discovery: false,
+            announce_contacts: false,
             username,
             default_key_path,
             save_channels,
         }
     }
 
+    pub fn with_listeners(mut self, listeners: Vec<ListenerConfig>) -> Self {
+        self.listeners = listeners;
+        self
+    }
+
+    pub fn with_access_rules(mut self, access_rules: AccessRules) -> Self {
+        self.access_rules = access_rules;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_send_queue(mut self, send_queue: QueueConfig) -> Self {
+        self.send_queue = send_queue;
+        self
+    }
+
+    pub fn with_checkpoints(mut self, checkpoints: Option<u64>) -> Self {
+        self.checkpoints = checkpoints;
+        self
+    }
+
+    pub fn with_gossip(mut self, gossip: GossipConfig) -> Self {
+        self.gossip = gossip;
+        self
+    }
+
+    pub fn with_relay(mut self, relay: Option<HostAddr>) -> Self {
+        self.relay = relay;
+        self
+    }
+
+    /// Keeps the key we are reached under, rather than making up a new one
+    pub fn with_relay_key(mut self, relay_key: PKey<Private>) -> Self {
+        self.relay_key = relay_key;
+        self
+    }
+
+    pub fn with_discovery(mut self, discovery: bool) -> Self {
+        self.discovery = discovery;
+        self
+    }
+
+    pub fn with_announce_contacts(mut self, announce_contacts: bool) -> Self {
+        self.announce_contacts = announce_contacts;
+        self
+    }
+
     pub fn username(&self) -> &str {
         if let Some(uname) = self.username.as_ref() {
             uname.as_str()
@@ -45,8 +225,91 @@ impl Settings {
         }
     }
 
-    pub fn listening(&self) -> &Option<SocketAddr> {
-        &self.listening
+    pub fn listeners(&self) -> &[ListenerConfig] {
+        &self.listeners
+    }
+
+    pub fn access_rules(&self) -> &AccessRules {
+        &self.access_rules
+    }
+
+    pub fn access_rules_mut(&mut self) -> &mut AccessRules {
+        &mut self.access_rules
+    }
+
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    pub fn send_queue(&self) -> &QueueConfig {
+        &self.send_queue
+    }
+
+    pub fn checkpoints(&self) -> Option<u64> {
+        self.checkpoints
+    }
+
+    pub fn gossip(&self) -> &GossipConfig {
+        &self.gossip
+    }
+
+    pub fn relay(&self) -> Option<&HostAddr> {
+        self.relay.as_ref()
+    }
+
+    pub fn relay_key(&self) -> &PKey<Private> {
+        &self.relay_key
+    }
+
+    /// Get the fingerprint of [Self::relay_key]
+    pub fn relay_id(&self) -> Fingerprint {
+        Fingerprint::of(&self.relay_key)
+    }
+
+    /// Get the fingerprints to wait at the relay under: the keys the
+    /// contacts have for us, and [Self::relay_id] for new peers
+    pub fn relay_ids(&self, contacts: &ContactBook) -> Vec<Fingerprint> {
+        let mut ids = known_as(contacts);
+        ids.push(self.relay_id());
+        ids
+    }
+
+    /// Get the keys the contacts have for us, to wait at the relay under
+    /// alongside [Self::relay_key], each for the contact it's meant for
+    pub fn contact_keys(&self, contacts: &ContactBook) -> Vec<PKey<Private>> {
+        contacts
+            .iter()
+            .map(|contact| contact.desc().our_key().clone())
+            .collect()
+    }
+
+    pub fn discovery(&self) -> bool {
+        self.discovery
+    }
+
+    pub fn announce_contacts(&self) -> bool {
+        self.announce_contacts
+    }
+
+    /// Get what we announce on the local network, while listening on the
+    /// port. The keys the contacts have for us are left out, unless
+    /// [Self::announce_contacts].
+    pub fn announcement(&self, port: u16, contacts: &ContactBook) -> Announcement {
+        let known_as = if self.announce_contacts {
+            known_as(contacts)
+        } else {
+            Vec::new()
+        };
+        Announcement::new(self.username().to_string(), port, self.relay_id(), known_as)
+    }
+
+    /// Get the keys to sign [Self::announcement] with
+    pub fn announcement_keys(&self, contacts: &ContactBook) -> Vec<PKey<Private>> {
+        let mut keys = vec![self.relay_key.clone()];
+        if self.announce_contacts {
+            keys.extend(self.contact_keys(contacts));
+        }
+        keys
     }
 
     pub fn default_key_path(&self) -> &PathBuf {
@@ -56,4 +319,14 @@ impl Settings {
     pub fn save_channels(&self) -> bool {
         self.save_channels
     }
+
+    /// Configures the app accordingly, save for the listeners, as binding
+    /// them may fail
+    pub fn apply<T: Transport>(&self, app: &mut GrapevineApp<T>) {
+        app.set_access_rules(self.access_rules.clone());
+        app.set_timeouts(self.timeouts);
+        app.set_send_queue(self.send_queue);
+        app.set_checkpoints(self.checkpoints);
+        app.set_gossip(self.gossip);
+    }
 }
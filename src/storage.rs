@@ -0,0 +1,277 @@
+use std::{
+    any::type_name,
+    collections::HashMap,
+    env,
+    fs::{self, DirBuilder, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+#[cfg(unix)]
+use std::{
+    fs::Permissions,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt},
+};
+
+use derive_more::{Display, Error, From};
+use grapevine_lib::{ChannelDesc, Contact, ContactBook, Fingerprint};
+use ron::ser::PrettyConfig;
+use serde_json::{from_slice, from_str, to_string, to_vec_pretty};
+
+use super::settings::Settings;
+
+/// What the GUI runs as, and names its storage directory after
+pub const APP_ID: &str = "grapevine";
+/// Name of the file within the storage directory
+const FILE_NAME: &str = "app.ron";
+
+#[derive(Debug, Display, From, Error)]
+pub enum StorageError {
+    /// There is no default storage directory on this platform
+    #[display("couldn't find the storage directory")]
+    NoStorageDir,
+    Io(io::Error),
+    Ron(ron::error::SpannedError),
+    RonWrite(ron::Error),
+    Json(serde_json::Error),
+}
+
+/// Gets the directory the GUI keeps its state in.
+/// Mirrors where eframe puts it.
+pub fn storage_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(|p| PathBuf::from(p).join(APP_ID).join("data"))
+    } else if cfg!(target_os = "macos") {
+        home::home_dir().map(|p| p.join("Library").join("Application Support").join(APP_ID))
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| home::home_dir().map(|p| p.join(".local").join("share")))
+            .map(|p| p.join(APP_ID))
+    }
+}
+
+/// Creates the [storage_dir], or narrows an existing one down to the user,
+/// as the files in it hold private keys, like the [Settings::relay_key]
+pub fn protect_storage_dir() -> Result<(), StorageError> {
+    let dir = storage_dir().ok_or(StorageError::NoStorageDir)?;
+    create_private_dir(&dir)?;
+    #[cfg(unix)]
+    fs::set_permissions(&dir, Permissions::from_mode(0o700))?;
+    Ok(())
+}
+
+/// Creates the directory along with its parents, the missing ones only
+/// accessible to the user
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(dir)
+}
+
+/// Writes the file, only readable by the user, as it holds private keys.
+/// An existing file is narrowed down to the user as well.
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(contents)
+}
+
+/// Reads the [Settings] out of the stored values
+pub fn settings_from(
+    get: impl Fn(&str) -> Option<String>,
+) -> Result<Option<Settings>, serde_json::Error> {
+    get(type_name::<Settings>())
+        .map(|serialized| from_str(&serialized))
+        .transpose()
+}
+
+/// Reads the [ContactBook] out of the stored values, falling back to the
+/// channels saved from before it
+pub fn contacts_from(
+    get: impl Fn(&str) -> Option<String>,
+) -> Result<Option<ContactBook>, serde_json::Error> {
+    if let Some(serialized) = get(type_name::<ContactBook>()) {
+        from_str(&serialized).map(Some)
+    } else if let Some(serialized) = get(type_name::<ChannelDesc>()) {
+        Ok(Some(from_str::<Vec<ChannelDesc>>(&serialized)?.into()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Writes the contacts as JSON, for another device to import
+pub fn export_contacts(contacts: &ContactBook, path: &Path) -> Result<(), StorageError> {
+    write_private(path, &to_vec_pretty(contacts)?)?;
+    Ok(())
+}
+
+/// Reads contacts exported by [export_contacts], or a plain list
+/// of [ChannelDesc] as saved by older versions
+pub fn import_contacts(path: &Path) -> Result<ContactBook, StorageError> {
+    let data = fs::read(path)?;
+    match from_slice::<ContactBook>(&data) {
+        Ok(book) => Ok(book),
+        Err(e) => from_slice::<Vec<ChannelDesc>>(&data)
+            .map(ContactBook::from)
+            .map_err(|_| e.into()),
+    }
+}
+
+/// Finds a contact by their fingerprint, or their name if it's unique
+pub fn find_contact<'a>(contacts: &'a ContactBook, query: &str) -> Result<&'a Contact, String> {
+    if let Ok(id) = query.parse::<Fingerprint>()
+        && let Some(contact) = contacts.get(&id)
+    {
+        return Ok(contact);
+    }
+    let mut named = contacts.iter().filter(|contact| contact.name() == query);
+    match (named.next(), named.next()) {
+        (Some(contact), None) => Ok(contact),
+        (Some(_), Some(_)) => Err(format!(
+            "there are several contacts named {}, use the fingerprint instead",
+            query
+        )),
+        (None, _) => Err(format!("no contact named {}", query)),
+    }
+}
+
+/// The key-value store the GUI persists its state in, for the other
+/// frontends to share. Values are JSON, keyed by the name of their type.
+pub struct Storage {
+    path: PathBuf,
+    values: HashMap<String, String>,
+    /// Values set since opening, which saving writes over the file
+    changed: HashMap<String, String>,
+}
+
+impl Storage {
+    /// Opens the store at the given path. A missing file is an empty store.
+    pub fn open(path: PathBuf) -> Result<Self, StorageError> {
+        Ok(Self {
+            values: read(&path)?,
+            path,
+            changed: HashMap::new(),
+        })
+    }
+
+    /// Opens the store the GUI uses
+    pub fn open_default() -> Result<Self, StorageError> {
+        let dir = storage_dir().ok_or(StorageError::NoStorageDir)?;
+        Self::open(dir.join(FILE_NAME))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the stored settings, or the defaults if there are none
+    pub fn settings(&self) -> Result<Settings, StorageError> {
+        Ok(settings_from(|key| self.values.get(key).cloned())?.unwrap_or_default())
+    }
+
+    /// Gets the stored contacts
+    pub fn contacts(&self) -> Result<ContactBook, StorageError> {
+        Ok(contacts_from(|key| self.values.get(key).cloned())?.unwrap_or_default())
+    }
+
+    pub fn set_settings(&mut self, settings: &Settings) -> Result<(), StorageError> {
+        self.set(type_name::<Settings>(), to_string(settings)?);
+        Ok(())
+    }
+
+    pub fn set_contacts(&mut self, contacts: &ContactBook) -> Result<(), StorageError> {
+        self.set(type_name::<ContactBook>(), to_string(contacts)?);
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: String) {
+        self.values.insert(key.to_string(), value.clone());
+        self.changed.insert(key.to_string(), value);
+    }
+
+    /// Writes the values set since opening to the file, leaving whatever
+    /// else was saved there in the meantime, like by the GUI, in place
+    pub fn save(&self) -> Result<(), StorageError> {
+        let mut values = read(&self.path)?;
+        values.extend(self.changed.clone());
+
+        if let Some(dir) = self.path.parent() {
+            create_private_dir(dir)?;
+        }
+        // replacing the file at once, so that a reader never sees half of it
+        let temporary = self.path.with_extension("ron.tmp");
+        write_private(
+            &temporary,
+            ron::ser::to_string_pretty(&values, PrettyConfig::default())?.as_bytes(),
+        )?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+fn read(path: &Path) -> Result<HashMap<String, String>, StorageError> {
+    match fs::read_to_string(path) {
+        Ok(serialized) => Ok(ron::from_str(&serialized)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_file_is_empty() {
+        let storage = Storage::open(env::temp_dir().join("grapevine-missing.ron")).unwrap();
+        assert!(storage.contacts().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_save_keeps_other_values() {
+        let path = env::temp_dir().join(format!("grapevine-{}.ron", std::process::id()));
+        let mut storage = Storage::open(path.clone()).unwrap();
+
+        // something else writes to the file after we opened it
+        let mut other = HashMap::new();
+        other.insert("window".to_string(), "{}".to_string());
+        fs::write(&path, ron::to_string(&other).unwrap()).unwrap();
+
+        storage.set_contacts(&ContactBook::default()).unwrap();
+        storage.save().unwrap();
+
+        let saved = read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved.get("window").map(String::as_str), Some("{}"));
+        assert!(saved.contains_key(type_name::<ContactBook>()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_files_are_private() {
+        let dir = env::temp_dir().join(format!("grapevine-private-{}", std::process::id()));
+        let path = dir.join(FILE_NAME);
+        let mut storage = Storage::open(path.clone()).unwrap();
+        storage.set_settings(&Settings::default()).unwrap();
+        storage.save().unwrap();
+
+        // an export over a file readable by others is narrowed down
+        let export = dir.join("contacts.json");
+        fs::write(&export, "[]").unwrap();
+        fs::set_permissions(&export, Permissions::from_mode(0o644)).unwrap();
+        export_contacts(&ContactBook::default(), &export).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let modes = (mode(&dir), mode(&path), mode(&export));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(modes, (0o700, 0o600, 0o600));
+    }
+}
@@ -0,0 +1,1027 @@
+use std::{fmt::Display, mem, path::Path, sync::Arc, time::Duration};
+
+use ratatui::{
+    Frame,
+    crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
+    layout::{Alignment, Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Clear, List, ListItem, ListState, Paragraph, Wrap},
+};
+
+use grapevine::{
+    settings::Settings,
+    storage::{Storage, StorageError, export_contacts, import_contacts},
+};
+use grapevine_lib::{
+    Channel, ChannelDesc, ConnectionAttempt, Contact, ContactBook, Endpoint, Event, EventReceiver,
+    Fingerprint, GrapevineApp, IpNet, ListenerPolicy, Message, PendingAesHandshake,
+    PendingConnection, PendingRsaHandshake, SendHandle, SendStatus,
+};
+
+use super::{
+    form::{Form, FormAction},
+    log::EventLog,
+    prompts::{self, ChannelArgs},
+};
+
+/// How long quitting may wait for channels to say goodbye
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+/// Width of the channel list
+const SIDE_WIDTH: u16 = 32;
+/// Width of the contacts panel
+const CONTACTS_WIDTH: u16 = 44;
+
+const HELP: &[(&str, &str)] = &[
+    ("tab", "move between the panels"),
+    ("F2", "show the contacts"),
+    ("F3", "show the security events"),
+    ("F4", "settings"),
+    ("F5, ctrl+n", "create a channel"),
+    ("ctrl+q", "quit"),
+    ("", ""),
+    ("Channels", ""),
+    ("enter", "open the channel, or accept the connection"),
+    ("c", "close the channel, or cancel connecting"),
+    ("r", "reconnect a closed channel"),
+    ("d", "remove a closed channel"),
+    ("s", "save the channel as a contact"),
+    ("x", "reject the connection"),
+    ("b", "reject the connection, and block its address"),
+    ("", ""),
+    ("Contacts", ""),
+    ("type", "search"),
+    ("enter", "connect"),
+    ("ctrl+e", "edit"),
+    ("ctrl+d", "remove"),
+    ("ctrl+b", "block the contact's key"),
+    ("ctrl+r / ctrl+w", "import or export the contacts"),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Channels,
+    Composer,
+    Contacts,
+}
+
+/// A form waiting for the user, along with what it's about
+enum Prompt {
+    NewChannel(Form),
+    AcceptRsa(Form, PendingRsaHandshake),
+    AcceptAes(Form, PendingAesHandshake),
+    Reconnect(Form, ChannelDesc),
+    EditContact(Form, Contact),
+    ImportContacts(Form),
+    ExportContacts(Form),
+    Settings(Form),
+}
+
+impl Prompt {
+    fn form(&self) -> &Form {
+        match self {
+            Prompt::NewChannel(form)
+            | Prompt::AcceptRsa(form, _)
+            | Prompt::AcceptAes(form, _)
+            | Prompt::Reconnect(form, _)
+            | Prompt::EditContact(form, _)
+            | Prompt::ImportContacts(form)
+            | Prompt::ExportContacts(form)
+            | Prompt::Settings(form) => form,
+        }
+    }
+
+    fn form_mut(&mut self) -> &mut Form {
+        match self {
+            Prompt::NewChannel(form)
+            | Prompt::AcceptRsa(form, _)
+            | Prompt::AcceptAes(form, _)
+            | Prompt::Reconnect(form, _)
+            | Prompt::EditContact(form, _)
+            | Prompt::ImportContacts(form)
+            | Prompt::ExportContacts(form)
+            | Prompt::Settings(form) => form,
+        }
+    }
+}
+
+/// A row of the channel list
+enum Row {
+    Channel(Arc<Channel>),
+    Attempt(Arc<ConnectionAttempt>),
+    /// A pending connection, by its position in the app
+    Pending {
+        index: usize,
+        name: String,
+        peer: Endpoint,
+        /// Whether accepting needs the keys to use
+        unknown: bool,
+    },
+}
+
+/// Terminal counterpart of the GUI's `GrapevineUI`
+pub struct TuiApp {
+    app: GrapevineApp,
+    log: EventLog,
+    events: EventReceiver,
+    composer: String,
+    /// Messages still waiting to be written
+    sending: Vec<(Arc<Channel>, SendHandle)>,
+    focus: Focus,
+    /// Row of the channel list the cursor is on
+    cursor: usize,
+    selected_channel: Option<Arc<Channel>>,
+    prompt: Option<Prompt>,
+    contacts_open: bool,
+    contacts_search: String,
+    contacts_cursor: usize,
+    security_open: bool,
+    security_cursor: usize,
+    help_open: bool,
+    quit: bool,
+    settings: Settings,
+}
+
+impl TuiApp {
+    pub fn new(settings: Settings, contacts: ContactBook) -> Self {
+        let mut app = GrapevineApp::new();
+        let events = app.subscribe();
+        app.set_contacts(contacts);
+        settings.apply(&mut app);
+
+        let mut tui = Self {
+            app,
+            log: EventLog::default(),
+            events,
+            composer: String::new(),
+            sending: Vec::new(),
+            focus: Focus::Channels,
+            cursor: 0,
+            selected_channel: None,
+            prompt: None,
+            contacts_open: false,
+            contacts_search: String::new(),
+            contacts_cursor: 0,
+            security_open: false,
+            security_cursor: 0,
+            help_open: false,
+            quit: false,
+            settings,
+        };
+        tui.apply_listeners();
+        tui
+    }
+
+    /// Rebinds all listeners according to [Settings::listeners]
+    fn apply_listeners(&mut self) {
+        self.app.stop_listening();
+        let mut port = None;
+        for config in self.settings.listeners() {
+            // failures get reported through the event log
+            if let Ok(addr) = self.app.add_listener(config.clone()) {
+                self.log.success(format!("Listening on {}", addr));
+                port.get_or_insert(addr.port());
+            }
+        }
+        if self.settings.discovery() {
+            let (announcement, keys) = {
+                let contacts = self.app.contacts().lock().unwrap();
+                (
+                    port.map(|port| self.settings.announcement(port, &contacts)),
+                    self.settings.announcement_keys(&contacts),
+                )
+            };
+            if let Err(e) = self.app.start_discovery(announcement, &keys) {
+                self.log.error(format!("Error finding peers: {}", e));
+            }
+        } else {
+            self.app.stop_discovery();
+        }
+        if let Some(relay) = self.settings.relay().cloned() {
+            let key = self.settings.relay_key().clone();
+            let contact_keys = self
+                .settings
+                .contact_keys(&self.app.contacts().lock().unwrap());
+            match self.app.listen_through_relay(
+                relay.clone(),
+                key,
+                contact_keys,
+                ListenerPolicy::default(),
+            ) {
+                Ok(_) => self.log.success(format!("Waiting at {}", relay)),
+                Err(e) => self.log.error(format!("Error waiting at {}: {}", relay, e)),
+            }
+        }
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Catches up with what happened in the background
+    pub fn update(&mut self) {
+        while let Ok(event) = self.events.try_recv() {
+            // a reconnected channel takes the place of the closed one
+            if let Event::ChannelOpened { channel } = &event
+                && let Some(selected) = &self.selected_channel
+                && selected.state().is_finished()
+                && selected.fingerprint() == channel.fingerprint()
+            {
+                self.selected_channel = Some(channel.clone());
+            }
+            self.log.on_event(&event);
+        }
+
+        self.sending.retain(|(channel, handle)| {
+            let status = handle.status();
+            match status {
+                SendStatus::Queued => return true,
+                SendStatus::Sent => {}
+                status => {
+                    self.log
+                        .error(format!("Message to {} {}", channel.name(), status));
+                }
+            }
+            false
+        });
+
+        let mut contacts = self.app.contacts().lock().unwrap();
+        for channel in self.app.channels().lock().unwrap().iter() {
+            if !channel.state().is_finished()
+                && let Some(contact) = contacts.get_mut(channel.fingerprint())
+            {
+                contact.touch();
+            }
+        }
+    }
+
+    /// Writes the settings, and the contacts if they are to be saved, and
+    /// says goodbye everywhere
+    pub fn finish(mut self, storage: &mut Storage) -> Result<(), StorageError> {
+        if let Err(e) = self.app.shutdown(SHUTDOWN_TIMEOUT) {
+            eprintln!("Error shutting down: {}", e);
+        }
+        if self.settings.save_channels() {
+            storage.set_contacts(&self.app.contacts().lock().unwrap())?;
+        }
+        storage.set_settings(&self.settings)?;
+        storage.save()
+    }
+}
+
+impl TuiApp {
+    /// Lists the channels, then the attempts, then the pending connections
+    fn rows(&mut self) -> Vec<Row> {
+        let mut rows: Vec<Row> = self
+            .app
+            .channels()
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .map(Row::Channel)
+            .collect();
+        rows.extend(
+            self.app
+                .connecting()
+                .lock()
+                .unwrap()
+                .iter()
+                .cloned()
+                .map(Row::Attempt),
+        );
+        let pending = self.app.inspect_pending();
+        for (index, connection) in pending.iter().enumerate() {
+            rows.push(Row::Pending {
+                index,
+                name: connection.name().to_string(),
+                peer: connection.peer().clone(),
+                unknown: matches!(connection, PendingConnection::Aes(aes) if aes.contact().is_none()),
+            });
+        }
+        for connection in pending {
+            self.app.add_pending(connection);
+        }
+        rows
+    }
+
+    /// Takes the pending connection out of the app, leaving the others
+    fn take_pending(&mut self, index: usize) -> Option<PendingConnection> {
+        let mut pending = self.app.inspect_pending();
+        let taken = (index < pending.len()).then(|| pending.remove(index));
+        for connection in pending {
+            self.app.add_pending(connection);
+        }
+        taken
+    }
+
+    /// Get the contacts matching the search, in the order they are shown
+    fn found_contacts(&self) -> Vec<Contact> {
+        self.app
+            .contacts()
+            .lock()
+            .unwrap()
+            .search(&self.contacts_search)
+            .cloned()
+            .collect()
+    }
+
+    /// Runs the handshake of a closed channel anew
+    fn reconnect(&mut self, channel: &Channel) {
+        if let Err(e) = self.app.reconnect(channel) {
+            self.log
+                .error(format!("Error reconnecting {}: {}", channel.name(), e));
+        }
+    }
+
+    /// Adds the network to the blocklist, and applies the new rules
+    fn block(&mut self, net: IpNet) {
+        self.settings.access_rules_mut().block(net);
+        self.app
+            .set_access_rules(self.settings.access_rules().clone());
+        self.log.info(format!("Blocked {}", net));
+    }
+
+    fn block_key(&mut self, id: Fingerprint) {
+        self.settings.access_rules_mut().block_key(id);
+        self.app
+            .set_access_rules(self.settings.access_rules().clone());
+        self.log.info(format!("Blocked key {}", id.short()));
+    }
+
+    fn save_contact(&mut self, channel: &Channel) {
+        if self
+            .app
+            .contacts()
+            .lock()
+            .unwrap()
+            .insert(channel.desc().clone().into())
+        {
+            self.log
+                .success(format!("Saved {} to contacts", channel.name()));
+        } else {
+            self.log
+                .info(format!("{} is already a contact", channel.name()));
+        }
+    }
+}
+
+impl TuiApp {
+    pub fn on_key(&mut self, key: KeyEvent) {
+        if let Some(prompt) = self.prompt.as_mut() {
+            match prompt.form_mut().on_key(key) {
+                FormAction::None => {}
+                FormAction::Submit => {
+                    let prompt = self.prompt.take().unwrap();
+                    self.prompt = self.submit(prompt);
+                }
+                FormAction::Cancel => self.cancel(),
+            }
+            return;
+        }
+        if self.help_open {
+            self.help_open = false;
+            return;
+        }
+        if self.security_open {
+            self.security_key(key);
+            return;
+        }
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Char('c') if ctrl => self.quit = true,
+            KeyCode::F(1) => self.help_open = true,
+            KeyCode::F(2) => {
+                self.contacts_open = !self.contacts_open;
+                self.focus = if self.contacts_open {
+                    Focus::Contacts
+                } else {
+                    Focus::Channels
+                };
+            }
+            KeyCode::F(3) => self.security_open = true,
+            KeyCode::F(4) => {
+                self.prompt = Some(Prompt::Settings(prompts::settings(&self.settings)));
+            }
+            KeyCode::F(5) => self.new_channel(),
+            KeyCode::Char('n') if ctrl => self.new_channel(),
+            KeyCode::Tab => self.next_focus(),
+            _ => match self.focus {
+                Focus::Channels => self.channels_key(key),
+                Focus::Composer => self.composer_key(key),
+                Focus::Contacts => self.contacts_key(key),
+            },
+        }
+    }
+
+    fn new_channel(&mut self) {
+        let form = prompts::new_channel(self.settings.default_key_path());
+        self.prompt = Some(Prompt::NewChannel(form));
+    }
+
+    fn next_focus(&mut self) {
+        self.focus = match self.focus {
+            Focus::Channels if self.selected_channel.is_some() => Focus::Composer,
+            Focus::Channels | Focus::Composer if self.contacts_open => Focus::Contacts,
+            _ => Focus::Channels,
+        };
+    }
+
+    fn channels_key(&mut self, key: KeyEvent) {
+        let mut rows = self.rows();
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.cursor = (self.cursor + 1).min(rows.len().saturating_sub(1));
+            }
+            _ if self.cursor < rows.len() => {
+                let row = rows.swap_remove(self.cursor);
+                self.row_key(row, key);
+            }
+            _ => {}
+        }
+    }
+
+    fn row_key(&mut self, row: Row, key: KeyEvent) {
+        match (row, key.code) {
+            (Row::Channel(channel), KeyCode::Enter) => {
+                self.selected_channel = Some(channel);
+                self.focus = Focus::Composer;
+            }
+            (Row::Channel(channel), KeyCode::Char('c')) if !channel.state().is_finished() => {
+                if let Err(e) = channel.close() {
+                    self.log.error(format!("Error closing the channel: {}", e));
+                }
+            }
+            (Row::Channel(channel), KeyCode::Char('r')) if channel.state().is_finished() => {
+                self.reconnect(&channel);
+            }
+            (Row::Channel(channel), KeyCode::Char('d')) if channel.state().is_finished() => {
+                if self
+                    .selected_channel
+                    .as_ref()
+                    .is_some_and(|selected| Arc::ptr_eq(selected, &channel))
+                {
+                    self.selected_channel = None;
+                }
+                // the channel is closed already
+                let _ = self.app.remove_channel(&channel);
+            }
+            (Row::Channel(channel), KeyCode::Char('s')) => self.save_contact(&channel),
+            (Row::Attempt(attempt), KeyCode::Char('c')) => attempt.cancel(),
+            (Row::Pending { index, .. }, KeyCode::Enter) => match self.take_pending(index) {
+                Some(PendingConnection::Aes(aes)) if aes.contact().is_some() => {
+                    self.app.add_known_aes_channel(aes);
+                }
+                Some(PendingConnection::Aes(aes)) => {
+                    let form = prompts::accept_aes(&aes, self.settings.default_key_path());
+                    self.prompt = Some(Prompt::AcceptAes(form, aes));
+                }
+                Some(PendingConnection::Rsa(rsa)) => {
+                    self.prompt = Some(Prompt::AcceptRsa(prompts::accept_rsa(&rsa), rsa));
+                }
+                None => {}
+            },
+            (Row::Pending { index, .. }, KeyCode::Char('x')) => {
+                if let Some(pending) = self.take_pending(index) {
+                    pending.reject();
+                    self.log.info("Connection rejected");
+                }
+            }
+            (Row::Pending { index, peer, .. }, KeyCode::Char('b')) => {
+                if let Some(ip) = peer.ip()
+                    && let Some(pending) = self.take_pending(index)
+                {
+                    pending.reject();
+                    self.block(IpNet::from(ip));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn composer_key(&mut self, key: KeyEvent) {
+        let Some(channel) = self.selected_channel.clone() else {
+            self.focus = Focus::Channels;
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => self.focus = Focus::Channels,
+            KeyCode::Enter if channel.state().is_finished() => self.reconnect(&channel),
+            KeyCode::Enter if !self.composer.is_empty() => {
+                let message = Message::new(mem::take(&mut self.composer));
+                match channel.send_message(message) {
+                    Ok(handle) => self.sending.push((channel, handle)),
+                    Err(e) => self.log.error(format!("Message sending error: {}", e)),
+                }
+            }
+            KeyCode::Backspace => {
+                self.composer.pop();
+            }
+            KeyCode::Char(c) => self.composer.push(c),
+            _ => {}
+        }
+    }
+
+    fn contacts_key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let key_path = self.settings.default_key_path();
+        match key.code {
+            KeyCode::Esc => self.focus = Focus::Channels,
+            KeyCode::Up => self.contacts_cursor = self.contacts_cursor.saturating_sub(1),
+            KeyCode::Down => self.contacts_cursor += 1,
+            KeyCode::Char('r') if ctrl => {
+                let form = prompts::contacts_file("Import contacts", key_path);
+                self.prompt = Some(Prompt::ImportContacts(form));
+            }
+            KeyCode::Char('w') if ctrl => {
+                let form = prompts::contacts_file("Export contacts", key_path);
+                self.prompt = Some(Prompt::ExportContacts(form));
+            }
+            KeyCode::Backspace => {
+                self.contacts_search.pop();
+                self.contacts_cursor = 0;
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.contacts_search.push(c);
+                self.contacts_cursor = 0;
+            }
+            code => {
+                let Some(contact) = self.found_contacts().into_iter().nth(self.contacts_cursor)
+                else {
+                    return;
+                };
+                match code {
+                    KeyCode::Enter => {
+                        let form = prompts::reconnect(&contact);
+                        self.prompt = Some(Prompt::Reconnect(form, contact.desc().clone()));
+                    }
+                    KeyCode::Char('e') => {
+                        let form = prompts::edit_contact(&contact);
+                        self.prompt = Some(Prompt::EditContact(form, contact));
+                    }
+                    KeyCode::Char('d') => {
+                        self.app.contacts().lock().unwrap().remove(&contact.id());
+                    }
+                    KeyCode::Char('b') => self.block_key(contact.id()),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn security_key(&mut self, key: KeyEvent) {
+        let firewall = self.app.firewall().clone();
+        let mut firewall = firewall.lock().unwrap();
+        match key.code {
+            KeyCode::Esc | KeyCode::F(3) => self.security_open = false,
+            KeyCode::Up => self.security_cursor = self.security_cursor.saturating_sub(1),
+            KeyCode::Down => self.security_cursor += 1,
+            KeyCode::Char('c') => firewall.clear_events(),
+            KeyCode::Char('b') => {
+                let net = firewall
+                    .events()
+                    .rev()
+                    .nth(self.security_cursor)
+                    .and_then(|event| event.peer().ip())
+                    .map(IpNet::from);
+                drop(firewall);
+                if let Some(net) = net
+                    && !self.settings.access_rules().blocked().contains(&net)
+                {
+                    self.block(net);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Acts on the filled in form, or hands it back showing what's wrong
+    fn submit(&mut self, prompt: Prompt) -> Option<Prompt> {
+        match prompt {
+            // failures are reported through events
+            Prompt::NewChannel(form) => match prompts::channel_args(&form) {
+                Ok(ChannelArgs::Rsa(addr, name)) => {
+                    self.app.new_rsa_channel(addr, name);
+                }
+                Ok(ChannelArgs::Aes(addr, name, private, public)) => {
+                    self.app.new_aes_channel(addr, private, public, name);
+                }
+                Err(e) => return failed(Prompt::NewChannel(form), e),
+            },
+            Prompt::AcceptRsa(form, pending) => {
+                self.app.add_rsa_channel(pending, form.optional("Name"));
+            }
+            Prompt::AcceptAes(form, pending) => match prompts::accept_aes_args(&form) {
+                Ok((name, private, public)) => {
+                    self.app.add_aes_channel(pending, name, private, public);
+                }
+                Err(e) => return failed(Prompt::AcceptAes(form, pending), e),
+            },
+            Prompt::Reconnect(form, desc) => match prompts::reconnect_addr(&form) {
+                Ok(addr) => {
+                    self.app.new_channel_from_desc(addr, desc);
+                }
+                Err(e) => return failed(Prompt::Reconnect(form, desc), e),
+            },
+            Prompt::EditContact(form, contact) => {
+                match prompts::edited_contact(&form, contact.clone()) {
+                    Ok(edited) => {
+                        let mut contacts = self.app.contacts().lock().unwrap();
+                        contacts.remove(&edited.id());
+                        contacts.insert(edited);
+                    }
+                    Err(e) => return failed(Prompt::EditContact(form, contact), e),
+                }
+            }
+            Prompt::ImportContacts(form) => match import_contacts(Path::new(form.text("Path"))) {
+                Ok(book) => {
+                    let added = self.app.contacts().lock().unwrap().merge(book);
+                    self.log.success(format!("Imported {} new contacts", added));
+                }
+                Err(e) => return failed(Prompt::ImportContacts(form), e),
+            },
+            Prompt::ExportContacts(form) => {
+                let exported = export_contacts(
+                    &self.app.contacts().lock().unwrap(),
+                    Path::new(form.text("Path")),
+                );
+                if let Err(e) = exported {
+                    return failed(Prompt::ExportContacts(form), e);
+                }
+            }
+            Prompt::Settings(form) => match prompts::edited_settings(&form, &self.settings) {
+                Ok(settings) => {
+                    self.settings = settings;
+                    self.settings.apply(&mut self.app);
+                    self.apply_listeners();
+                }
+                Err(e) => return failed(Prompt::Settings(form), e),
+            },
+        }
+        None
+    }
+
+    /// Dismisses the form, rejecting the connection it was about, if any
+    fn cancel(&mut self) {
+        match self.prompt.take() {
+            Some(Prompt::AcceptRsa(_, pending)) => pending.reject(),
+            Some(Prompt::AcceptAes(_, pending)) => pending.reject(),
+            _ => {}
+        }
+    }
+}
+
+/// Hands the form back, showing the error
+fn failed(mut prompt: Prompt, error: impl Display) -> Option<Prompt> {
+    prompt.form_mut().set_error(error);
+    Some(prompt)
+}
+
+/// Style of the border around a panel
+fn border(focused: bool) -> Style {
+    if focused {
+        Style::new().fg(Color::Cyan)
+    } else {
+        Style::new().dim()
+    }
+}
+
+/// Counts the lines the text takes up, once wrapped to the width
+fn wrapped_height(lines: &[Line], width: u16) -> usize {
+    let width = width.max(1) as usize;
+    lines
+        .iter()
+        .map(|line| line.width().max(1).div_ceil(width))
+        .sum()
+}
+
+impl TuiApp {
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let [top, body, status] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Line::from(vec![
+                Span::raw(" grapevine ").bold().reversed(),
+                Span::raw("  F1 help  F2 contacts  F3 security  F4 settings  F5 new channel").dim(),
+            ]),
+            top,
+        );
+
+        let mut constraints = vec![Constraint::Length(SIDE_WIDTH), Constraint::Min(0)];
+        if self.contacts_open {
+            constraints.push(Constraint::Length(CONTACTS_WIDTH));
+        }
+        let panels = Layout::horizontal(constraints).split(body);
+        self.channels_panel(frame, panels[0]);
+        self.central_panel(frame, panels[1]);
+        if self.contacts_open {
+            self.contacts_panel(frame, panels[2]);
+        }
+
+        if let Some(notice) = self.log.current() {
+            frame.render_widget(Line::styled(notice.text(), notice.level().color()), status);
+        }
+
+        if self.security_open {
+            self.security_panel(frame, body);
+        }
+        if self.help_open {
+            help(frame, body);
+        }
+        if let Some(prompt) = &self.prompt {
+            prompt.form().draw(frame, body);
+        }
+    }
+
+    fn channels_panel(&mut self, frame: &mut Frame, area: Rect) {
+        let rows = self.rows();
+        self.cursor = self.cursor.min(rows.len().saturating_sub(1));
+
+        let items: Vec<ListItem> = rows
+            .iter()
+            .map(|row| match row {
+                Row::Channel(channel) => {
+                    let selected = self
+                        .selected_channel
+                        .as_ref()
+                        .is_some_and(|selected| Arc::ptr_eq(selected, channel));
+                    let marker = if selected { "› " } else { "  " };
+                    let line = Line::from(format!("{}{}", marker, channel.name()));
+                    if channel.state().is_finished() {
+                        ListItem::new(line.dim())
+                    } else {
+                        ListItem::new(line)
+                    }
+                }
+                Row::Attempt(attempt) => ListItem::new(Line::from(vec![
+                    Span::raw(format!("… {} ", attempt.name())),
+                    Span::raw(attempt.state().to_string()).dim(),
+                ])),
+                Row::Pending {
+                    name,
+                    peer,
+                    unknown,
+                    ..
+                } => {
+                    let label = if *unknown { "?" } else { "✔" };
+                    ListItem::new(Line::from(vec![
+                        Span::raw(format!("{} {} ", label, name)).yellow(),
+                        Span::raw(peer.to_string()).dim(),
+                    ]))
+                }
+            })
+            .collect();
+
+        let listeners: Vec<Line> = self
+            .app
+            .listeners()
+            .map(|(addr, _)| Line::from(format!("Listening on {}", addr)).dim())
+            .collect();
+        let [list_area, listeners_area] = Layout::vertical([
+            Constraint::Min(0),
+            Constraint::Length(listeners.len() as u16),
+        ])
+        .areas(area);
+
+        let focused = self.focus == Focus::Channels;
+        let mut state = ListState::default().with_selected(focused.then_some(self.cursor));
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(
+                    Block::bordered()
+                        .title("Channels")
+                        .border_style(border(focused)),
+                )
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            list_area,
+            &mut state,
+        );
+        frame.render_widget(Paragraph::new(listeners), listeners_area);
+    }
+
+    fn central_panel(&mut self, frame: &mut Frame, area: Rect) {
+        let Some(channel) = self.selected_channel.clone() else {
+            frame.render_widget(
+                Paragraph::new("Pick a channel, or create one with F5")
+                    .alignment(Alignment::Center)
+                    .block(Block::bordered().border_style(border(false))),
+                area,
+            );
+            return;
+        };
+
+        let [messages_area, composer_area] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(3)]).areas(area);
+
+        let lines: Vec<Line> = channel
+            .messages()
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|message| {
+                let time = message.timestamp().format("%H:%M").to_string();
+                if message.is_ours() {
+                    Line::from(vec![
+                        Span::raw(format!(
+                            "{}: {} ",
+                            self.settings.username(),
+                            message.content()
+                        )),
+                        Span::raw(time).dim(),
+                    ])
+                    .alignment(Alignment::Right)
+                } else {
+                    Line::from(vec![
+                        Span::raw(time).dim(),
+                        Span::raw(format!(" {}: {}", channel.name(), message.content())),
+                    ])
+                }
+            })
+            .collect();
+        let inner_height = messages_area.height.saturating_sub(2) as usize;
+        let scroll = wrapped_height(&lines, messages_area.width.saturating_sub(2))
+            .saturating_sub(inner_height);
+        let state = channel.state();
+        frame.render_widget(
+            Paragraph::new(lines)
+                .wrap(Wrap { trim: false })
+                .scroll((scroll as u16, 0))
+                .block(
+                    Block::bordered()
+                        .title(format!("{} ({})", channel.name(), channel.peer()))
+                        .title_bottom(Line::from(state.to_string()).dim())
+                        .border_style(border(false)),
+                ),
+            messages_area,
+        );
+
+        let focused = self.focus == Focus::Composer;
+        let mut block = Block::bordered().border_style(border(focused));
+        let composer = if state.is_finished() {
+            Line::from(format!("Disconnected: {}, enter to reconnect", state)).dim()
+        } else {
+            let waiting = self
+                .sending
+                .iter()
+                .filter(|(other, _)| Arc::ptr_eq(other, &channel))
+                .count();
+            if waiting > 0 {
+                block = block.title(Line::from(format!("Sending {} message(s)", waiting)).dim());
+            }
+            let cursor = if focused { "▏" } else { "" };
+            Line::from(format!("> {}{}", self.composer, cursor))
+        };
+        frame.render_widget(Paragraph::new(composer).block(block), composer_area);
+    }
+
+    fn contacts_panel(&mut self, frame: &mut Frame, area: Rect) {
+        let found = self.found_contacts();
+        self.contacts_cursor = self.contacts_cursor.min(found.len().saturating_sub(1));
+        let focused = self.focus == Focus::Contacts;
+
+        let [search_area, list_area, details_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(8),
+        ])
+        .areas(area);
+
+        frame.render_widget(
+            Paragraph::new(format!("🔍 {}", self.contacts_search)).block(
+                Block::bordered()
+                    .title("Contacts")
+                    .border_style(border(focused)),
+            ),
+            search_area,
+        );
+
+        let items: Vec<ListItem> = found
+            .iter()
+            .map(|contact| {
+                if contact.is_verified() {
+                    ListItem::new(format!("{} ✔", contact.name()))
+                } else {
+                    ListItem::new(contact.name().to_string())
+                }
+            })
+            .collect();
+        let mut state = ListState::default().with_selected(Some(self.contacts_cursor));
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::bordered().border_style(border(focused)))
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            list_area,
+            &mut state,
+        );
+
+        let details = match found.get(self.contacts_cursor) {
+            Some(contact) => {
+                let mut lines = vec![
+                    Line::from(format!("Fingerprint: {}", contact.id().short())),
+                    Line::from(match contact.last_seen() {
+                        Some(seen) => {
+                            format!("Last seen: {}", seen.format("%Y-%m-%d %H:%M:%S"))
+                        }
+                        None => "Never seen".to_string(),
+                    }),
+                ];
+                lines.extend(
+                    contact
+                        .addresses()
+                        .iter()
+                        .map(|addr| Line::from(addr.to_string())),
+                );
+                if !contact.notes().is_empty() {
+                    lines.push(Line::from(contact.notes()).italic());
+                }
+                lines
+            }
+            None => vec![Line::from("No contacts").dim()],
+        };
+        frame.render_widget(
+            Paragraph::new(details)
+                .wrap(Wrap { trim: true })
+                .block(Block::bordered().border_style(border(focused))),
+            details_area,
+        );
+    }
+
+    /// Log of rejected incoming connections, newest first
+    fn security_panel(&mut self, frame: &mut Frame, area: Rect) {
+        let firewall = self.app.firewall().clone();
+        let firewall = firewall.lock().unwrap();
+        let items: Vec<ListItem> = firewall
+            .events()
+            .rev()
+            .map(|event| {
+                let blocked = event.peer().ip().is_some_and(|ip| {
+                    self.settings
+                        .access_rules()
+                        .blocked()
+                        .contains(&IpNet::from(ip))
+                });
+                let line = Line::from(format!(
+                    "{}  {}  {}",
+                    event.time().format("%Y-%m-%d %H:%M:%S"),
+                    event.peer(),
+                    event.reason()
+                ));
+                ListItem::new(if blocked { line.dim() } else { line })
+            })
+            .collect();
+        self.security_cursor = self.security_cursor.min(items.len().saturating_sub(1));
+
+        let popup = area.centered(Constraint::Percentage(80), Constraint::Percentage(70));
+        let block = Block::bordered()
+            .title("Security events")
+            .title_bottom(Line::from("b: block  c: clear  esc: close").dim());
+        frame.render_widget(Clear, popup);
+        if items.is_empty() {
+            frame.render_widget(
+                Paragraph::new(Line::from("No connections were rejected").dim()).block(block),
+                popup,
+            );
+        } else {
+            let mut state = ListState::default().with_selected(Some(self.security_cursor));
+            frame.render_stateful_widget(
+                List::new(items)
+                    .block(block)
+                    .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+                popup,
+                &mut state,
+            );
+        }
+    }
+}
+
+fn help(frame: &mut Frame, area: Rect) {
+    let lines: Vec<Line> = HELP
+        .iter()
+        .map(|(keys, what)| {
+            Line::from(vec![
+                Span::raw(format!("{:>16}  ", keys)).bold(),
+                Span::raw(*what),
+            ])
+        })
+        .collect();
+    let popup = area.centered(
+        Constraint::Length(72),
+        Constraint::Length(lines.len() as u16 + 2),
+    );
+    frame.render_widget(Clear, popup);
+    frame.render_widget(
+        Paragraph::new(lines).block(
+            Block::bordered()
+                .title("Keys")
+                .title_bottom(Line::from("any key to close").dim()),
+        ),
+        popup,
+    );
+}
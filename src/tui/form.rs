@@ -0,0 +1,201 @@
+use ratatui::{
+    Frame,
+    crossterm::event::{KeyCode, KeyEvent},
+    layout::{Constraint, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Clear, Paragraph, Wrap},
+};
+
+/// Width of the popup forms are shown in
+const WIDTH: u16 = 72;
+
+enum Input {
+    Text(String),
+    Toggle(bool),
+    Choice {
+        options: &'static [&'static str],
+        selected: usize,
+    },
+}
+
+struct Field {
+    label: &'static str,
+    input: Input,
+}
+
+/// What a key press did to the form
+pub enum FormAction {
+    None,
+    Submit,
+    Cancel,
+}
+
+/// A popup of labelled inputs, the terminal's take on a modal.
+/// Tab and the arrows move between fields, space toggles, left and right
+/// pick between choices, enter submits and escape cancels.
+pub struct Form {
+    title: String,
+    note: Option<String>,
+    fields: Vec<Field>,
+    focused: usize,
+    error: Option<String>,
+}
+
+impl Form {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            note: None,
+            fields: Vec::new(),
+            focused: 0,
+            error: None,
+        }
+    }
+
+    /// Adds a line of explanation above the fields
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.note = Some(note.into());
+        self
+    }
+
+    pub fn with_text(mut self, label: &'static str, value: impl Into<String>) -> Self {
+        self.fields.push(Field {
+            label,
+            input: Input::Text(value.into()),
+        });
+        self
+    }
+
+    pub fn with_toggle(mut self, label: &'static str, value: bool) -> Self {
+        self.fields.push(Field {
+            label,
+            input: Input::Toggle(value),
+        });
+        self
+    }
+
+    pub fn with_choice(
+        mut self,
+        label: &'static str,
+        options: &'static [&'static str],
+        selected: usize,
+    ) -> Self {
+        self.fields.push(Field {
+            label,
+            input: Input::Choice { options, selected },
+        });
+        self
+    }
+
+    fn field(&self, label: &str) -> &Input {
+        // the labels are all ours
+        &self
+            .fields
+            .iter()
+            .find(|field| field.label == label)
+            .expect("no such field")
+            .input
+    }
+
+    /// Get the trimmed contents of a text field
+    pub fn text(&self, label: &str) -> &str {
+        match self.field(label) {
+            Input::Text(text) => text.trim(),
+            _ => "",
+        }
+    }
+
+    /// Get the contents of a text field, if it isn't empty
+    pub fn optional(&self, label: &str) -> Option<String> {
+        let text = self.text(label);
+        (!text.is_empty()).then(|| text.to_string())
+    }
+
+    pub fn toggle(&self, label: &str) -> bool {
+        matches!(self.field(label), Input::Toggle(true))
+    }
+
+    /// Get the index of the chosen option
+    pub fn choice(&self, label: &str) -> usize {
+        match self.field(label) {
+            Input::Choice { selected, .. } => *selected,
+            _ => 0,
+        }
+    }
+
+    /// Shows an error, until the next key press
+    pub fn set_error(&mut self, error: impl ToString) {
+        self.error = Some(error.to_string());
+    }
+
+    pub fn on_key(&mut self, key: KeyEvent) -> FormAction {
+        self.error = None;
+        let count = self.fields.len();
+        match key.code {
+            KeyCode::Esc => return FormAction::Cancel,
+            KeyCode::Enter => return FormAction::Submit,
+            KeyCode::Tab | KeyCode::Down => self.focused = (self.focused + 1) % count,
+            KeyCode::BackTab | KeyCode::Up => self.focused = (self.focused + count - 1) % count,
+            code => match (&mut self.fields[self.focused].input, code) {
+                (Input::Text(text), KeyCode::Char(c)) => text.push(c),
+                (Input::Text(text), KeyCode::Backspace) => {
+                    text.pop();
+                }
+                (Input::Toggle(value), KeyCode::Char(' ')) => *value = !*value,
+                (Input::Choice { options, selected }, KeyCode::Right | KeyCode::Char(' ')) => {
+                    *selected = (*selected + 1) % options.len();
+                }
+                (Input::Choice { options, selected }, KeyCode::Left) => {
+                    *selected = (*selected + options.len() - 1) % options.len();
+                }
+                _ => {}
+            },
+        }
+        FormAction::None
+    }
+
+    pub fn draw(&self, frame: &mut Frame, area: Rect) {
+        let mut lines = Vec::new();
+        if let Some(note) = &self.note {
+            lines.push(Line::from(note.as_str()));
+            lines.push(Line::default());
+        }
+        for (i, field) in self.fields.iter().enumerate() {
+            let focused = i == self.focused;
+            let value = match &field.input {
+                Input::Text(text) if focused => format!("{}▏", text),
+                Input::Text(text) => text.clone(),
+                Input::Toggle(value) => if *value { "[x]" } else { "[ ]" }.to_string(),
+                Input::Choice { options, selected } => format!("< {} >", options[*selected]),
+            };
+            let style = if focused {
+                Style::new().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::new()
+            };
+            lines.push(Line::from(vec![
+                Span::styled(format!("{}: ", field.label), Style::new().bold()),
+                Span::styled(value, style),
+            ]));
+        }
+        lines.push(Line::default());
+        match &self.error {
+            Some(error) => lines.push(Line::styled(error.as_str(), Color::Red)),
+            None => lines.push(Line::styled(
+                "enter: confirm  esc: cancel  tab: next field",
+                Style::new().dim(),
+            )),
+        }
+
+        let height = lines.len() as u16 + 2;
+        let popup = area.centered(Constraint::Length(WIDTH), Constraint::Length(height));
+        frame.render_widget(Clear, popup);
+        frame.render_widget(
+            Paragraph::new(lines)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title(self.title.as_str())),
+            popup,
+        );
+    }
+}
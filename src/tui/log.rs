@@ -0,0 +1,119 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use ratatui::style::Color;
+
+use grapevine_lib::{CloseReason, Event};
+
+/// How many notices are kept around
+const CAPACITY: usize = 64;
+/// How long a notice stays in the status bar
+const SHOWN_FOR: Duration = Duration::from_secs(8);
+
+#[derive(Clone, Copy)]
+pub enum Level {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+impl Level {
+    pub fn color(&self) -> Color {
+        match self {
+            Level::Info => Color::Reset,
+            Level::Success => Color::Green,
+            Level::Warning => Color::Yellow,
+            Level::Error => Color::Red,
+        }
+    }
+}
+
+pub struct Notice {
+    level: Level,
+    text: String,
+    time: Instant,
+}
+
+impl Notice {
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// The terminal's take on toasts, shown one at a time in the status bar
+#[derive(Default)]
+pub struct EventLog {
+    notices: VecDeque<Notice>,
+}
+
+impl EventLog {
+    fn push(&mut self, level: Level, text: impl Into<String>) {
+        if self.notices.len() == CAPACITY {
+            self.notices.pop_front();
+        }
+        self.notices.push_back(Notice {
+            level,
+            text: text.into(),
+            time: Instant::now(),
+        });
+    }
+
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.push(Level::Info, text);
+    }
+
+    pub fn success(&mut self, text: impl Into<String>) {
+        self.push(Level::Success, text);
+    }
+
+    pub fn warning(&mut self, text: impl Into<String>) {
+        self.push(Level::Warning, text);
+    }
+
+    pub fn error(&mut self, text: impl Into<String>) {
+        self.push(Level::Error, text);
+    }
+
+    /// Get the latest notice, unless it has been shown for long enough
+    pub fn current(&self) -> Option<&Notice> {
+        self.notices
+            .back()
+            .filter(|notice| notice.time.elapsed() < SHOWN_FOR)
+    }
+
+    /// Turns the app event into a notice, if it's worth one
+    pub fn on_event(&mut self, event: &Event) {
+        let message = event.to_string();
+        match event {
+            // the channel view shows these already
+            Event::MessageReceived { .. } => {}
+            // meant for other applications
+            Event::PayloadReceived { .. } => {}
+            Event::ChannelOpened { .. } => self.success(message),
+            Event::ChannelClosed {
+                reason: CloseReason::Failed(_),
+                ..
+            }
+            | Event::HandshakeFailed { .. }
+            | Event::ConnectionFailed { .. }
+            | Event::ListenerError { .. } => self.error(message),
+            Event::ChannelClosed { .. } | Event::PendingConnectionArrived { .. } => {
+                self.info(message)
+            }
+            Event::PendingConnectionExpired { .. } | Event::ConnectionRejected { .. } => {
+                self.warning(message)
+            }
+            Event::GroupUpdated { .. }
+            | Event::GroupMessageReceived { .. }
+            | Event::RelayedMessageReceived { .. }
+            | Event::PeerDiscovered { .. } => self.info(message),
+        }
+    }
+}
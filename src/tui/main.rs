@@ -0,0 +1,81 @@
+//! Terminal grapevine client. Shares its settings and contacts with the GUI.
+
+use std::{env, io, path::PathBuf, process::ExitCode, time::Duration};
+
+use ratatui::{
+    DefaultTerminal,
+    crossterm::event::{self, Event, KeyEventKind},
+};
+
+use grapevine::storage::Storage;
+
+mod app;
+use app::TuiApp;
+
+mod form;
+mod log;
+mod prompts;
+
+/// How often the screen is redrawn, when nothing is pressed
+const TICK: Duration = Duration::from_millis(100);
+
+const USAGE: &str = "usage: grapevine-tui [--store <PATH>]";
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let storage = match (args.next().as_deref(), args.next(), args.next()) {
+        (None, _, _) => Storage::open_default(),
+        (Some("--store"), Some(path), None) => Storage::open(PathBuf::from(path)),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut storage = match storage {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!("Couldn't open the storage: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // like the GUI, broken settings or contacts are started over
+    let settings = storage.settings().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        Default::default()
+    });
+    let contacts = storage.contacts().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        Default::default()
+    });
+
+    let mut tui = TuiApp::new(settings, contacts);
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut tui);
+    ratatui::restore();
+
+    let saved = tui.finish(&mut storage);
+    if let Err(e) = result {
+        eprintln!("Terminal error: {}", e);
+        return ExitCode::FAILURE;
+    }
+    if let Err(e) = saved {
+        eprintln!("Error saving to {}: {}", storage.path().display(), e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn run(terminal: &mut DefaultTerminal, tui: &mut TuiApp) -> io::Result<()> {
+    while !tui.should_quit() {
+        tui.update();
+        terminal.draw(|frame| tui.draw(frame))?;
+        if event::poll(TICK)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            tui.on_key(key);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,286 @@
+//! The forms of the terminal client, each standing in for one of the GUI's
+//! modals, along with reading their results.
+
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use openssl::pkey::{PKey, Private, Public};
+
+use grapevine::settings::Settings;
+use grapevine_lib::{
+    AccessRules, Backpressure, Contact, GossipConfig, HostAddr, ListenerConfig,
+    PendingAesHandshake, PendingRsaHandshake, QueueConfig, Timeouts,
+};
+
+use super::form::Form;
+
+type FormResult<T> = Result<T, Box<dyn Error>>;
+
+const BACKPRESSURE: &[&str] = &["wait for room", "drop the message", "refuse the message"];
+
+/// Parses space separated items
+fn parse_words<T: FromStr>(input: &str) -> Result<Vec<T>, T::Err> {
+    input.split_whitespace().map(T::from_str).collect()
+}
+
+/// Writes space separated items
+fn join_words<T: ToString>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn read_keys(form: &Form) -> FormResult<(PKey<Private>, PKey<Public>)> {
+    Ok((
+        PKey::private_key_from_pem(&fs::read(form.text("Our private key"))?)?,
+        PKey::public_key_from_pem(&fs::read(form.text("Their public key"))?)?,
+    ))
+}
+
+pub enum ChannelArgs {
+    Rsa(HostAddr, Option<String>),
+    Aes(HostAddr, Option<String>, PKey<Private>, PKey<Public>),
+}
+
+pub fn new_channel(default_key_path: &Path) -> Form {
+    let path = default_key_path.to_string_lossy();
+    Form::new("New channel")
+        .with_text("Name", "")
+        .with_text("Address", "")
+        .with_toggle("Known keys", false)
+        .with_text("Our private key", path.clone())
+        .with_text("Their public key", path)
+}
+
+pub fn channel_args(form: &Form) -> FormResult<ChannelArgs> {
+    let addr = HostAddr::from_str(form.text("Address"))?;
+    let name = form.optional("Name");
+    if form.toggle("Known keys") {
+        let (private, public) = read_keys(form)?;
+        Ok(ChannelArgs::Aes(addr, name, private, public))
+    } else {
+        Ok(ChannelArgs::Rsa(addr, name))
+    }
+}
+
+pub fn accept_rsa(pending: &PendingRsaHandshake) -> Form {
+    Form::new("Accept")
+        .with_note(format!(
+            "{} wants to exchange RSA keys. Do you wish to accept their public key?",
+            pending.name()
+        ))
+        .with_text("Name", "")
+}
+
+pub fn accept_aes(pending: &PendingAesHandshake, default_key_path: &Path) -> Form {
+    let path = default_key_path.to_string_lossy();
+    Form::new("Accept")
+        .with_note(format!(
+            "{} knows our public key, and expects us to know theirs",
+            pending.name()
+        ))
+        .with_text("Name", "")
+        .with_text("Our private key", path.clone())
+        .with_text("Their public key", path)
+}
+
+pub fn accept_aes_args(form: &Form) -> FormResult<(Option<String>, PKey<Private>, PKey<Public>)> {
+    let (private, public) = read_keys(form)?;
+    Ok((form.optional("Name"), private, public))
+}
+
+pub fn reconnect(contact: &Contact) -> Form {
+    let mut form = Form::new("Connect");
+    if contact.addresses().len() > 1 {
+        form = form.with_note(format!(
+            "{} was also known under {}",
+            contact.name(),
+            join_words(&contact.addresses()[1..])
+        ));
+    }
+    form.with_text("Address", contact.desc().last_addr().to_string())
+}
+
+pub fn reconnect_addr(form: &Form) -> FormResult<HostAddr> {
+    Ok(HostAddr::from_str(form.text("Address"))?)
+}
+
+pub fn edit_contact(contact: &Contact) -> Form {
+    Form::new("Edit contact")
+        .with_note(format!("Fingerprint: {}", contact.id()))
+        .with_text("Name", contact.name())
+        .with_text(
+            "Addresses, most recent first",
+            join_words(contact.addresses()),
+        )
+        .with_text("Notes", contact.notes())
+        .with_toggle("Fingerprint verified", contact.is_verified())
+}
+
+pub fn edited_contact(form: &Form, mut contact: Contact) -> FormResult<Contact> {
+    let addresses: Vec<HostAddr> = parse_words(form.text("Addresses, most recent first"))?;
+    for addr in addresses.iter().rev() {
+        contact.add_address(addr.clone());
+    }
+    for addr in contact.addresses().to_vec() {
+        if !addresses.contains(&addr) {
+            contact.remove_address(&addr);
+        }
+    }
+    contact.rename(form.text("Name").to_string());
+    contact.set_notes(form.text("Notes").to_string());
+    contact.set_verified(form.toggle("Fingerprint verified"));
+    Ok(contact)
+}
+
+pub fn contacts_file(title: &'static str, default_key_path: &Path) -> Form {
+    Form::new(title).with_text("Path", default_key_path.to_string_lossy())
+}
+
+pub fn settings(settings: &Settings) -> Form {
+    let rules = settings.access_rules();
+    let backpressure = match settings.send_queue().backpressure() {
+        Backpressure::Block => 0,
+        Backpressure::Drop => 1,
+        Backpressure::Error => 2,
+    };
+    Form::new("Settings")
+        .with_note(
+            "Lists are space separated. Listener policies and rate limits are set in the GUI.",
+        )
+        .with_text("Username", settings.username())
+        .with_text(
+            "Listen on",
+            join_words(
+                &settings
+                    .listeners()
+                    .iter()
+                    .map(ListenerConfig::addr)
+                    .collect::<Vec<_>>(),
+            ),
+        )
+        .with_text("Allowed networks", join_words(rules.allowed()))
+        .with_text("Blocked networks", join_words(rules.blocked()))
+        .with_text("Blocked keys", join_words(rules.blocked_keys()))
+        .with_text(
+            "Connect timeout (s)",
+            settings.timeouts().connect().as_secs().to_string(),
+        )
+        .with_text(
+            "Key exchange timeout (s)",
+            settings.timeouts().handshake().as_secs().to_string(),
+        )
+        .with_text(
+            "Queue capacity",
+            settings.send_queue().capacity().to_string(),
+        )
+        .with_choice("Once the queue is full", BACKPRESSURE, backpressure)
+        .with_text(
+            "Checkpoint every (empty for never)",
+            settings
+                .checkpoints()
+                .map(|every| every.to_string())
+                .unwrap_or_default(),
+        )
+        .with_toggle("Pass on messages for others", settings.gossip().relay())
+        .with_text("Relay hops", settings.gossip().hops().to_string())
+        .with_text(
+            "Relay expiry (h)",
+            (settings.gossip().expiry().as_secs() / 3600).to_string(),
+        )
+        .with_text(
+            "Relay (empty for none)",
+            settings
+                .relay()
+                .map(|relay| relay.to_string())
+                .unwrap_or_default(),
+        )
+        .with_toggle("Find peers on the local network", settings.discovery())
+        .with_toggle(
+            "Let contacts recognize us there",
+            settings.announce_contacts(),
+        )
+        .with_text(
+            "Default key path",
+            settings.default_key_path().to_string_lossy(),
+        )
+        .with_toggle("Save channels", settings.save_channels())
+}
+
+/// Reads the settings, keeping what the form doesn't cover from the old ones
+pub fn edited_settings(form: &Form, old: &Settings) -> FormResult<Settings> {
+    let listeners = parse_words::<HostAddr>(form.text("Listen on"))?
+        .into_iter()
+        .map(|addr| {
+            let policy = old
+                .listeners()
+                .iter()
+                .find(|config| *config.addr() == addr)
+                .map(|config| config.policy().clone())
+                .unwrap_or_default();
+            ListenerConfig::new(addr, policy)
+        })
+        .collect();
+
+    let rules = old.access_rules();
+    let access_rules = AccessRules::new(
+        parse_words(form.text("Allowed networks"))?,
+        parse_words(form.text("Blocked networks"))?,
+        parse_words(form.text("Blocked keys"))?,
+        rules.rate_limit().copied(),
+        rules.max_pending(),
+    );
+
+    let backpressure = match form.choice("Once the queue is full") {
+        0 => Backpressure::Block,
+        1 => Backpressure::Drop,
+        _ => Backpressure::Error,
+    };
+    let checkpoints = form
+        .optional("Checkpoint every (empty for never)")
+        .map(|every| every.parse())
+        .transpose()?;
+
+    Ok(Settings::new(
+        form.optional("Username"),
+        Some(PathBuf::from(form.text("Default key path")).canonicalize()?),
+        form.toggle("Save channels"),
+    )
+    .with_listeners(listeners)
+    .with_access_rules(access_rules)
+    .with_timeouts(Timeouts::new(
+        Duration::from_secs(form.text("Connect timeout (s)").parse()?),
+        Duration::from_secs(form.text("Key exchange timeout (s)").parse()?),
+    ))
+    .with_send_queue(QueueConfig::new(
+        form.text("Queue capacity").parse()?,
+        backpressure,
+    ))
+    .with_checkpoints(checkpoints)
+    .with_gossip(GossipConfig::new(
+        form.toggle("Pass on messages for others"),
+        form.text("Relay hops").parse()?,
+        Duration::from_secs(
+            form.text("Relay expiry (h)")
+                .parse::<u64>()?
+                .checked_mul(3600)
+                .ok_or("relay expiry is too long")?,
+        ),
+        old.gossip().capacity(),
+    ))
+    .with_relay(
+        form.optional("Relay (empty for none)")
+            .map(|relay| relay.parse())
+            .transpose()?,
+    )
+    .with_relay_key(old.relay_key().clone())
+    .with_discovery(form.toggle("Find peers on the local network"))
+    .with_announce_contacts(form.toggle("Let contacts recognize us there")))
+}
@@ -1,104 +1,319 @@
-use std::{
-    any::type_name,
-    mem,
-    sync::{Arc, Mutex},
-};
+use std::{any::type_name, mem, path::Path, sync::Arc, time::Duration};
 
 use egui::{
-    Align, Button, CentralPanel, Context, Frame, Layout, RichText, ScrollArea, SidePanel,
-    TopBottomPanel, Ui,
+    Align, Button, CentralPanel, CollapsingHeader, Context, Frame, Layout, RichText, ScrollArea,
+    SidePanel, TextEdit, TopBottomPanel, Ui, Window,
 };
+use egui_path_picker::PathPicker;
 use serde_json::to_string;
 
-use grapevine_lib::{Channel, ChannelDesc, GrapevineApp, Message, PendingConnection};
+use grapevine::{
+    settings::Settings,
+    storage::{export_contacts, import_contacts},
+};
+use grapevine_lib::{
+    Announcement, Channel, ChannelDesc, ContactBook, Endpoint, Event, EventReceiver, Fingerprint,
+    GrapevineApp, Group, IpNet, ListenerPolicy, Message, PendingConnection, SendHandle, SendStatus,
+};
 
 use super::{
     handler::UiEventHandler,
     modals::{
-        ChannelAcceptAesForm, ChannelAcceptRsaForm, ChannelArgs, ChannelDescEditForm, ChannelForm,
-        ChannelRecreationForm, ModalForm, SettingsForm,
+        ChannelAcceptAesForm, ChannelAcceptRsaForm, ChannelArgs, ChannelForm,
+        ChannelRecreationForm, ContactEditForm, GroupCreationForm, ModalForm, SettingsForm,
     },
-    settings::Settings,
 };
 
+/// How long closing the app may wait for channels to say goodbye
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub struct GrapevineUI {
     // encapsulations
     app: GrapevineApp,
-    event_handler: Arc<Mutex<UiEventHandler>>,
+    event_handler: UiEventHandler,
+    events: EventReceiver,
     channel_message_input: String,
+    /// Messages still waiting to be written
+    sending: Vec<(Arc<Channel>, SendHandle)>,
     // Vis
     selected_channel: Option<Arc<Channel>>,
+    selected_group: Option<Arc<Group>>,
     settings_modal: Option<ModalForm<SettingsForm>>,
     channel_modal: Option<ModalForm<ChannelForm>>,
     channel_rsa_modal: Option<ModalForm<ChannelAcceptRsaForm>>,
     channel_aes_modal: Option<ModalForm<ChannelAcceptAesForm>>,
     channel_recreation_modal: Option<ModalForm<ChannelRecreationForm>>,
-    channel_desc_edit_modal: Option<ModalForm<ChannelDescEditForm>>,
+    contact_edit_modal: Option<ModalForm<ContactEditForm>>,
+    group_modal: Option<ModalForm<GroupCreationForm>>,
+    contacts_open: bool,
+    contacts_search: String,
+    contacts_path: String,
+    security_open: bool,
+    /// The port of the first listener, announced on the local network
+    listening_port: Option<u16>,
+    /// What we announce on the local network, while finding peers there
+    announcement: Option<Announcement>,
+    /// How the listener at the relay is known, and the fingerprints it's
+    /// registered under, if waiting at one
+    relay_listener: Option<(Endpoint, Vec<Fingerprint>)>,
     // User config
-    saved_channels: Vec<ChannelDesc>,
     settings: Settings,
 }
 
 impl GrapevineUI {
-    pub fn new(settings: Settings, saved_channels: Vec<ChannelDesc>) -> Self {
-        let event_handler = Arc::new(Mutex::new(UiEventHandler::default()));
-
+    pub fn new(settings: Settings, contacts: ContactBook, ctx: Context) -> Self {
         let mut app = GrapevineApp::new();
 
-        app.add_event_recipient(event_handler.clone());
-        if let Some(addr) = settings.listening() {
-            app.start_listening(addr.clone());
-        }
+        // events arrive from background tasks, so the UI has to be woken up
+        app.on_event(move |_| ctx.request_repaint());
+        let events = app.subscribe();
+        app.set_contacts(contacts);
+        settings.apply(&mut app);
 
-        Self {
+        let mut ui = Self {
             app,
-            event_handler,
+            event_handler: UiEventHandler::default(),
+            events,
             selected_channel: None,
+            selected_group: None,
             channel_message_input: String::new(),
+            sending: Vec::new(),
             settings_modal: None,
             channel_modal: None,
             channel_rsa_modal: None,
             channel_aes_modal: None,
             channel_recreation_modal: None,
-            channel_desc_edit_modal: None,
-            saved_channels,
-            settings: settings,
+            contact_edit_modal: None,
+            group_modal: None,
+            contacts_open: false,
+            contacts_search: String::new(),
+            contacts_path: settings.default_key_path().to_string_lossy().to_string(),
+            security_open: false,
+            listening_port: None,
+            announcement: None,
+            relay_listener: None,
+            settings,
+        };
+        ui.apply_listeners();
+        ui
+    }
+
+    /// Rebinds all listeners according to [Settings::listeners]
+    fn apply_listeners(&mut self) {
+        self.app.stop_listening();
+        self.listening_port = None;
+        for config in self.settings.listeners() {
+            // failures get reported through the event handler
+            if let Ok(addr) = self.app.add_listener(config.clone()) {
+                self.event_handler.success(format!("Listening on {}", addr));
+                self.listening_port.get_or_insert(addr.port());
+            }
+        }
+        self.relay_listener = None;
+        self.apply_relay();
+        self.apply_discovery();
+    }
+
+    /// What we should be announcing on the local network, if anything
+    fn current_announcement(&self) -> Option<Announcement> {
+        let port = self.listening_port?;
+        let contacts = self.app.contacts().lock().unwrap();
+        Some(self.settings.announcement(port, &contacts))
+    }
+
+    /// Finds peers on the local network if [Settings::discovery] is on,
+    /// announcing ourselves as long as we listen
+    fn apply_discovery(&mut self) {
+        if !self.settings.discovery() {
+            self.app.stop_discovery();
+            self.announcement = None;
+            return;
+        }
+        self.announcement = self.current_announcement();
+        let keys = self
+            .settings
+            .announcement_keys(&self.app.contacts().lock().unwrap());
+        if let Err(e) = self.app.start_discovery(self.announcement.clone(), &keys) {
+            self.event_handler
+                .error(format!("Error finding peers: {}", e));
+        }
+    }
+
+    fn relay_ids(&self) -> Vec<Fingerprint> {
+        self.settings
+            .relay_ids(&self.app.contacts().lock().unwrap())
+    }
+
+    /// Registers at [Settings::relay] anew, under [Settings::relay_ids]
+    fn apply_relay(&mut self) {
+        if let Some((local, _)) = self.relay_listener.take() {
+            self.app.remove_listener(&local);
+        }
+        let Some(relay) = self.settings.relay().cloned() else {
+            return;
+        };
+        let ids = self.relay_ids();
+        let key = self.settings.relay_key().clone();
+        let contact_keys = self
+            .settings
+            .contact_keys(&self.app.contacts().lock().unwrap());
+
+        match self.app.listen_through_relay(
+            relay.clone(),
+            key,
+            contact_keys,
+            ListenerPolicy::default(),
+        ) {
+            Ok(local) => self.relay_listener = Some((local, ids)),
+            Err(e) => {
+                self.event_handler
+                    .error(format!("Error waiting at {}: {}", relay, e));
+            }
         }
     }
 }
 
 impl GrapevineUI {
     fn channels_panel(&mut self, ui: &mut Ui) {
-        for channel in self.app.channels().lock().unwrap().iter() {
-            let selected = self.selected_channel.as_ref().is_some_and(|c| c == channel);
-            let resp = ui.add(Button::new(RichText::new(channel.name())).selected(selected));
+        let contacts = self.app.contacts().clone();
+        let channels = self.app.channels().lock().unwrap().clone();
+        // groups we run, which open channels can be invited to
+        let our_groups = self
+            .app
+            .groups()
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|group| group.is_creator() && group.is_active())
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut reconnected = None;
+        let mut removed = None;
+        let mut invited = None;
+        for channel in channels.iter() {
+            let finished = channel.state().is_finished();
+            if !finished
+                && let Some(contact) = contacts.lock().unwrap().get_mut(channel.fingerprint())
+            {
+                contact.touch();
+            }
+
+            let selected = self
+                .selected_channel
+                .as_ref()
+                .is_some_and(|c| Arc::ptr_eq(c, channel));
+            let mut text = RichText::new(channel.name());
+            if finished {
+                text = text.weak();
+            }
+            let resp = ui.add(Button::new(text).selected(selected));
 
             resp.context_menu(|ui| {
-                if ui.button("Close").clicked() {
-                    if let Err(e) = channel.close() {
+                if finished {
+                    if ui.button("Reconnect").clicked() {
+                        reconnected = Some(channel.clone());
+                    }
+                    if ui.button("Remove").clicked() {
+                        removed = Some(channel.clone());
+                    }
+                } else {
+                    if ui.button("Close").clicked()
+                        && let Err(e) = channel.close()
+                    {
                         self.event_handler
-                            .lock()
-                            .unwrap()
                             .error(format!("Error closing the channel: {}", e));
                     }
+                    for group in &our_groups {
+                        if group.member(channel.fingerprint()).is_none()
+                            && ui.button(format!("Invite to {}", group.name())).clicked()
+                        {
+                            invited = Some((group.clone(), channel.clone()));
+                        }
+                    }
                 }
                 if ui.button("Save").clicked() {
-                    self.saved_channels.push(channel.desc().clone());
+                    if contacts
+                        .lock()
+                        .unwrap()
+                        .insert(channel.desc().clone().into())
+                    {
+                        self.event_handler
+                            .success(format!("Saved {} to contacts", channel.name()));
+                    } else {
+                        self.event_handler
+                            .info(format!("{} is already a contact", channel.name()));
+                    }
                 }
             });
 
             if resp.clicked() {
                 self.selected_channel = Some(channel.clone());
+                self.selected_group = None;
             }
         }
 
+        if let Some((group, channel)) = invited
+            && let Err(e) = self.app.invite_to_group(&group, &channel)
+        {
+            self.event_handler
+                .error(format!("Error inviting {}: {}", channel.name(), e));
+        }
+
+        if let Some(channel) = reconnected {
+            self.reconnect(&channel);
+        }
+        if let Some(channel) = removed {
+            if self
+                .selected_channel
+                .as_ref()
+                .is_some_and(|c| Arc::ptr_eq(c, &channel))
+            {
+                self.selected_channel = None;
+            }
+            // the channel is closed already
+            let _ = self.app.remove_channel(&channel);
+        }
+
+        self.groups_list(ui);
+
+        let connecting = self.app.connecting().lock().unwrap().clone();
+        for connection in connecting {
+            Frame::group(ui.style()).show(ui, |ui| {
+                let width = ui.available_width();
+                ui.horizontal(|ui| {
+                    ui.set_min_width(width);
+                    // keeps repainting, so the state stays up to date
+                    ui.spinner();
+                    ui.label(connection.name());
+                    ui.weak(connection.state().to_string());
+                    if ui
+                        .small_button("✘")
+                        .on_hover_text("Cancel connecting")
+                        .clicked()
+                    {
+                        connection.cancel();
+                    }
+                })
+                .response
+                .on_hover_text(connection.target());
+            });
+        }
+
         if ui.button("Create channel").clicked() {
             self.channel_modal = Some(ModalForm::new(
-                ChannelForm::new(self.settings.default_key_path().clone()),
+                ChannelForm::new(
+                    self.settings.default_key_path().clone(),
+                    self.settings.relay(),
+                ),
                 "New Channel",
             ));
         }
+        if ui.button("Create group").clicked() {
+            self.group_modal = Some(ModalForm::new(GroupCreationForm::new(), "New Group"));
+        }
+        self.discovered_list(ui);
 
+        let mut blocked = None;
         // first we clear the pending connections
         for pending in self.app.inspect_pending() {
             Frame::group(ui.style()).show(ui, |ui| {
@@ -107,13 +322,16 @@ impl GrapevineUI {
                     ui.set_min_width(width);
                     ui.label(pending.name());
 
-                    let label = match pending {
-                        PendingConnection::Aes(_) => "?",
-                        PendingConnection::Rsa(_) => "✔",
+                    let label = match &pending {
+                        PendingConnection::Aes(aes) if aes.contact().is_none() => "?",
+                        _ => "✔",
                     };
 
                     if ui.small_button(label).clicked() {
                         match pending {
+                            PendingConnection::Aes(aes) if aes.contact().is_some() => {
+                                self.app.add_known_aes_channel(aes);
+                            }
                             PendingConnection::Aes(aes) => {
                                 self.channel_aes_modal = Some(ModalForm::new(
                                     ChannelAcceptAesForm::new(
@@ -132,10 +350,15 @@ impl GrapevineUI {
                         };
                     } else if ui.small_button("✘").clicked() {
                         pending.reject();
-                        self.event_handler
-                            .lock()
-                            .unwrap()
-                            .info("Connection rejected");
+                        self.event_handler.info("Connection rejected");
+                    } else if let Some(ip) = pending.peer().ip()
+                        && ui
+                            .small_button("⛔")
+                            .on_hover_text("Block address")
+                            .clicked()
+                    {
+                        blocked = Some(IpNet::from(ip));
+                        pending.reject();
                     } else {
                         // keep the pending connection if nothing was done
                         self.app.add_pending(pending);
@@ -143,10 +366,216 @@ impl GrapevineUI {
                 })
             });
         }
+
+        if let Some(net) = blocked {
+            self.block(net);
+        }
+
+        for (addr, _) in self.app.listeners() {
+            ui.weak(format!("Listening on {}", addr));
+        }
+    }
+
+    /// The groups we are, or were in
+    fn groups_list(&mut self, ui: &mut Ui) {
+        let groups = self.app.groups().lock().unwrap().clone();
+        let mut left = None;
+        let mut removed = None;
+        for group in groups.iter() {
+            let active = group.is_active();
+            let selected = self
+                .selected_group
+                .as_ref()
+                .is_some_and(|g| Arc::ptr_eq(g, group));
+            let mut text = RichText::new(format!("👥 {}", group.name()));
+            if !active {
+                text = text.weak();
+            }
+            let resp = ui.add(Button::new(text).selected(selected));
+
+            resp.context_menu(|ui| {
+                if active {
+                    let label = if group.is_creator() { "End" } else { "Leave" };
+                    if ui.button(label).clicked() {
+                        left = Some(group.clone());
+                    }
+                } else if ui.button("Remove").clicked() {
+                    removed = Some(group.clone());
+                }
+            });
+
+            if resp.clicked() {
+                self.selected_group = Some(group.clone());
+                self.selected_channel = None;
+            }
+        }
+
+        if let Some(group) = left
+            && let Err(e) = self.app.leave_group(&group)
+        {
+            self.event_handler
+                .error(format!("Error leaving {}: {}", group.name(), e));
+        }
+        if let Some(group) = removed {
+            if self
+                .selected_group
+                .as_ref()
+                .is_some_and(|g| Arc::ptr_eq(g, &group))
+            {
+                self.selected_group = None;
+            }
+            // we are out of the group already
+            let _ = self.app.remove_group(&group);
+        }
+    }
+
+    /// Peers found on the local network, one click away from a channel
+    fn discovered_list(&mut self, ui: &mut Ui) {
+        let peers = self.app.discovered();
+        if peers.is_empty() {
+            return;
+        }
+        ui.separator();
+        ui.weak("On the local network");
+        for peer in peers {
+            // contacts are reconnected with at the address suggested, under the
+            // keys we already have, which refreshes their address once open
+            let contact = {
+                let contacts = self.app.contacts().lock().unwrap();
+                peer.known_as()
+                    .iter()
+                    .find_map(|id| contacts.get(id))
+                    .map(|contact| contact.desc().clone())
+            };
+            Frame::group(ui.style()).show(ui, |ui| {
+                let width = ui.available_width();
+                ui.horizontal(|ui| {
+                    ui.set_min_width(width);
+                    match &contact {
+                        Some(desc) => ui.label(format!("{} ({})", peer.name(), desc.name())),
+                        None => ui.label(peer.name()),
+                    };
+                    let label = match contact {
+                        Some(_) => "Connect",
+                        None => "Create channel",
+                    };
+                    if ui.small_button(label).clicked() {
+                        // failures are reported through events
+                        match contact {
+                            Some(desc) => {
+                                self.app.new_channel_from_desc(peer.addr().clone(), desc);
+                            }
+                            None => {
+                                self.app.new_rsa_channel(
+                                    peer.addr().clone(),
+                                    Some(peer.name().to_string()),
+                                );
+                            }
+                        }
+                    }
+                })
+                .response
+                .on_hover_text(format!("{}\n{}", peer.addr(), peer.id()));
+            });
+        }
+    }
+
+    /// Members of the selected group, and its messages
+    fn group_panel(&mut self, ctx: &Context, ui: &mut Ui, group: &Arc<Group>) {
+        let mut kicked = None;
+        SidePanel::right("Members")
+            .resizable(false)
+            .show_inside(ui, |ui| {
+                ui.heading("Members");
+                for member in group.members() {
+                    ui.horizontal(|ui| {
+                        if member.id() == group.me() {
+                            ui.strong(member.name());
+                        } else {
+                            ui.label(member.name());
+                        }
+                        if group.is_creator()
+                            && member.id() != group.me()
+                            && ui
+                                .small_button("✘")
+                                .on_hover_text("Remove from the group")
+                                .clicked()
+                        {
+                            kicked = Some(member.id().to_owned());
+                        }
+                    })
+                    .response
+                    .on_hover_text(member.id().short());
+                }
+            });
+
+        if let Some(member) = kicked
+            && let Err(e) = self.app.remove_from_group(group, &member)
+        {
+            self.event_handler
+                .error(format!("Error removing the member: {}", e));
+        }
+
+        ScrollArea::vertical()
+            .auto_shrink([false; 2])
+            .show(ui, |ui| {
+                for message in group.messages().lock().unwrap().iter() {
+                    let layout = if message.is_ours() {
+                        Layout::right_to_left(Align::TOP)
+                    } else {
+                        Layout::left_to_right(Align::TOP)
+                    };
+                    let text = format!(
+                        "{}: {}",
+                        message.author().name(),
+                        message.message().content()
+                    );
+
+                    ui.with_layout(layout, |ui| {
+                        Frame::group(ui.style())
+                            .show(ui, |ui| {
+                                ui.label(text);
+                            })
+                            .response
+                            .on_hover_text(
+                                message
+                                    .message()
+                                    .timestamp()
+                                    .format("%Y-%m-%d %H:%M:%S")
+                                    .to_string(),
+                            );
+                    });
+                }
+            });
+
+        if !group.is_active() {
+            TopBottomPanel::bottom("message_panel").show(ctx, |ui| {
+                ui.label("No longer in the group");
+            });
+            return;
+        }
+
+        TopBottomPanel::bottom("message_panel").show(ctx, |ui| {
+            ui.vertical_centered_justified(|ui| {
+                let resp = ui.text_edit_singleline(&mut self.channel_message_input);
+                if resp.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    if !self.channel_message_input.is_empty() {
+                        let message = Message::new(mem::take(&mut self.channel_message_input));
+                        if let Err(e) = self.app.send_to_group(group, message) {
+                            self.event_handler
+                                .error(format!("Message sending error: {}", e));
+                        }
+                    }
+                    resp.request_focus();
+                }
+            })
+        });
     }
 
     fn central_panel(&mut self, ctx: &Context, ui: &mut Ui) {
-        if let Some(channel) = &self.selected_channel {
+        if let Some(group) = self.selected_group.clone() {
+            self.group_panel(ctx, ui, &group);
+        } else if let Some(channel) = &self.selected_channel {
             ScrollArea::vertical()
                 .auto_shrink([false; 2])
                 .show(ui, |ui| {
@@ -171,17 +600,61 @@ impl GrapevineUI {
                     }
                 });
 
+            let state = channel.state();
+            if state.is_finished() {
+                let channel = channel.clone();
+                TopBottomPanel::bottom("message_panel").show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Disconnected: {}", state));
+                        if ui.button("Reconnect").clicked() {
+                            self.reconnect(&channel);
+                        }
+                    });
+                    ui.vertical_centered_justified(|ui| {
+                        let resp = ui.add(
+                            TextEdit::singleline(&mut self.channel_message_input)
+                                .hint_text("Send through peers"),
+                        );
+                        if resp.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                            if !self.channel_message_input.is_empty() {
+                                let message =
+                                    Message::new(mem::take(&mut self.channel_message_input));
+                                if let Err(e) = self.app.send_through_peers(channel.desc(), message)
+                                {
+                                    self.event_handler
+                                        .error(format!("Message sending error: {}", e));
+                                }
+                            }
+                            resp.request_focus();
+                        }
+                    })
+                });
+                return;
+            }
+
+            let waiting = self
+                .sending
+                .iter()
+                .filter(|(other, _)| Arc::ptr_eq(other, channel))
+                .count();
             TopBottomPanel::bottom("message_panel").show(ctx, |ui| {
+                if waiting > 0 {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.weak(format!("Sending {} message(s)", waiting));
+                    });
+                }
                 ui.vertical_centered_justified(|ui| {
                     let resp = ui.text_edit_singleline(&mut self.channel_message_input);
                     if resp.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
                         if !self.channel_message_input.is_empty() {
                             let message = Message::new(mem::take(&mut self.channel_message_input));
-                            if let Err(e) = channel.send_message(message) {
-                                self.event_handler
-                                    .lock()
-                                    .unwrap()
-                                    .error(&format!("Message sending error: {}", e));
+                            match channel.send_message(message) {
+                                Ok(handle) => self.sending.push((channel.clone(), handle)),
+                                Err(e) => {
+                                    self.event_handler
+                                        .error(format!("Message sending error: {}", e));
+                                }
                             }
                         }
                         resp.request_focus();
@@ -191,36 +664,35 @@ impl GrapevineUI {
         }
     }
 
-    fn top_panel(&mut self, ui: &mut Ui) {
-        ScrollArea::horizontal()
-            .auto_shrink([false; 2])
-            .show(ui, |ui| {
-                self.saved_channels.retain_mut(|desc| {
-                    let resp = ui.button(desc.name());
-                    if resp.clicked() {
-                        self.channel_recreation_modal = Some(ModalForm::new(
-                            ChannelRecreationForm::new(desc.clone()),
-                            "Channel recreation",
-                        ))
-                    }
+    /// Runs the handshake of a closed channel anew
+    fn reconnect(&mut self, channel: &Channel) {
+        if let Err(e) = self.app.reconnect(channel) {
+            self.event_handler
+                .error(format!("Error reconnecting {}: {}", channel.name(), e));
+        }
+    }
 
-                    let mut retain = true;
-                    resp.context_menu(|ui| {
-                        if ui.button("Remove").clicked() {
-                            retain = false;
-                        }
+    /// Adds the network to the blocklist, and applies the new rules
+    fn block(&mut self, net: IpNet) {
+        self.settings.access_rules_mut().block(net);
+        self.app
+            .set_access_rules(self.settings.access_rules().clone());
+        self.event_handler.info(format!("Blocked {}", net));
+    }
 
-                        if ui.button("Edit").clicked() {
-                            self.channel_desc_edit_modal = Some(ModalForm::new(
-                                ChannelDescEditForm::new(desc.clone()),
-                                "Edit Channel",
-                            ));
-                            retain = false;
-                        }
-                    });
-                    return retain;
-                });
-            });
+    fn top_panel(&mut self, ui: &mut Ui) {
+        if ui
+            .add(Button::new("Contacts").selected(self.contacts_open))
+            .clicked()
+        {
+            self.contacts_open = !self.contacts_open;
+        }
+        if ui
+            .add(Button::new("Security").selected(self.security_open))
+            .clicked()
+        {
+            self.security_open = !self.security_open;
+        }
 
         ui.with_layout(Layout::right_to_left(Align::Max), |ui| {
             if ui.button("Settings").clicked() {
@@ -231,10 +703,178 @@ impl GrapevineUI {
             }
         });
     }
+
+    fn contacts_panel(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("🔍");
+            ui.text_edit_singleline(&mut self.contacts_search);
+        });
+        ui.separator();
+
+        let contacts = self.app.contacts().clone();
+        let mut contacts = contacts.lock().unwrap();
+        let mut removed: Option<Fingerprint> = None;
+        let mut blocked_key: Option<Fingerprint> = None;
+        ScrollArea::vertical()
+            .auto_shrink([false, true])
+            .max_height(ui.available_height() - 100.0)
+            .show(ui, |ui| {
+                for contact in contacts.search(&self.contacts_search) {
+                    let title = if contact.is_verified() {
+                        format!("{} ✔", contact.name())
+                    } else {
+                        contact.name().to_string()
+                    };
+
+                    CollapsingHeader::new(title)
+                        .id_salt(contact.id())
+                        .show(ui, |ui| {
+                            ui.label(format!("Fingerprint: {}", contact.id().short()))
+                                .on_hover_text(contact.id().to_string());
+                            ui.label(match contact.last_seen() {
+                                Some(seen) => {
+                                    format!("Last seen: {}", seen.format("%Y-%m-%d %H:%M:%S"))
+                                }
+                                None => "Never seen".to_string(),
+                            });
+                            for addr in contact.addresses() {
+                                ui.label(addr.to_string());
+                            }
+                            if !contact.notes().is_empty() {
+                                ui.label(RichText::new(contact.notes()).italics());
+                            }
+
+                            ui.horizontal(|ui| {
+                                if ui.button("Connect").clicked() {
+                                    self.channel_recreation_modal = Some(ModalForm::new(
+                                        ChannelRecreationForm::new(contact, self.settings.relay()),
+                                        "Channel recreation",
+                                    ));
+                                }
+                                if ui.button("Edit").clicked() {
+                                    self.contact_edit_modal = Some(ModalForm::new(
+                                        ContactEditForm::new(contact.clone()),
+                                        "Edit Contact",
+                                    ));
+                                }
+                                if ui.button("Remove").clicked() {
+                                    removed = Some(contact.id());
+                                }
+                                if ui.button("Block").clicked() {
+                                    blocked_key = Some(contact.id());
+                                }
+                            });
+                        });
+                }
+            });
+
+        if let Some(id) = removed {
+            contacts.remove(&id);
+        }
+        if let Some(id) = blocked_key {
+            self.settings.access_rules_mut().block_key(id);
+            self.app
+                .set_access_rules(self.settings.access_rules().clone());
+            self.event_handler
+                .info(format!("Blocked key {}", id.short()));
+        }
+
+        ui.separator();
+        ui.label("Contacts file");
+        ui.add(PathPicker::new(
+            &mut self.contacts_path,
+            self.settings.default_key_path(),
+        ));
+        ui.horizontal(|ui| {
+            if ui.button("Import").clicked() {
+                match import_contacts(Path::new(&self.contacts_path)) {
+                    Ok(book) => {
+                        let added = contacts.merge(book);
+                        self.event_handler
+                            .success(format!("Imported {} new contacts", added));
+                    }
+                    Err(e) => {
+                        self.event_handler
+                            .error(format!("Error importing contacts: {}", e));
+                    }
+                }
+            }
+            if ui.button("Export").clicked()
+                && let Err(e) = export_contacts(&contacts, Path::new(&self.contacts_path))
+            {
+                self.event_handler
+                    .error(format!("Error exporting contacts: {}", e));
+            }
+        });
+    }
+
+    /// Log of rejected incoming connections, newest first
+    fn security_panel(&mut self, ui: &mut Ui) {
+        let firewall = self.app.firewall().clone();
+        let mut blocked = None;
+        {
+            let mut firewall = firewall.lock().unwrap();
+            if ui.button("Clear").clicked() {
+                firewall.clear_events();
+            }
+            ui.separator();
+
+            ScrollArea::vertical()
+                .auto_shrink([false, true])
+                .show(ui, |ui| {
+                    if firewall.events().next().is_none() {
+                        ui.weak("No connections were rejected");
+                    }
+                    for event in firewall.events().rev() {
+                        ui.horizontal(|ui| {
+                            ui.label(event.time().format("%Y-%m-%d %H:%M:%S").to_string());
+                            ui.label(event.peer().to_string());
+                            ui.label(event.reason().to_string());
+
+                            if let Some(net) = event.peer().ip().map(IpNet::from)
+                                && !self.settings.access_rules().blocked().contains(&net)
+                                && ui.small_button("Block").clicked()
+                            {
+                                blocked = Some(net);
+                            }
+                        });
+                    }
+                });
+        }
+
+        if let Some(net) = blocked {
+            self.block(net);
+        }
+    }
 }
 
 impl eframe::App for GrapevineUI {
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
+        while let Ok(event) = self.events.try_recv() {
+            // a reconnected channel takes the place of the closed one
+            if let Event::ChannelOpened { channel } = &event
+                && let Some(selected) = &self.selected_channel
+                && selected.state().is_finished()
+                && selected.fingerprint() == channel.fingerprint()
+            {
+                self.selected_channel = Some(channel.clone());
+            }
+            self.event_handler.on_event(&event);
+        }
+
+        self.sending.retain(|(channel, handle)| {
+            let status = handle.status();
+            match status {
+                SendStatus::Queued => return true,
+                SendStatus::Sent => {}
+                status => {
+                    self.event_handler
+                        .error(format!("Message to {} {}", channel.name(), status));
+                }
+            }
+            false
+        });
+
         TopBottomPanel::top("Options Panel")
             .frame(
                 Frame::new()
@@ -244,6 +884,20 @@ impl eframe::App for GrapevineUI {
             .resizable(false)
             .show(ctx, |ui| ui.horizontal(|ui| self.top_panel(ui)));
 
+        if self.contacts_open {
+            SidePanel::right("Contacts")
+                .resizable(true)
+                .show(ctx, |ui| self.contacts_panel(ui));
+        }
+
+        if self.security_open {
+            let mut open = true;
+            Window::new("Security events")
+                .open(&mut open)
+                .show(ctx, |ui| self.security_panel(ui));
+            self.security_open = open;
+        }
+
         SidePanel::left("Channels")
             .resizable(false)
             .show(ctx, |ui| {
@@ -262,30 +916,45 @@ impl eframe::App for GrapevineUI {
             .and_then(|modal| modal.show(ctx))
         {
             self.settings = settings;
-            if let Some(addr) = self.settings.listening() {
-                self.app.start_listening(addr.clone());
-            } else {
-                self.app.stop_listening();
-            }
+            self.settings.apply(&mut self.app);
+            self.apply_listeners();
 
             self.settings_modal = None;
         }
 
+        // contacts saved, imported or removed are reached through the relay
+        // under other keys
+        if let Some((_, ids)) = &self.relay_listener
+            && *ids != self.relay_ids()
+        {
+            self.apply_relay();
+        }
+        if self.settings.discovery() && self.announcement != self.current_announcement() {
+            self.apply_discovery();
+        }
+
         if let Some(ret) = self
             .channel_modal
             .as_mut()
             .and_then(|modal| modal.show(ctx))
         {
-            if let Some(args) = ret {
-                if let Err(e) = match args {
-                    ChannelArgs::Rsa(rsa) => self.app.new_rsa_channel(rsa.0, rsa.1),
-                    ChannelArgs::Aes(aes) => self.app.new_aes_channel(aes.0, aes.2, aes.3, aes.1),
-                } {
-                    self.event_handler
-                        .lock()
-                        .unwrap()
-                        .error(format!("Error adding channel: {}", e));
+            // failures are reported through events
+            match ret {
+                Some(ChannelArgs::Rsa(rsa)) => {
+                    self.app.new_rsa_channel(rsa.0, rsa.1);
+                }
+                Some(ChannelArgs::Aes(aes)) => {
+                    self.app.new_aes_channel(aes.0, aes.2, aes.3, aes.1);
+                }
+                Some(ChannelArgs::RelayedRsa(rsa)) => {
+                    self.app.new_rsa_channel_through_relay(rsa.0, rsa.1, rsa.2);
+                }
+                Some(ChannelArgs::RelayedAes((relay, name, ours, theirs))) => {
+                    let name = name.unwrap_or_else(|| relay.to_string());
+                    let desc = ChannelDesc::new(name, relay.clone(), ours, theirs);
+                    self.app.new_channel_through_relay(relay, desc);
                 }
+                None => {}
             }
             self.channel_modal = None;
         }
@@ -297,12 +966,7 @@ impl eframe::App for GrapevineUI {
         {
             let pending = self.channel_rsa_modal.take().unwrap().inner().pending();
             if let Some(name) = res {
-                if let Err(e) = self.app.add_rsa_channel(pending, name) {
-                    self.event_handler
-                        .lock()
-                        .unwrap()
-                        .error(format!("Error while accepting: {}", e));
-                }
+                self.app.add_rsa_channel(pending, name);
             } else {
                 pending.reject();
             }
@@ -315,12 +979,7 @@ impl eframe::App for GrapevineUI {
         {
             let pending = self.channel_aes_modal.take().unwrap().inner().pending();
             if let Some(args) = res {
-                if let Err(e) = self.app.add_aes_channel(pending, args.0, args.1, args.2) {
-                    self.event_handler
-                        .lock()
-                        .unwrap()
-                        .error(format!("Error while accepting: {}", e));
-                }
+                self.app.add_aes_channel(pending, args.0, args.1, args.2);
             } else {
                 pending.reject();
             }
@@ -332,37 +991,58 @@ impl eframe::App for GrapevineUI {
             .and_then(|modal| modal.show(ctx))
         {
             let desc = self.channel_recreation_modal.take().unwrap().inner().desc();
-            if let Some(addr) = res {
-                if let Err(e) = self.app.new_channel_from_desc(addr, desc) {
+            match res {
+                Some((addr, false)) => {
+                    self.app.new_channel_from_desc(addr, desc);
+                }
+                Some((relay, true)) => {
+                    self.app.new_channel_through_relay(relay, desc);
+                }
+                None => {}
+            }
+        }
+
+        if let Some(res) = self.group_modal.as_mut().and_then(|modal| modal.show(ctx)) {
+            if let Some(name) = res {
+                let our_name = self.settings.username().to_string();
+                if let Err(e) = self.app.create_group(name, our_name) {
                     self.event_handler
-                        .lock()
-                        .unwrap()
-                        .error(format!("Error while recreating: {}", e));
+                        .error(format!("Error creating the group: {}", e));
                 }
             }
+            self.group_modal = None;
         }
 
-        if let Some(_) = self
-            .channel_desc_edit_modal
+        if let Some(saved) = self
+            .contact_edit_modal
             .as_mut()
             .and_then(|modal| modal.show(ctx))
         {
-            let desc = self.channel_desc_edit_modal.take().unwrap().inner().desc();
-            self.saved_channels.push(desc);
+            let contact = self.contact_edit_modal.take().unwrap().inner().contact();
+            if saved {
+                let mut contacts = self.app.contacts().lock().unwrap();
+                contacts.remove(&contact.id());
+                contacts.insert(contact);
+            }
         }
 
-        self.event_handler.lock().unwrap().ui(ctx);
+        self.event_handler.ui(ctx);
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // nobody would see a toast anymore
+        if let Err(e) = self.app.shutdown(SHUTDOWN_TIMEOUT) {
+            eprintln!("Error shutting down: {}", e);
+        }
     }
 
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
         if self.settings.save_channels() {
-            match to_string(&self.saved_channels) {
-                Ok(json) => storage.set_string(type_name::<ChannelDesc>(), json),
+            match to_string(&*self.app.contacts().lock().unwrap()) {
+                Ok(json) => storage.set_string(type_name::<ContactBook>(), json),
                 Err(e) => {
                     self.event_handler
-                        .lock()
-                        .unwrap()
-                        .error(format!("Error saving channels: {}", e));
+                        .error(format!("Error saving contacts: {}", e));
                 }
             }
         }
@@ -371,8 +1051,6 @@ impl eframe::App for GrapevineUI {
             Ok(json) => storage.set_string(type_name::<Settings>(), json),
             Err(e) => {
                 self.event_handler
-                    .lock()
-                    .unwrap()
                     .error(format!("Error saving settings: {}", e));
             }
         };